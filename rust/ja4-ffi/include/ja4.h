@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) 2023, FoxIO, LLC.
+ * All rights reserved.
+ * Patent Pending
+ * JA4 is Open-Source, Licensed under BSD 3-Clause
+ * JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+ * For full license text, see the repo root.
+ */
+
+#ifndef JA4_H
+#define JA4_H
+
+/* Generated with cbindgen from rust/ja4-ffi/src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+/*
+ * Every function returns a Ja4Status and writes the fingerprint, NUL-terminated,
+ * to a buffer that the caller owns:
+ *
+ * - `out_buf` points to `*out_len` writable bytes. JA4_BUFFER_SIZE bytes fit
+ *   every fingerprint.
+ * - On success, `*out_len` is set to the length of the fingerprint, without the
+ *   terminating NUL.
+ * - If the buffer is too small, JA4_STATUS_BUFFER_TOO_SMALL is returned,
+ *   `*out_len` is set to the size needed (NUL included), and nothing is written.
+ * - On any other error, `*out_len` is set to 0 and the buffer is left as it is.
+ *
+ * The library keeps no pointers beyond the call and allocates nothing that the
+ * caller has to free. The functions are thread-safe.
+ */
+
+// Size of a buffer that fits every fingerprint, NUL included.
+#define JA4_BUFFER_SIZE 128
+
+// Result of a call.
+typedef enum Ja4Status {
+  // The fingerprint has been written.
+  JA4_STATUS_OK = 0,
+  // A pointer argument is null.
+  JA4_STATUS_NULL_POINTER = 1,
+  // A string argument is not valid UTF-8.
+  JA4_STATUS_INVALID_UTF8 = 2,
+  // The input is malformed, e.g. not a Client Hello or not a certificate.
+  JA4_STATUS_INVALID_INPUT = 3,
+  // `out_buf` is too small; `*out_len` is set to the size needed.
+  JA4_STATUS_BUFFER_TOO_SMALL = 4,
+  // The library failed unexpectedly. Please report a bug.
+  JA4_STATUS_INTERNAL = 5,
+} Ja4Status;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns a static, NUL-terminated description of `status`.
+const char *ja4_status_message(enum Ja4Status status);
+
+// Computes JA4 of a TLS Client Hello sent over TCP.
+//
+// `bytes` is either the Client Hello message, starting with its handshake type,
+// or the TLS handshake records carrying it.
+//
+// # Safety
+//
+// `bytes` must point to `len` readable bytes; `out_buf` and `out_len` follow
+// the buffer convention of the [crate documentation](crate) (top of `ja4.h`).
+enum Ja4Status ja4_from_client_hello(const uint8_t *bytes,
+                                     size_t len,
+                                     char *out_buf,
+                                     size_t *out_len);
+
+// Computes JA4H of an HTTP request.
+//
+// `method` and `version` are as in the request line, e.g. `"GET"` and
+// `"HTTP/1.1"` (`"HTTP/2"`, `"HTTP/3"` for the later versions).
+// `header_names[i]` and `header_values[i]` are the `nr_headers` headers of the
+// request, in order. The strings are NUL-terminated UTF-8.
+//
+// # Safety
+//
+// `method` and `version` must be valid C strings; `header_names` and
+// `header_values` must point to `nr_headers` valid C strings each (they may be
+// null if `nr_headers` is 0); `out_buf` and `out_len` follow the buffer
+// convention of the [crate documentation](crate) (top of `ja4.h`).
+enum Ja4Status ja4h_from_request(const char *method,
+                                 const char *version,
+                                 const char *const *header_names,
+                                 const char *const *header_values,
+                                 size_t nr_headers,
+                                 char *out_buf,
+                                 size_t *out_len);
+
+// Computes JA4X of a DER-encoded X.509 certificate.
+//
+// # Safety
+//
+// `der` must point to `len` readable bytes; `out_buf` and `out_len` follow the
+// buffer convention of the [crate documentation](crate) (top of `ja4.h`).
+enum Ja4Status ja4x_from_der(const uint8_t *der, size_t len, char *out_buf, size_t *out_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* JA4_H */
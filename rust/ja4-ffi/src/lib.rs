@@ -0,0 +1,246 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! C interface to the fingerprint functions of the `ja4` library
+//!
+//! The header is `include/ja4.h`. Every function returns a [`Ja4Status`] and
+//! writes the fingerprint, NUL-terminated, to a buffer that the caller owns:
+//!
+//! - `out_buf` points to `*out_len` writable bytes. [`JA4_BUFFER_SIZE`] bytes fit
+//!   every fingerprint.
+//! - On success, `*out_len` is set to the length of the fingerprint, without the
+//!   terminating NUL.
+//! - If the buffer is too small, [`Ja4Status::BufferTooSmall`] is returned,
+//!   `*out_len` is set to the size needed (NUL included), and nothing is written.
+//! - On any other error, `*out_len` is set to 0 and the buffer is left as it is.
+//!
+//! The library keeps no pointers beyond the call and allocates nothing that the
+//! caller has to free. The functions are thread-safe.
+
+use std::{
+    ffi::{c_char, CStr},
+    panic::{catch_unwind, UnwindSafe},
+    ptr, slice,
+};
+
+use ja4::{http, tls, x509};
+
+/// Size of a buffer that fits every fingerprint, NUL included.
+pub const JA4_BUFFER_SIZE: usize = 128;
+
+/// Result of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ja4Status {
+    /// The fingerprint has been written.
+    Ok = 0,
+    /// A pointer argument is null.
+    NullPointer = 1,
+    /// A string argument is not valid UTF-8.
+    InvalidUtf8 = 2,
+    /// The input is malformed, e.g. not a Client Hello or not a certificate.
+    InvalidInput = 3,
+    /// `out_buf` is too small; `*out_len` is set to the size needed.
+    BufferTooSmall = 4,
+    /// The library failed unexpectedly. Please report a bug.
+    Internal = 5,
+}
+
+/// Returns a static, NUL-terminated description of `status`.
+#[no_mangle]
+pub extern "C" fn ja4_status_message(status: Ja4Status) -> *const c_char {
+    let msg: &CStr = match status {
+        Ja4Status::Ok => c"success",
+        Ja4Status::NullPointer => c"null pointer argument",
+        Ja4Status::InvalidUtf8 => c"string argument is not valid UTF-8",
+        Ja4Status::InvalidInput => c"malformed input",
+        Ja4Status::BufferTooSmall => c"output buffer is too small",
+        Ja4Status::Internal => c"internal error",
+    };
+    msg.as_ptr()
+}
+
+/// Computes JA4 of a TLS Client Hello sent over TCP.
+///
+/// `bytes` is either the Client Hello message, starting with its handshake type,
+/// or the TLS handshake records carrying it.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes; `out_buf` and `out_len` follow
+/// the buffer convention of the [crate documentation](crate) (top of `ja4.h`).
+#[no_mangle]
+pub unsafe extern "C" fn ja4_from_client_hello(
+    bytes: *const u8,
+    len: usize,
+    out_buf: *mut c_char,
+    out_len: *mut usize,
+) -> Ja4Status {
+    run(out_buf, out_len, || {
+        let data = byte_slice(bytes, len)?;
+        tls::ja4_from_client_hello(data, tls::Transport::Tcp)
+            .map(|fp| fp.ja4)
+            .map_err(|_| Ja4Status::InvalidInput)
+    })
+}
+
+/// Computes JA4H of an HTTP request.
+///
+/// `method` and `version` are as in the request line, e.g. `"GET"` and
+/// `"HTTP/1.1"` (`"HTTP/2"`, `"HTTP/3"` for the later versions).
+/// `header_names[i]` and `header_values[i]` are the `nr_headers` headers of the
+/// request, in order. The strings are NUL-terminated UTF-8.
+///
+/// # Safety
+///
+/// `method` and `version` must be valid C strings; `header_names` and
+/// `header_values` must point to `nr_headers` valid C strings each (they may be
+/// null if `nr_headers` is 0); `out_buf` and `out_len` follow the buffer
+/// convention of the [crate documentation](crate) (top of `ja4.h`).
+#[no_mangle]
+pub unsafe extern "C" fn ja4h_from_request(
+    method: *const c_char,
+    version: *const c_char,
+    header_names: *const *const c_char,
+    header_values: *const *const c_char,
+    nr_headers: usize,
+    out_buf: *mut c_char,
+    out_len: *mut usize,
+) -> Ja4Status {
+    run(out_buf, out_len, || {
+        let method = str_arg(method)?;
+        let version = str_arg(version)?;
+        let names = ptr_slice(header_names, nr_headers)?;
+        let values = ptr_slice(header_values, nr_headers)?;
+        let headers = names
+            .iter()
+            .zip(values)
+            .map(|(&name, &value)| Ok((str_arg(name)?, str_arg(value)?)))
+            .collect::<Result<Vec<_>, Ja4Status>>()?;
+        http::ja4h(method, version, &headers, false)
+            .map(|fp| fp.ja4h)
+            .map_err(|_| Ja4Status::InvalidInput)
+    })
+}
+
+/// Computes JA4X of a DER-encoded X.509 certificate.
+///
+/// # Safety
+///
+/// `der` must point to `len` readable bytes; `out_buf` and `out_len` follow the
+/// buffer convention of the [crate documentation](crate) (top of `ja4.h`).
+#[no_mangle]
+pub unsafe extern "C" fn ja4x_from_der(
+    der: *const u8,
+    len: usize,
+    out_buf: *mut c_char,
+    out_len: *mut usize,
+) -> Ja4Status {
+    run(out_buf, out_len, || {
+        let der = byte_slice(der, len)?;
+        x509::ja4x(der)
+            .map(|fp| fp.ja4x)
+            .map_err(|_| Ja4Status::InvalidInput)
+    })
+}
+
+/// Computes the fingerprint with `f` and copies it to the caller's buffer.
+///
+/// # Safety
+///
+/// See the crate documentation for `out_buf` and `out_len`.
+unsafe fn run(
+    out_buf: *mut c_char,
+    out_len: *mut usize,
+    f: impl FnOnce() -> Result<String, Ja4Status> + UnwindSafe,
+) -> Ja4Status {
+    if out_buf.is_null() || out_len.is_null() {
+        return Ja4Status::NullPointer;
+    }
+    let fingerprint = match catch_unwind(f) {
+        Ok(Ok(fingerprint)) => fingerprint,
+        Ok(Err(status)) => {
+            *out_len = 0;
+            return status;
+        }
+        Err(_) => {
+            *out_len = 0;
+            return Ja4Status::Internal;
+        }
+    };
+    let needed = fingerprint.len() + 1;
+    if *out_len < needed {
+        *out_len = needed;
+        return Ja4Status::BufferTooSmall;
+    }
+    ptr::copy_nonoverlapping(fingerprint.as_ptr(), out_buf.cast(), fingerprint.len());
+    *out_buf.add(fingerprint.len()) = 0;
+    *out_len = fingerprint.len();
+    Ja4Status::Ok
+}
+
+unsafe fn byte_slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Ja4Status> {
+    if data.is_null() {
+        return Err(Ja4Status::NullPointer);
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+unsafe fn ptr_slice<'a>(
+    data: *const *const c_char,
+    len: usize,
+) -> Result<&'a [*const c_char], Ja4Status> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(Ja4Status::NullPointer),
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, Ja4Status> {
+    if s.is_null() {
+        return Err(Ja4Status::NullPointer);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| Ja4Status::InvalidUtf8)
+}
+
+#[test]
+fn test_buffer() {
+    let names = [c"Host".as_ptr(), c"Accept".as_ptr()];
+    let values = [c"example.com".as_ptr(), c"*/*".as_ptr()];
+    let ja4h = |buf: &mut [c_char], len: &mut usize| unsafe {
+        ja4h_from_request(
+            c"GET".as_ptr(),
+            c"HTTP/1.1".as_ptr(),
+            names.as_ptr(),
+            values.as_ptr(),
+            names.len(),
+            buf.as_mut_ptr(),
+            len,
+        )
+    };
+
+    let mut buf = [0; JA4_BUFFER_SIZE];
+    let mut len = 10;
+    assert_eq!(ja4h(&mut buf, &mut len), Ja4Status::BufferTooSmall);
+    assert_eq!(len, 52);
+    assert_eq!(buf[0], 0);
+
+    len = buf.len();
+    assert_eq!(ja4h(&mut buf, &mut len), Ja4Status::Ok);
+    let s = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    assert_eq!(s.to_bytes().len(), len);
+    assert!(s.to_str().unwrap().starts_with("ge11nn020000_"));
+
+    let mut len = buf.len();
+    let status = unsafe { ja4x_from_der([0x30, 0].as_ptr(), 2, buf.as_mut_ptr(), &mut len) };
+    assert_eq!((status, len), (Ja4Status::InvalidInput, 0));
+    let status = unsafe { ja4x_from_der(ptr::null(), 0, buf.as_mut_ptr(), &mut len) };
+    assert_eq!(status, Ja4Status::NullPointer);
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) 2023, FoxIO, LLC.
+ * All rights reserved.
+ * Patent Pending
+ * JA4 is Open-Source, Licensed under BSD 3-Clause
+ * JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+ * For full license text, see the repo root.
+ */
+
+/*
+ * Calls the C interface as an application would. Built and run by
+ * `tests/c_api.rs` with the path of a DER-encoded certificate as the argument.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "ja4.h"
+
+static int failures = 0;
+
+static void check(int ok, const char *what)
+{
+    if (!ok) {
+        fprintf(stderr, "FAILED: %s\n", what);
+        failures++;
+    }
+}
+
+static void check_fingerprint(Ja4Status status, const char *buf, size_t len,
+                              const char *expected)
+{
+    if (status != JA4_STATUS_OK) {
+        fprintf(stderr, "FAILED: %s: %s\n", expected, ja4_status_message(status));
+        failures++;
+        return;
+    }
+    check(len == strlen(expected), "length of the fingerprint");
+    check(strcmp(buf, expected) == 0, expected);
+}
+
+/* Client Hello of the example in the JA4 specification. */
+static const uint8_t CLIENT_HELLO[] = {
+    0x01, 0x00, 0x00, 0xc8, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x20, 0x0a, 0x0a, 0x13, 0x01, 0x13, 0x02, 0x13,
+    0x03, 0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc,
+    0xa8, 0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00, 0x9d, 0x00, 0x2f, 0x00,
+    0x35, 0x01, 0x00, 0x00, 0x7f, 0x1a, 0x1a, 0x00, 0x00, 0x00, 0x1b, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0x65, 0x78,
+    0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0x00, 0x33, 0x00,
+    0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x0c, 0x02, 0x68, 0x32, 0x08, 0x68,
+    0x74, 0x74, 0x70, 0x2f, 0x31, 0x2e, 0x31, 0x44, 0x69, 0x00, 0x00, 0x00,
+    0x17, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x12, 0x00,
+    0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05,
+    0x01, 0x08, 0x06, 0x06, 0x01, 0x00, 0x05, 0x00, 0x00, 0x00, 0x23, 0x00,
+    0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x07, 0x06, 0x3a, 0x3a,
+    0x03, 0x04, 0x03, 0x03, 0xff, 0x01, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00,
+    0x00, 0x0a, 0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x2a, 0x2a, 0x00, 0x00,
+};
+
+static void test_ja4(void)
+{
+    char buf[JA4_BUFFER_SIZE];
+    size_t len = sizeof buf;
+    Ja4Status status =
+        ja4_from_client_hello(CLIENT_HELLO, sizeof CLIENT_HELLO, buf, &len);
+    check_fingerprint(status, buf, len, "t13d1516h2_8daaf6152771_e5627efa2ab1");
+
+    len = sizeof buf;
+    status = ja4_from_client_hello(CLIENT_HELLO, 10, buf, &len);
+    check(status == JA4_STATUS_INVALID_INPUT && len == 0, "truncated Client Hello");
+}
+
+static void test_ja4h(void)
+{
+    const char *names[] = {"Host", "User-Agent", "Cookie", "Accept-Language"};
+    const char *values[] = {"example.com", "curl/8.0", "b=2; a=1", "en-US,en;q=0.9"};
+    const char *expected = "ge11cn03enus_ea59799162d6_1eb7c54d5283_06beefe2b477";
+    char buf[JA4_BUFFER_SIZE];
+    size_t len = 8;
+    Ja4Status status =
+        ja4h_from_request("GET", "HTTP/1.1", names, values, 4, buf, &len);
+    check(status == JA4_STATUS_BUFFER_TOO_SMALL, "buffer too small");
+    check(len == strlen(expected) + 1, "size needed");
+
+    status = ja4h_from_request("GET", "HTTP/1.1", names, values, 4, buf, &len);
+    check_fingerprint(status, buf, len, expected);
+
+    len = sizeof buf;
+    status = ja4h_from_request(NULL, "HTTP/1.1", NULL, NULL, 0, buf, &len);
+    check(status == JA4_STATUS_NULL_POINTER, "null method");
+}
+
+static void test_ja4x(const char *path)
+{
+    static uint8_t der[16384];
+    FILE *file = fopen(path, "rb");
+    if (file == NULL) {
+        perror(path);
+        exit(2);
+    }
+    size_t der_len = fread(der, 1, sizeof der, file);
+    fclose(file);
+
+    char buf[JA4_BUFFER_SIZE];
+    size_t len = sizeof buf;
+    Ja4Status status = ja4x_from_der(der, der_len, buf, &len);
+    check_fingerprint(status, buf, len, "7d5dbb3783b4_ba7ce0880c07_7bf9a7bf7029");
+
+    len = sizeof buf;
+    status = ja4x_from_der(der, der_len / 2, buf, &len);
+    check(status == JA4_STATUS_INVALID_INPUT && len == 0, "truncated certificate");
+}
+
+int main(int argc, char **argv)
+{
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s CERT.der\n", argv[0]);
+        return 2;
+    }
+    test_ja4();
+    test_ja4h();
+    test_ja4x(argv[1]);
+    return failures == 0 ? 0 : 1;
+}
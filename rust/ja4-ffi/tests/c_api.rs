@@ -0,0 +1,47 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Builds `tests/c/test.c` against the shared library and runs it
+
+use std::{env, path::Path, process::Command};
+
+#[test]
+fn test_c_program() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // Test executables are in `target/<profile>/deps`, next to the library.
+    let exe = env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    assert!(
+        lib_dir.join("libja4_ffi.so").exists() || lib_dir.join("libja4_ffi.dylib").exists(),
+        "shared library not found in {}",
+        lib_dir.display(),
+    );
+    let program = lib_dir.join("ja4-ffi-test");
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = Command::new(&cc)
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/c/test.c"))
+        .arg("-o")
+        .arg(&program)
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg("-lja4_ffi")
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run {cc}: {e}"));
+    assert!(status.success(), "{cc} failed: {status}");
+
+    let status = Command::new(&program)
+        .arg(manifest_dir.join("tests/c/cert.der"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "C test program failed: {status}");
+}
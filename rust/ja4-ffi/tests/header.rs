@@ -0,0 +1,36 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Checks that `include/ja4.h` is up to date
+
+use std::{env, fs, path::Path};
+
+#[test]
+fn test_header() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(manifest_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(manifest_dir)
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+    let generated = String::from_utf8(generated).unwrap();
+
+    let path = manifest_dir.join("include/ja4.h");
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, generated).unwrap();
+        return;
+    }
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        current == generated,
+        "{} is out of date; run `UPDATE_HEADER=1 cargo test -p ja4-ffi --test header`",
+        path.display(),
+    );
+}
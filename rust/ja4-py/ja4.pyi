@@ -0,0 +1,70 @@
+# Copyright (c) 2023, FoxIO, LLC.
+# All rights reserved.
+# Patent Pending
+# JA4 is Open-Source, Licensed under BSD 3-Clause
+# JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+# For full license text, see the repo root.
+
+"""JA4+ fingerprint functions, implemented in Rust.
+
+The dicts are keyed as the fields of the `ja4` output records. The functions
+raise ValueError for malformed input and release the GIL while they compute.
+"""
+
+from typing import List, Optional, Sequence, Tuple, TypedDict
+
+__version__: str
+
+class Ja4Fingerprint(TypedDict):
+    ja4: str
+    ja4_r: str
+    ja4_o: str
+    ja4_ro: str
+
+class Ja4hFingerprint(TypedDict):
+    ja4h: str
+    ja4h_r: str
+    ja4h_a: str
+    ja4h_b: str
+    ja4h_c: str
+    ja4h_d: str
+    header_names: List[str]
+    cookie_names: List[str]
+    cookies: List[str]
+
+class Ja4xFingerprint(TypedDict):
+    ja4x: str
+    ja4x_r: str
+    issuer_oids: List[str]
+    subject_oids: List[str]
+    extension_oids: List[str]
+
+def ja4_from_client_hello(data: bytes, quic: bool = False) -> Ja4Fingerprint:
+    """Computes JA4 of a TLS Client Hello.
+
+    `data` is either the Client Hello message, starting with its handshake type,
+    or the TLS handshake records carrying it.
+    """
+
+def ja4h(
+    method: str,
+    version: str,
+    headers: Sequence[Tuple[str, str]],
+    cookies: Optional[str] = None,
+    original_order: bool = False,
+) -> Ja4hFingerprint:
+    """Computes JA4H of an HTTP request.
+
+    `method` and `version` are as in the request line, e.g. "GET" and "HTTP/1.1"
+    ("HTTP/2", "HTTP/3" for the later versions). `headers` are the (name, value)
+    pairs of the request, in order. `cookies`, if given, is the value of a Cookie
+    header that follows them. The cookies are sorted unless `original_order` is
+    set.
+    """
+
+def ja4x(der: bytes) -> Ja4xFingerprint:
+    """Computes JA4X of a DER-encoded X.509 certificate."""
+
+def hash12(s: str) -> str:
+    """Returns the first 12 characters of the SHA-256 hash of `s`, or 12 zeros if
+    `s` is empty."""
@@ -0,0 +1,116 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Python module `ja4`: the fingerprint functions of the `ja4` library
+//!
+//! The functions compute fingerprints from bytes and strings that the caller has
+//! extracted; nothing here runs tshark. They return dicts keyed as the fields of
+//! the `ja4` output records. The types are described in `ja4.pyi`.
+
+use ja4::{http, tls, x509};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
+
+/// Computes JA4 of a TLS Client Hello.
+///
+/// `data` is either the Client Hello message, starting with its handshake type,
+/// or the TLS handshake records carrying it. Returns the `ja4`, `ja4_r`, `ja4_o`,
+/// and `ja4_ro` fingerprints.
+#[pyfunction]
+#[pyo3(signature = (data, quic = false))]
+fn ja4_from_client_hello<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    quic: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let transport = if quic {
+        tls::Transport::Quic
+    } else {
+        tls::Transport::Tcp
+    };
+    let fp = py
+        .allow_threads(|| tls::ja4_from_client_hello(data, transport))
+        .map_err(value_error)?;
+    let dict = PyDict::new(py);
+    dict.set_item("ja4", fp.ja4)?;
+    dict.set_item("ja4_r", fp.ja4_r)?;
+    dict.set_item("ja4_o", fp.ja4_o)?;
+    dict.set_item("ja4_ro", fp.ja4_ro)?;
+    Ok(dict)
+}
+
+/// Computes JA4H of an HTTP request.
+///
+/// `headers` are the `(name, value)` pairs of the request, in order. `cookies`,
+/// if given, is the value of a `Cookie` header that follows them. The cookies are
+/// sorted unless `original_order` is set; the result is then `ja4h_o`.
+#[pyfunction]
+#[pyo3(signature = (method, version, headers, cookies = None, original_order = false))]
+fn ja4h<'py>(
+    py: Python<'py>,
+    method: &str,
+    version: &str,
+    headers: Vec<(String, String)>,
+    cookies: Option<&str>,
+    original_order: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let fp = py
+        .allow_threads(|| {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .chain(cookies.map(|cookies| ("Cookie", cookies)))
+                .collect::<Vec<_>>();
+            http::ja4h(method, version, &headers, original_order)
+        })
+        .map_err(value_error)?;
+    let dict = PyDict::new(py);
+    dict.set_item("ja4h", fp.ja4h)?;
+    dict.set_item("ja4h_r", fp.ja4h_r)?;
+    dict.set_item("ja4h_a", fp.ja4h_a)?;
+    dict.set_item("ja4h_b", fp.ja4h_b)?;
+    dict.set_item("ja4h_c", fp.ja4h_c)?;
+    dict.set_item("ja4h_d", fp.ja4h_d)?;
+    dict.set_item("header_names", fp.header_names)?;
+    dict.set_item("cookie_names", fp.cookie_names)?;
+    dict.set_item("cookies", fp.cookies)?;
+    Ok(dict)
+}
+
+/// Computes JA4X of a DER-encoded X.509 certificate.
+#[pyfunction]
+fn ja4x<'py>(py: Python<'py>, der: &[u8]) -> PyResult<Bound<'py, PyDict>> {
+    let fp = py.allow_threads(|| x509::ja4x(der)).map_err(value_error)?;
+    let dict = PyDict::new(py);
+    dict.set_item("ja4x", fp.ja4x)?;
+    dict.set_item("ja4x_r", fp.ja4x_r)?;
+    dict.set_item("issuer_oids", fp.issuer_oids)?;
+    dict.set_item("subject_oids", fp.subject_oids)?;
+    dict.set_item("extension_oids", fp.extension_oids)?;
+    Ok(dict)
+}
+
+/// Returns the first 12 characters of the SHA-256 hash of `s`, or 12 zeros if `s`
+/// is empty.
+#[pyfunction]
+fn hash12(s: &str) -> String {
+    ja4::hash12(s)
+}
+
+fn value_error(error: ja4::Error) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+#[pymodule]
+#[pyo3(name = "ja4")]
+fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(ja4_from_client_hello, m)?)?;
+    m.add_function(wrap_pyfunction!(ja4h, m)?)?;
+    m.add_function(wrap_pyfunction!(ja4x, m)?)?;
+    m.add_function(wrap_pyfunction!(hash12, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+# Copyright (c) 2023, FoxIO, LLC.
+# All rights reserved.
+# Patent Pending
+# JA4 is Open-Source, Licensed under BSD 3-Clause
+# JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+# For full license text, see the repo root.
+
+from pathlib import Path
+
+import pytest
+
+import ja4
+
+# Client Hello of the example in the JA4 specification.
+CLIENT_HELLO = bytes.fromhex(
+    "010000c803030000000000000000000000000000000000000000000000000000"
+    "0000000000000000200a0a130113021303c02bc02fc02cc030cca9cca8c013c0"
+    "14009c009d002f00350100007f1a1a0000001b000000000010000e00000b6578"
+    "616d706c652e636f6d003300000010000e000c02683208687474702f312e3144"
+    "69000000170000002d0000000d00120010040308040401050308050501080606"
+    "01000500000023000000120000002b0007063a3a03040303ff010000000b0000"
+    "000a0000001500002a2a0000"
+)
+
+CERT = Path(__file__).with_name("cert.der").read_bytes()
+
+
+def test_ja4_from_client_hello():
+    fp = ja4.ja4_from_client_hello(CLIENT_HELLO)
+    assert fp["ja4"] == "t13d1516h2_8daaf6152771_e5627efa2ab1"
+    assert fp["ja4_r"].startswith("t13d1516h2_002f,0035,009c,009d,1301,1302,1303,")
+    assert fp["ja4_o"].startswith("t13d1516h2_acb858a92679_")
+    assert set(fp) == {"ja4", "ja4_r", "ja4_o", "ja4_ro"}
+
+    assert ja4.ja4_from_client_hello(CLIENT_HELLO, quic=True)["ja4"].startswith("q13d")
+
+
+def test_ja4_from_client_hello_error():
+    with pytest.raises(ValueError, match="truncated"):
+        ja4.ja4_from_client_hello(CLIENT_HELLO[:10])
+
+
+def test_ja4h():
+    headers = [
+        ("Host", "example.com"),
+        ("User-Agent", "curl/8.0"),
+        ("Accept-Language", "en-US,en;q=0.9"),
+    ]
+    fp = ja4.ja4h("GET", "HTTP/1.1", headers, cookies="b=2; a=1")
+    assert fp["ja4h"] == "ge11cn03enus_ea59799162d6_1eb7c54d5283_06beefe2b477"
+    assert fp["ja4h_a"] == "ge11cn03enus"
+    assert fp["header_names"] == ["Host", "User-Agent", "Accept-Language"]
+    assert fp["cookie_names"] == ["a", "b"]
+    assert fp["cookies"] == ["a=1", "b=2"]
+    assert fp["ja4h_b"] == ja4.hash12("Host,User-Agent,Accept-Language")
+
+    fp_o = ja4.ja4h("GET", "HTTP/1.1", headers, cookies="b=2; a=1", original_order=True)
+    assert fp_o["cookie_names"] == ["b", "a"]
+
+    with pytest.raises(ValueError):
+        ja4.ja4h("BREW", "HTTP/1.1", headers)
+
+
+def test_ja4x():
+    fp = ja4.ja4x(CERT)
+    assert fp["ja4x"] == "7d5dbb3783b4_ba7ce0880c07_7bf9a7bf7029"
+    assert fp["extension_oids"]
+    with pytest.raises(ValueError):
+        ja4.ja4x(CERT[:100])
+
+
+def test_hash12():
+    assert ja4.hash12("551d0f,551d25,551d11") == "aae71e8db6d7"
+    assert ja4.hash12("") == "000000000000"
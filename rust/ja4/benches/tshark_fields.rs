@@ -0,0 +1,108 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Throughput of `ja4` with and without `--all-fields`
+//!
+//! ```sh
+//! cargo bench -p ja4 --bench tshark_fields [-- PCAP_DIR]
+//! ```
+//!
+//! Every capture file in `PCAP_DIR` (default: `pcap/` at the repo root) is read
+//! a few times in both modes; the best time of each is reported in packets per
+//! second. Requires tshark in `PATH`.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const ROUNDS: usize = 3;
+
+fn main() {
+    let dir = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map_or_else(
+            || Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap"),
+            PathBuf::from,
+        );
+    let mut pcaps = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ["pcap", "pcapng", "cap"].contains(&&*ext.to_string_lossy()))
+        })
+        .collect::<Vec<_>>();
+    pcaps.sort();
+
+    let mut total = [Duration::ZERO; 2];
+    let mut total_packets = 0;
+    println!(
+        "{:<50} {:>8} {:>14} {:>14}",
+        "file", "packets", "all fields", "needed fields"
+    );
+    for pcap in &pcaps {
+        let Some(packets) = count_packets(pcap) else {
+            continue;
+        };
+        let all = best_time(pcap, true);
+        let needed = best_time(pcap, false);
+        total[0] += all;
+        total[1] += needed;
+        total_packets += packets;
+        println!(
+            "{:<50} {packets:>8} {:>12.0}/s {:>12.0}/s",
+            pcap.file_name().unwrap().to_string_lossy(),
+            rate(packets, all),
+            rate(packets, needed),
+        );
+    }
+    println!(
+        "{:<50} {total_packets:>8} {:>12.0}/s {:>12.0}/s",
+        "total",
+        rate(total_packets, total[0]),
+        rate(total_packets, total[1]),
+    );
+}
+
+fn count_packets(pcap: &Path) -> Option<usize> {
+    let out = Command::new("tshark")
+        .args(["-r".as_ref(), pcap.as_os_str()])
+        .args(["-T", "fields", "-e", "frame.number"])
+        .stderr(Stdio::null())
+        .output()
+        .expect("failed to run tshark");
+    out.status
+        .success()
+        .then(|| out.stdout.iter().filter(|&&b| b == b'\n').count())
+}
+
+fn best_time(pcap: &Path, all_fields: bool) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let mut cmd = Command::new(env!("CARGO_BIN_EXE_ja4"));
+            if all_fields {
+                cmd.arg("--all-fields");
+            }
+            let start = Instant::now();
+            cmd.arg(pcap)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .expect("failed to run ja4");
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn rate(packets: usize, time: Duration) -> f64 {
+    packets as f64 / time.as_secs_f64()
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Pseudonymization of IP addresses (`--anonymize`)
+
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    fmt,
+    hash::{BuildHasher as _, Hasher as _},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use sha2::{Digest as _, Sha256};
+
+/// Secret that pseudonyms are derived from.
+#[derive(Clone)]
+pub(crate) struct Key(Vec<u8>);
+
+impl Key {
+    /// Generates a random key, so that pseudonyms are only stable within a run.
+    fn random() -> Self {
+        // `RandomState` is seeded from the operating system's random number generator.
+        let key = (0..2)
+            .flat_map(|_| RandomState::new().build_hasher().finish().to_be_bytes())
+            .collect();
+        Self(key)
+    }
+}
+
+impl FromStr for Key {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s).map(Self)
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// IPv4 networks reserved for documentation ([RFC 5737]): TEST-NET-1, -2, -3.
+///
+/// [RFC 5737]: https://datatracker.ietf.org/doc/html/rfc5737
+const IPV4_NETS: [[u8; 3]; 3] = [[192, 0, 2], [198, 51, 100], [203, 0, 113]];
+
+/// Number of IPv4 pseudonyms.
+const NR_IPV4: usize = IPV4_NETS.len() * 256;
+
+/// Replaces IP addresses with pseudonyms.
+///
+/// IPv4 addresses are mapped into 192.0.2.0/24, 198.51.100.0/24, and
+/// 203.0.113.0/24 (reserved for documentation, [RFC 5737]), IPv6 addresses ---
+/// into 2001:db8::/32 (reserved for documentation, [RFC 3849]). The pseudonym is
+/// chosen by a keyed SHA-256 hash of the address.
+///
+/// Distinct addresses always get distinct pseudonyms: the pseudonyms given so far
+/// are remembered, and on a collision the next candidate of the colliding address
+/// is tried. Without collisions, the pseudonym depends only on the key and the
+/// address; after one, also on the order in which the addresses appear, so runs
+/// with the same key only agree on the addresses that haven't collided. There are
+/// 768 IPv4 pseudonyms; once they are used up, IPv4 addresses get IPv6 pseudonyms.
+///
+/// [RFC 5737]: https://datatracker.ietf.org/doc/html/rfc5737
+/// [RFC 3849]: https://datatracker.ietf.org/doc/html/rfc3849
+#[derive(Debug)]
+pub(crate) struct Anonymizer {
+    key: Key,
+    /// Pseudonyms given so far, by address.
+    pseudonyms: HashMap<IpAddr, IpAddr>,
+    /// The values of `pseudonyms`.
+    used: HashSet<IpAddr>,
+    /// Number of IPv4 addresses with IPv4 pseudonyms.
+    nr_ipv4: usize,
+    /// Whether an IPv4 address has got an IPv6 pseudonym.
+    ipv4_used_up: bool,
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer with the given key or, if `None`, a random one.
+    pub(crate) fn new(key: Option<Key>) -> Self {
+        Self {
+            key: key.unwrap_or_else(Key::random),
+            pseudonyms: HashMap::new(),
+            used: HashSet::new(),
+            nr_ipv4: 0,
+            ipv4_used_up: false,
+        }
+    }
+
+    /// Returns the pseudonym of an IP address.
+    ///
+    /// A socket address (e.g. `192.0.2.1:443`) keeps its port. Anything else, e.g.
+    /// a host name, is returned unchanged.
+    pub(crate) fn anonymize(&mut self, addr: &str) -> String {
+        if let Ok(ip) = addr.parse::<IpAddr>() {
+            self.anonymize_ip(ip).to_string()
+        } else if let Ok(sa) = addr.parse::<SocketAddr>() {
+            SocketAddr::new(self.anonymize_ip(sa.ip()), sa.port()).to_string()
+        } else {
+            addr.to_owned()
+        }
+    }
+
+    fn anonymize_ip(&mut self, ip: IpAddr) -> IpAddr {
+        if let Some(&pseudonym) = self.pseudonyms.get(&ip) {
+            return pseudonym;
+        }
+        let ipv4 = ip.is_ipv4() && self.nr_ipv4 < NR_IPV4;
+        if ip.is_ipv4() && !ipv4 && !self.ipv4_used_up {
+            tracing::warn!("IPv4 pseudonyms are used up, giving IPv6 pseudonyms instead");
+            self.ipv4_used_up = true;
+        }
+        let pseudonym = (0u32..)
+            .map(|attempt| self.candidate(ip, attempt, ipv4))
+            .find(|candidate| !self.used.contains(candidate))
+            .expect("unused pseudonym");
+        if ipv4 {
+            self.nr_ipv4 += 1;
+        }
+        self.used.insert(pseudonym);
+        self.pseudonyms.insert(ip, pseudonym);
+        pseudonym
+    }
+
+    /// Returns the `attempt`th candidate pseudonym of `ip`, an IPv4 address if
+    /// `ipv4`.
+    fn candidate(&self, ip: IpAddr, attempt: u32, ipv4: bool) -> IpAddr {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key.0);
+        match ip {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+        if attempt > 0 {
+            hasher.update(attempt.to_be_bytes());
+        }
+        let hash = hasher.finalize();
+
+        if ipv4 {
+            let index = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as usize % NR_IPV4;
+            let [a, b, c] = IPV4_NETS[index / 256];
+            Ipv4Addr::new(a, b, c, (index % 256) as u8).into()
+        } else {
+            let mut octets = [0; 16];
+            octets[..4].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+            octets[4..].copy_from_slice(&hash[..12]);
+            Ipv6Addr::from(octets).into()
+        }
+    }
+}
+
+#[test]
+fn test_anonymize() {
+    let mut anonymizer = Anonymizer::new(Some("00112233".parse().unwrap()));
+
+    let v4 = anonymizer.anonymize("192.168.1.168");
+    assert_eq!(v4, anonymizer.anonymize("192.168.1.168"));
+    assert_ne!(v4, anonymizer.anonymize("192.168.1.169"));
+    let v4 = v4.parse::<Ipv4Addr>().unwrap();
+    assert!(IPV4_NETS.contains(&[v4.octets()[0], v4.octets()[1], v4.octets()[2]]));
+
+    let v6 = anonymizer.anonymize("2607:f8b0:4004:c1b::65");
+    assert!(v6.starts_with("2001:db8:"), "{v6}");
+
+    let sa = anonymizer.anonymize("192.168.1.168:443");
+    assert_eq!(sa, format!("{v4}:443"));
+
+    assert_eq!(anonymizer.anonymize("example.com:443"), "example.com:443");
+
+    let mut other = Anonymizer::new(Some("ffeeddcc".parse().unwrap()));
+    assert_ne!(other.anonymize("192.168.1.168"), v4.to_string());
+}
+
+#[test]
+fn test_anonymize_distinct() {
+    let mut anonymizer = Anonymizer::new(Some("00112233".parse().unwrap()));
+    let mut pseudonyms = HashSet::new();
+    // More addresses than IPv4 pseudonyms: the last ones get IPv6 pseudonyms.
+    for i in 0..1000u32 {
+        let ip = Ipv4Addr::from(u32::from_be_bytes([10, 0, 0, 0]) + i).to_string();
+        let pseudonym = anonymizer.anonymize(&ip);
+        assert_eq!(anonymizer.anonymize(&ip), pseudonym);
+        assert!(pseudonyms.insert(pseudonym), "{ip}");
+    }
+    let nr_ipv4 = pseudonyms
+        .iter()
+        .filter(|pseudonym| pseudonym.parse::<Ipv4Addr>().is_ok())
+        .count();
+    assert_eq!(nr_ipv4, NR_IPV4);
+    for i in 0..1000u16 {
+        let ip = Ipv6Addr::new(0x2607, 0xf8b0, 0, 0, 0, 0, 0, i).to_string();
+        assert!(pseudonyms.insert(anonymizer.anonymize(&ip)), "{ip}");
+    }
+}
@@ -0,0 +1,653 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Reading packets from capture files or a network interface
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, OnceLock,
+    },
+    thread::{self, Scope},
+    time::Duration,
+};
+
+use rtshark::RTSharkBuilder;
+
+use crate::{
+    conf::Conf,
+    ek::EkCommand,
+    error::ErrorReport,
+    fields,
+    filter::StreamFilter,
+    merge::{self, Merged, Part},
+    native,
+    pcap::FieldOrder,
+    progress::Progress,
+    stream::{MinPackets, Streams},
+    timing::{Phase, Timing},
+    tshark::{self, Input, Program, Tshark, TsharkOutput},
+    Error, FormatFlags, OutputRecord as OutRec, Packet, Result,
+};
+
+/// Number of packets that the tshark reader thread may be ahead of fingerprinting.
+const PACKET_QUEUE_LEN: usize = 1024;
+
+/// How the capture files are read (`--engine`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Engine {
+    /// Dissect the packets with tshark.
+    Tshark,
+    /// Parse TLS handshakes in pcap files without tshark.
+    Native,
+}
+
+/// Settings for reading packets, shared by all inputs.
+pub(crate) struct Capture {
+    pub(crate) conf: Conf,
+    pub(crate) filter: StreamFilter,
+    pub(crate) flags: FormatFlags,
+    /// Whether to output the records of the streams as soon as they are finalized.
+    pub(crate) incremental: bool,
+    /// Whether to list the streams instead of fingerprinting them (`--list-streams`).
+    pub(crate) list_streams: bool,
+    pub(crate) with_packet_numbers: bool,
+    pub(crate) keylog_file: Option<PathBuf>,
+    pub(crate) display_filter: Option<String>,
+    pub(crate) decode_as: Vec<String>,
+    pub(crate) engine: Engine,
+    pub(crate) tshark_program: Program,
+    pub(crate) tshark_output: TsharkOutput,
+    pub(crate) max_streams: Option<usize>,
+    /// Number of inputs whose processing `max_streams` has cut short.
+    pub(crate) nr_stopped: AtomicUsize,
+    pub(crate) udp_timeout: Duration,
+    pub(crate) tcp_idle_timeout: Option<Duration>,
+    pub(crate) min_packets: MinPackets,
+    pub(crate) progress: Progress,
+    pub(crate) timing: Timing,
+    pub(crate) tshark_timeout: Option<Duration>,
+    /// Whether to report the packets that failed to process (`--errors-json`).
+    pub(crate) errors_json: bool,
+    /// Whether to have tshark dissect all fields rather than [`fields::needed`]
+    /// (`--all-fields`).
+    pub(crate) all_fields: bool,
+    /// Whether the version of tshark has been checked.
+    pub(crate) tshark_checked: Mutex<bool>,
+    /// Fields to ask tshark for; set by [`Capture::check_tshark`].
+    pub(crate) fields: OnceLock<Vec<&'static str>>,
+}
+
+impl Capture {
+    /// Returns `true` if the capture files are to be read without tshark, when
+    /// they allow: `--engine native` is given, and only the TLS fingerprints of
+    /// unencrypted handshakes are needed.
+    pub(crate) fn is_native(&self) -> bool {
+        self.engine == Engine::Native
+            && self.conf.tls.enabled
+            && !self.conf.http.enabled
+            && !self.conf.time.enabled
+            && !self.conf.ssh.enabled
+            && self.keylog_file.is_none()
+    }
+
+    /// Checks the version of tshark and finds out which of the needed fields it
+    /// knows, once per run.
+    pub(crate) fn check_tshark(&self) -> Result<()> {
+        let mut checked = self.tshark_checked.lock().expect("BUG: poisoned mutex");
+        if *checked {
+            return Ok(());
+        }
+        if let Some(version) = crate::check_tshark_version(&self.tshark_program, &self.conf)? {
+            self.timing.set_tshark_version(&version);
+        }
+        if !self.all_fields {
+            let known = tshark::known_fields(&self.tshark_program)?;
+            let (fields, unknown): (Vec<_>, Vec<_>) = fields::needed(&self.conf)
+                .into_iter()
+                .partition(|&field| known.contains(field));
+            if !unknown.is_empty() {
+                tracing::debug!(?unknown, "fields unknown to this version of tshark");
+            }
+            // No fields at all means the glossary could not be parsed; then tshark
+            // dissects everything, same as with `--all-fields`.
+            let _ = self.fields.set(fields);
+        }
+        *checked = true;
+        Ok(())
+    }
+
+    /// Captures packets from the network interface until tshark exits.
+    pub(crate) fn live<'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, '_>,
+        interface: &'scope str,
+        capture_filter: Option<&'scope str>,
+    ) -> Records {
+        Records::spawn(scope, 1, 1, true, move |_, emit| {
+            let input = Input::Interface {
+                name: interface,
+                capture_filter,
+            };
+            self.read(input, None, &[], emit)
+        })
+    }
+
+    /// Reads the capture files, processing up to `jobs` of them concurrently.
+    ///
+    /// Each file is read by its own tshark process into its own [`Streams`].
+    pub(crate) fn read_files<'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, '_>,
+        pcaps: &'scope [PathBuf],
+        jobs: usize,
+        ordered: bool,
+    ) -> Records {
+        Records::spawn(scope, jobs, pcaps.len(), ordered, move |i, emit| {
+            self.read_file(&pcaps[i], emit)
+        })
+    }
+
+    /// Reads the capture files with a single tshark process (`--merge`).
+    ///
+    /// The records of each file are output as they would be without `--merge`.
+    pub(crate) fn read_merged<'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, '_>,
+        pcaps: &'scope [PathBuf],
+    ) -> Records {
+        Records::spawn(scope, 1, 1, true, move |_, emit| {
+            let merged = Merged::new(&self.tshark_program, pcaps)?;
+            let Some(path) = merged.path().to_str() else {
+                return Err(Error::NonUtf8Path(merged.path().to_owned()));
+            };
+            self.read(Input::File(path), None, &merged.parts, emit)
+        })
+    }
+
+    fn read_file(&self, pcap: &Path, emit: &mut Emit) -> Result<()> {
+        if self.is_native() {
+            let streams = self.timing.measure(Phase::Fingerprint, || {
+                native::tls_streams(
+                    pcap,
+                    &self.conf,
+                    self.with_packet_numbers,
+                    self.filter.clone(),
+                )
+            });
+            if let Some((streams, nr_packets)) = streams {
+                let streams = streams.with_min_packets(self.min_packets);
+                let recs = self.timing.measure(Phase::Fingerprint, || {
+                    streams.into_out(self.flags).collect::<Vec<_>>()
+                });
+                self.timing.add_packets(nr_packets);
+                self.progress.add_packets(nr_packets);
+                // Every record that the native reader returns has a TLS fingerprint.
+                self.progress.add_streams(recs.len());
+                self.progress.finish_file(pcap, 0);
+                emit(recs);
+                return Ok(());
+            }
+        }
+        let Some(pcap_path) = pcap.to_str() else {
+            return Err(Error::NonUtf8Path(pcap.to_owned()));
+        };
+        self.read(Input::File(pcap_path), Some(pcap), &[], emit)
+    }
+
+    /// Starts tshark with the output format of `--tshark-output`.
+    fn spawn_tshark(&self, input: Input) -> Result<Tshark> {
+        let keylog_path = match &self.keylog_file {
+            Some(keylog) => match keylog.to_str() {
+                Some(path) => Some(path),
+                None => return Err(Error::NonUtf8Path(keylog.clone())),
+            },
+            None => None,
+        };
+        match self.tshark_output {
+            TsharkOutput::Pdml => {
+                let mut builder = match input {
+                    Input::File(path) => RTSharkBuilder::builder().input_path(path),
+                    Input::Interface {
+                        name,
+                        capture_filter,
+                    } => {
+                        let mut builder = RTSharkBuilder::builder().input_path(name).live_capture();
+                        if let Some(filter) = capture_filter {
+                            builder = builder.capture_filter(filter);
+                        }
+                        builder
+                    }
+                };
+                if let Some(dir) = self.tshark_program.env_path()? {
+                    builder = builder.env_path(dir);
+                }
+                if let Some(path) = keylog_path {
+                    builder = builder.keylog_file(path);
+                }
+                if let Some(filter) = &self.display_filter {
+                    builder = builder.display_filter(filter);
+                }
+                for spec in &self.decode_as {
+                    builder = builder.decode_as(spec);
+                }
+                for &field in self.fields.get().into_iter().flatten() {
+                    builder = builder.metadata_whitelist(field);
+                }
+                Tshark::spawn(&builder, self.tshark_timeout)
+            }
+            TsharkOutput::Ek => {
+                // All fields are dissected: with `-e`, ek output has no protocol
+                // layers. The fields that nobody reads are dropped when parsing.
+                let mut command =
+                    EkCommand::new(&self.tshark_program, input, &fields::needed(&self.conf));
+                if let Some(path) = keylog_path {
+                    command = command.keylog_file(path);
+                }
+                if let Some(filter) = &self.display_filter {
+                    command = command.display_filter(filter);
+                }
+                for spec in &self.decode_as {
+                    command = command.decode_as(spec);
+                }
+                Tshark::spawn_ek(&command, self.tshark_timeout)
+            }
+        }
+    }
+
+    /// Runs tshark and passes the records to `emit`: in incremental mode, as soon as
+    /// the streams are finalized, otherwise all of them once tshark is done.
+    ///
+    /// tshark output is parsed on a thread of its own, so that it overlaps with the
+    /// fingerprinting. The packets are passed through a bounded queue: when the
+    /// fingerprinting falls behind, the reader thread blocks and so does tshark.
+    ///
+    /// `parts` are the files that a merged capture consists of, if any.
+    fn read(
+        &self,
+        input: Input,
+        pcap: Option<&Path>,
+        parts: &[Part],
+        emit: &mut Emit,
+    ) -> Result<()> {
+        self.check_tshark()?;
+        let mut tshark = self.spawn_tshark(input)?;
+        let field_order = tshark.field_order();
+        let stopper = tshark.stopper();
+        let (sender, packets) = mpsc::sync_channel(PACKET_QUEUE_LEN);
+
+        thread::scope(|scope| {
+            // On Ctrl-C tshark exits, the reader gets to the end of its output and
+            // the records of the packets read so far are emitted.
+            let reader = scope.spawn(move || {
+                while let Some(packet) = tshark.read().transpose() {
+                    let failed = packet.is_err();
+                    // `send` fails if the packets are no longer needed.
+                    if sender.send(packet).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            let mut queue = packets.iter().peekable();
+            let result = if parts.is_empty() {
+                self.process_packets(&mut queue, field_order, pcap, None, emit)
+            } else {
+                self.process_parts(&mut queue, field_order, parts, emit)
+            };
+            drop(queue);
+            drop(packets);
+            if !matches!(result, Ok(true)) {
+                // Stop tshark if we haven't read its entire output.
+                stopper.stop();
+            }
+            reader.join().expect("BUG: tshark reader panicked");
+            result.map(|_| ())
+        })
+    }
+
+    /// Fingerprints the packets of every file of a merged capture separately, and
+    /// sets the `pcap` of their records.
+    fn process_parts(
+        &self,
+        packets: &mut Queue,
+        field_order: FieldOrder,
+        parts: &[Part],
+        emit: &mut Emit,
+    ) -> Result<bool> {
+        for part in parts {
+            let mut emit_part = |mut recs: Vec<OutRec>| {
+                for rec in &mut recs {
+                    rec.set_pcap(Some(part.pcap));
+                }
+                emit(recs)
+            };
+            let pcap = Some(part.pcap);
+            let last_frame = Some(part.last_frame);
+            if !self.process_packets(packets, field_order, pcap, last_frame, &mut emit_part)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fingerprints the packets until the queue is closed or, if `last_frame` is
+    /// set, until a packet with a larger frame number. Returns `false` if
+    /// processing stopped before the end of tshark output.
+    fn process_packets(
+        &self,
+        packets: &mut Queue,
+        field_order: FieldOrder,
+        pcap: Option<&Path>,
+        last_frame: Option<usize>,
+        emit: &mut Emit,
+    ) -> Result<bool> {
+        // Errors are passed on to whoever reads them first.
+        let in_part = |packet: &Result<rtshark::Packet>| match (packet, last_frame) {
+            (Ok(packet), Some(last)) => merge::frame_number(packet).is_none_or(|num| num <= last),
+            _ => true,
+        };
+        let mut streams = Streams::new(self.filter.clone())
+            .with_udp_timeout(self.udp_timeout)
+            .with_tcp_idle_timeout(self.tcp_idle_timeout)
+            .with_min_packets(self.min_packets)
+            .with_list_streams(self.list_streams);
+
+        let mut packet_num = 0;
+        // Bytes of the capture file accounted for by `Progress::add_packet`.
+        let mut consumed = 0;
+        let mut finished = true;
+        // The queue is closed when tshark is done.
+        while let Some(packet) = self
+            .timing
+            .measure(Phase::Tshark, || packets.next_if(in_part))
+        {
+            let packet = packet?;
+            packet_num += 1;
+            let pkt = Packet::new(&packet, packet_num).with_field_order(field_order);
+            let nr_fingerprinted = streams.nr_fingerprinted();
+            self.timing.add_packet(&pkt);
+
+            let result = self.timing.measure(Phase::Fingerprint, || {
+                streams.update(&pkt, &self.conf, self.with_packet_numbers)
+            });
+            if let Err(error) = result {
+                tracing::debug!(packet_num, %error, "failed to handle packet");
+                if self.errors_json {
+                    ErrorReport::new(&error, pcap, Some(pkt.num)).print();
+                }
+            }
+            if self.progress.is_enabled() {
+                consumed += self.progress.add_packet(captured_len(&pkt));
+                self.progress
+                    .add_streams(streams.nr_fingerprinted() - nr_fingerprinted);
+            }
+            if self.incremental {
+                let recs = self
+                    .timing
+                    .measure(Phase::Fingerprint, || streams.drain_closed(self.flags));
+                if !recs.is_empty() && !emit(recs) {
+                    // Nobody is interested in the records anymore.
+                    return Ok(false);
+                }
+            } else if self.keylog_file.is_none() {
+                // `check_decryption` needs all TLS sessions at the end.
+                self.timing
+                    .measure(Phase::Fingerprint, || streams.finalize_closed(self.flags));
+            }
+            if let Some(max) = self
+                .max_streams
+                .filter(|&max| streams.nr_fingerprinted() >= max)
+            {
+                tracing::debug!(max, "stopped after --max-streams");
+                self.nr_stopped.fetch_add(1, Ordering::Relaxed);
+                if last_frame.is_some() {
+                    // Skip to the next file of the merged capture.
+                    while packets.next_if(in_part).is_some() {}
+                } else {
+                    finished = false;
+                }
+                break;
+            }
+        }
+        if let Some(pcap) = pcap {
+            self.progress.finish_file(pcap, consumed);
+        }
+        if self.keylog_file.is_some() && streams.check_decryption() {
+            crate::warn_decryption_failed();
+        }
+        let recs = self.timing.measure(Phase::Fingerprint, || {
+            streams.into_out(self.flags).collect()
+        });
+        emit(recs);
+        Ok(finished)
+    }
+}
+
+/// Returns the number of bytes of the packet in the capture file.
+fn captured_len(pkt: &Packet) -> u64 {
+    pkt.find_proto("frame")
+        .and_then(|frame| frame.first("frame.cap_len").ok()?.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Packets parsed by the tshark reader thread.
+type Queue<'a> = Peekable<mpsc::Iter<'a, Result<rtshark::Packet>>>;
+
+/// Receives a batch of records. Returns `false` if the records are no longer needed.
+type Emit<'a> = dyn FnMut(Vec<OutRec>) -> bool + 'a;
+
+enum Message {
+    Records(Vec<OutRec>),
+    /// The input has been processed.
+    Done(Result<()>),
+}
+
+/// Records of the inputs, see [`Capture::read_files`].
+///
+/// Yields the records with the index of their input, grouped by input in the order
+/// of the inputs if `ordered` is set, otherwise as soon as they are produced. To
+/// bound the memory use, no more than `jobs` inputs are being processed or waiting
+/// to be consumed at any time: a job starts the next input once the consumer has
+/// taken all records of another one.
+pub(crate) struct Records {
+    receiver: mpsc::Receiver<(usize, Message)>,
+    ordered: bool,
+    /// Input whose records are being yielded, if `ordered`.
+    current: usize,
+    /// Messages of the inputs after `current`, if `ordered`.
+    pending: BTreeMap<usize, VecDeque<Message>>,
+    /// Records to be yielded next, with the index of their input.
+    ready: VecDeque<(usize, OutRec)>,
+    slots: Arc<Slots>,
+}
+
+impl Records {
+    /// Starts `jobs` threads that call `read` for the inputs `0..nr_inputs`.
+    fn spawn<'scope>(
+        scope: &'scope Scope<'scope, '_>,
+        jobs: usize,
+        nr_inputs: usize,
+        ordered: bool,
+        read: impl Fn(usize, &mut Emit) -> Result<()> + Send + Sync + 'scope,
+    ) -> Self {
+        let read = Arc::new(read);
+        let slots = Arc::new(Slots::new(jobs));
+        let next_input = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs.min(nr_inputs) {
+            let (read, slots, next_input) = (read.clone(), slots.clone(), next_input.clone());
+            let sender = sender.clone();
+            scope.spawn(move || {
+                while slots.acquire() {
+                    let i = next_input.fetch_add(1, Ordering::Relaxed);
+                    if i >= nr_inputs {
+                        break;
+                    }
+                    let mut emit = |recs| sender.send((i, Message::Records(recs))).is_ok();
+                    let result = read(i, &mut emit);
+                    if sender.send((i, Message::Done(result))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Self {
+            receiver,
+            ordered,
+            current: 0,
+            pending: BTreeMap::new(),
+            ready: VecDeque::new(),
+            slots,
+        }
+    }
+
+    /// Returns the next message to handle, or `None` once all inputs are processed.
+    fn receive(&mut self) -> Option<(usize, Message)> {
+        if !self.ordered {
+            return self.receiver.recv().ok();
+        }
+        loop {
+            if let Some(msg) = self
+                .pending
+                .get_mut(&self.current)
+                .and_then(VecDeque::pop_front)
+            {
+                return Some((self.current, msg));
+            }
+            let (i, msg) = self.receiver.recv().ok()?;
+            if i == self.current {
+                return Some((i, msg));
+            }
+            self.pending.entry(i).or_default().push_back(msg);
+        }
+    }
+}
+
+impl Iterator for Records {
+    /// Index of the input and its record, or the error that stopped its processing.
+    type Item = (usize, Result<OutRec>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((i, rec)) = self.ready.pop_front() {
+                return Some((i, Ok(rec)));
+            }
+            let (i, msg) = self.receive()?;
+            match msg {
+                Message::Records(recs) => self.ready.extend(recs.into_iter().map(|rec| (i, rec))),
+                Message::Done(result) => {
+                    self.pending.remove(&i);
+                    self.current = i + 1;
+                    self.slots.release();
+                    if let Err(error) = result {
+                        return Some((i, Err(error)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Records {
+    fn drop(&mut self) {
+        // Let the jobs waiting for a slot exit.
+        self.slots.close();
+    }
+}
+
+/// Counting semaphore limiting the number of capture files in memory.
+struct Slots {
+    state: Mutex<SlotState>,
+    released: Condvar,
+}
+
+struct SlotState {
+    free: usize,
+    closed: bool,
+}
+
+impl Slots {
+    fn new(n: usize) -> Self {
+        Self {
+            state: Mutex::new(SlotState {
+                free: n,
+                closed: false,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits for a free slot and takes it. Returns `false` if the slots have been
+    /// closed.
+    fn acquire(&self) -> bool {
+        let mut state = self.state.lock().expect("BUG: poisoned mutex");
+        while state.free == 0 && !state.closed {
+            state = self.released.wait(state).expect("BUG: poisoned mutex");
+        }
+        if state.closed {
+            return false;
+        }
+        state.free -= 1;
+        true
+    }
+
+    fn release(&self) {
+        self.state.lock().expect("BUG: poisoned mutex").free += 1;
+        self.released.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().expect("BUG: poisoned mutex").closed = true;
+        self.released.notify_all();
+    }
+}
+
+#[test]
+fn test_slots() {
+    use std::thread;
+
+    let slots = Slots::new(2);
+    assert!(slots.acquire());
+    assert!(slots.acquire());
+    thread::scope(|scope| {
+        let waiter = scope.spawn(|| slots.acquire());
+        thread::sleep(Duration::from_millis(10));
+        assert!(!waiter.is_finished());
+        slots.release();
+        assert!(waiter.join().unwrap());
+
+        let waiter = scope.spawn(|| slots.acquire());
+        slots.close();
+        assert!(!waiter.join().unwrap());
+    });
+}
+
+#[test]
+fn test_records_order() {
+    use std::thread;
+
+    // Input `i` fails after a delay that decreases with `i`, so the inputs finish in
+    // reverse order.
+    let errors = |ordered| {
+        thread::scope(|scope| {
+            Records::spawn(scope, 3, 3, ordered, |i, _emit| {
+                thread::sleep(Duration::from_millis(30 * (3 - i as u64)));
+                Err(Error::NonUtf8Path(i.to_string().into()))
+            })
+            .map(|(_, res)| match res {
+                Err(Error::NonUtf8Path(path)) => path.display().to_string(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(errors(true), ["0", "1", "2"]);
+    assert_eq!(errors(false), ["2", "1", "0"]);
+}
@@ -6,24 +6,182 @@
 // For full license text, see the repo root.
 
 use fs_err::File;
+use itertools::Itertools as _;
 use serde::Deserialize;
 
-use std::{env, io::Write as _, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct Conf {
-    pub(crate) http: ConfBasic,
+use crate::select::{FingerprintKind, Selection};
+
+/// Settings of the fingerprinters.
+///
+/// The command line tool reads them from the configuration file (see `--config`)
+/// and `JA4_*` environment variables. Library users can construct them in code with [`Conf::builder`],
+/// which starts from the built-in defaults and consults neither the file nor the
+/// environment.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Conf {
+    /// tshark executable to run instead of the one found in `PATH`. `JA4_TSHARK`
+    /// environment variable and `--tshark-path` override it.
+    #[serde(default)]
+    pub(crate) tshark_path: Option<PathBuf>,
+    /// Oldest supported tshark version; `--tshark-min-version` overrides it.
+    pub(crate) tshark_min_version: String,
+    /// What to do if tshark is older than `tshark_min_version`;
+    /// `--tshark-version-check` overrides it.
+    pub(crate) tshark_version_check: VersionCheck,
+    /// Fingerprint types to compute; `--only` overrides it.
+    #[serde(default)]
+    pub(crate) fingerprints: Selection,
+    #[serde(default)]
+    pub(crate) ports: ConfPorts,
+    #[serde(default)]
+    pub(crate) output: ConfOutput,
+    pub(crate) http: ConfHttp,
     pub(crate) ssh: ConfSsh,
     pub(crate) time: ConfBasic,
-    pub(crate) tls: ConfBasic,
+    pub(crate) tls: ConfTls,
+}
+
+/// What to do if tshark is older than the supported versions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionCheck {
+    /// Print a warning and carry on
+    #[default]
+    Warn,
+    /// Abort the run with exit status 3 (`Error::TsharkUnsupported`)
+    Error,
+    /// Don't check the version
+    Off,
+}
+
+/// TCP ports of services that tshark would not dissect, because they run on
+/// non-standard ports. tshark is told to decode the traffic on these ports as the
+/// protocol of the list (`-d`); the fingerprinters then find the protocol layers
+/// as usual.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub(crate) struct ConfPorts {
+    pub(crate) tls: Vec<u16>,
+    pub(crate) ssh: Vec<u16>,
+    pub(crate) http: Vec<u16>,
+}
+
+impl ConfPorts {
+    /// Returns the decode-as rules for tshark, e.g. `tcp.port==8443,tls`.
+    pub(crate) fn decode_as(&self) -> Vec<String> {
+        self.lists()
+            .into_iter()
+            .flat_map(|(proto, ports)| {
+                ports
+                    .iter()
+                    .map(move |port| format!("tcp.port=={port},{proto}"))
+            })
+            .collect()
+    }
+
+    fn lists(&self) -> [(&'static str, &[u16]); 3] {
+        [("tls", &self.tls), ("ssh", &self.ssh), ("http", &self.http)]
+    }
+
+    /// Rejects a port listed for two protocols.
+    fn check(&self) -> crate::Result<()> {
+        let mut seen = HashMap::new();
+        for (proto, ports) in self.lists() {
+            for &port in ports {
+                match seen.insert(port, proto) {
+                    Some(first) if first != proto => {
+                        return Err(crate::Error::PortConflict {
+                            port,
+                            first,
+                            second: proto,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Deserialize)]
+/// Defaults of the output options. The command line options override them, e.g.
+/// `--json=false` with `format = "json"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub(crate) struct ConfOutput {
+    /// `--json`
+    pub(crate) format: OutputFormat,
+    /// `--with-raw`
+    pub(crate) with_raw: bool,
+    /// `--original-order`
+    pub(crate) original_order: bool,
+    /// `--with-packet-numbers`
+    pub(crate) with_packet_numbers: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    #[default]
+    Yaml,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub(crate) struct ConfBasic {
     pub(crate) enabled: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct ConfHttp {
+    pub(crate) enabled: bool,
+    /// If not empty, only the headers with these names feed JA4H. Non-standard.
+    #[serde(default)]
+    pub(crate) include_headers: Vec<String>,
+    /// Headers with these names don't feed JA4H. Non-standard.
+    #[serde(default)]
+    pub(crate) exclude_headers: Vec<String>,
+}
+
+impl ConfHttp {
+    /// Returns `true` if the header named `name` feeds JA4H: the header count and
+    /// the hash of the header names. Names are compared case-insensitively.
+    ///
+    /// `Cookie` and `Referer` are handled before these rules and never feed the
+    /// header list.
+    pub(crate) fn feeds_ja4h(&self, name: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+        (self.include_headers.is_empty() || listed(&self.include_headers))
+            && !listed(&self.exclude_headers)
+    }
+
+    /// Returns `true` if the header rules differ from the JA4H specification.
+    pub(crate) fn is_non_standard(&self) -> bool {
+        !self.include_headers.is_empty() || !self.exclude_headers.is_empty()
+    }
+
+    fn prepare(self) -> Self {
+        if self.enabled && self.is_non_standard() {
+            tracing::warn!(
+                include_headers = ?self.include_headers,
+                exclude_headers = ?self.exclude_headers,
+                "custom JA4H header rules: JA4H fingerprints are non-standard and \
+                 not comparable with those of other tools"
+            );
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub(crate) struct ConfSsh {
     pub(crate) enabled: bool,
     /// JA4SSH (SSH traffic fingerprinting) runs every `sample_size` packets
@@ -31,8 +189,51 @@ pub(crate) struct ConfSsh {
     pub(crate) sample_size: usize,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct ConfTls {
+    pub(crate) enabled: bool,
+    /// Server names of DNS-over-HTTPS resolvers. TLS streams with a matching SNI
+    /// are tagged with `dns_encrypted: doh`.
+    pub(crate) doh_servers: Vec<String>,
+    /// Fingerprint types to compute; set with `--only`, not in the configuration file.
+    #[serde(skip)]
+    pub(crate) selection: Selection,
+    /// Fingerprint TLS tunneled in WebSocket connections; set with `--experimental`,
+    /// not in the configuration file.
+    #[serde(skip)]
+    pub(crate) experimental: bool,
+    /// Annotate TLS streams with the names resolved to their servers' addresses;
+    /// set with `--resolve-dns`, not in the configuration file.
+    #[serde(skip)]
+    pub(crate) resolve_dns: bool,
+}
+
+impl ConfTls {
+    const DEFAULT_DOH_SERVERS: [&'static str; 12] = [
+        "chrome.cloudflare-dns.com",
+        "cloudflare-dns.com",
+        "dns.adguard-dns.com",
+        "dns.cloudflare.com",
+        "dns.google",
+        "dns.nextdns.io",
+        "dns.quad9.net",
+        "doh.cleanbrowsing.org",
+        "doh.opendns.com",
+        "dns10.quad9.net",
+        "mozilla.cloudflare-dns.com",
+        "one.one.one.one",
+    ];
+
+    /// Returns `true` if `sni` is the server name of a known DNS-over-HTTPS resolver.
+    pub(crate) fn is_doh_server(&self, sni: &str) -> bool {
+        self.doh_servers
+            .iter()
+            .any(|server| server.eq_ignore_ascii_case(sni))
+    }
+}
+
 impl ConfSsh {
-    const DEFAULT_SAMPLE_SIZE: usize = 200;
+    pub(crate) const DEFAULT_SAMPLE_SIZE: usize = 200;
 
     fn prepare(mut self) -> Self {
         if self.enabled && self.sample_size == 0 {
@@ -47,58 +248,405 @@ impl ConfSsh {
 }
 
 impl Conf {
+    /// Default of `tshark_min_version`: JA4 is designed to work with tshark 4.0.6
+    /// and above.
+    pub(crate) const DEFAULT_TSHARK_MIN_VERSION: &'static str = "4.0.6";
+
+    /// Reads the configuration from the first file of the search path, see
+    /// [`Conf::load_from`].
     pub(crate) fn load() -> crate::Result<Self> {
-        let config_dir = config_dir();
-        let config_file = config_dir.join("config.toml");
+        Self::load_from(None, None)
+    }
+
+    /// Reads the configuration from `path` (`--config`), which must exist, even if
+    /// `JA4_CONFIG` is set. Without `path`, the first file that exists of
+    ///
+    /// 1. `$JA4_CONFIG`, which must exist if set;
+    /// 2. `ja4.toml` in the current directory;
+    /// 3. `$XDG_CONFIG_HOME/ja4/config.toml` (`~/.config/ja4/config.toml`), which
+    ///    is created with the example configuration if missing.
+    ///
+    /// The built-in defaults are used if there is no file and it cannot be created.
+    ///
+    /// The settings of `[profile.<name>]` table of the file (`--profile`, or
+    /// `JA4_PROFILE` without it) override those of the rest of the file.
+    /// `JA4_*` environment variables override both, e.g. `JA4_PORTS_TLS=443,8443`.
+    pub(crate) fn load_from(path: Option<&Path>, profile: Option<&str>) -> crate::Result<Self> {
+        Self::load_with_env(path, profile, |name| env::var_os(name))
+    }
+
+    /// Same as [`Conf::load_from`], with the `JA4_*` variables looked up with `var`.
+    fn load_with_env(
+        path: Option<&Path>,
+        profile: Option<&str>,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> crate::Result<Self> {
+        let config_file = match path {
+            Some(path) => Some(existing(path.to_owned())?),
+            None => find_config_file()?,
+        };
+        match &config_file {
+            Some(path) => tracing::debug!(config_file = %path.display(), "reading configuration"),
+            None => tracing::debug!("no configuration file, using the defaults"),
+        }
+        let config_file = config_file.map(config::File::from);
+        let profile = match profile {
+            Some(name) => Some(name.to_owned()),
+            None => var("JA4_PROFILE").map(|name| name.to_string_lossy().into_owned()),
+        };
+        let profile = match &profile {
+            Some(name) => {
+                tracing::debug!(profile = name, "applying configuration profile");
+                Some(Profile::find(config_file.as_ref(), name)?)
+            }
+            None => None,
+        };
 
         // Configuration sources precedence:
-        // environment variables > config file > defaults
+        // command line > environment variables > profile > config file > defaults
 
         let config_builder = config::Config::builder()
+            .set_default("tshark_min_version", Self::DEFAULT_TSHARK_MIN_VERSION)?
+            .set_default("tshark_version_check", "warn")?
+            .set_default("fingerprints.ja4", true)?
+            .set_default("fingerprints.ja4s", true)?
+            .set_default("fingerprints.ja4h", true)?
+            .set_default("fingerprints.ja4l", true)?
+            .set_default("fingerprints.ja4x", true)?
+            .set_default("fingerprints.ja4ssh", true)?
             .set_default("http.enabled", true)?
+            .set_default("http.include_headers", Vec::<String>::new())?
+            .set_default("http.exclude_headers", Vec::<String>::new())?
             .set_default("ssh.enabled", true)?
-            .set_default("ssh.sample_size", 200)?
+            .set_default("ssh.sample_size", ConfSsh::DEFAULT_SAMPLE_SIZE as u64)?
             .set_default("time.enabled", true)?
-            .set_default("tls.enabled", true)?;
-
-        if !config_file.exists() {
-            let example_config = include_bytes!("../config.toml");
-            fs_err::create_dir_all(&config_dir).map_err(|e| {
-                tracing::error!(error = %e, ?config_dir, "cannot create directory");
-                e
-            })?;
-            let mut file = File::create(&config_file).map_err(|e| {
-                tracing::error!(error = %e, "could not create config file");
-                e
-            })?;
-            file.write_all(example_config).map_err(|e| {
-                tracing::error!(error = %e, "could not write default config file");
-                e
-            })?;
-        }
-
-        let config = config_builder
-            .add_source(config::File::from(config_file))
-            .add_source(
-                config::Environment::with_prefix("JA4")
-                    .prefix_separator("_")
-                    .separator("__"),
-            )
-            .build()?;
-
-        let conf = config.try_deserialize::<Conf>()?.prepare();
-
-        if conf.http.enabled || conf.time.enabled || conf.ssh.enabled || conf.tls.enabled {
-            Ok(conf)
+            .set_default("tls.enabled", true)?
+            .set_default("tls.doh_servers", ConfTls::DEFAULT_DOH_SERVERS.to_vec())?;
+
+        let config_builder = match config_file {
+            Some(file) => config_builder.add_source(file),
+            None => config_builder,
+        };
+        let config_builder = match profile {
+            Some(profile) => config_builder.add_source(profile),
+            None => config_builder,
+        };
+        // `JA4_SSH__SAMPLE_SIZE` form of the variables, accepted for compatibility.
+        let mut config_builder = config_builder.add_source(
+            config::Environment::with_prefix("JA4")
+                .prefix_separator("_")
+                .separator("__"),
+        );
+        for (key, value) in env_overrides(&var)? {
+            config_builder = config_builder.set_override(key, value)?;
+        }
+        let config = config_builder.build()?;
+
+        let mut conf = config.try_deserialize::<Conf>()?;
+        if let Some(path) = var("JA4_TSHARK") {
+            conf.tshark_path = Some(path.into());
+        }
+        if let Some(path) = &conf.tshark_path {
+            tracing::debug!(tshark_path = %path.display(), "configured tshark");
+        }
+        conf.prepare()
+    }
+
+    /// Returns a builder of the configuration, initialized with the built-in
+    /// defaults: all fingerprints enabled, JA4SSH every 200 SSH packets, the
+    /// built-in list of DNS-over-HTTPS resolvers.
+    ///
+    /// Unlike the command line tool, the builder doesn't read `config.toml` or
+    /// `JA4_*` environment variables; the values set with its methods are final.
+    pub fn builder() -> ConfBuilder {
+        ConfBuilder(Self {
+            tshark_path: None,
+            tshark_min_version: Self::DEFAULT_TSHARK_MIN_VERSION.to_owned(),
+            tshark_version_check: VersionCheck::Warn,
+            fingerprints: Selection::default(),
+            ports: ConfPorts::default(),
+            output: ConfOutput::default(),
+            http: ConfHttp {
+                enabled: true,
+                include_headers: Vec::new(),
+                exclude_headers: Vec::new(),
+            },
+            ssh: ConfSsh {
+                enabled: true,
+                sample_size: ConfSsh::DEFAULT_SAMPLE_SIZE,
+            },
+            time: ConfBasic { enabled: true },
+            tls: ConfTls {
+                enabled: true,
+                doh_servers: ConfTls::DEFAULT_DOH_SERVERS.map(str::to_owned).to_vec(),
+                selection: Selection::default(),
+                experimental: false,
+                resolve_dns: false,
+            },
+        })
+    }
+
+    fn prepare(mut self) -> crate::Result<Self> {
+        if let Err(source) = semver::Version::parse(&self.tshark_min_version) {
+            return Err(crate::Error::TsharkMinVersion {
+                value: self.tshark_min_version,
+                source,
+            });
+        }
+        self.ports.check()?;
+        self.http = self.http.prepare();
+        self.ssh = self.ssh.prepare();
+        if self.http.enabled || self.time.enabled || self.ssh.enabled || self.tls.enabled {
+            Ok(self)
         } else {
             Err(crate::Error::VoidConf)
         }
     }
 
-    fn prepare(mut self) -> Self {
-        self.ssh = self.ssh.prepare();
+    /// Returns the supported tshark versions, e.g. `>=4.0.6`.
+    pub(crate) fn tshark_requirement(&self) -> semver::VersionReq {
+        semver::VersionReq::parse(&format!(">={}", self.tshark_min_version))
+            .expect("BUG: tshark_min_version is checked by Conf::prepare")
+    }
+
+    /// Returns the fingerprint types given with `--only`, or those enabled in the
+    /// `[fingerprints]` table if `only` is empty.
+    pub(crate) fn selection(&self, only: &[FingerprintKind]) -> Selection {
+        if only.is_empty() {
+            self.fingerprints
+        } else {
+            Selection::new(only)
+        }
+    }
+
+    /// Disables processing that is not needed for the selected fingerprint types.
+    pub(crate) fn restrict(&mut self, selection: Selection) {
+        self.tls.enabled &= selection.tls();
+        self.tls.selection = selection;
+        self.http.enabled &= selection.ja4h;
+        self.time.enabled &= selection.ja4l;
+        self.ssh.enabled &= selection.ja4ssh;
+    }
+
+    /// Disables all fingerprinting, for `--list-streams`. tshark is then only asked
+    /// for the fields that identify streams.
+    pub(crate) fn disable_all(&mut self) {
+        self.tls.enabled = false;
+        self.http.enabled = false;
+        self.time.enabled = false;
+        self.ssh.enabled = false;
+    }
+}
+
+/// Builder of [`Conf`], see [`Conf::builder`].
+#[derive(Debug, Clone)]
+pub struct ConfBuilder(Conf);
+
+impl ConfBuilder {
+    /// Computes only the given fingerprint types, unless the processor is given
+    /// others with `--only`. An empty list, the default, selects all of them.
+    pub fn fingerprints(mut self, kinds: impl IntoIterator<Item = FingerprintKind>) -> Self {
+        self.0.fingerprints = Selection::new(&kinds.into_iter().collect::<Vec<_>>());
+        self
+    }
+
+    /// Sets the TCP ports on which tshark decodes the traffic as TLS, SSH, and HTTP.
+    /// The lists are empty by default; a port may only be in one of them.
+    pub fn ports(
+        mut self,
+        tls: impl IntoIterator<Item = u16>,
+        ssh: impl IntoIterator<Item = u16>,
+        http: impl IntoIterator<Item = u16>,
+    ) -> Self {
+        self.0.ports = ConfPorts {
+            tls: tls.into_iter().collect(),
+            ssh: ssh.into_iter().collect(),
+            http: http.into_iter().collect(),
+        };
+        self
+    }
+
+    /// Enables HTTP client fingerprinting (JA4H).
+    pub fn http(mut self, enabled: bool) -> Self {
+        self.0.http.enabled = enabled;
+        self
+    }
+
+    /// Restricts the headers that feed JA4H to those with the given names
+    /// (case-insensitive). An empty list, the default, means all headers.
+    ///
+    /// **Non-standard:** a non-empty list produces JA4H fingerprints that are not
+    /// comparable with those of other tools.
+    pub fn http_include_headers<S: Into<String>>(
+        mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.0.http.include_headers = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Excludes the headers with the given names (case-insensitive) from JA4H.
+    /// The default is an empty list.
+    ///
+    /// **Non-standard:** a non-empty list produces JA4H fingerprints that are not
+    /// comparable with those of other tools.
+    pub fn http_exclude_headers<S: Into<String>>(
+        mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.0.http.exclude_headers = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Enables SSH traffic fingerprinting (JA4SSH).
+    pub fn ssh(mut self, enabled: bool) -> Self {
+        self.0.ssh.enabled = enabled;
+        self
+    }
+
+    /// Sets the number of SSH packets per JA4SSH fingerprint. Zero means the
+    /// default, 200.
+    pub fn ssh_sample_size(mut self, sample_size: usize) -> Self {
+        self.0.ssh.sample_size = sample_size;
+        self
+    }
+
+    /// Enables light distance (latency) fingerprinting (JA4L-C, JA4L-S).
+    pub fn time(mut self, enabled: bool) -> Self {
+        self.0.time.enabled = enabled;
+        self
+    }
+
+    /// Enables TLS fingerprinting (JA4, JA4S, JA4X).
+    pub fn tls(mut self, enabled: bool) -> Self {
+        self.0.tls.enabled = enabled;
+        self
+    }
+
+    /// Replaces the list of server names of DNS-over-HTTPS resolvers.
+    pub fn doh_servers<S: Into<String>>(mut self, servers: impl IntoIterator<Item = S>) -> Self {
+        self.0.tls.doh_servers = servers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the oldest supported tshark version, e.g. `"4.2.0"`; the default is
+    /// 4.0.6. [`ConfBuilder::build`] fails if it is not a valid version.
+    pub fn tshark_min_version(mut self, version: impl Into<String>) -> Self {
+        self.0.tshark_min_version = version.into();
+        self
+    }
+
+    /// Sets what to do if tshark is older than the supported versions; the default
+    /// is to warn.
+    pub fn tshark_version_check(mut self, check: VersionCheck) -> Self {
+        self.0.tshark_version_check = check;
+        self
+    }
+
+    /// Sets the tshark executable to run instead of the one found in `PATH`.
+    pub fn tshark_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.0.tshark_path = Some(path.into());
         self
     }
+
+    /// Returns the configuration, or [`crate::Error::VoidConf`] if all
+    /// fingerprinters are disabled.
+    ///
+    /// Also fails if `tshark_min_version` is not a valid version or a port is in
+    /// two lists of [`ConfBuilder::ports`].
+    pub fn build(self) -> crate::Result<Conf> {
+        self.0.prepare()
+    }
+}
+
+/// Type of the value of a setting, as written in an environment variable.
+#[derive(Debug, Clone, Copy)]
+enum EnvValue {
+    Bool,
+    Int,
+    Str,
+    /// Comma-separated strings
+    List,
+    /// Comma-separated TCP ports
+    Ports,
+}
+
+/// Settings that `JA4_*` environment variables override. The name of the variable
+/// is that of the setting, uppercased, with `_` for `.`: `JA4_SSH_SAMPLE_SIZE`
+/// for `ssh.sample_size`.
+const ENV_SETTINGS: &[(&str, EnvValue)] = &[
+    ("tshark_path", EnvValue::Str),
+    ("tshark_min_version", EnvValue::Str),
+    ("tshark_version_check", EnvValue::Str),
+    ("fingerprints.ja4", EnvValue::Bool),
+    ("fingerprints.ja4s", EnvValue::Bool),
+    ("fingerprints.ja4h", EnvValue::Bool),
+    ("fingerprints.ja4l", EnvValue::Bool),
+    ("fingerprints.ja4x", EnvValue::Bool),
+    ("fingerprints.ja4ssh", EnvValue::Bool),
+    ("ports.tls", EnvValue::Ports),
+    ("ports.ssh", EnvValue::Ports),
+    ("ports.http", EnvValue::Ports),
+    ("output.format", EnvValue::Str),
+    ("output.with_raw", EnvValue::Bool),
+    ("output.original_order", EnvValue::Bool),
+    ("output.with_packet_numbers", EnvValue::Bool),
+    ("http.enabled", EnvValue::Bool),
+    ("http.include_headers", EnvValue::List),
+    ("http.exclude_headers", EnvValue::List),
+    ("ssh.enabled", EnvValue::Bool),
+    ("ssh.sample_size", EnvValue::Int),
+    ("time.enabled", EnvValue::Bool),
+    ("tls.enabled", EnvValue::Bool),
+    ("tls.doh_servers", EnvValue::List),
+];
+
+/// Returns the name of the environment variable of a setting, e.g.
+/// `JA4_PORTS_TLS` for `ports.tls`.
+fn env_var_name(key: &str) -> String {
+    format!("JA4_{}", key.replace('.', "_").to_ascii_uppercase())
+}
+
+/// Returns the settings given with `JA4_*` environment variables, looked up with
+/// `var`.
+fn env_overrides(
+    var: impl Fn(&str) -> Option<OsString>,
+) -> crate::Result<Vec<(&'static str, config::Value)>> {
+    let mut overrides = Vec::new();
+    for &(key, kind) in ENV_SETTINGS {
+        let name = env_var_name(key);
+        let Some(value) = var(&name) else {
+            continue;
+        };
+        let invalid = |expected: &'static str| crate::Error::EnvVar {
+            name: name.clone(),
+            value: value.to_string_lossy().into_owned(),
+            expected,
+        };
+        let s = value.to_str().ok_or_else(|| invalid("UTF-8 string"))?;
+        let list = || s.split(',').map(str::trim).filter(|item| !item.is_empty());
+        let value = match kind {
+            EnvValue::Str => s.into(),
+            EnvValue::Bool => match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => true.into(),
+                "false" | "0" => false.into(),
+                _ => return Err(invalid("true or false")),
+            },
+            EnvValue::Int => s
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| invalid("non-negative integer"))?
+                .into(),
+            EnvValue::List => list().map(str::to_owned).collect::<Vec<_>>().into(),
+            EnvValue::Ports => list()
+                .map(|port| port.parse::<u16>().map(u64::from))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("comma-separated TCP ports"))?
+                .into(),
+        };
+        overrides.push((key, value));
+    }
+    Ok(overrides)
 }
 
 #[cfg(not(target_os = "windows"))]
@@ -118,3 +666,637 @@ fn config_dir() -> PathBuf {
         env::var("XDG_CONFIG_HOME").map_or_else(|_| home_dir().join(".config"), PathBuf::from);
     config_dir.join("ja4")
 }
+
+/// Returns the first configuration file of the search path, see [`Conf::load_from`].
+fn find_config_file() -> crate::Result<Option<PathBuf>> {
+    if let Some(path) = env::var_os("JA4_CONFIG") {
+        return existing(path.into()).map(Some);
+    }
+    let local = PathBuf::from("ja4.toml");
+    if local.is_file() {
+        return Ok(Some(local));
+    }
+    let config_dir = config_dir();
+    let config_file = config_dir.join("config.toml");
+    if !config_file.exists() {
+        if let Err(error) = create_example_config(&config_dir, &config_file) {
+            tracing::warn!(%error, "could not create the example configuration file");
+            return Ok(None);
+        }
+    }
+    Ok(Some(config_file))
+}
+
+/// The example configuration file that [`find_config_file`] creates.
+const EXAMPLE_CONFIG: &[u8] = include_bytes!("../config.toml");
+
+fn create_example_config(config_dir: &Path, config_file: &Path) -> std::io::Result<()> {
+    fs_err::create_dir_all(config_dir)?;
+    File::create(config_file)?.write_all(EXAMPLE_CONFIG)
+}
+
+/// Returns the default configuration as TOML: every setting at its built-in value,
+/// with a comment (`ja4 config init`).
+///
+/// `tshark_path` has no default value, so it is the only setting commented out.
+pub(crate) fn default_config() -> String {
+    let Conf {
+        tshark_path: _,
+        tshark_min_version,
+        tshark_version_check,
+        fingerprints: fps,
+        ports,
+        output,
+        http,
+        ssh,
+        time,
+        tls,
+    } = Conf::builder().0;
+    let version_check = match tshark_version_check {
+        VersionCheck::Warn => "warn",
+        VersionCheck::Error => "error",
+        VersionCheck::Off => "off",
+    };
+    let format = match output.format {
+        OutputFormat::Yaml => "yaml",
+        OutputFormat::Json => "json",
+    };
+    let list = |ports: &[u16]| format!("[{}]", ports.iter().join(", "));
+    let strings = |items: &[String]| {
+        if items.is_empty() {
+            "[]".to_owned()
+        } else {
+            let items = items.iter().map(|s| format!("    {s:?},\n")).join("");
+            format!("[\n{items}]")
+        }
+    };
+    format!(
+        "\
+## ja4 configuration with the default settings. `JA4_*` environment variables
+## override them, e.g. `JA4_SSH_SAMPLE_SIZE=100`; command line options override both.
+
+## tshark executable to run instead of the one found in PATH.
+# tshark_path = \"/usr/bin/tshark\"
+## Oldest supported tshark version (`--tshark-min-version`)
+tshark_min_version = \"{tshark_min_version}\"
+## \"warn\", \"error\", or \"off\": what to do with an older tshark (`--tshark-version-check`)
+tshark_version_check = \"{version_check}\"
+
+## Fingerprint types to compute; `--only` overrides this table.
+[fingerprints]
+## JA4 (TLS client)
+ja4 = {}
+## JA4S (TLS server)
+ja4s = {}
+## JA4H (HTTP client)
+ja4h = {}
+## JA4L (light distance)
+ja4l = {}
+## JA4X (X.509 certificates)
+ja4x = {}
+## JA4SSH (SSH traffic)
+ja4ssh = {}
+
+## Services on non-standard TCP ports, decoded as the protocol of the list. A port
+## may only be in one list.
+[ports]
+## Ports decoded as TLS, e.g. [8443, 9443]
+tls = {}
+## Ports decoded as SSH, e.g. [2222]
+ssh = {}
+## Ports decoded as HTTP, e.g. [8080]
+http = {}
+
+## Defaults of the output options; the command line options override them.
+[output]
+## \"yaml\" or \"json\" (`--json`)
+format = \"{format}\"
+## Include raw (unhashed) fingerprints (`--with-raw`)
+with_raw = {}
+## Preserve the original order of values (`--original-order`)
+original_order = {}
+## Include packet numbers (`--with-packet-numbers`)
+with_packet_numbers = {}
+
+[http]
+## HTTP client fingerprinting (JA4H)
+enabled = {}
+## NON-STANDARD if not empty: only the headers with these names feed JA4H
+include_headers = {}
+## NON-STANDARD if not empty: the headers with these names don't feed JA4H
+exclude_headers = {}
+
+[ssh]
+## SSH traffic fingerprinting (JA4SSH)
+enabled = {}
+## Number of SSH packets per JA4SSH fingerprint
+sample_size = {}
+
+[time]
+## Light distance (latency) fingerprinting (JA4L)
+enabled = {}
+
+[tls]
+## TLS fingerprinting (JA4, JA4S, JA4X)
+enabled = {}
+## Server names of DNS-over-HTTPS resolvers, tagged with `dns_encrypted: doh`
+doh_servers = {}
+
+## Profiles (`--profile <NAME>`) override the settings above, e.g.
+# [profile.triage.fingerprints]
+# ja4h = false
+# ja4ssh = false
+",
+        fps.ja4,
+        fps.ja4s,
+        fps.ja4h,
+        fps.ja4l,
+        fps.ja4x,
+        fps.ja4ssh,
+        list(&ports.tls),
+        list(&ports.ssh),
+        list(&ports.http),
+        output.with_raw,
+        output.original_order,
+        output.with_packet_numbers,
+        http.enabled,
+        strings(&http.include_headers),
+        strings(&http.exclude_headers),
+        ssh.enabled,
+        ssh.sample_size,
+        time.enabled,
+        tls.enabled,
+        strings(&tls.doh_servers),
+    )
+}
+
+/// Writes [`default_config`] to stdout, or with `write` to the configuration file
+/// of the search path (`$XDG_CONFIG_HOME/ja4/config.toml`). An existing file is
+/// only replaced with `force`.
+pub(crate) fn init(
+    write: bool,
+    force: bool,
+    writer: &mut impl std::io::Write,
+) -> crate::Result<()> {
+    init_in(&config_dir(), write, force, writer)
+}
+
+/// Same as [`init`], with the configuration file in `config_dir`.
+fn init_in(
+    config_dir: &Path,
+    write: bool,
+    force: bool,
+    writer: &mut impl std::io::Write,
+) -> crate::Result<()> {
+    if !write {
+        writer.write_all(default_config().as_bytes())?;
+        return Ok(());
+    }
+    let config_file = config_dir.join("config.toml");
+    write_default_config(config_dir, &config_file, force)?;
+    tracing::info!(config_file = %config_file.display(), "wrote the default configuration");
+    Ok(())
+}
+
+/// Writes [`default_config`] to `config_file`. An existing file is only replaced
+/// with `force`, or if it is the example configuration as created by
+/// [`find_config_file`], i.e. it has not been edited.
+fn write_default_config(config_dir: &Path, config_file: &Path, force: bool) -> crate::Result<()> {
+    if config_file.exists()
+        && !force
+        && fs_err::read(config_file).map_or(true, |contents| contents != EXAMPLE_CONFIG)
+    {
+        return Err(crate::Error::ConfigExists(config_file.to_owned()));
+    }
+    fs_err::create_dir_all(config_dir)?;
+    fs_err::write(config_file, default_config())?;
+    Ok(())
+}
+
+/// Settings of a `[profile.<name>]` table of the configuration file.
+///
+/// As a configuration source added after the file, the profile's tables are merged
+/// into the file's: a setting the profile doesn't mention keeps its value.
+#[derive(Debug, Clone)]
+struct Profile(config::Map<String, config::Value>);
+
+impl Profile {
+    /// Looks up the profile in the configuration file, if any. An unknown name is an
+    /// error that lists the profiles of the file.
+    fn find(
+        file: Option<&config::File<config::FileSourceFile, config::FileFormat>>,
+        name: &str,
+    ) -> crate::Result<Self> {
+        use config::Source as _;
+
+        let mut profiles = match file {
+            Some(file) => match file.collect()?.remove("profile") {
+                Some(profiles) => profiles.into_table()?,
+                None => config::Map::new(),
+            },
+            None => config::Map::new(),
+        };
+        match profiles.remove(name) {
+            Some(profile) => Ok(Self(profile.into_table()?)),
+            None => Err(crate::Error::UnknownProfile {
+                name: name.to_owned(),
+                available: match profiles.keys().sorted().join(", ") {
+                    names if names.is_empty() => "none".to_owned(),
+                    names => names,
+                },
+            }),
+        }
+    }
+}
+
+impl config::Source for Profile {
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Checks that the configuration file given by the user exists.
+fn existing(path: PathBuf) -> crate::Result<PathBuf> {
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(crate::Error::ConfigNotFound(path))
+    }
+}
+
+#[test]
+fn test_builder() {
+    let conf = Conf::builder()
+        .http(false)
+        .ssh_sample_size(0)
+        .doh_servers(["doh.example.net"])
+        .build()
+        .unwrap();
+    assert!(!conf.http.enabled);
+    assert!(conf.tls.enabled);
+    assert_eq!(conf.ssh.sample_size, ConfSsh::DEFAULT_SAMPLE_SIZE);
+    assert!(conf.tls.is_doh_server("DoH.example.net"));
+    assert!(!conf.tls.is_doh_server("dns.google"));
+
+    assert!(!conf.http.is_non_standard());
+    assert!(conf.http.feeds_ja4h("User-Agent"));
+
+    let conf = Conf::builder()
+        .http_include_headers(["host", "user-agent", "accept"])
+        .http_exclude_headers(["Accept"])
+        .build()
+        .unwrap();
+    assert!(conf.http.is_non_standard());
+    assert!(conf.http.feeds_ja4h("User-Agent"));
+    assert!(!conf.http.feeds_ja4h("Accept"));
+    assert!(!conf.http.feeds_ja4h("Accept-Language"));
+
+    let conf = Conf::builder()
+        .fingerprints([FingerprintKind::Ja4, FingerprintKind::Ja4x])
+        .build()
+        .unwrap();
+    assert_eq!(
+        conf.selection(&[]),
+        Selection::new(&[FingerprintKind::Ja4, FingerprintKind::Ja4x])
+    );
+
+    let conf = Conf::builder()
+        .ports([8443, 9443], [2222], [])
+        .build()
+        .unwrap();
+    assert_eq!(
+        conf.ports.decode_as(),
+        [
+            "tcp.port==8443,tls",
+            "tcp.port==9443,tls",
+            "tcp.port==2222,ssh"
+        ]
+    );
+    let conflict = Conf::builder()
+        .ports([443, 8443], [], [8080, 8443])
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        conflict.to_string(),
+        "port 8443 is listed both as tls and as http in [ports] table of the configuration"
+    );
+    assert_eq!(conflict.kind(), "config");
+
+    let void = Conf::builder()
+        .http(false)
+        .ssh(false)
+        .time(false)
+        .tls(false)
+        .build();
+    assert!(matches!(void, Err(crate::Error::VoidConf)));
+}
+
+#[test]
+fn test_load_from() {
+    let dir = env::temp_dir().join(format!("ja4-test-config-{}", std::process::id()));
+    fs_err::create_dir_all(&dir).unwrap();
+    let path = dir.join("ja4.toml");
+    fs_err::write(
+        &path,
+        "tshark_path = \"/opt/wireshark/bin/tshark\"\n\
+         [fingerprints]\nja4h = false\nja4ssh = false\n\
+         [output]\nformat = \"json\"\nwith_raw = true\n\
+         [ssh]\nsample_size = 50\n[time]\nenabled = false\n",
+    )
+    .unwrap();
+    let conf = Conf::load_from(Some(&path), None).unwrap();
+    assert_eq!(
+        conf.output,
+        ConfOutput {
+            format: OutputFormat::Json,
+            with_raw: true,
+            original_order: false,
+            with_packet_numbers: false,
+        }
+    );
+    assert_eq!(
+        conf.tshark_path.as_deref(),
+        Some(Path::new("/opt/wireshark/bin/tshark"))
+    );
+    assert_eq!(conf.ssh.sample_size, 50);
+    assert!(!conf.time.enabled);
+    assert!(conf.tls.enabled);
+
+    // `--only` takes precedence over `[fingerprints]` table.
+    let selection = conf.selection(&[]);
+    assert!(selection.ja4 && selection.ja4l);
+    assert!(!selection.ja4h && !selection.ja4ssh);
+    assert_eq!(
+        conf.selection(&[FingerprintKind::Ja4h]),
+        Selection::new(&[FingerprintKind::Ja4h])
+    );
+    let mut restricted = conf.clone();
+    restricted.restrict(conf.selection(&[]));
+    assert!(!restricted.http.enabled && !restricted.ssh.enabled);
+    assert!(restricted.tls.enabled);
+
+    let missing = dir.join("missing.toml");
+    let error = Conf::load_from(Some(&missing), None).unwrap_err();
+    assert!(matches!(&error, crate::Error::ConfigNotFound(p) if *p == missing));
+    assert_eq!(error.kind(), "config");
+    fs_err::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_env_overrides() {
+    // Variables are looked up in a map, not in the environment of the test process.
+    let env = |vars: &[(&str, &str)]| {
+        let vars: HashMap<String, OsString> = vars
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.into()))
+            .collect();
+        move |name: &str| vars.get(name).cloned()
+    };
+
+    let dir = env::temp_dir().join(format!("ja4-test-env-{}", std::process::id()));
+    fs_err::create_dir_all(&dir).unwrap();
+    let path = dir.join("ja4.toml");
+    fs_err::write(
+        &path,
+        "tshark_path = \"/usr/bin/tshark\"\n[ports]\ntls = [8443]\n[ssh]\nsample_size = 50\n",
+    )
+    .unwrap();
+
+    let conf = Conf::load_with_env(
+        Some(&path),
+        None,
+        env(&[
+            ("JA4_TSHARK_PATH", "/opt/wireshark/bin/tshark"),
+            ("JA4_FINGERPRINTS_JA4H", "false"),
+            ("JA4_PORTS_TLS", "443, 9443"),
+            ("JA4_PORTS_SSH", "2222"),
+            ("JA4_SSH_SAMPLE_SIZE", "100"),
+            ("JA4_TIME_ENABLED", "0"),
+            ("JA4_HTTP_EXCLUDE_HEADERS", "Accept,User-Agent,"),
+            ("JA4_TLS_DOH_SERVERS", "doh.example.net"),
+            ("JA4_OUTPUT_FORMAT", "json"),
+            ("JA4_OUTPUT_WITH_PACKET_NUMBERS", "true"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        conf.tshark_path.as_deref(),
+        Some(Path::new("/opt/wireshark/bin/tshark"))
+    );
+    assert!(!conf.selection(&[]).ja4h);
+    assert!(conf.selection(&[]).ja4);
+    assert_eq!(conf.ports.tls, [443, 9443]);
+    assert_eq!(conf.ports.ssh, [2222]);
+    assert_eq!(conf.ssh.sample_size, 100);
+    assert!(!conf.time.enabled);
+    assert_eq!(conf.http.exclude_headers, ["Accept", "User-Agent"]);
+    assert!(conf.tls.is_doh_server("doh.example.net"));
+    assert!(!conf.tls.is_doh_server("dns.google"));
+    assert_eq!(conf.output.format, OutputFormat::Json);
+    assert!(conf.output.with_packet_numbers);
+
+    // `JA4_TSHARK` takes precedence over `tshark_path` setting.
+    let conf = Conf::load_with_env(
+        Some(&path),
+        None,
+        env(&[
+            ("JA4_TSHARK_PATH", "/opt/wireshark/bin/tshark"),
+            ("JA4_TSHARK", "/usr/local/bin/tshark"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        conf.tshark_path.as_deref(),
+        Some(Path::new("/usr/local/bin/tshark"))
+    );
+    assert_eq!(conf.ports.tls, [8443]);
+    assert_eq!(conf.ssh.sample_size, 50);
+
+    for (name, value, message) in [
+        (
+            "JA4_PORTS_HTTP",
+            "80,http",
+            r#"invalid value "80,http" of environment variable JA4_PORTS_HTTP: expected comma-separated TCP ports"#,
+        ),
+        (
+            "JA4_FINGERPRINTS_JA4X",
+            "no",
+            r#"invalid value "no" of environment variable JA4_FINGERPRINTS_JA4X: expected true or false"#,
+        ),
+        (
+            "JA4_SSH_SAMPLE_SIZE",
+            "-1",
+            r#"invalid value "-1" of environment variable JA4_SSH_SAMPLE_SIZE: expected non-negative integer"#,
+        ),
+    ] {
+        let error = Conf::load_with_env(Some(&path), None, env(&[(name, value)])).unwrap_err();
+        assert_eq!(error.to_string(), message);
+        assert_eq!(error.kind(), "config");
+    }
+    fs_err::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_profiles() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|&&(var, _)| var == name)
+                .map(|&(_, value)| OsString::from(value))
+        }
+    };
+
+    let dir = env::temp_dir().join(format!("ja4-test-profiles-{}", std::process::id()));
+    fs_err::create_dir_all(&dir).unwrap();
+    let path = dir.join("ja4.toml");
+    fs_err::write(
+        &path,
+        "[fingerprints]\nja4x = false\n\
+         [output]\nwith_packet_numbers = true\n\
+         [ssh]\nsample_size = 50\n\
+         [profile.triage.fingerprints]\nja4h = false\nja4l = false\nja4ssh = false\n\
+         [profile.triage.output]\nformat = \"json\"\n\
+         [profile.forensics]\nfingerprints = { ja4x = true }\n\
+         output = { with_raw = true }\nssh = { sample_size = 20 }\n",
+    )
+    .unwrap();
+
+    // Without a profile, the tables of the profiles are ignored.
+    let base = Conf::load_with_env(Some(&path), None, |_| None).unwrap();
+    assert!(!base.selection(&[]).ja4x && base.selection(&[]).ja4h);
+    assert_eq!(base.output.format, OutputFormat::Yaml);
+
+    // The profile's settings are merged into the file's.
+    let triage = Conf::load_with_env(Some(&path), Some("triage"), |_| None).unwrap();
+    let selection = triage.selection(&[]);
+    assert!(selection.ja4 && selection.ja4s);
+    assert!(!selection.ja4h && !selection.ja4l && !selection.ja4x && !selection.ja4ssh);
+    assert_eq!(
+        triage.output,
+        ConfOutput {
+            format: OutputFormat::Json,
+            with_raw: false,
+            original_order: false,
+            with_packet_numbers: true,
+        }
+    );
+    assert_eq!(triage.ssh.sample_size, 50);
+    // `--only` overrides the profile's `[fingerprints]`.
+    assert_eq!(
+        triage.selection(&[FingerprintKind::Ja4h]),
+        Selection::new(&[FingerprintKind::Ja4h])
+    );
+
+    // `JA4_PROFILE` selects a profile unless `--profile` is given; `JA4_*` settings
+    // override the profile.
+    let forensics = Conf::load_with_env(
+        Some(&path),
+        None,
+        env(&[("JA4_PROFILE", "forensics"), ("JA4_SSH_SAMPLE_SIZE", "100")]),
+    )
+    .unwrap();
+    assert!(forensics.selection(&[]).ja4x);
+    assert!(forensics.output.with_raw && forensics.output.with_packet_numbers);
+    assert_eq!(forensics.ssh.sample_size, 100);
+    let triage = Conf::load_with_env(
+        Some(&path),
+        Some("triage"),
+        env(&[("JA4_PROFILE", "forensics")]),
+    )
+    .unwrap();
+    assert!(!triage.output.with_raw);
+
+    let error = Conf::load_with_env(Some(&path), Some("privacy"), |_| None).unwrap_err();
+    expect_test::expect![[r#"profile "privacy" is not defined in the configuration file; available profiles: forensics, triage"#]]
+        .assert_eq(&error.to_string());
+    assert_eq!(error.kind(), "config");
+
+    let plain = dir.join("plain.toml");
+    fs_err::write(&plain, "[ssh]\nsample_size = 50\n").unwrap();
+    let error = Conf::load_with_env(Some(&plain), Some("triage"), |_| None).unwrap_err();
+    assert!(
+        matches!(&error, crate::Error::UnknownProfile { available, .. } if available == "none"),
+        "{error}"
+    );
+    fs_err::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_default_config() {
+    let dir = env::temp_dir().join(format!("ja4-test-init-{}", std::process::id()));
+    let path = dir.join("config.toml");
+    write_default_config(&dir, &path, false).unwrap();
+
+    // Loading the file gives the built-in defaults.
+    let conf = Conf::load_with_env(Some(&path), None, |_| None).unwrap();
+    assert_eq!(conf, Conf::builder().build().unwrap());
+
+    // Every setting but `tshark_path`, which has no default, is in the file.
+    let text = default_config();
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(&text, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    for &(key, _) in ENV_SETTINGS {
+        if key != "tshark_path" {
+            assert!(config.get::<config::Value>(key).is_ok(), "{key} is missing");
+        }
+    }
+    assert!(text.contains("\n# tshark_path = "));
+
+    let error = write_default_config(&dir, &path, false).unwrap_err();
+    assert!(matches!(&error, crate::Error::ConfigExists(p) if *p == path));
+    assert_eq!(error.kind(), "config");
+    fs_err::write(&path, "[ssh]\nsample_size = 50\n").unwrap();
+    write_default_config(&dir, &path, true).unwrap();
+    assert_eq!(fs_err::read_to_string(&path).unwrap(), text);
+    fs_err::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_init_write() {
+    let dir = env::temp_dir().join(format!("ja4-test-init-write-{}", std::process::id()));
+    let path = dir.join("config.toml");
+
+    // The example configuration that a run has created is not the user's: it is
+    // replaced without `--force`.
+    create_example_config(&dir, &path).unwrap();
+    let mut stdout = Vec::new();
+    init_in(&dir, true, false, &mut stdout).unwrap();
+    assert!(stdout.is_empty());
+    assert_eq!(fs_err::read_to_string(&path).unwrap(), default_config());
+
+    // An edited file is kept unless `--force` is given.
+    fs_err::write(&path, "[ssh]\nsample_size = 50\n").unwrap();
+    let error = init_in(&dir, true, false, &mut stdout).unwrap_err();
+    assert!(matches!(&error, crate::Error::ConfigExists(p) if *p == path));
+    assert_eq!(
+        fs_err::read_to_string(&path).unwrap(),
+        "[ssh]\nsample_size = 50\n"
+    );
+    init_in(&dir, true, true, &mut stdout).unwrap();
+    assert_eq!(fs_err::read_to_string(&path).unwrap(), default_config());
+    fs_err::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_is_doh_server() {
+    let conf = Conf::builder().build().unwrap();
+    assert!(conf.tls.is_doh_server("dns.google"));
+    // Server names are case-insensitive.
+    assert!(conf.tls.is_doh_server("Mozilla.Cloudflare-DNS.com"));
+    assert!(!conf.tls.is_doh_server("www.google.com"));
+    assert!(!conf.tls.is_doh_server("google"));
+
+    // A custom list replaces the built-in one.
+    let conf = Conf::builder()
+        .doh_servers(["doh.example.net"])
+        .build()
+        .unwrap();
+    assert!(conf.tls.is_doh_server("doh.example.net"));
+    assert!(!conf.tls.is_doh_server("dns.google"));
+}
@@ -0,0 +1,46 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Collapsing of repeated connections (`--dedupe-streams`)
+
+use indexmap::{map::Entry, IndexMap};
+
+use crate::OutputRecord;
+
+/// Identity of a connection for the purpose of deduplication.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    pub(crate) src: String,
+    pub(crate) dst: String,
+    pub(crate) dst_port: u32,
+    pub(crate) ja4: Option<String>,
+    pub(crate) ja4h: Option<String>,
+}
+
+/// Earliest record of every distinct [`Key`], with the number of later records
+/// that were dropped as its duplicates.
+#[derive(Debug, Default)]
+pub(crate) struct Dedupe {
+    recs: IndexMap<Key, OutputRecord>,
+}
+
+impl Dedupe {
+    pub(crate) fn add(&mut self, mut rec: OutputRecord) {
+        match self.recs.entry(rec.dedupe_key()) {
+            Entry::Occupied(mut first) => first.get_mut().add_duplicate(),
+            Entry::Vacant(entry) => {
+                rec.set_duplicates(0);
+                entry.insert(rec);
+            }
+        }
+    }
+
+    /// Returns the records in the order of their first appearance.
+    pub(crate) fn into_recs(self) -> impl Iterator<Item = OutputRecord> {
+        self.recs.into_values()
+    }
+}
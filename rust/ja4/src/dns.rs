@@ -0,0 +1,108 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Server names from DNS responses (`--resolve-dns`)
+//!
+//! Without SNI (Encrypted Client Hello, clients that omit it), the name that
+//! resolved to the server's address is the best guess at the server. This is a
+//! heuristic: an address may be shared by many names (CDNs, virtual hosting), the
+//! client may have resolved the name before the capture began (DNS caching), and
+//! the latest name resolved to an address replaces the earlier ones.
+
+use std::collections::HashMap;
+
+use crate::Packet;
+
+/// Names that DNS responses have resolved to IP addresses.
+#[derive(Debug, Default)]
+pub(crate) struct Resolutions(HashMap<String, String>);
+
+impl Resolutions {
+    /// Records the addresses of the A and AAAA records of a DNS response under the
+    /// queried name, rather than the names of the CNAME chain that led to them.
+    pub(crate) fn update(&mut self, pkt: &Packet) {
+        let Some(dns) = pkt.find_proto("dns") else {
+            return;
+        };
+        if !matches!(dns.first("dns.flags.response"), Ok("1" | "True")) {
+            return;
+        }
+        let Ok(name) = dns.first("dns.qry.name") else {
+            return;
+        };
+        for addr in dns.values("dns.a").chain(dns.values("dns.aaaa")) {
+            self.0.insert(addr.to_owned(), name.to_owned());
+        }
+    }
+
+    /// Returns the name that was last resolved to `ip`.
+    pub(crate) fn lookup(&self, ip: &str) -> Option<&str> {
+        self.0.get(ip).map(String::as_str)
+    }
+}
+
+#[test]
+fn test_resolutions() {
+    let packet = |fields: &[(&str, &str)]| {
+        let mut packet = rtshark::Packet::new();
+        packet.push("dns".to_owned());
+        let layer = packet.last_layer_mut().unwrap();
+        for &(name, value) in fields {
+            layer.add(rtshark::Metadata::new(
+                name.to_owned(),
+                value.to_owned(),
+                String::new(),
+                0,
+                0,
+            ));
+        }
+        packet
+    };
+
+    let query = packet(&[
+        ("dns.flags.response", "0"),
+        ("dns.qry.name", "www.example.com"),
+    ]);
+    let response = packet(&[
+        ("dns.flags.response", "1"),
+        ("dns.qry.name", "www.example.com"),
+        ("dns.resp.name", "www.example.com"),
+        ("dns.cname", "cdn.example.net"),
+        ("dns.resp.name", "cdn.example.net"),
+        ("dns.a", "192.0.2.10"),
+        ("dns.resp.name", "cdn.example.net"),
+        ("dns.a", "192.0.2.11"),
+    ]);
+    let response6 = packet(&[
+        ("dns.flags.response", "1"),
+        ("dns.qry.name", "static.example.org"),
+        ("dns.aaaa", "2001:db8::10"),
+    ]);
+    // A later response for a shared address.
+    let shared = packet(&[
+        ("dns.flags.response", "1"),
+        ("dns.qry.name", "api.example.com"),
+        ("dns.a", "192.0.2.11"),
+    ]);
+
+    let mut resolutions = Resolutions::default();
+    resolutions.update(&Packet::new(&query, 1));
+    assert!(resolutions.0.is_empty());
+    resolutions.update(&Packet::new(&response, 2));
+    resolutions.update(&Packet::new(&response6, 3));
+    assert_eq!(resolutions.lookup("192.0.2.10"), Some("www.example.com"));
+    assert_eq!(resolutions.lookup("192.0.2.11"), Some("www.example.com"));
+    assert_eq!(
+        resolutions.lookup("2001:db8::10"),
+        Some("static.example.org")
+    );
+    assert_eq!(resolutions.lookup("198.51.100.1"), None);
+
+    resolutions.update(&Packet::new(&shared, 4));
+    assert_eq!(resolutions.lookup("192.0.2.11"), Some("api.example.com"));
+    assert_eq!(resolutions.lookup("192.0.2.10"), Some("www.example.com"));
+}
@@ -0,0 +1,357 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Reading `tshark -T ek` output (`--tshark-output ek`)
+//!
+//! With `-T ek` tshark writes a line of JSON per packet (preceded by an
+//! Elasticsearch index line), which is much cheaper to parse than the PDML that
+//! rtshark reads. The packets are converted to [`rtshark::Packet`]s, so the
+//! fingerprinters don't know which output they come from. The conversion loses
+//! some information:
+//!
+//! - ek names a field after its protocol and filter name with the dots replaced by
+//!   underscores, e.g. `tls_tls_handshake_type`. Only the fields of
+//!   [`fields::needed`] are mapped back to their names; the rest are dropped.
+//! - The fields of a protocol are grouped by name rather than listed in the order
+//!   of the dissection tree.
+//! - There are no display strings ([`rtshark::Metadata::display`] is empty).
+//!
+//! [`fields::needed`]: crate::fields::needed
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead as _, BufReader},
+    path::PathBuf,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use serde::{
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    Deserialize,
+};
+
+use crate::tshark::{Input, Program};
+
+/// Command line of a `tshark -T ek` process.
+pub(crate) struct EkCommand {
+    program: PathBuf,
+    args: Vec<String>,
+    /// Field names by their ek keys without the protocol prefix.
+    names: Arc<HashMap<String, &'static str>>,
+}
+
+impl EkCommand {
+    /// Reads packets from `input`, keeping the given fields.
+    pub(crate) fn new(program: &Program, input: Input, fields: &[&'static str]) -> Self {
+        let mut args = ["-l", "-n", "-T", "ek"].map(String::from).to_vec();
+        match input {
+            Input::File(path) => args.extend(["-r".to_owned(), path.to_owned()]),
+            Input::Interface {
+                name,
+                capture_filter,
+            } => {
+                args.extend(["-i".to_owned(), name.to_owned()]);
+                if let Some(filter) = capture_filter {
+                    args.extend(["-f".to_owned(), filter.to_owned()]);
+                }
+            }
+        }
+        args.extend(program.args().iter().cloned());
+        let names = fields
+            .iter()
+            .map(|&field| (field.replace('.', "_"), field))
+            .collect();
+        Self {
+            program: program.path().to_owned(),
+            args,
+            names: Arc::new(names),
+        }
+    }
+
+    pub(crate) fn keylog_file(mut self, path: &str) -> Self {
+        self.args
+            .extend(["-o".to_owned(), format!("tls.keylog_file:{path}")]);
+        self
+    }
+
+    pub(crate) fn display_filter(mut self, filter: &str) -> Self {
+        self.args.extend(["-Y".to_owned(), filter.to_owned()]);
+        self
+    }
+
+    pub(crate) fn decode_as(mut self, spec: &str) -> Self {
+        self.args.extend(["-d".to_owned(), spec.to_owned()]);
+        self
+    }
+
+    pub(crate) fn spawn(&self) -> io::Result<EkProcess> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("BUG: stdout is piped");
+        Ok(EkProcess {
+            child: Arc::new(Mutex::new(child)),
+            stdout: BufReader::new(stdout),
+            line: String::new(),
+            names: Arc::clone(&self.names),
+        })
+    }
+}
+
+/// Running `tshark -T ek` process.
+pub(crate) struct EkProcess {
+    /// Shared with the threads that may kill tshark, see [`EkProcess::child`].
+    child: Arc<Mutex<Child>>,
+    stdout: BufReader<ChildStdout>,
+    /// Buffer for the lines of tshark output.
+    line: String,
+    names: Arc<HashMap<String, &'static str>>,
+}
+
+impl EkProcess {
+    /// Reads the next packet. Returns `None` when tshark has exited.
+    pub(crate) fn read(&mut self) -> io::Result<Option<rtshark::Packet>> {
+        loop {
+            self.line.clear();
+            if self.stdout.read_line(&mut self.line)? == 0 {
+                self.child.lock().expect("BUG: poisoned mutex").wait()?;
+                return Ok(None);
+            }
+            let line = self.line.trim();
+            if line.is_empty() || line.starts_with(r#"{"index""#) {
+                continue;
+            }
+            let doc: Document = serde_json::from_str(line)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            return Ok(Some(to_packet(doc.layers, &self.names)));
+        }
+    }
+
+    /// Returns the tshark process, for killing it from another thread while this
+    /// one is blocked in [`EkProcess::read`].
+    pub(crate) fn child(&self) -> Arc<Mutex<Child>> {
+        Arc::clone(&self.child)
+    }
+
+    pub(crate) fn kill(&mut self) {
+        let mut child = self.child.lock().expect("BUG: poisoned mutex");
+        // tshark may have exited already.
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Packet in ek output. The other members (`timestamp`) are ignored.
+#[derive(Deserialize)]
+struct Document {
+    layers: Members,
+}
+
+/// Members of a JSON object, in the order of appearance.
+///
+/// `serde_json::Map` would sort them, losing the order of the protocols.
+#[derive(Debug, Default)]
+struct Members(Vec<(String, Node)>);
+
+#[derive(Debug)]
+enum Node {
+    Value(String),
+    List(Vec<Node>),
+    Object(Members),
+}
+
+impl<'de> Deserialize<'de> for Members {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Node::deserialize(deserializer)? {
+            Node::Object(members) => Ok(members),
+            _ => Err(de::Error::custom("expected a JSON object")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Node, E> {
+        Ok(Node::Value(v.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Node, E> {
+        Ok(Node::Value(v))
+    }
+
+    /// Booleans are written the way PDML shows them.
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Node, E> {
+        Ok(Node::Value(if v { "1" } else { "0" }.to_owned()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Node, E> {
+        Ok(Node::Value(v.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Node, E> {
+        Ok(Node::Value(v.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Node, E> {
+        Ok(Node::Value(v.to_string()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Node, E> {
+        Ok(Node::List(Vec::new()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Node::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut members = Vec::new();
+        while let Some(member) = map.next_entry()? {
+            members.push(member);
+        }
+        Ok(Node::Object(Members(members)))
+    }
+}
+
+/// Converts the `layers` of an ek document to a packet.
+///
+/// A protocol that occurs several times in a packet (tunnels) is either repeated or
+/// has a list of objects; both become separate layers.
+fn to_packet(layers: Members, names: &HashMap<String, &'static str>) -> rtshark::Packet {
+    let mut packet = rtshark::Packet::new();
+    for (proto, node) in layers.0 {
+        let objects = match node {
+            Node::Object(members) => vec![members],
+            Node::List(items) => items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Node::Object(members) => Some(members),
+                    _ => None,
+                })
+                .collect(),
+            Node::Value(_) => continue,
+        };
+        for members in objects {
+            packet.push(proto.clone());
+            let layer = packet.last_layer_mut().expect("BUG: no layer after push");
+            add_fields(layer, &proto, members, names);
+        }
+    }
+    packet
+}
+
+fn add_fields(
+    layer: &mut rtshark::Layer,
+    proto: &str,
+    members: Members,
+    names: &HashMap<String, &'static str>,
+) {
+    for (key, node) in members.0 {
+        let name = field_name(proto, &key, names);
+        let mut add = |node| match node {
+            Node::Value(value) => {
+                if let Some(name) = name {
+                    layer.add(rtshark::Metadata::new(
+                        name.to_owned(),
+                        value,
+                        String::new(),
+                        0,
+                        0,
+                    ));
+                }
+            }
+            Node::Object(members) => add_fields(layer, proto, members, names),
+            Node::List(_) => {}
+        };
+        match node {
+            Node::List(items) => items.into_iter().for_each(&mut add),
+            node => add(node),
+        }
+    }
+}
+
+/// Returns the name of the field with the given ek key, if it is one of `names`.
+///
+/// The key is the field name with the dots replaced by underscores, prefixed with
+/// the name of the protocol (`tls_tls_handshake_type`) unless tshark has been asked
+/// for specific fields (`-e`).
+fn field_name(
+    proto: &str,
+    key: &str,
+    names: &HashMap<String, &'static str>,
+) -> Option<&'static str> {
+    let unprefixed = key
+        .strip_prefix(proto)
+        .and_then(|key| key.strip_prefix('_'));
+    unprefixed
+        .and_then(|key| names.get(key))
+        .or_else(|| names.get(key))
+        .copied()
+}
+
+#[test]
+fn test_to_packet() {
+    let names = [
+        "frame.time_epoch",
+        "ip.src",
+        "tcp.flags.syn",
+        "tls.handshake.extension.type",
+    ]
+    .map(|field| (field.replace('.', "_"), field))
+    .into_iter()
+    .collect();
+    let line = r#"{"timestamp":"1690000000123","layers":{
+        "frame":{"frame_frame_time_epoch":"1690000000.123456000","frame_frame_len":"583"},
+        "ip":[{"ip_ip_src":"10.0.0.1"},{"ip_ip_src":"192.168.1.2"}],
+        "tcp":{"tcp_tcp_flags_syn":false,"tcp_tcp_srcport":54321},
+        "tls":{"tls_tls_record":{"tls_tls_handshake_extension_type":["0","13"]}}}}"#;
+    let doc: Document = serde_json::from_str(line).unwrap();
+    let packet = to_packet(doc.layers, &names);
+
+    let layers = packet
+        .iter()
+        .map(|layer| {
+            let fields = layer
+                .iter()
+                .map(|md| format!("{}={}", md.name(), md.value()))
+                .collect::<Vec<_>>();
+            format!("{}: {}", layer.name(), fields.join(" "))
+        })
+        .collect::<Vec<_>>();
+    expect_test::expect![[r#"
+        [
+            "frame: frame.time_epoch=1690000000.123456000",
+            "ip: ip.src=10.0.0.1",
+            "ip: ip.src=192.168.1.2",
+            "tcp: tcp.flags.syn=0",
+            "tls: tls.handshake.extension.type=0 tls.handshake.extension.type=13",
+        ]
+    "#]]
+    .assert_debug_eq(&layers);
+}
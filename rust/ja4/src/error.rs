@@ -5,26 +5,94 @@
 // JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
 // For full license text, see the repo root.
 
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use ja4x::x509_parser;
+use serde::Serialize;
+
+use crate::PacketNum;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unable to run 'tshark': {source}")]
     TsharkNotFound { source: io::Error },
+    #[error("invalid tshark path {path:?}: {reason}")]
+    TsharkPath { path: PathBuf, reason: String },
+    #[error("--tshark-arg requires --tshark-output ek")]
+    TsharkArgs,
+    #[error("{0} requires JSON output; use --json or set format = \"json\" in [output] table of the configuration")]
+    RequiresJson(&'static str),
+    #[error("{0} needs tshark and is not supported with --engine native")]
+    NativeEngine(&'static str),
     #[error("failed to parse `tshark --version` output")]
     ParseTsharkVersion,
     #[error("failed to parse tshark version: {0}")]
     ParseTsharkSemver(#[from] semver::Error),
+    #[error(
+        "tshark {available} is not supported, {required} is required (see --tshark-version-check)"
+    )]
+    TsharkUnsupported {
+        available: semver::Version,
+        required: semver::VersionReq,
+    },
+    #[error("tshark produced no output for {0} seconds")]
+    TsharkTimeout(u64),
+    #[error("failed to read tshark output: {0}")]
+    TsharkRead(io::Error),
+    #[error("invalid display filter {filter:?}: {message}")]
+    DisplayFilter { filter: String, message: String },
+    #[error("unable to run 'dumpcap': {source}")]
+    DumpcapNotFound { source: io::Error },
+    #[error("invalid capture filter {filter:?}: {message}")]
+    CaptureFilter { filter: String, message: String },
+    #[error("unable to run 'mergecap': {source}")]
+    MergecapNotFound { source: io::Error },
+    #[error("mergecap failed: {0}")]
+    Mergecap(String),
+    #[error("cannot --merge {path:?}: {reason}")]
+    Merge { path: PathBuf, reason: String },
+    #[error("failed to set Ctrl-C handler: {0}")]
+    CtrlC(#[from] ctrlc::Error),
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error("path contains non-UTF-8 characters: {0:?}")]
     NonUtf8Path(PathBuf),
     #[error("failed to load configuration: {0}")]
     Config(#[from] config::ConfigError),
+    #[error("configuration file {0:?} not found")]
+    ConfigNotFound(PathBuf),
+    #[error("configuration file {0:?} already exists; use --force to overwrite it")]
+    ConfigExists(PathBuf),
+    #[error("profile {name:?} is not defined in the configuration file; available profiles: {available}")]
+    UnknownProfile { name: String, available: String },
+    #[error("invalid tshark_min_version {value:?}: {source}")]
+    TsharkMinVersion {
+        value: String,
+        source: semver::Error,
+    },
+    #[error("invalid value {value:?} of environment variable {name}: expected {expected}")]
+    EnvVar {
+        name: String,
+        value: String,
+        expected: &'static str,
+    },
     #[error("none of fingerprints is enabled; check config.toml and environment")]
     VoidConf,
+    #[error("port {port} is listed both as {first} and as {second} in [ports] table of the configuration")]
+    PortConflict {
+        port: u16,
+        first: &'static str,
+        second: &'static str,
+    },
+    #[error("no fingerprints were produced")]
+    NoFingerprints,
+    #[error("{0} fingerprint(s) don't match their raw form")]
+    Validation(usize),
+    #[error("failed to process {0} input(s)")]
+    FailedInputs(usize),
     #[error("'{name}' is missing")]
     MissingField { name: String },
     #[error("packet timestamp is missing")]
@@ -37,6 +105,134 @@ pub enum Error {
     Json(#[from] serde_json::Error),
     #[error("YAML error: {0}")]
     Yaml(#[from] serde_yaml::Error),
+    #[error("not a TLS Client Hello")]
+    NotClientHello,
+    #[error("truncated TLS Client Hello")]
+    TruncatedClientHello,
     #[error("failed to parse tls.handshake.certificate: {0}")]
     X509(#[from] x509_parser::nom::Err<x509_parser::error::X509Error>),
+    #[error("invalid X.509 certificate: {0}")]
+    Certificate(String),
+    #[error("invalid PEM: {0}")]
+    Pem(String),
+    #[error("insufficient data for JA4L: no {0}")]
+    InsufficientData(&'static str),
+    #[error("JA4L timestamp of the {later} precedes that of the {earlier}")]
+    OutOfOrderTimestamps {
+        earlier: &'static str,
+        later: &'static str,
+    },
+}
+
+impl Error {
+    /// Returns a machine-readable name of the error class, e.g. `"tshark_timeout"`.
+    ///
+    /// The names are stable: scripts may rely on them (see `--errors-json`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::TsharkNotFound { .. } => "tshark_not_found",
+            Self::TsharkPath { .. } => "tshark_path",
+            Self::TsharkArgs => "tshark_args",
+            Self::RequiresJson(_) => "requires_json",
+            Self::NativeEngine(_) => "native_engine",
+            Self::ParseTsharkVersion | Self::ParseTsharkSemver(_) => "tshark_version",
+            Self::TsharkUnsupported { .. } => "tshark_unsupported",
+            Self::TsharkTimeout(_) => "tshark_timeout",
+            Self::TsharkRead(_) => "tshark_read",
+            Self::DisplayFilter { .. } => "display_filter",
+            Self::DumpcapNotFound { .. } => "dumpcap_not_found",
+            Self::CaptureFilter { .. } => "capture_filter",
+            Self::MergecapNotFound { .. } => "mergecap_not_found",
+            Self::Mergecap(_) => "mergecap",
+            Self::Merge { .. } => "merge",
+            Self::CtrlC(_) => "ctrl_c",
+            Self::Io(_) => "io",
+            Self::NonUtf8Path(_) => "non_utf8_path",
+            Self::Config(_)
+            | Self::ConfigNotFound(_)
+            | Self::ConfigExists(_)
+            | Self::UnknownProfile { .. }
+            | Self::EnvVar { .. }
+            | Self::TsharkMinVersion { .. }
+            | Self::VoidConf
+            | Self::PortConflict { .. } => "config",
+            Self::NoFingerprints => "no_fingerprints",
+            Self::Validation(_) => "validation",
+            Self::FailedInputs(_) => "failed_inputs",
+            Self::MissingField { .. } => "missing_field",
+            Self::MissingTimestamp => "missing_timestamp",
+            Self::ParseInt(_) => "parse_int",
+            Self::InvalidHttpRequest { .. } => "invalid_http_request",
+            Self::Json(_) => "json",
+            Self::Yaml(_) => "yaml",
+            Self::NotClientHello => "not_client_hello",
+            Self::TruncatedClientHello => "truncated_client_hello",
+            Self::X509(_) => "x509",
+            Self::Certificate(_) => "certificate",
+            Self::Pem(_) => "pem",
+            Self::InsufficientData(_) => "insufficient_data",
+            Self::OutOfOrderTimestamps { .. } => "out_of_order_timestamps",
+        }
+    }
+
+    /// Returns the exit status of the command line tool for this error: 3 for
+    /// [`Error::TsharkUnsupported`], so that scripts can tell it from other
+    /// failures, 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::TsharkUnsupported { .. } => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// Failure to process a capture file or a packet, printed to stderr as a JSON line
+/// with `--errors-json`.
+#[derive(Debug, Serialize)]
+pub(crate) struct ErrorReport<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packet_num: Option<PacketNum>,
+    error_kind: &'static str,
+    message: String,
+}
+
+impl<'a> ErrorReport<'a> {
+    pub(crate) fn new(
+        error: &Error,
+        file: Option<&'a Path>,
+        packet_num: Option<PacketNum>,
+    ) -> Self {
+        Self {
+            file,
+            packet_num,
+            error_kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn print(&self) {
+        match serde_json::to_string(self) {
+            // A single call, so that reports of concurrent jobs don't interleave.
+            Ok(json) => eprintln!("{json}"),
+            Err(error) => tracing::error!(%error, "failed to serialize error report"),
+        }
+    }
+}
+
+#[test]
+fn test_error_report() {
+    let error = Error::MissingField {
+        name: "tls.handshake.type".to_owned(),
+    };
+    let report = ErrorReport::new(&error, Some(Path::new("in.pcap")), Some(PacketNum(7)));
+    expect_test::expect![[r#"{"file":"in.pcap","packet_num":7,"error_kind":"missing_field","message":"'tls.handshake.type' is missing"}"#]]
+        .assert_eq(&serde_json::to_string(&report).unwrap());
+
+    let report = ErrorReport::new(&Error::TsharkTimeout(30), None, None);
+    expect_test::expect![[
+        r#"{"error_kind":"tshark_timeout","message":"tshark produced no output for 30 seconds"}"#
+    ]]
+    .assert_eq(&serde_json::to_string(&report).unwrap());
 }
@@ -0,0 +1,207 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! tshark fields read by the fingerprinters
+//!
+//! Asking tshark for these fields only (`-e`) spares it generating the rest of the
+//! dissection, which is most of its output. Protocol layers are output regardless,
+//! so detection of protocols by layer name (tunnels, STARTTLS) is not affected.
+//!
+//! When adding a `Proto::first`/`Proto::values` call, add the field here too.
+
+use std::collections::HashSet;
+
+use crate::conf::Conf;
+
+/// Fields that identify streams and their endpoints.
+const STREAM: &[&str] = &[
+    "frame.time_epoch",
+    // `--merge`
+    "frame.number",
+    // `--progress`, `ja4_quality`
+    "frame.cap_len",
+    "frame.len",
+    // `frame_protocols` (`--with-details`)
+    "frame.protocols",
+    "ip.src",
+    "ip.dst",
+    "ipv6.src",
+    "ipv6.dst",
+    "tcp.stream",
+    "tcp.srcport",
+    "tcp.dstport",
+    "tcp.len",
+    "tcp.flags",
+    "tcp.flags.ack",
+    "tcp.flags.fin",
+    "tcp.flags.reset",
+    "tcp.flags.syn",
+    "udp.stream",
+    "udp.srcport",
+    "udp.dstport",
+    // Tunnels (`tunnel.rs`)
+    "http.request.method",
+    "http.request.uri",
+    "http.response.code",
+    "socks.command",
+    "socks.dst",
+    "socks.dstport",
+    "socks.remote_name",
+    "socks.results_v5",
+    "socks.version",
+];
+
+/// JA4, JA4S, JA4X
+const TLS: &[&str] = &[
+    "tls.record.version",
+    "tls.handshake",
+    "tls.handshake.type",
+    "tls.handshake.version",
+    "tls.handshake.ciphersuite",
+    "tls.handshake.extension.type",
+    "tls.handshake.extension.len",
+    // `ech_outer_extensions` of the inner Client Hello of ECH
+    "tls.handshake.extension.data",
+    "tls.handshake.extensions.supported_version",
+    "tls.handshake.extensions_server_name",
+    "tls.handshake.extensions_alpn_str",
+    "tls.handshake.sig_hash_alg_len",
+    "tls.handshake.sig_hash_alg",
+    // JA4X; the DER encoding is parsed here, so no `x509af.*` fields are needed
+    "tls.handshake.certificate",
+    "tls.app_data",
+    "tls.app_data_proto",
+    "quic.version",
+    "quic.frame",
+    "quic.frame_type",
+    "quic.crypto.offset",
+    "quic.crypto.length",
+    "quic.crypto.crypto_data",
+    "gquic.version",
+    // `ja4_quality`
+    "tcp.segment.count",
+    "tls.handshake.fragment.count",
+    "quic.crypto.fragment.count",
+    // DNS over HTTPS detection
+    "http.content_type",
+    "http2.headers.content_type",
+];
+
+/// JA4H
+const HTTP: &[&str] = &[
+    "http.request.method",
+    "http.request.version",
+    "http.request.line",
+    "http.accept_language",
+    "http.cookie",
+    "http2.headers.method",
+    "http2.headers.accept_language",
+    "http2.header.name",
+    "http2.headers.cookie",
+    // TLS over WebSocket
+    "http.upgrade",
+    "http.sec_websocket_protocol",
+];
+
+/// TLS tunneled in WebSocket connections (`--experimental`)
+const WEBSOCKET: &[&str] = &[
+    "http.upgrade",
+    "http.host",
+    "websocket.opcode",
+    "websocket.masking_key",
+    "websocket.masked_payload",
+];
+
+/// Server names of TLS streams (`--resolve-dns`)
+const DNS: &[&str] = &["dns.flags.response", "dns.qry.name", "dns.a", "dns.aaaa"];
+
+/// JA4L, round-trip times
+const TIME: &[&str] = &[
+    "ip.ttl",
+    "ipv6.hlim",
+    "tcp.seq",
+    "tcp.ack",
+    "quic.long.packet_type",
+];
+
+/// JA4SSH
+const SSH: &[&str] = &[
+    "ssh.direction",
+    "ssh.protocol",
+    "ssh.kex.hassh",
+    "ssh.kex.hasshserver",
+    "ssh.encryption_algorithms_client_to_server",
+    "ssh.encryption_algorithms_server_to_client",
+];
+
+/// Returns the fields needed for the enabled fingerprints, without duplicates.
+pub(crate) fn needed(conf: &Conf) -> Vec<&'static str> {
+    let groups = [
+        (true, STREAM),
+        (conf.tls.enabled, TLS),
+        (conf.tls.enabled && conf.tls.experimental, WEBSOCKET),
+        (conf.tls.enabled && conf.tls.resolve_dns, DNS),
+        (conf.http.enabled, HTTP),
+        (conf.time.enabled, TIME),
+        (conf.ssh.enabled, SSH),
+    ];
+    let mut seen = HashSet::new();
+    groups
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .flat_map(|(_, fields)| fields.iter().copied())
+        .filter(|field| seen.insert(*field))
+        .collect()
+}
+
+#[test]
+fn test_needed() {
+    use crate::{
+        conf::{ConfBasic, ConfHttp, ConfSsh, ConfTls},
+        select::{FingerprintKind, Selection},
+    };
+
+    let mut conf = Conf {
+        tshark_path: None,
+        tshark_min_version: Conf::DEFAULT_TSHARK_MIN_VERSION.to_owned(),
+        tshark_version_check: Default::default(),
+        fingerprints: Selection::default(),
+        ports: Default::default(),
+        output: Default::default(),
+        http: ConfHttp {
+            enabled: true,
+            include_headers: Vec::new(),
+            exclude_headers: Vec::new(),
+        },
+        ssh: ConfSsh {
+            enabled: true,
+            sample_size: 200,
+        },
+        time: ConfBasic { enabled: true },
+        tls: ConfTls {
+            enabled: true,
+            doh_servers: Vec::new(),
+            selection: Selection::default(),
+            experimental: false,
+            resolve_dns: false,
+        },
+    };
+    let all = needed(&conf);
+    assert!(all.contains(&"tls.handshake.ciphersuite"));
+    assert!(all.contains(&"ssh.kex.hassh"));
+    assert_eq!(
+        all.iter().filter(|&&f| f == "http.request.method").count(),
+        1
+    );
+
+    conf.restrict(Selection::new(&[FingerprintKind::Ja4h]));
+    let ja4h = needed(&conf);
+    assert!(ja4h.contains(&"http.cookie"));
+    assert!(ja4h.contains(&"tcp.stream"));
+    assert!(!ja4h.contains(&"tls.handshake.ciphersuite"));
+    assert!(!ja4h.contains(&"ip.ttl"));
+}
@@ -0,0 +1,495 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Selection of streams by their endpoints (`--host`, `--net`, `--port`, `--port-range`),
+//! tshark stream indices (`--stream`), time (`--after`, `--before`), or TLS server
+//! name (`--sni`), and of packets by time (`--since`, `--until`)
+
+use std::{fmt, net::IpAddr, ops::RangeInclusive, str::FromStr};
+
+use crate::{
+    stream::{StreamId, Transport},
+    time::{TimeBound, Timestamp},
+};
+
+/// Streams to fingerprint. By default, all streams are selected.
+///
+/// A stream is selected if it matches both the address and the port criteria.
+/// If stream indices are given, the stream must also be one of them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StreamFilter {
+    /// A stream is selected if either of its endpoints belongs to any of these
+    /// networks. Empty means "any address".
+    nets: Vec<Cidr>,
+    /// A stream is selected if either of its endpoints uses a port from any of these
+    /// ranges. Empty means "any port".
+    ports: Vec<RangeInclusive<u32>>,
+    /// tshark stream indices (`tcp.stream`, `udp.stream`). Empty means "any stream".
+    streams: Vec<StreamIndex>,
+    /// A stream is selected if its first packet was captured at or after this time.
+    after: Option<Timestamp>,
+    /// A stream is selected if its first packet was captured before this time.
+    before: Option<Timestamp>,
+    /// Packets captured earlier are ignored.
+    since: Option<TimeBound>,
+    /// Packets captured at or after this time are ignored.
+    until: Option<TimeBound>,
+    /// A TLS stream is selected if its server name matches any of these patterns.
+    /// Empty means "any server name".
+    sni: Vec<SniPattern>,
+}
+
+impl StreamFilter {
+    pub(crate) fn new(
+        hosts: Vec<IpAddr>,
+        nets: Vec<Cidr>,
+        ports: Vec<u16>,
+        port_ranges: Vec<PortRange>,
+    ) -> Self {
+        let hosts = hosts.into_iter().map(Cidr::host);
+        let ports = ports.into_iter().map(|port| PortRange {
+            start: port,
+            end: port,
+        });
+        Self {
+            nets: hosts.chain(nets).collect(),
+            ports: ports
+                .chain(port_ranges)
+                .map(|PortRange { start, end }| u32::from(start)..=u32::from(end))
+                .collect(),
+            streams: Vec::new(),
+            after: None,
+            before: None,
+            since: None,
+            until: None,
+            sni: Vec::new(),
+        }
+    }
+
+    /// Restricts the selection to the given streams.
+    pub(crate) fn with_streams(self, streams: Vec<StreamIndex>) -> Self {
+        Self { streams, ..self }
+    }
+
+    /// Restricts the selection to the streams that start within the time window.
+    pub(crate) fn with_time_window(
+        self,
+        after: Option<Timestamp>,
+        before: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            after,
+            before,
+            ..self
+        }
+    }
+
+    /// Restricts the packets to process to those captured within the time window.
+    pub(crate) fn with_packet_window(
+        self,
+        since: Option<TimeBound>,
+        until: Option<TimeBound>,
+    ) -> Self {
+        Self {
+            since,
+            until,
+            ..self
+        }
+    }
+
+    /// Returns `true` if the packets are restricted by time, see
+    /// [`Self::selects_packet_time`].
+    pub(crate) fn has_packet_window(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    /// Returns `true` if a packet with the given timestamp is within the packet
+    /// time window. Relative bounds count from `first_packet_micros`, the timestamp
+    /// of the capture's first packet.
+    ///
+    /// Unlike [`Self::selects_time`], this applies to every packet: a stream that
+    /// straddles a bound is fingerprinted from its packets inside the window.
+    pub(crate) fn selects_packet_time(&self, micros: i64, first_packet_micros: i64) -> bool {
+        let since = self
+            .since
+            .map_or(i64::MIN, |bound| bound.resolve(first_packet_micros));
+        let until = self
+            .until
+            .map_or(i64::MAX, |bound| bound.resolve(first_packet_micros));
+        (since..until).contains(&micros)
+    }
+
+    /// Restricts the selection of TLS streams to the given server names.
+    pub(crate) fn with_sni(self, sni: Vec<SniPattern>) -> Self {
+        Self { sni, ..self }
+    }
+
+    /// Returns `true` if a stream with the given TLS server name is selected.
+    ///
+    /// The server name is only known once the stream has been processed, so this
+    /// check is applied to the output records. Streams without TLS are selected.
+    pub(crate) fn selects_sni(&self, is_tls: bool, sni: Option<&str>) -> bool {
+        !is_tls || self.sni.is_empty() || self.sni.iter().any(|pat| pat.matches(sni))
+    }
+
+    /// Returns `true` if a stream whose first packet has the given timestamp (in
+    /// microseconds since the Unix epoch) is within the time window.
+    ///
+    /// The remaining packets of the stream are processed even if they are outside
+    /// the window, so that a handshake which straddles the boundary is not cut.
+    pub(crate) fn selects_time(&self, micros: i64) -> bool {
+        let after = self.after.map_or(i64::MIN, Timestamp::micros);
+        let before = self.before.map_or(i64::MAX, Timestamp::micros);
+        (after..before).contains(&micros)
+    }
+
+    /// Returns `true` unless the stream is ruled out by its index.
+    ///
+    /// This check is cheap, so it's done for every packet, before [`Self::matches`].
+    pub(crate) fn selects_stream(&self, transport: Transport, id: StreamId) -> bool {
+        self.streams.is_empty()
+            || self
+                .streams
+                .iter()
+                .any(|sel| sel.transport == transport && sel.id == id)
+    }
+
+    /// Returns `true` if the stream between the given endpoints (address, port)
+    /// is selected.
+    pub(crate) fn matches(&self, src: (&str, u32), dst: (&str, u32)) -> bool {
+        let endpoints = [src, dst];
+        let address_matches = self.nets.is_empty()
+            || endpoints
+                .iter()
+                .filter_map(|(addr, _)| addr.parse::<IpAddr>().ok())
+                .any(|ip| self.nets.iter().any(|net| net.contains(ip)));
+        let port_matches = self.ports.is_empty()
+            || endpoints
+                .iter()
+                .any(|(_, port)| self.ports.iter().any(|range| range.contains(port)));
+        address_matches && port_matches
+    }
+}
+
+/// IPv4 or IPv6 network, e.g. `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    fn host(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            prefix_len: max_prefix_len(addr),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s.split_once('/').ok_or(ParseCidrError)?;
+        let addr = addr.parse::<IpAddr>().map_err(|_| ParseCidrError)?;
+        let prefix_len = prefix_len.parse::<u8>().map_err(|_| ParseCidrError)?;
+        if prefix_len > max_prefix_len(addr) {
+            return Err(ParseCidrError);
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Inclusive range of port numbers, e.g. `8000-8999`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <first port>-<last port>, e.g. 8000-8999";
+
+        let (start, end) = s.split_once('-').ok_or(EXPECTED)?;
+        let start = start.trim().parse::<u16>().map_err(|e| e.to_string())?;
+        let end = end.trim().parse::<u16>().map_err(|e| e.to_string())?;
+        if start > end {
+            return Err(format!("{start} is greater than {end}"));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// tshark stream index prefixed with the transport protocol, e.g. `tcp:42`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StreamIndex {
+    transport: Transport,
+    id: StreamId,
+}
+
+impl FromStr for StreamIndex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected tcp:<index> or udp:<index>, e.g. tcp:42";
+
+        let (transport, id) = s.split_once(':').ok_or(EXPECTED)?;
+        let transport = match transport {
+            "tcp" => Transport::Tcp,
+            "udp" => Transport::Udp,
+            _ => return Err(EXPECTED.to_owned()),
+        };
+        let id = id.parse::<u32>().map_err(|e| e.to_string())?;
+        Ok(Self {
+            transport,
+            id: StreamId(id),
+        })
+    }
+}
+
+/// Pattern for TLS server names (`--sni`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SniPattern {
+    /// `-`: matches streams without a server name.
+    Missing,
+    /// Lowercase glob pattern, where `*` matches any sequence of characters.
+    Glob(String),
+}
+
+impl SniPattern {
+    fn matches(&self, sni: Option<&str>) -> bool {
+        match (self, sni) {
+            (Self::Missing, sni) => sni.is_none(),
+            (Self::Glob(_), None) => false,
+            (Self::Glob(pat), Some(sni)) => glob_match(pat, &sni.to_ascii_lowercase()),
+        }
+    }
+}
+
+impl FromStr for SniPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("expected a server name pattern, e.g. *.example.com".to_owned()),
+            "-" => Ok(Self::Missing),
+            _ => Ok(Self::Glob(s.to_ascii_lowercase())),
+        }
+    }
+}
+
+/// Returns `true` if the text matches the glob pattern, where `*` stands for any
+/// (possibly empty) sequence of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // Position of the latest `*` in the pattern and of the text it's matched at.
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the `*` consume one more character.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[derive(Debug)]
+pub(crate) struct ParseCidrError;
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected <address>/<prefix length>, e.g. 10.0.0.0/8 or 2001:db8::/32")
+    }
+}
+
+impl std::error::Error for ParseCidrError {}
+
+#[test]
+fn test_stream_filter() {
+    let any = StreamFilter::default();
+    assert!(any.matches(("192.0.2.1", 50000), ("198.51.100.1", 443)));
+
+    let filter = StreamFilter::new(
+        vec!["203.0.113.7".parse().unwrap()],
+        vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ],
+        vec![],
+        vec![],
+    );
+    assert!(filter.matches(("10.1.2.3", 50000), ("198.51.100.1", 443)));
+    assert!(filter.matches(("198.51.100.1", 443), ("10.1.2.3", 50000)));
+    assert!(filter.matches(("203.0.113.7", 50000), ("198.51.100.1", 443)));
+    assert!(!filter.matches(("203.0.113.8", 50000), ("198.51.100.1", 443)));
+    assert!(filter.matches(("2001:db8:1::1", 50000), ("2606:4700::6811:d209", 443)));
+    assert!(!filter.matches(("2001:db9::1", 50000), ("2606:4700::6811:d209", 443)));
+
+    let all = StreamFilter::new(vec![], vec!["0.0.0.0/0".parse().unwrap()], vec![], vec![]);
+    assert!(all.matches(("192.0.2.1", 50000), ("198.51.100.1", 443)));
+    assert!(!all.matches(("2001:db8::1", 50000), ("2001:db8::2", 443)));
+}
+
+#[test]
+fn test_stream_filter_ports() {
+    let filter = StreamFilter::new(
+        vec![],
+        vec![],
+        vec![443, 8443],
+        vec!["2222-2223".parse().unwrap()],
+    );
+    assert!(filter.matches(("192.0.2.1", 50000), ("198.51.100.1", 443)));
+    assert!(filter.matches(("198.51.100.1", 8443), ("192.0.2.1", 50000)));
+    assert!(filter.matches(("192.0.2.1", 50000), ("198.51.100.1", 2223)));
+    assert!(!filter.matches(("192.0.2.1", 50000), ("198.51.100.1", 2224)));
+
+    // Address and port criteria are combined as an intersection.
+    let filter = StreamFilter::new(
+        vec![],
+        vec!["10.0.0.0/8".parse().unwrap()],
+        vec![443],
+        vec![],
+    );
+    assert!(filter.matches(("10.0.0.1", 50000), ("198.51.100.1", 443)));
+    assert!(!filter.matches(("10.0.0.1", 50000), ("198.51.100.1", 80)));
+    assert!(!filter.matches(("192.0.2.1", 50000), ("198.51.100.1", 443)));
+}
+
+#[test]
+fn test_stream_filter_streams() {
+    let filter = StreamFilter::default()
+        .with_streams(vec!["tcp:42".parse().unwrap(), "udp:7".parse().unwrap()]);
+    assert!(filter.selects_stream(Transport::Tcp, StreamId(42)));
+    assert!(filter.selects_stream(Transport::Udp, StreamId(7)));
+    assert!(!filter.selects_stream(Transport::Udp, StreamId(42)));
+    assert!(!filter.selects_stream(Transport::Tcp, StreamId(0)));
+    assert!(StreamFilter::default().selects_stream(Transport::Tcp, StreamId(0)));
+
+    assert!("tcp".parse::<StreamIndex>().is_err());
+    assert!("sctp:1".parse::<StreamIndex>().is_err());
+    assert!("udp:-1".parse::<StreamIndex>().is_err());
+}
+
+#[test]
+fn test_stream_filter_time() {
+    let filter = StreamFilter::default().with_time_window(
+        Some("2023-11-14T22:13:20Z".parse().unwrap()),
+        Some("2023-11-14T23:14:20+01:00".parse().unwrap()),
+    );
+    assert!(!filter.selects_time(1_699_999_999_999_999));
+    assert!(filter.selects_time(1_700_000_000_000_000));
+    assert!(filter.selects_time(1_700_000_059_999_999));
+    assert!(!filter.selects_time(1_700_000_060_000_000));
+
+    let filter =
+        StreamFilter::default().with_time_window(None, Some("1700000000".parse().unwrap()));
+    assert!(filter.selects_time(0));
+    assert!(!filter.selects_time(1_700_000_000_000_000));
+    assert!(StreamFilter::default().selects_time(i64::MIN));
+}
+
+#[test]
+fn test_stream_filter_packet_time() {
+    let filter = StreamFilter::default().with_packet_window(
+        Some("+10s".parse().unwrap()),
+        Some("1700000060".parse().unwrap()),
+    );
+    assert!(filter.has_packet_window());
+    let start = 1_700_000_000_000_000;
+    assert!(!filter.selects_packet_time(start, start));
+    assert!(!filter.selects_packet_time(start + 9_999_999, start));
+    assert!(filter.selects_packet_time(start + 10_000_000, start));
+    assert!(filter.selects_packet_time(start + 59_999_999, start));
+    assert!(!filter.selects_packet_time(start + 60_000_000, start));
+
+    assert!(!StreamFilter::default().has_packet_window());
+    assert!(StreamFilter::default().selects_packet_time(i64::MIN, 0));
+}
+
+#[test]
+fn test_parse_port_range() {
+    assert_eq!(
+        "8000-8999".parse::<PortRange>(),
+        Ok(PortRange {
+            start: 8000,
+            end: 8999
+        })
+    );
+    assert!("8000".parse::<PortRange>().is_err());
+    assert!("8999-8000".parse::<PortRange>().is_err());
+    assert!("8000-65536".parse::<PortRange>().is_err());
+}
+
+#[test]
+fn test_parse_cidr() {
+    assert!("10.0.0.0/8".parse::<Cidr>().is_ok());
+    assert!("::/0".parse::<Cidr>().is_ok());
+    assert!("10.0.0.0".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("2001:db8::/129".parse::<Cidr>().is_err());
+    assert!("example.com/8".parse::<Cidr>().is_err());
+}
+
+#[test]
+fn test_sni_pattern() {
+    let pat = |s: &str| s.parse::<SniPattern>().unwrap();
+
+    assert!(pat("*.example.com").matches(Some("www.Example.COM")));
+    assert!(!pat("*.example.com").matches(Some("example.com")));
+    assert!(!pat("*.example.com").matches(None));
+    assert!(pat("api*.example.*").matches(Some("api-v2.example.org")));
+    assert!(pat("*").matches(Some("")));
+    assert!(!pat("example.com").matches(Some("example.com.evil")));
+    assert!(pat("-").matches(None));
+    assert!(!pat("-").matches(Some("example.com")));
+    assert!("".parse::<SniPattern>().is_err());
+
+    let filter = StreamFilter::default().with_sni(vec![pat("*.example.com")]);
+    assert!(filter.selects_sni(false, None));
+    assert!(!filter.selects_sni(true, None));
+    assert!(filter.selects_sni(true, Some("a.example.com")));
+}
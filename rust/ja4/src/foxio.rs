@@ -0,0 +1,173 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Field names of the reference implementation (`--foxio-names`)
+//!
+//! The Python scripts by FoxIO name the fingerprints `JA4`, `JA4S`, `JA4L-C`,
+//! `JA4SSH.1`, ..., see `python/README.md`. Tools built for their output can read
+//! ours if the records are serialized with these names.
+
+use serde::{ser::Error as _, Serialize, Serializer};
+use serde_yaml::{Mapping, Value};
+
+use crate::OutputRecord;
+
+/// Top-level keys of the output record and their names in the reference output.
+const RENAMES: [(&str, &str); 11] = [
+    ("src_port", "srcport"),
+    ("dst_port", "dstport"),
+    ("tls_server_name", "domain"),
+    ("ja4", "JA4"),
+    ("ja4_r", "JA4_r"),
+    ("ja4_o", "JA4_o"),
+    ("ja4_ro", "JA4_ro"),
+    ("ja4s", "JA4S"),
+    ("ja4s_r", "JA4S_r"),
+    ("ja4l_c", "JA4L-C"),
+    ("ja4l_s", "JA4L-S"),
+];
+
+/// Keys of an HTTP request and their names in the reference output.
+const HTTP_RENAMES: [(&str, &str); 3] = [
+    ("ja4h", "JA4H"),
+    ("ja4h_r", "JA4H_r"),
+    ("ja4h_ro", "JA4H_ro"),
+];
+
+/// Output record serialized with the field names of the reference implementation.
+pub(crate) struct FoxioNames(pub(crate) OutputRecord);
+
+impl Serialize for FoxioNames {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serde_yaml::to_value(&self.0).map_err(S::Error::custom)? {
+            Value::Mapping(rec) => rename(rec).serialize(serializer),
+            _ => unreachable!("BUG: record is not a map"),
+        }
+    }
+}
+
+/// Renames the keys of a serialized record.
+///
+/// Lists of fingerprints become numbered keys, as in the reference output:
+/// `ja4ssh` turns into `JA4SSH.1`, `JA4SSH.2`, ...; the certificates of
+/// `tls_certs` get `JA4X.1`, `JA4X.2`, .... The reference output has a single
+/// `JA4H`, so the fingerprints of the first HTTP request are added at the top
+/// level; `http` and `tls_certs` are kept for the other details.
+fn rename(rec: Mapping) -> Mapping {
+    let mut out = Mapping::with_capacity(rec.len());
+    for (key, value) in rec {
+        let Some(name) = key.as_str() else {
+            out.insert(key, value);
+            continue;
+        };
+        match name {
+            "ja4ssh" => {
+                let fps = value.as_sequence().into_iter().flatten();
+                insert_numbered(&mut out, "JA4SSH", fps.cloned());
+            }
+            "http" => {
+                let first = value.as_sequence().and_then(|reqs| reqs.first());
+                for (from, to) in HTTP_RENAMES {
+                    if let Some(fp) = first.and_then(|req| req.get(from)) {
+                        out.insert(to.into(), fp.clone());
+                    }
+                }
+                out.insert(key, value);
+            }
+            "tls_certs" => {
+                let fps = value
+                    .as_sequence()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|certs| certs.get("x509")?.as_sequence())
+                    .flatten()
+                    .filter_map(|x509| x509.get("ja4x"));
+                insert_numbered(&mut out, "JA4X", fps.cloned());
+                out.insert(key, value);
+            }
+            _ => {
+                let name = RENAMES
+                    .iter()
+                    .find(|&&(from, _)| from == name)
+                    .map_or(name, |&(_, to)| to);
+                out.insert(name.into(), value);
+            }
+        }
+    }
+    out
+}
+
+fn insert_numbered(out: &mut Mapping, name: &str, values: impl Iterator<Item = Value>) {
+    for (i, value) in values.enumerate() {
+        out.insert(format!("{name}.{}", i + 1).into(), value);
+    }
+}
+
+#[test]
+fn test_rename() {
+    let rec: Mapping = serde_yaml::from_str(
+        "
+        stream: 4
+        transport: tcp
+        src: 192.168.1.169
+        dst: 44.212.59.210
+        src_port: 64339
+        dst_port: 443
+        tls_server_name: example.com
+        ja4: t13d1516h2_8daaf6152771_e5627efa2ab1
+        ja4_r: t13d1516h2_002f,0035_0005,000a_0403,0804
+        ja4s: t130200_1301_234ea6891581
+        tls_certs:
+        - x509:
+          - ja4x: a373a9f83c6b_2bab15409345_7bf9a7bf7029
+          - ja4x: 2bab15409345_2bab15409345_7bf9a7bf7029
+        ja4l_c: 20_128
+        ja4l_s: 2925_115
+        http:
+        - ja4h: ge11nn07enus_bc8d2ed93139_000000000000_000000000000
+        - ja4h: ge11cn08enus_bc8d2ed93139_f1d5ab92d2ad_9c7a5bd98a2f
+        ja4ssh:
+        - c36s36_c38s93_c60s8
+        - c36s36_c40s95_c62s3
+        ja4ssh_c:
+        - c36_c38_c60
+        - c36_c40_c62
+        ja4plus: 4133fa3eadd7
+        ",
+    )
+    .unwrap();
+    let keys = rename(rec)
+        .into_iter()
+        .map(|(key, _)| key.as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    expect_test::expect![[r#"
+        [
+            "stream",
+            "transport",
+            "src",
+            "dst",
+            "srcport",
+            "dstport",
+            "domain",
+            "JA4",
+            "JA4_r",
+            "JA4S",
+            "JA4X.1",
+            "JA4X.2",
+            "tls_certs",
+            "JA4L-C",
+            "JA4L-S",
+            "JA4H",
+            "http",
+            "JA4SSH.1",
+            "JA4SSH.2",
+            "ja4ssh_c",
+            "ja4plus",
+        ]
+    "#]]
+    .assert_debug_eq(&keys);
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Fingerprints as they are computed, see [`process`]
+
+use std::path::Path;
+
+use crate::{
+    record::Transport, records::Running, ssh::Ja4SshFingerprint, OutputRecord, ReaderOptions,
+    Result,
+};
+
+/// Receives the fingerprints of a capture file as soon as they are computed, see
+/// [`process`].
+///
+/// All methods do nothing by default. The fingerprints are those of the output
+/// records: `ja4_o` rather than `ja4` with [`crate::FormatFlags::original_order`],
+/// and so on. Fingerprints of a TLS session tunneled inside another one are only
+/// reported in the record of the stream.
+pub trait Ja4Handler {
+    /// JA4 of the stream's TLS Client Hello.
+    fn on_tls_client(&mut self, _ctx: &EventContext, _ja4: &str) {}
+
+    /// JA4S of the stream's TLS Server Hello.
+    fn on_tls_server(&mut self, _ctx: &EventContext, _ja4s: &str) {}
+
+    /// JA4H of an HTTP request; `index` counts the requests of the stream from 0.
+    fn on_http_request(&mut self, _ctx: &EventContext, _index: usize, _ja4h: &str) {}
+
+    /// JA4SSH of a complete sample of SSH packets; the fingerprint of the last,
+    /// partial sample only comes with the record.
+    fn on_ssh_window(&mut self, _ctx: &EventContext, _ja4ssh: &Ja4SshFingerprint) {}
+
+    /// The record of a finalized stream, as `ja4` would output it. TCP streams are
+    /// finalized when the connection is closed, the rest at the end of the file.
+    fn on_stream_done(&mut self, _rec: OutputRecord) {}
+}
+
+/// Stream and packet that an event comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EventContext {
+    /// Index of the stream, as in the output records.
+    pub stream: u32,
+    pub transport: Transport,
+    /// Number of the packet that completed the fingerprint, counting from 1.
+    pub packet: usize,
+}
+
+/// Reads the capture file, passing the fingerprints to `handler`.
+///
+/// The events of a stream come in the order of the packets, and before its
+/// [`Ja4Handler::on_stream_done`]. Unlike with [`crate::Reader`], processing stops
+/// at the first error.
+///
+/// # Errors
+///
+/// Fails if the capture file cannot be read, see [`crate::Reader::open`].
+pub fn process(
+    pcap: impl AsRef<Path>,
+    options: ReaderOptions,
+    handler: &mut impl Ja4Handler,
+) -> Result<()> {
+    let original_order = options.flags.original_order;
+    let mut running = Running::new(pcap.as_ref(), options)?.with_events(original_order);
+    loop {
+        let rec = running.next().transpose()?;
+        for (ctx, event) in running.take_events() {
+            match event {
+                Event::TlsClient(ja4) => handler.on_tls_client(&ctx, &ja4),
+                Event::TlsServer(ja4s) => handler.on_tls_server(&ctx, &ja4s),
+                Event::HttpRequest { index, ja4h } => handler.on_http_request(&ctx, index, &ja4h),
+                Event::SshWindow(fp) => handler.on_ssh_window(&ctx, &fp),
+            }
+        }
+        match rec {
+            Some(rec) => handler.on_stream_done(rec),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Fingerprint computed for a packet.
+#[derive(Debug)]
+pub(crate) enum Event {
+    TlsClient(String),
+    TlsServer(String),
+    HttpRequest { index: usize, ja4h: String },
+    SshWindow(Ja4SshFingerprint),
+}
+
+/// Events that haven't been passed to the handler yet.
+#[derive(Debug, Default)]
+pub(crate) struct Events {
+    pub(crate) original_order: bool,
+    pub(crate) queue: Vec<(EventContext, Event)>,
+}
+
+#[test]
+fn test_handler() {
+    use crate::{conf::Conf, stream::Streams, FormatFlags, Packet};
+    use rtshark::Metadata;
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Ja4Handler for Log {
+        fn on_http_request(&mut self, ctx: &EventContext, index: usize, ja4h: &str) {
+            self.0.push(format!("{} {index} {ja4h}", ctx.packet));
+        }
+        fn on_stream_done(&mut self, rec: OutputRecord) {
+            self.0.push(format!("done {}", rec.fingerprints().count()));
+        }
+    }
+
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default().with_events(false);
+    let mut log = Log::default();
+    for num in 1..=2 {
+        let mut packet = rtshark::Packet::new();
+        for (name, fields) in [
+            ("frame", &[("frame.time_epoch", "1700000000.0")][..]),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            (
+                "tcp",
+                &[
+                    ("tcp.stream", "7"),
+                    ("tcp.srcport", "50000"),
+                    ("tcp.dstport", "80"),
+                ],
+            ),
+            (
+                "http",
+                &[
+                    ("http.request.method", "GET"),
+                    ("http.request.version", "HTTP/1.1"),
+                    ("http.request.line", "Host: example.com\r\n"),
+                ],
+            ),
+        ] {
+            packet.push(name.to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            for &(name, value) in fields {
+                layer.add(Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+        }
+        streams
+            .update(&Packet::new(&packet, num), &conf, false)
+            .unwrap();
+    }
+    for (ctx, event) in streams.take_events() {
+        assert_eq!((ctx.stream, ctx.transport), (7, Transport::Tcp));
+        let Event::HttpRequest { index, ja4h } = event else {
+            panic!("{event:?}");
+        };
+        log.on_http_request(&ctx, index, &ja4h);
+    }
+    for rec in streams.into_out(FormatFlags::default()) {
+        log.on_stream_done(rec);
+    }
+    expect_test::expect![[r#"
+        [
+            "1 0 ge11nn010000_4a823118b9ba_000000000000_000000000000",
+            "2 1 ge11nn010000_4a823118b9ba_000000000000_000000000000",
+            "done 3",
+        ]
+    "#]]
+    .assert_debug_eq(&log.0);
+}
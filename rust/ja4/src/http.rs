@@ -12,21 +12,41 @@ use std::fmt;
 use itertools::Itertools as _;
 use serde::Serialize;
 
-use crate::{Error, FormatFlags, Packet, PacketNum, Proto, Result};
+use crate::{conf::ConfHttp, tls::Quality, Error, FormatFlags, Packet, PacketNum, Proto, Result};
 
 #[derive(Debug, Default)]
 pub(crate) struct Stream(Vec<HttpStats>);
 
 impl Stream {
-    pub(crate) fn update(&mut self, pkt: &Packet, store_pkt_num: bool) -> Result<()> {
+    pub(crate) fn update(
+        &mut self,
+        pkt: &Packet,
+        conf: &ConfHttp,
+        store_pkt_num: bool,
+    ) -> Result<()> {
+        if let Some(websocket) = self.websocket_mut() {
+            // The connection has been switched to the WebSocket protocol.
+            // Whatever follows is not HTTP.
+            websocket.count(pkt);
+            return Ok(());
+        }
         let stats = if let Some(http) = pkt.find_proto("http") {
+            if self.switch_to_websocket(&http) {
+                return Ok(());
+            }
             HttpStats::from_http1(&http, store_pkt_num)?
         } else if let Some(http2) = pkt.find_proto("http2") {
             HttpStats::from_http2(&http2, store_pkt_num)?
         } else {
             None
         };
-        self.0.extend(stats);
+        // HTTP/3 and requests sent over TLS can only be seen decrypted.
+        let decrypted = pkt.find_proto("tls").is_some() || pkt.find_proto("quic").is_some();
+        self.0.extend(stats.map(|mut stats| {
+            stats.headers.retain(|name| conf.feeds_ja4h(name));
+            stats.quality = Quality::of_packet(pkt, decrypted);
+            stats
+        }));
         Ok(())
     }
 
@@ -34,17 +54,78 @@ impl Stream {
         if self.0.is_empty() {
             None
         } else {
+            // Requests are added as the packets are processed.
+            debug_assert!(self.0.windows(2).all(|w| w[0].packet <= w[1].packet));
             let http = self.0.into_iter().map(|s| s.into_out(flags)).collect();
             Some(OutStream { http })
         }
     }
+
+    /// Returns the number of HTTP requests so far.
+    pub(crate) fn nr_requests(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns JA4H (`ja4h_o` with `original_order`) of the request with the given
+    /// index.
+    pub(crate) fn ja4h(&self, index: usize, original_order: bool) -> Option<String> {
+        Some(self.0.get(index)?.fingerprint(original_order).ja4h)
+    }
+
+    /// Returns `true` if a JA4H fingerprint has been computed.
+    pub(crate) fn has_fingerprints(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn websocket_mut(&mut self) -> Option<&mut WebSocket> {
+        self.0.last_mut()?.websocket.as_mut()
+    }
+
+    /// Handles the server's response to a WebSocket upgrade request.
+    ///
+    /// Returns `true` if `http` is a "101 Switching Protocols" response to the latest
+    /// request and that request asked to upgrade the connection to WebSocket.
+    ///
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc6455#section-4.2.2>
+    fn switch_to_websocket(&mut self, http: &Proto) -> bool {
+        const SWITCHING_PROTOCOLS: &str = "101";
+
+        if !matches!(http.first("http.response.code"), Ok(SWITCHING_PROTOCOLS)) {
+            return false;
+        }
+        let Some(stats) = self.0.last_mut() else {
+            return false;
+        };
+        if !stats.upgrade_websocket {
+            return false;
+        }
+        stats.websocket = Some(WebSocket {
+            protocol: http
+                .first("http.sec_websocket_protocol")
+                .ok()
+                .map(str::to_owned),
+            packets: 0,
+            bytes: 0,
+        });
+        true
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub(crate) struct OutStream {
+    /// Requests in the order of their packets.
     http: Vec<OutHttp>,
 }
 
+impl OutStream {
+    /// Returns JA4H fingerprints of the requests, in the order of appearance.
+    pub(crate) fn ja4h(&self) -> impl Iterator<Item = &str> {
+        self.http.iter().map(|http| match &http.ja4h {
+            OutJa4h::Sorted(s) | OutJa4h::Unsorted(s) => s.as_str(),
+        })
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(test, derive(Clone))]
 struct HttpStats {
@@ -58,6 +139,39 @@ struct HttpStats {
     headers: Vec<String>,
     // Reference: https://datatracker.ietf.org/doc/html/rfc6265#section-4.2.1
     cookie_pairs: Vec<(String, Option<String>)>,
+    /// Whether the request asked to upgrade the connection to WebSocket.
+    upgrade_websocket: bool,
+    /// Set if the server has accepted the WebSocket upgrade.
+    websocket: Option<WebSocket>,
+    quality: Quality,
+}
+
+/// WebSocket connection established by an HTTP/1.1 Upgrade request.
+#[derive(Debug)]
+#[cfg_attr(test, derive(Clone))]
+struct WebSocket {
+    /// The subprotocol selected by the server (`Sec-WebSocket-Protocol` header
+    /// of the "101 Switching Protocols" response).
+    protocol: Option<String>,
+    /// Number of TCP segments with payload after the upgrade, in both directions.
+    packets: u64,
+    /// Total TCP payload length of these segments.
+    bytes: u64,
+}
+
+impl WebSocket {
+    fn count(&mut self, pkt: &Packet) {
+        let Some(len) = pkt
+            .find_proto("tcp")
+            .and_then(|tcp| tcp.first("tcp.len").ok()?.parse::<u64>().ok())
+        else {
+            return;
+        };
+        if len > 0 {
+            self.packets += 1;
+            self.bytes += len;
+        }
+    }
 }
 
 impl HttpStats {
@@ -97,6 +211,9 @@ impl HttpStats {
             Err(_) => Vec::new(),
             Ok(s) => cookie_pairs(s.split("; ")).collect(),
         };
+        let upgrade_websocket = http
+            .first("http.upgrade")
+            .is_ok_and(|s| s.eq_ignore_ascii_case("websocket"));
 
         Ok(Some(Self {
             packet: store_pkt_num.then_some(http.packet_num),
@@ -107,6 +224,9 @@ impl HttpStats {
             language,
             headers,
             cookie_pairs,
+            upgrade_websocket,
+            websocket: None,
+            quality: Quality::Full,
         }))
     }
 
@@ -156,69 +276,197 @@ impl HttpStats {
             language,
             headers,
             cookie_pairs,
+            // HTTP/2 has no Upgrade mechanism; WebSockets are bootstrapped with
+            // an extended CONNECT instead (RFC 8441).
+            upgrade_websocket: false,
+            websocket: None,
+            quality: Quality::Full,
         }))
     }
 
-    fn into_out(self, flags: FormatFlags) -> OutHttp {
+    /// Builds the request from the header fields that [`ja4h`] receives.
+    fn from_headers(
+        req_method: HttpRequestMethod,
+        version: HttpVersion,
+        headers: &[(&str, &str)],
+    ) -> Self {
+        let mut has_cookie_header = false;
+        let mut has_referer_header = false;
+        let mut language = None;
+        let mut cookie_values = Vec::new();
+        let mut names = Vec::new();
+        for &(name, value) in headers {
+            if name.eq_ignore_ascii_case("cookie") {
+                has_cookie_header = true;
+                cookie_values.extend(value.split("; "));
+            } else if name.eq_ignore_ascii_case("referer") {
+                has_referer_header = true;
+            } else {
+                if name.eq_ignore_ascii_case("accept-language") && language.is_none() {
+                    language = Some(value.to_owned());
+                }
+                names.push(name.to_owned());
+            }
+        }
+        Self {
+            packet: None,
+            req_method,
+            version,
+            has_cookie_header,
+            has_referer_header,
+            language,
+            headers: names,
+            cookie_pairs: cookie_pairs(cookie_values)
+                .filter(|(name, _)| !name.is_empty())
+                .collect(),
+            upgrade_websocket: false,
+            websocket: None,
+            quality: Quality::Full,
+        }
+    }
+
+    /// Computes JA4H. The cookies are sorted unless `original_order` is set.
+    fn fingerprint(&self, original_order: bool) -> Ja4hFingerprint {
         let Self {
-            packet,
+            packet: _,
             req_method,
             version,
             has_cookie_header,
             has_referer_header,
             language,
             headers,
-            mut cookie_pairs,
+            cookie_pairs,
+            upgrade_websocket: _,
+            websocket: _,
+            quality: _,
         } = self;
-        let FormatFlags {
-            with_raw,
-            original_order,
-        } = flags;
 
-        let cookie_marker = if has_cookie_header { 'c' } else { 'n' };
-        let referer_marker = if has_referer_header { 'r' } else { 'n' };
+        let cookie_marker = if *has_cookie_header { 'c' } else { 'n' };
+        let referer_marker = if *has_referer_header { 'r' } else { 'n' };
         let nr_headers = 99.min(headers.len());
-        let lang = truncate_to(4, primary_language(language.unwrap_or_default()));
+        let lang = truncate_to(4, primary_language(language.as_deref().unwrap_or_default()));
 
         let first_chunk =
             format!("{req_method}{version}{cookie_marker}{referer_marker}{nr_headers:02}{lang}");
 
+        let mut cookie_pairs = cookie_pairs.clone();
         if !original_order {
             cookie_pairs.sort_unstable();
         }
         let cookie_names = joined_cookie_names(&cookie_pairs);
-        let cookies = joined_cookie_pairs(cookie_pairs);
-        let headers = headers.into_iter().join(",");
+        let cookie_list = cookie_pairs.iter().map(cookie_pair).collect::<Vec<_>>();
+        let cookies = cookie_list.join(",");
+        let joined_headers = headers.iter().join(",");
+
+        let ja4h_b = crate::hash12(&joined_headers);
+        let ja4h_c = crate::hash12(&cookie_names);
+        let ja4h_d = crate::hash12(&cookies);
+        Ja4hFingerprint {
+            ja4h: format!("{first_chunk}_{ja4h_b}_{ja4h_c}_{ja4h_d}"),
+            ja4h_r: format!("{first_chunk}_{joined_headers}_{cookie_names}_{cookies}"),
+            ja4h_a: first_chunk,
+            ja4h_b,
+            ja4h_c,
+            ja4h_d,
+            header_names: headers.clone(),
+            cookie_names: cookie_pairs.iter().map(|(name, _)| name.clone()).collect(),
+            cookies: cookie_list,
+        }
+    }
 
-        let ja4h_r = with_raw.then(|| {
-            let s = format!("{first_chunk}_{headers}_{cookie_names}_{cookies}");
-            if original_order {
-                Ja4hRawFingerprint::Unsorted(s)
-            } else {
-                Ja4hRawFingerprint::Sorted(s)
-            }
-        });
+    fn into_out(self, flags: FormatFlags) -> OutHttp {
+        let FormatFlags {
+            with_raw,
+            original_order,
+            with_details,
+        } = flags;
 
-        let headers = crate::hash12(headers);
-        let cookie_names = crate::hash12(cookie_names);
-        let cookies = crate::hash12(cookies);
-        let ja4h = {
-            let s = format!("{first_chunk}_{headers}_{cookie_names}_{cookies}");
-            if original_order {
-                Ja4hFingerprint::Unsorted(s)
-            } else {
-                Ja4hFingerprint::Sorted(s)
-            }
+        let fp = self.fingerprint(original_order);
+        let ja4h_r = with_raw.then_some(if original_order {
+            OutJa4hRaw::Unsorted(fp.ja4h_r)
+        } else {
+            OutJa4hRaw::Sorted(fp.ja4h_r)
+        });
+        let ja4h = if original_order {
+            OutJa4h::Unsorted(fp.ja4h)
+        } else {
+            OutJa4h::Sorted(fp.ja4h)
         };
+        let details = with_details.then_some(HttpDetails {
+            ja4h_b: fp.ja4h_b,
+            ja4h_c: fp.ja4h_c,
+            ja4h_d: fp.ja4h_d,
+            cookie_names: fp.cookie_names,
+            ja4h_quality: self.quality,
+        });
 
+        let Self {
+            packet, websocket, ..
+        } = self;
         OutHttp {
             pkt_ja4h: packet,
             ja4h,
             ja4h_r,
+            details,
+            websocket: websocket.is_some(),
+            websocket_packets: websocket.as_ref().map(|ws| ws.packets),
+            websocket_bytes: websocket.as_ref().map(|ws| ws.bytes),
+            websocket_protocol: websocket.and_then(|ws| ws.protocol),
         }
     }
 }
 
+/// JA4H fingerprint of an HTTP request, see [`ja4h`].
+///
+/// The fields are named as in the output records of `ja4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4hFingerprint {
+    /// The fingerprint: `{ja4h_a}_{ja4h_b}_{ja4h_c}_{ja4h_d}`.
+    pub ja4h: String,
+    /// `ja4h` before hashing: header names, cookie names and cookies are listed.
+    pub ja4h_r: String,
+    /// Method, version, cookie and referer markers, number of headers, language.
+    pub ja4h_a: String,
+    /// Hash of the header names.
+    pub ja4h_b: String,
+    /// Hash of the cookie names.
+    pub ja4h_c: String,
+    /// Hash of the cookies, names and values.
+    pub ja4h_d: String,
+    /// Names of the headers other than `Cookie` and `Referer`, in the request order.
+    pub header_names: Vec<String>,
+    /// Names of the cookies, sorted unless the original order was asked for.
+    pub cookie_names: Vec<String>,
+    /// The cookies (`name=value`), in the order of `cookie_names`.
+    pub cookies: Vec<String>,
+}
+
+/// Computes JA4H of an HTTP request.
+///
+/// `method` and `version` are given as in the request line (`GET`, `HTTP/1.1`;
+/// `HTTP/2` and `HTTP/3` for the later versions). `headers` are the name-value
+/// pairs of the request, in order; header names are compared case-insensitively.
+/// `Cookie` and `Referer` headers are not counted among the headers, the cookies
+/// are taken from `Cookie` headers and the language from the first
+/// `Accept-Language` header.
+///
+/// The cookies are sorted unless `original_order` is set, as with
+/// `--original-order`. The result is then the `ja4h_o` fingerprint of the output
+/// records.
+///
+/// # Errors
+///
+/// [`Error::InvalidHttpRequest`] if the method or the version is unknown.
+pub fn ja4h(
+    method: &str,
+    version: &str,
+    headers: &[(&str, &str)],
+    original_order: bool,
+) -> Result<Ja4hFingerprint> {
+    let stats = HttpStats::from_headers(method.parse()?, version.parse()?, headers);
+    Ok(stats.fingerprint(original_order))
+}
+
 fn cookie_pairs<'a, I>(cookies: I) -> impl Iterator<Item = (String, Option<String>)> + 'a
 where
     I: IntoIterator<Item = &'a str> + 'a,
@@ -231,6 +479,14 @@ where
         })
 }
 
+fn cookie_pair((name, value): &(String, Option<String>)) -> String {
+    assert!(!name.is_empty());
+    match value {
+        None => name.to_owned(),
+        Some(value) => format!("{name}={value}"),
+    }
+}
+
 fn joined_cookie_names<'a, I>(cookie_pairs: I) -> String
 where
     I: IntoIterator<Item = &'a (String, Option<String>)>,
@@ -244,34 +500,48 @@ where
         .join(",")
 }
 
-fn joined_cookie_pairs<I>(cookie_pairs: I) -> String
-where
-    I: IntoIterator<Item = (String, Option<String>)>,
-{
-    cookie_pairs
-        .into_iter()
-        .map(|(name, value)| {
-            assert!(!name.is_empty());
-            match value {
-                None => name.to_owned(),
-                Some(value) => format!("{name}={value}"),
-            }
-        })
-        .join(",")
-}
-
 #[derive(Debug, Serialize)]
 pub(crate) struct OutHttp {
     #[serde(skip_serializing_if = "Option::is_none")]
     pkt_ja4h: Option<PacketNum>,
     #[serde(flatten)]
-    ja4h: Ja4hFingerprint,
+    ja4h: OutJa4h,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
-    ja4h_r: Option<Ja4hRawFingerprint>,
+    ja4h_r: Option<OutJa4hRaw>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    details: Option<HttpDetails>,
+    /// Whether the server switched the connection to the WebSocket protocol
+    /// in response to this request.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    websocket: bool,
+    /// The WebSocket subprotocol selected by the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    websocket_protocol: Option<String>,
+    /// TCP segments with payload that followed the upgrade.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    websocket_packets: Option<u64>,
+    /// TCP payload bytes that followed the upgrade.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    websocket_bytes: Option<u64>,
 }
 
+/// Sections of JA4H and the names of the cookies, see `--with-details`.
 #[derive(Debug, Serialize)]
-enum Ja4hFingerprint {
+struct HttpDetails {
+    /// Hash of the header names.
+    ja4h_b: String,
+    /// Hash of the cookie names.
+    ja4h_c: String,
+    /// Hash of the cookies with their values.
+    ja4h_d: String,
+    /// Sorted unless `--original-order` is given, same as in JA4H.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cookie_names: Vec<String>,
+    ja4h_quality: Quality,
+}
+
+#[derive(Debug, Serialize)]
+enum OutJa4h {
     #[serde(rename = "ja4h")]
     Sorted(String),
     #[serde(rename = "ja4h_o")]
@@ -279,7 +549,7 @@ enum Ja4hFingerprint {
 }
 
 #[derive(Debug, Serialize)]
-enum Ja4hRawFingerprint {
+enum OutJa4hRaw {
     #[serde(rename = "ja4h_r")]
     Sorted(String),
     #[serde(rename = "ja4h_ro")]
@@ -503,6 +773,9 @@ mod tests {
             language,
             headers,
             cookie_pairs,
+            upgrade_websocket: false,
+            websocket: None,
+            quality: Quality::Full,
         };
 
         let out = stats.clone().into_out(FormatFlags::default());
@@ -536,6 +809,7 @@ mod tests {
         let out = stats.clone().into_out(FormatFlags {
             with_raw: true,
             original_order: true,
+            ..Default::default()
         });
         expect![[r#"
             {
@@ -544,6 +818,55 @@ mod tests {
             }"#]]
         .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
 
+        let out = stats.clone().into_out(FormatFlags {
+            with_details: true,
+            ..Default::default()
+        });
+        expect![[r#"
+            {
+              "ja4h": "ge11cr13enus_88d2d584d47f_0f2659b474bf_161698816dab",
+              "ja4h_b": "88d2d584d47f",
+              "ja4h_c": "0f2659b474bf",
+              "ja4h_d": "161698816dab",
+              "cookie_names": [
+                "FastAB",
+                "_dd_s",
+                "countryCode",
+                "geoData",
+                "sato",
+                "stateCode",
+                "umto",
+                "usprivacy"
+              ],
+              "ja4h_quality": "full"
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+
+        let out = stats.clone().into_out(FormatFlags {
+            with_details: true,
+            original_order: true,
+            ..Default::default()
+        });
+        expect![[r#"
+            {
+              "ja4h_o": "ge11cr13enus_88d2d584d47f_457935509480_ff4b0b83634b",
+              "ja4h_b": "88d2d584d47f",
+              "ja4h_c": "457935509480",
+              "ja4h_d": "ff4b0b83634b",
+              "cookie_names": [
+                "FastAB",
+                "sato",
+                "countryCode",
+                "stateCode",
+                "geoData",
+                "usprivacy",
+                "umto",
+                "_dd_s"
+              ],
+              "ja4h_quality": "full"
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+
         let stats = HttpStats {
             packet: Some(PacketNum(113)),
             ..stats
@@ -557,6 +880,53 @@ mod tests {
         .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
     }
 
+    #[test]
+    fn test_websocket_into_out() {
+        let stats = HttpStats {
+            packet: None,
+            req_method: HttpRequestMethod::Get,
+            version: HttpVersion::Http1_1,
+            has_cookie_header: false,
+            has_referer_header: false,
+            language: None,
+            headers: ["Host", "Upgrade", "Connection", "Sec-WebSocket-Key"]
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            cookie_pairs: Vec::new(),
+            upgrade_websocket: true,
+            websocket: None,
+            quality: Quality::Full,
+        };
+
+        // The server hasn't agreed to switch protocols.
+        let out = stats.clone().into_out(FormatFlags::default());
+        expect![[r#"
+            {
+              "ja4h": "ge11nn040000_38366be499dd_000000000000_000000000000"
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+
+        let stats = HttpStats {
+            websocket: Some(WebSocket {
+                protocol: Some("chat".to_owned()),
+                packets: 3,
+                bytes: 120,
+            }),
+            ..stats
+        };
+        let out = stats.into_out(FormatFlags::default());
+        expect![[r#"
+            {
+              "ja4h": "ge11nn040000_38366be499dd_000000000000_000000000000",
+              "websocket": true,
+              "websocket_protocol": "chat",
+              "websocket_packets": 3,
+              "websocket_bytes": 120
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+    }
+
     #[test]
     fn test_cookie_pairs() {
         // No cookies
@@ -698,6 +1068,205 @@ mod tests {
         ];
 
         assert_eq!(joined_cookie_names(&cookie_pairs), "a,b,c,d");
-        assert_eq!(joined_cookie_pairs(cookie_pairs), "a=1,b,c=3,d=");
+        assert_eq!(
+            cookie_pairs.iter().map(cookie_pair).join(","),
+            "a=1,b,c=3,d="
+        );
+    }
+
+    #[test]
+    fn test_ja4h() {
+        let headers = [
+            ("Host", "www.cnn.com"),
+            ("Cookie", "sato=1; countryCode=US; umto=1"),
+            ("User-Agent", "Mozilla/5.0"),
+            ("Accept", "*/*"),
+            ("Referer", "https://www.cnn.com/"),
+            ("accept-language", "en-US,en;q=0.9"),
+        ];
+        let fp = ja4h("GET", "HTTP/1.1", &headers, false).unwrap();
+        expect![[r#"
+            Ja4hFingerprint {
+                ja4h: "ge11cr04enus_0150e93c762e_cc050dca9d4f_93ec44070ab8",
+                ja4h_r: "ge11cr04enus_Host,User-Agent,Accept,accept-language_countryCode,sato,umto_countryCode=US,sato=1,umto=1",
+                ja4h_a: "ge11cr04enus",
+                ja4h_b: "0150e93c762e",
+                ja4h_c: "cc050dca9d4f",
+                ja4h_d: "93ec44070ab8",
+                header_names: [
+                    "Host",
+                    "User-Agent",
+                    "Accept",
+                    "accept-language",
+                ],
+                cookie_names: [
+                    "countryCode",
+                    "sato",
+                    "umto",
+                ],
+                cookies: [
+                    "countryCode=US",
+                    "sato=1",
+                    "umto=1",
+                ],
+            }
+        "#]]
+        .assert_debug_eq(&fp);
+
+        // The output records have the same fingerprints.
+        let stats = HttpStats::from_headers(HttpRequestMethod::Get, HttpVersion::Http1_1, &headers);
+        let out = serde_json::to_value(stats.clone().into_out(FormatFlags {
+            with_raw: true,
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(out["ja4h"], fp.ja4h);
+        assert_eq!(out["ja4h_r"], fp.ja4h_r);
+
+        let fp = ja4h("GET", "HTTP/1.1", &headers, true).unwrap();
+        assert_eq!(fp.cookie_names, ["sato", "countryCode", "umto"]);
+        let out = serde_json::to_value(stats.into_out(FormatFlags {
+            original_order: true,
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(out["ja4h_o"], fp.ja4h);
+
+        let fp = ja4h("POST", "HTTP/2", &[("cookie", "; a")], false).unwrap();
+        assert_eq!(fp.ja4h_a, "po20cn000000");
+        assert_eq!(fp.cookies, ["a"]);
+
+        assert!(matches!(
+            ja4h("get", "HTTP/1.1", &headers, false),
+            Err(Error::InvalidHttpRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ja4h_quality() {
+        let conf = ConfHttp {
+            enabled: true,
+            include_headers: Vec::new(),
+            exclude_headers: Vec::new(),
+        };
+        let quality = |protos: &[&str]| {
+            let mut packet = rtshark::Packet::new();
+            for &name in protos {
+                packet.push(name.to_owned());
+            }
+            let http = packet.last_layer_mut().unwrap();
+            for (name, value) in [
+                ("http.request.method", "GET"),
+                ("http.request.version", "HTTP/1.1"),
+            ] {
+                http.add(rtshark::Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+            let mut stream = Stream::default();
+            stream
+                .update(&Packet::new(&packet, 1), &conf, false)
+                .unwrap();
+            stream.0[0].quality
+        };
+        assert_eq!(quality(&["tcp", "http"]), Quality::Full);
+        assert_eq!(quality(&["tcp", "tls", "http"]), Quality::Decrypted);
+    }
+
+    #[test]
+    fn test_websocket_upgrade() {
+        let conf = ConfHttp {
+            enabled: true,
+            include_headers: Vec::new(),
+            exclude_headers: Vec::new(),
+        };
+        let packet = |tcp_len: &str, http: &[(&str, &str)]| {
+            let mut packet = rtshark::Packet::new();
+            packet.push("tcp".to_owned());
+            packet.last_layer_mut().unwrap().add(rtshark::Metadata::new(
+                "tcp.len".to_owned(),
+                tcp_len.to_owned(),
+                String::new(),
+                0,
+                0,
+            ));
+            if !http.is_empty() {
+                packet.push("http".to_owned());
+                let layer = packet.last_layer_mut().unwrap();
+                for &(name, value) in http {
+                    layer.add(rtshark::Metadata::new(
+                        name.to_owned(),
+                        value.to_owned(),
+                        String::new(),
+                        0,
+                        0,
+                    ));
+                }
+            }
+            packet
+        };
+        let request = [
+            ("http.request.method", "GET"),
+            ("http.request.version", "HTTP/1.1"),
+            ("http.request.line", "Host: example.com\r\n"),
+            ("http.request.line", "Upgrade: websocket\r\n"),
+            ("http.upgrade", "websocket"),
+        ];
+        let packets = [
+            packet("120", &request),
+            packet("0", &[]),
+            packet(
+                "150",
+                &[
+                    ("http.response.code", "101"),
+                    ("http.sec_websocket_protocol", "chat"),
+                ],
+            ),
+            // WebSocket frames, in both directions, and a bare ACK.
+            packet("30", &[]),
+            packet("0", &[]),
+            packet("45", &[]),
+            // WebSocket payload that tshark happens to dissect as an HTTP request.
+            packet("25", &request),
+        ];
+        let mut stream = Stream::default();
+        for (i, packet) in packets.iter().enumerate() {
+            stream
+                .update(&Packet::new(packet, i + 1), &conf, true)
+                .unwrap();
+        }
+        let out = stream.into_out(FormatFlags::default()).unwrap();
+        expect![[r#"
+            {
+              "http": [
+                {
+                  "pkt_ja4h": 1,
+                  "ja4h": "ge11nn020000_c0e13996639b_000000000000_000000000000",
+                  "websocket": true,
+                  "websocket_protocol": "chat",
+                  "websocket_packets": 3,
+                  "websocket_bytes": 100
+                }
+              ]
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+
+        // Without the Upgrade header, a 101 response doesn't switch protocols.
+        let mut stream = Stream::default();
+        let plain_request = packet("80", &request[..2]);
+        for (i, packet) in [&plain_request, &packets[2], &packets[6]]
+            .into_iter()
+            .enumerate()
+        {
+            stream
+                .update(&Packet::new(packet, i + 1), &conf, true)
+                .unwrap();
+        }
+        assert_eq!(stream.nr_requests(), 2);
+        assert!(stream.websocket_mut().is_none());
     }
 }
@@ -0,0 +1,221 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! JA4L -- light distance (latency) fingerprinting
+//!
+//! JA4L is computed from the timestamps and TTLs (IPv6 hop limits) of handshake
+//! packets. [`Ja4lCalculator`] takes them from any source; `ja4` feeds it with the
+//! packets that tshark dissects.
+
+use crate::{tls::Transport, Error, Result};
+
+/// JA4L fingerprints of a connection, see [`Ja4lCalculator`].
+///
+/// The fields are named as in the output records of `ja4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4lFingerprint {
+    /// Client fingerprint: `{latency}_{ttl}`, half the time between the server's
+    /// last handshake packet and the client's answer, in microseconds, and the TTL
+    /// of the client's first packet.
+    pub ja4l_c: String,
+    /// Server fingerprint: `{latency}_{ttl}`, half the time between the client's
+    /// first packet and the server's answer, in microseconds, and the TTL of the
+    /// server's first packet.
+    pub ja4l_s: String,
+}
+
+/// Computes JA4L from the handshake packets of a TCP connection or QUIC session.
+///
+/// Timestamps are in microseconds, e.g. since the Unix epoch. The packets are
+/// passed as they are observed, at a single point of the network:
+///
+/// | Packet | TCP                     | QUIC                                 |
+/// |--------|-------------------------|--------------------------------------|
+/// | A      | [`syn`]                 | [`client_initial`]                   |
+/// | B      | [`syn_ack`]             | [`server_initial`]                   |
+/// | C      | (B)                     | [`server_handshake`], the latest one |
+/// | D      | [`ack`]                 | [`client_handshake`]                 |
+///
+/// JA4L-S is `(B - A) / 2` with the TTL of B; JA4L-C is `(D - C) / 2` with the
+/// TTL of A. Packets of the other transport are ignored, as are repeated ones
+/// (retransmissions), except for the latest QUIC Handshake of the server.
+///
+/// [`syn`]: Ja4lCalculator::syn
+/// [`syn_ack`]: Ja4lCalculator::syn_ack
+/// [`ack`]: Ja4lCalculator::ack
+/// [`client_initial`]: Ja4lCalculator::client_initial
+/// [`server_initial`]: Ja4lCalculator::server_initial
+/// [`server_handshake`]: Ja4lCalculator::server_handshake
+/// [`client_handshake`]: Ja4lCalculator::client_handshake
+#[derive(Debug, Clone)]
+pub struct Ja4lCalculator {
+    transport: Transport,
+    /// Timestamp and TTL of the client's first packet.
+    a: Option<(i64, u8)>,
+    /// Timestamp and TTL of the server's first packet.
+    b: Option<(i64, u8)>,
+    /// Timestamp of the server's last handshake packet (QUIC only).
+    c: Option<i64>,
+    /// Timestamp of the client's answer.
+    d: Option<i64>,
+}
+
+impl Ja4lCalculator {
+    /// Returns a calculator for a handshake over `transport`.
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            a: None,
+            b: None,
+            c: None,
+            d: None,
+        }
+    }
+
+    /// TCP: the client's SYN.
+    pub fn syn(&mut self, micros: i64, ttl: u8) -> &mut Self {
+        if self.transport == Transport::Tcp {
+            self.a.get_or_insert((micros, ttl));
+        }
+        self
+    }
+
+    /// TCP: the server's SYN-ACK.
+    pub fn syn_ack(&mut self, micros: i64, ttl: u8) -> &mut Self {
+        if self.transport == Transport::Tcp {
+            self.b.get_or_insert((micros, ttl));
+        }
+        self
+    }
+
+    /// TCP: the client's ACK completing the handshake.
+    pub fn ack(&mut self, micros: i64) -> &mut Self {
+        if self.transport == Transport::Tcp {
+            self.d.get_or_insert(micros);
+        }
+        self
+    }
+
+    /// QUIC: the client's first Initial packet.
+    pub fn client_initial(&mut self, micros: i64, ttl: u8) -> &mut Self {
+        if self.transport == Transport::Quic {
+            self.a.get_or_insert((micros, ttl));
+        }
+        self
+    }
+
+    /// QUIC: the server's first Initial packet.
+    pub fn server_initial(&mut self, micros: i64, ttl: u8) -> &mut Self {
+        if self.transport == Transport::Quic {
+            self.b.get_or_insert((micros, ttl));
+        }
+        self
+    }
+
+    /// QUIC: a Handshake packet of the server. The latest one before the client's
+    /// Handshake packet counts.
+    pub fn server_handshake(&mut self, micros: i64) -> &mut Self {
+        if self.transport == Transport::Quic && self.d.is_none() {
+            self.c = Some(micros);
+        }
+        self
+    }
+
+    /// QUIC: the client's first Handshake packet.
+    pub fn client_handshake(&mut self, micros: i64) -> &mut Self {
+        if self.transport == Transport::Quic {
+            self.d.get_or_insert(micros);
+        }
+        self
+    }
+
+    /// Returns the fingerprints.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InsufficientData`] if a packet is missing.
+    /// - [`Error::OutOfOrderTimestamps`] if a packet has an earlier timestamp than
+    ///   the one it answers, which would make the latency negative.
+    pub fn finish(&self) -> Result<Ja4lFingerprint> {
+        let (names, c) = match self.transport {
+            Transport::Tcp => (["SYN", "SYN-ACK", "SYN-ACK", "ACK"], self.b.map(|b| b.0)),
+            Transport::Quic => (
+                [
+                    "client Initial",
+                    "server Initial",
+                    "server Handshake",
+                    "client Handshake",
+                ],
+                self.c,
+            ),
+        };
+        let (Some((a, client_ttl)), Some((b, server_ttl)), Some(c), Some(d)) =
+            (self.a, self.b, c, self.d)
+        else {
+            let missing = [self.a.is_none(), self.b.is_none(), c.is_none()]
+                .iter()
+                .position(|&missing| missing)
+                .unwrap_or(3);
+            return Err(Error::InsufficientData(names[missing]));
+        };
+        let times = [a, b, c, d];
+        if let Some(i) = (1..times.len()).find(|&i| times[i] < times[i - 1]) {
+            return Err(Error::OutOfOrderTimestamps {
+                earlier: names[i - 1],
+                later: names[i],
+            });
+        }
+        Ok(Ja4lFingerprint {
+            ja4l_c: format!("{}_{client_ttl}", (d - c) / 2),
+            ja4l_s: format!("{}_{server_ttl}", (b - a) / 2),
+        })
+    }
+}
+
+#[test]
+fn test_ja4l_calculator() {
+    let mut tcp = Ja4lCalculator::new(Transport::Tcp);
+    tcp.syn(1_000_000, 64).syn_ack(1_000_290, 53).ack(1_000_800);
+    // Retransmissions don't count.
+    tcp.syn(1_100_000, 64).ack(1_200_000);
+    expect_test::expect![[r#"
+        Ok(
+            Ja4lFingerprint {
+                ja4l_c: "255_64",
+                ja4l_s: "145_53",
+            },
+        )
+    "#]]
+    .assert_debug_eq(&tcp.finish());
+
+    let mut quic = Ja4lCalculator::new(Transport::Quic);
+    quic.client_initial(0, 128)
+        .server_initial(20_000, 57)
+        .server_handshake(21_000)
+        .server_handshake(22_000)
+        .syn(0, 1);
+    expect_test::expect!["insufficient data for JA4L: no client Handshake"]
+        .assert_eq(&quic.finish().unwrap_err().to_string());
+    quic.client_handshake(30_000).server_handshake(40_000);
+    assert_eq!(
+        quic.finish().unwrap(),
+        Ja4lFingerprint {
+            ja4l_c: "4000_128".to_owned(),
+            ja4l_s: "10000_57".to_owned(),
+        }
+    );
+
+    let mut tcp = Ja4lCalculator::new(Transport::Tcp);
+    tcp.syn(1_000, 64).ack(2_000);
+    expect_test::expect!["insufficient data for JA4L: no SYN-ACK"]
+        .assert_eq(&tcp.finish().unwrap_err().to_string());
+    tcp.syn_ack(3_000, 60);
+    let error = tcp.finish().unwrap_err();
+    expect_test::expect!["JA4L timestamp of the ACK precedes that of the SYN-ACK"]
+        .assert_eq(&error.to_string());
+    assert_eq!(error.kind(), "out_of_order_timestamps");
+}
@@ -5,46 +5,216 @@
 // JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
 // For full license text, see the repo root.
 
+mod anonymize;
+mod capture;
 mod conf;
+mod dedupe;
+mod dns;
+mod ek;
 mod error;
-mod http;
+mod fields;
+mod filter;
+mod foxio;
+mod handler;
+pub mod http;
+pub mod latency;
+mod merge;
+mod native;
+mod output;
 mod pcap;
-mod ssh;
+mod processor;
+mod progress;
+pub mod record;
+mod records;
+mod select;
+pub mod ssh;
+mod starttls;
 mod stream;
+mod summary;
+mod syslog;
 mod time;
-mod tls;
+mod timing;
+pub mod tls;
+mod tshark;
+mod tunnel;
+mod unique;
+mod validate;
+mod watchlist;
+pub mod x509;
 
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
 
 use clap::Parser;
-use rtshark::RTSharkBuilder;
 
-pub use crate::error::Error;
 use crate::{
-    conf::Conf,
+    capture::Engine,
+    filter::{Cidr, PortRange, SniPattern, StreamIndex},
     pcap::{Packet, PacketNum, Proto},
-    stream::Streams,
+    syslog::SyslogTransport,
+    time::{TimeBound, Timestamp},
+};
+pub use crate::{
+    conf::{Conf, ConfBuilder, VersionCheck},
+    error::Error,
+    handler::{process, EventContext, Ja4Handler},
+    processor::{Processor, ProcessorBuilder},
+    records::{Reader, ReaderOptions, Records},
+    select::FingerprintKind,
+    stream::OutRec as OutputRecord,
+    tshark::TsharkOutput,
 };
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// Calculate JA4 fingerprints
-#[derive(Debug, Parser)]
-#[command(version = env!("CARGO_PKG_VERSION"))]
+#[derive(Debug, Clone, Parser)]
+#[command(
+    version = env!("CARGO_PKG_VERSION"),
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Cli {
-    /// JSON output (default is YAML)
-    #[arg(short, long)]
-    json: bool,
-    /// Include raw (unhashed) fingerprints in the output
-    #[arg(short = 'r', long)]
-    with_raw: bool,
+    /// Configuration file.
+    ///
+    /// Without this option, the first of these files that exists is read:
+    /// `$JA4_CONFIG`, `./ja4.toml`, `$XDG_CONFIG_HOME/ja4/config.toml`
+    /// (`~/.config/ja4/config.toml` if `XDG_CONFIG_HOME` is not set). The last one
+    /// is created with the example configuration if missing; the built-in defaults
+    /// are used if it cannot be. A file given with this option or `JA4_CONFIG` must
+    /// exist; this option takes precedence over `JA4_CONFIG`.
+    ///
+    /// `JA4_*` environment variables override the settings of the file; command
+    /// line options override both. A variable is named after its setting, e.g.
+    /// `JA4_SSH_SAMPLE_SIZE=100` for `ssh.sample_size`, `JA4_FINGERPRINTS_JA4H=false`,
+    /// `JA4_PORTS_TLS=443,8443`; list values are comma-separated. Run with
+    /// `RUST_LOG=ja4=debug` to see which file is read; `ja4 config init` prints
+    /// all settings with their default values.
+    #[arg(short = 'c', long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Apply `[profile.<NAME>]` table of the configuration file.
+    ///
+    /// A profile bundles settings for a kind of job, e.g. `[profile.triage]` with
+    /// `fingerprints` and `output` tables of its own. Its settings override those
+    /// of the rest of the file; `JA4_*` environment variables and command line
+    /// options override the profile's. Without this option, `JA4_PROFILE` names the
+    /// profile, if set.
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// JSON output (default is YAML).
+    ///
+    /// One record per line. A record is output as soon as its stream is finalized
+    /// --- TCP connection closed, UDP flow timed out; the other records once the
+    /// entire capture has been processed. YAML output is printed at the end.
+    ///
+    /// `format` of `[output]` table of the configuration file sets the default;
+    /// `--json=false` selects YAML regardless.
+    #[arg(
+        short,
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    json: Option<bool>,
+    /// Indent JSON output.
+    ///
+    /// Instead of one record per line, the records are buffered and printed as a
+    /// single JSON array. Requires JSON output: `--json`, or `format = "json"` in
+    /// `[output]` table of the configuration file.
+    #[arg(long)]
+    pretty: bool,
+    /// Include raw (unhashed) fingerprints in the output.
+    ///
+    /// JA4 (TLS client): also `ciphers` and `extensions` --- the cipher suites and
+    /// extensions of the Client Hello as hex strings, GREASE values skipped, in the
+    /// original order.
+    ///
+    /// The default is `with_raw` of `[output]` table of the configuration file.
+    #[arg(
+        short = 'r',
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    with_raw: Option<bool>,
     /// Preserve the original order of values.
     ///
     /// JA4 (TLS client): disable sorting of ciphers and TLS extensions.
     ///
     /// JA4H (HTTP client): disable sorting of headers and cookies.
-    #[arg(short = 'O', long)]
-    original_order: bool,
+    ///
+    /// The default is `original_order` of `[output]` table of the configuration
+    /// file.
+    #[arg(
+        short = 'O',
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    original_order: Option<bool>,
+    /// Include exact values that fingerprints summarize.
+    ///
+    /// JA4 (TLS client): `cipher_count`, `extension_count`, and `sig_alg_count`.
+    /// The JA4 fingerprint caps the numbers of ciphers and extensions at 99.
+    /// `alpn_list` --- all ALPN values in the order of the Client Hello; JA4 only
+    /// uses the first one. `ja4_quality` --- how completely the Client Hello was
+    /// seen, see below. `ciphers` and `extensions`, as with `--with-raw`.
+    ///
+    /// JA4S (TLS server): `ja4s_quality` --- the same for the Server Hello.
+    ///
+    /// JA4H (HTTP client): `ja4h_b`, `ja4h_c`, and `ja4h_d` --- the hashes of the
+    /// header names, the cookie names, and the cookies with their values;
+    /// `cookie_names` --- sorted unless `--original-order` is given;
+    /// `ja4h_quality` --- the same for the request.
+    ///
+    /// JA4L (latency): `rtt_stats` --- minimum, median, and maximum round-trip time
+    /// in microseconds, measured from the ACKs of the entire TCP stream rather than
+    /// the handshake only.
+    ///
+    /// JA4X (X.509 certificates): `cert_chain_len` and `cert_chain_bytes` --- the
+    /// number of certificates in the server's Certificate message and the total
+    /// size of their DER encodings.
+    /// `ja4x_quality` --- the same for each Certificate message.
+    ///
+    /// Quality values: `full` if the message came in a single packet;
+    /// `reassembled` if tshark put it together from several TCP segments, TLS
+    /// records, or QUIC CRYPTO frames; `decrypted` if tshark decrypted it (QUIC, a
+    /// TLS 1.3 Certificate, a session tunneled in TLS, HTTP over TLS; all but QUIC
+    /// need `--keylog-file`); `partial` if part of it may be missing (the packet
+    /// was truncated when captured or is malformed) or it is the outer Client Hello
+    /// of Encrypted Client Hello, which hides the real one. JA4L and JA4SSH have no
+    /// quality: they are computed from packet timestamps and sizes, which tshark
+    /// neither reassembles nor decrypts.
+    ///
+    /// All streams: `frame_protocols` --- the deepest protocol stack that tshark
+    /// has dissected in the packets of the stream (`frame.protocols`), e.g.
+    /// `eth:ethertype:ip:tcp:tls`. A stack ending with `tcp` means that tshark
+    /// has not recognized the application protocol; see `--decode-as`.
+    /// `packets`, `bytes_client`, and `bytes_server` --- the number of packets of
+    /// the stream and the bytes on the wire (`frame.len`) sent by either side.
+    /// Packets are counted as captured: retransmissions are counted again.
+    #[arg(long)]
+    with_details: bool,
+    /// Name the fields as the reference implementation (Python scripts) does.
+    ///
+    /// E.g., `JA4`, `JA4S`, `JA4L-C`, `domain`, `srcport`. Lists of fingerprints
+    /// become numbered fields: `JA4SSH.1`, `JA4SSH.2`, ..., `JA4X.1`, .... `JA4H` is
+    /// that of the first HTTP request of the stream. Other fields keep their names.
+    #[arg(long, conflicts_with_all = ["summary", "unique", "syslog"])]
+    foxio_names: bool,
     /// The key log file that enables decryption of TLS traffic.
     ///
     /// This file is generated by the browser when `SSLKEYLOGFILE` environment variable is set.
@@ -52,89 +222,625 @@ pub struct Cli {
     ///
     /// Note that you can embed the TLS key log file in a capture file:
     /// `editcap --inject-secrets tls,keys.txt in.pcap out-dsb.pcapng`
+    ///
+    /// If the file decrypts none of the TLS sessions, a warning is printed and the
+    /// records of the sessions with application data get `decryption_failed: true`.
+    ///
+    /// A Client Hello with Encrypted Client Hello (ECH) gets `ja4_outer`, the JA4 of
+    /// the outer Client Hello, same as `ja4`. If the file has the ECH secret of the
+    /// session (`ECH_SECRET` and `ECH_CONFIG` lines), tshark decrypts the inner
+    /// Client Hello and its JA4 is `ja4_inner`; the extensions that it copies from
+    /// the outer Client Hello (`ech_outer_extensions`) count towards it. `ja4_inner`
+    /// needs `--tshark-output pdml`, the default, and has only been tested with
+    /// made-up tshark output, not with a capture of a real ECH session.
     #[arg(long)]
     keylog_file: Option<PathBuf>,
     /// Include packet numbers (`pkt_*` fields) in the output.
     ///
-    /// This information is useful for debugging.
-    #[arg(short = 'n', long)]
-    with_packet_numbers: bool,
-    /// The capture file to process
-    pcap: PathBuf,
+    /// This information is useful for debugging. The lists of a record --- HTTP
+    /// requests (`http`), certificates (`tls_certs`), JA4SSH samples --- are always
+    /// in the order of their packets.
+    ///
+    /// The default is `with_packet_numbers` of `[output]` table of the
+    /// configuration file.
+    #[arg(
+        short = 'n',
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        hide_possible_values = true
+    )]
+    with_packet_numbers: Option<bool>,
+    /// Only process packets matching this Wireshark display filter.
+    ///
+    /// Fingerprints are computed from the matching packets only, so a filter that
+    /// drops a part of the handshake breaks the fingerprints of that stream. E.g.,
+    /// `tls.handshake.type == 1` keeps JA4 but loses JA4S, JA4X, and JA4L.
+    /// Packet numbers (`pkt_*` fields) count the matching packets.
+    ///
+    /// Example: `-Y 'ip.addr == 192.0.2.1'`
+    #[arg(short = 'Y', long, value_name = "FILTER")]
+    display_filter: Option<String>,
+    /// Dissect traffic matching the selector as the given protocol (tshark's `-d`).
+    ///
+    /// tshark detects most protocols by content, but some of them only on their
+    /// standard ports. The option may be repeated. Examples:
+    ///
+    /// - `tcp.port==8888,http` --- HTTP on port 8888;
+    ///
+    /// - `tcp.port==2222,ssh` --- SSH on port 2222;
+    ///
+    /// - `tcp.port==8443-8445,tls` --- TLS on ports 8443 to 8445;
+    ///
+    /// - `udp.port==37009,tzsp` --- traffic mirrored with TZSP to port 37009.
+    ///
+    /// Rules for the TLS, SSH, and HTTP ports of a site are better put in the
+    /// `[ports]` table of the configuration file; this option overrides them.
+    ///
+    /// Mirrored and tunneled traffic (ERSPAN, GRE, VXLAN, TZSP to its standard port
+    /// 37008) is fingerprinted by its innermost IP and TCP/UDP headers.
+    #[arg(short = 'd', long, value_name = "SPEC", value_parser = parse_decode_as)]
+    decode_as: Vec<String>,
+    /// Only output streams with this IP address at either end.
+    ///
+    /// May be repeated; combined with `--net`, a stream is output if any of the
+    /// addresses or networks matches.
+    #[arg(long, value_name = "IP")]
+    host: Vec<IpAddr>,
+    /// Only output streams with either end in this network, e.g. `10.0.0.0/8` or
+    /// `2001:db8::/32`.
+    ///
+    /// May be repeated; see `--host`.
+    #[arg(long, value_name = "CIDR")]
+    net: Vec<Cidr>,
+    /// Only output streams with this port number at either end.
+    ///
+    /// May be repeated; combined with `--port-range`, a stream is output if any of
+    /// the ports matches. When used together with `--host` or `--net`, a stream must
+    /// match both the address and the port.
+    #[arg(long, value_name = "PORT")]
+    port: Vec<u16>,
+    /// Only output streams with a port from this range at either end, e.g.
+    /// `8000-8999`.
+    ///
+    /// May be repeated; see `--port`.
+    #[arg(long, value_name = "FIRST-LAST")]
+    port_range: Vec<PortRange>,
+    /// Only process the stream with this tshark stream index (`tcp.stream` or
+    /// `udp.stream`), e.g. `tcp:42` or `udp:7`.
+    ///
+    /// May be repeated. Packets of other streams are skipped, but packet numbers
+    /// (`pkt_*` fields) still refer to the frames of the capture file, so the
+    /// records are the same as in the output for the entire file.
+    #[arg(long, value_name = "PROTO:INDEX")]
+    stream: Vec<StreamIndex>,
+    /// Only output streams whose first packet was captured at or after this time.
+    ///
+    /// The time is given in seconds since the Unix epoch (e.g. `1700000000`) or in
+    /// RFC 3339 format (e.g. `2023-11-14T22:13:20Z`); UTC is assumed if there is no
+    /// offset. Later packets of a selected stream are processed even if they fall
+    /// outside the time range.
+    #[arg(long, value_name = "TIME")]
+    after: Option<Timestamp>,
+    /// Only output streams whose first packet was captured before this time.
+    ///
+    /// See `--after` for the time format.
+    #[arg(long, value_name = "TIME")]
+    before: Option<Timestamp>,
+    /// Only process the packets captured at or after this time.
+    ///
+    /// Unlike `--after`, this applies to every packet: a stream that started
+    /// earlier is fingerprinted from its packets within the time window, so the
+    /// fingerprints of its handshake may be missing. Besides the formats of
+    /// `--after`, the time can be given as `+` and a duration since the first
+    /// packet of the capture file (of the merged capture with `--merge`), e.g.
+    /// `+90s`, `+30m`, or `+1h30m`.
+    ///
+    /// tshark still reads the packets outside the window, and packet numbers
+    /// (`pkt_*` fields) still refer to the frames of the capture file. Only the
+    /// streams fingerprinted from packets within the window count towards
+    /// `--max-streams`.
+    #[arg(long, value_name = "TIME")]
+    since: Option<TimeBound>,
+    /// Only process the packets captured before this time.
+    ///
+    /// See `--since`.
+    #[arg(long, value_name = "TIME")]
+    until: Option<TimeBound>,
+    /// Only output TLS streams whose server name (SNI) matches this pattern, e.g.
+    /// `--sni '*.example.com'`.
+    ///
+    /// The match is case-insensitive; `*` stands for any sequence of characters.
+    /// May be repeated. TLS streams without a server name are only output if `-` is
+    /// given as a pattern. Streams without TLS are not affected.
+    #[arg(long, value_name = "PATTERN")]
+    sni: Vec<SniPattern>,
+    /// Compute only these fingerprint types, e.g. `--only ja4,ja4s`.
+    ///
+    /// Processing needed for other fingerprints is skipped, and the streams that
+    /// have none of the selected fingerprints are not output.
+    ///
+    /// Without this option, the fingerprint types enabled in the `[fingerprints]`
+    /// table of the configuration file are computed, all of them by default.
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    only: Vec<FingerprintKind>,
+    /// How to read the capture files.
+    ///
+    /// `native` parses pcap files directly, without tshark, which is much faster.
+    /// It computes JA4, JA4S, and JA4X from unencrypted TLS handshakes over TCP;
+    /// the output is the same as with `--only ja4,ja4s,ja4x`, or with the subset of
+    /// these given by `--only`. Files that need tshark --- pcapng format, tunnels,
+    /// IP fragments, QUIC, TLS after STARTTLS, ... --- are still processed by
+    /// tshark. So are all of them if `--keylog-file` is given or `--only` selects
+    /// other fingerprints.
+    ///
+    /// `--since`, `--until`, `--max-streams`, `--experimental`, `--resolve-dns`,
+    /// `--display-filter`, `--decode-as`, and `--interface` need tshark and are an
+    /// error with `native`.
+    #[arg(long, value_name = "ENGINE", default_value = "tshark")]
+    engine: Engine,
+    /// Stop reading the capture once this many streams have got a fingerprint.
+    ///
+    /// tshark is stopped, and the streams processed so far are output, including
+    /// those that are still in progress. A note is printed to stderr; the exit
+    /// status is not affected.
+    #[arg(long, value_name = "N")]
+    max_streams: Option<usize>,
+    /// Exit with an error if no fingerprints were produced.
+    ///
+    /// The exit status is 1, same as for any other error. Without this option,
+    /// an empty output is not an error.
+    #[arg(long)]
+    fail_on_empty: bool,
+    /// Don't output records without fingerprints.
+    ///
+    /// E.g., a QUIC stream whose Client Hello tshark cannot decrypt is reported with
+    /// its `quic_version` only.
+    #[arg(long)]
+    skip_empty: bool,
+    /// Finalize a UDP flow (QUIC, DTLS, ...) after this many seconds without packets.
+    ///
+    /// UDP has no connection teardown. The time is measured by packet timestamps; a
+    /// later packet of the same address pair starts a new record.
+    #[arg(long, value_name = "SECS", default_value_t = stream::DEFAULT_UDP_TIMEOUT_SECS)]
+    udp_timeout: u64,
+    /// Finalize a TCP stream after this many seconds without packets.
+    ///
+    /// A stream is normally finalized once it's closed (FIN from both sides or RST).
+    /// Streams that are never closed stay in memory until the end of the capture;
+    /// with this option the memory use depends on the number of concurrently active
+    /// streams rather than all streams (plus the records, unless they are output as
+    /// JSON lines). Later packets of a timed out stream are ignored.
+    #[arg(long, value_name = "SECS")]
+    tcp_idle_timeout: Option<u64>,
+    /// Don't output streams with fewer than this many packets.
+    ///
+    /// Port scans and aborted connections produce lots of streams of a packet or
+    /// two. Streams with a TLS fingerprint are output regardless, since JA4 only
+    /// needs the Client Hello; see `--strict-min`.
+    #[arg(long, value_name = "N")]
+    min_packets: Option<usize>,
+    /// Apply `--min-packets` to the streams with TLS fingerprints as well.
+    #[arg(long, requires = "min_packets")]
+    strict_min: bool,
+    /// Kill tshark if it produces no output for this many seconds
+    #[arg(long, value_name = "SECS")]
+    tshark_timeout: Option<u64>,
+    /// Have tshark dissect all fields of the packets.
+    ///
+    /// By default, tshark is only asked for the fields that the enabled
+    /// fingerprints need, which makes it considerably faster.
+    #[arg(long)]
+    all_fields: bool,
+    /// Enable experimental fingerprinting of tunneled traffic.
+    ///
+    /// TLS over WebSocket: after the server accepts a WebSocket upgrade (`101
+    /// Switching Protocols`), the Client Hello of a TLS session tunneled in the
+    /// client's binary messages gets the JA4 fingerprints, as `tls_inner` if the
+    /// WebSocket itself runs over decrypted TLS. The stream is annotated with
+    /// `tunnel: websocket` and the `Host` header of the upgrade request as
+    /// `tunnel_dst`.
+    #[arg(long)]
+    experimental: bool,
+    /// Annotate TLS streams with the server name resolved by DNS.
+    ///
+    /// The DNS responses of the capture map IP addresses to the names queried; a
+    /// TLS stream whose server address is in the map gets the name as
+    /// `resolved_host`. This gives a server name for streams without SNI (e.g.
+    /// Encrypted Client Hello), but it is a heuristic: an address may be shared by
+    /// many names (CDNs, virtual hosting), the client may have resolved the name
+    /// before the capture began (DNS caching), and the latest name resolved to an
+    /// address wins.
+    #[arg(long)]
+    resolve_dns: bool,
+    /// Format in which tshark passes the dissected packets.
+    ///
+    /// `ek` (newline-delimited JSON) is faster to parse than the default `pdml`
+    /// (XML). It is experimental: tshark groups the fields of a protocol by name
+    /// in ek output, losing their order, which the fingerprinters have to make up
+    /// for. `--all-fields` has no effect with `ek`.
+    #[arg(long, value_name = "FORMAT", default_value = "pdml")]
+    tshark_output: TsharkOutput,
+    /// tshark executable to run instead of the one found in PATH.
+    ///
+    /// With the default `--tshark-output pdml`, the file has to be named `tshark`
+    /// (`tshark.exe` on Windows).
+    ///
+    /// Overrides `JA4_TSHARK` environment variable, which in turn overrides
+    /// `tshark_path` setting of the configuration file.
+    #[arg(long, value_name = "PATH")]
+    tshark_path: Option<PathBuf>,
+    /// Oldest supported tshark version, e.g. `4.2.0`.
+    ///
+    /// Overrides `tshark_min_version` setting of the configuration file; the
+    /// default is 4.0.6.
+    #[arg(long, value_name = "VERSION")]
+    tshark_min_version: Option<semver::Version>,
+    /// What to do if tshark is older than `--tshark-min-version`.
+    ///
+    /// Overrides `tshark_version_check` setting of the configuration file; the
+    /// default is `warn`.
+    #[arg(long, value_name = "MODE")]
+    tshark_version_check: Option<VersionCheck>,
+    /// Pass an extra argument to tshark, e.g. `--tshark-arg=-o
+    /// --tshark-arg=tcp.desegment_tcp_streams:FALSE`. Can be repeated.
+    ///
+    /// The arguments are added to the command lines that read packets and check
+    /// `--display-filter`. Requires `--tshark-output ek`.
+    #[arg(long = "tshark-arg", value_name = "ARG", allow_hyphen_values = true)]
+    tshark_args: Vec<String>,
+    /// Report failures as JSON lines on stderr and carry on with the other files.
+    ///
+    /// Every line is an object with `file` (if any), `packet_num` (for failures
+    /// of individual packets), `error_kind`, and `message`. `error_kind` is a stable
+    /// name of the error class, e.g. `tshark_timeout` or `missing_field`. If any
+    /// file has failed, the exit status is 1 once the others are processed.
+    #[arg(long)]
+    errors_json: bool,
+    /// Print a summary of the capture file instead of per-stream records.
+    ///
+    /// The summary lists servers that presented more than one distinct JA4S
+    /// fingerprint, with the number of streams per fingerprint.
+    #[arg(long)]
+    summary: bool,
+    /// Output one record per distinct value of these fingerprints, e.g.
+    /// `--unique ja4` or `--unique ja4,ja4s`.
+    ///
+    /// Each record has the fingerprints, the number of streams that carried them
+    /// (`count`), and the earliest of these streams (`example`). Multiple
+    /// fingerprints of a stream, e.g. JA4H of several requests, are joined with `,`.
+    /// Streams lacking any of the fingerprints are not counted.
+    #[arg(
+        long,
+        value_name = "TYPES",
+        value_delimiter = ',',
+        conflicts_with = "summary"
+    )]
+    unique: Vec<FingerprintKind>,
+    /// Check that every fingerprint matches its raw form, instead of outputting
+    /// the records.
+    ///
+    /// The fingerprints are computed with `--with-raw`, and the sections of each
+    /// raw fingerprint are hashed again. A record is output for each fingerprint
+    /// that doesn't match: the stream, the location of the fingerprint in its record
+    /// (`field`), the fingerprint, and the raw form (`raw`). If there is any, the
+    /// exit status is 1.
+    #[arg(long, conflicts_with_all = ["summary", "unique", "syslog", "foxio_names"])]
+    validate: bool,
+    /// Replace IP addresses in the output with pseudonyms.
+    ///
+    /// Each address is replaced with an address of the documentation ranges,
+    /// chosen by a keyed hash of it: 192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24
+    /// (IPv4) or 2001:db8::/32 (IPv6). An address always gets the same pseudonym
+    /// within a run, and distinct addresses get distinct ones; past 768 IPv4
+    /// addresses, IPv4 addresses get IPv6 pseudonyms. Ports and fingerprints are
+    /// not affected.
+    #[arg(long)]
+    anonymize: bool,
+    /// Key for `--anonymize`, as a hex string.
+    ///
+    /// Use the same key to get the same pseudonyms across runs. If two addresses
+    /// hash to the same pseudonym, the one seen later gets another; such addresses
+    /// may get different pseudonyms in runs over other captures. By default, a
+    /// random key is generated for each run.
+    #[arg(long, value_name = "HEX", requires = "anonymize")]
+    anonymize_key: Option<anonymize::Key>,
+    /// Mark the streams with any fingerprint from this file with `matched: true`.
+    ///
+    /// The file lists one fingerprint (JA4, JA4S, JA4H, JA4X, JA4SSH, ...) per line;
+    /// text after `#` is a comment. Malformed lines are reported and ignored.
+    #[arg(long, value_name = "PATH")]
+    match_file: Option<PathBuf>,
+    /// Only output the streams matched by `--match-file`.
+    #[arg(long, requires = "match_file")]
+    match_only: bool,
+    /// Output only the first of the streams that share the client address, server
+    /// address and port, JA4, and JA4H.
+    ///
+    /// Repeated connections of a client then produce a single record, with the
+    /// number of dropped repetitions in its `duplicates` field. Unlike `--summary`,
+    /// the first record is output in full. The records are output after the entire
+    /// capture has been processed.
+    #[arg(long, conflicts_with_all = ["summary", "unique"])]
+    dedupe_streams: bool,
+    /// List the streams with their protocols and packet and byte counts, without
+    /// computing fingerprints.
+    ///
+    /// Every TCP and UDP stream is output, with `protocols` (the layers tshark has
+    /// dissected above TCP or UDP, e.g. `[tls]`), `packets`, and `bytes`. Useful to
+    /// find out why a stream has no fingerprints: e.g. `[data]` means that tshark
+    /// did not recognize the protocol, and `--decode-as` may help.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "only", "summary", "unique", "dedupe_streams", "match_file", "skip_empty",
+            "foxio_names", "syslog",
+        ]
+    )]
+    list_streams: bool,
+    /// Send the records to this syslog server as CEF events instead of writing them
+    /// to the standard output.
+    ///
+    /// Fingerprints are mapped to CEF custom string extensions (`cs1` = JA4,
+    /// `cs2` = JA4S, `cs3` = JA4H, `cs4` = JA4X, `cs5` = JA4SSH, `cs6` = JA4+,
+    /// `flexString1`/`flexString2` = JA4L-C/JA4L-S), each with a `<key>Label`.
+    ///
+    /// Example: `--syslog siem.example.com:514`
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["summary", "unique"])]
+    syslog: Option<String>,
+    /// Transport protocol for `--syslog`.
+    #[arg(long, value_name = "PROTO", default_value = "udp", requires = "syslog")]
+    syslog_transport: SyslogTransport,
+    /// Write the records to this file instead of the standard output.
+    #[arg(short = 'o', long, value_name = "FILE", conflicts_with = "syslog")]
+    output: Option<PathBuf>,
+    /// Start a new `--output` file once the current one has this many bytes.
+    ///
+    /// The current file is renamed after the time of the rotation (UTC), e.g.
+    /// `ja4.ndjson` to `ja4.20231114T221320Z.ndjson`, and the `--output` file is
+    /// created anew. Requires JSON output (see `--pretty`), which has one record per
+    /// line; records are never split between files.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        requires = "output",
+        conflicts_with = "pretty",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    rotate_size: Option<u64>,
+    /// Start a new `--output` file once the current one has been open for this many
+    /// seconds.
+    ///
+    /// Useful with `--interface`. The file is rotated as with `--rotate-size`, when
+    /// the next record is written.
+    #[arg(
+        long,
+        value_name = "SECS",
+        requires = "output",
+        conflicts_with = "pretty",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    rotate_interval: Option<u64>,
+    /// Capture packets live from this network interface instead of reading a file.
+    ///
+    /// Press Ctrl-C to stop the capture; the records are output once tshark exits.
+    /// See `tshark -D` for the list of interfaces.
+    #[arg(short = 'i', long, value_name = "INTERFACE", conflicts_with = "pcap")]
+    interface: Option<String>,
+    /// Only capture packets matching this BPF capture filter (`--interface` only).
+    ///
+    /// Unlike `--display-filter`, the filter is applied by the kernel, so the packets
+    /// it drops cost nothing. The filter is recorded in every output record as
+    /// `capture_filter`.
+    ///
+    /// Example: `-f 'tcp port 443'`
+    #[arg(
+        short = 'f',
+        long,
+        value_name = "BPF",
+        requires = "interface",
+        conflicts_with = "pcap"
+    )]
+    capture_filter: Option<String>,
+    /// Number of capture files to process concurrently [default: number of CPUs]
+    ///
+    /// Each file is processed by its own tshark process. At most this many files
+    /// are held in memory at a time.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+    /// Output the records of each capture file as soon as it's processed, rather than
+    /// in the order of the files.
+    #[arg(long)]
+    unordered: bool,
+    /// Process all the capture files with a single tshark process.
+    ///
+    /// Starting tshark may take longer than dissecting a small file, so this can
+    /// save time with many small files. The files are concatenated with `mergecap`
+    /// (found next to `--tshark-path`, if given) into a temporary file; they must be
+    /// uncompressed pcap or pcapng, `.pcap.gz` is rejected. Records are output per
+    /// file as without `--merge`, with the `pcap` field, but the stream numbers
+    /// continue from one file to the next. Ignored with `--engine native` if tshark
+    /// is not needed.
+    ///
+    /// tshark sees the files as a single capture. A connection that continues from
+    /// one file into the next, as in the files of a ring buffer (`dumpcap -b`), is
+    /// still fingerprinted per file, but a message that the file boundary splits is
+    /// reassembled by tshark and counts for the later file. Without `--merge`
+    /// neither file has it whole.
+    #[arg(long, conflicts_with = "interface")]
+    merge: bool,
+    /// Show the progress of reading the capture on stderr.
+    ///
+    /// The number of packets and fingerprinted streams, and, for capture files, the
+    /// bytes read and the estimated time left. The progress is only shown if stderr
+    /// is a terminal; a summary line is printed at the end regardless.
+    #[arg(long)]
+    progress: bool,
+    /// Print where the time went as a JSON object to stderr at the end.
+    ///
+    /// The time spent waiting for tshark, fingerprinting, and serializing the
+    /// records (summed over the jobs), the numbers of packets read --- in total and
+    /// by protocol --- and of records output, along with the versions of ja4 and of
+    /// tshark.
+    #[arg(long)]
+    timing: bool,
+    /// Print version.
+    ///
+    /// With `--json`, print a JSON object with the versions of ja4 and of tshark
+    /// (`null` if tshark cannot be run), whether the latter is supported, and the
+    /// supported tshark versions: `{"ja4_version": ..., "tshark_version": ...,
+    /// "tshark_ok": ..., "required_tshark": ...}`.
+    #[arg(short = 'V', long)]
+    version: bool,
+    /// The capture files to process.
+    ///
+    /// Stream numbers are per file; with more than one file, every record has the
+    /// `pcap` field with the path of its file.
+    #[arg(required_unless_present_any = ["interface", "version"])]
+    pcap: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-impl Cli {
-    /// Write JSON with JA4 fingerprints to the standard output.
-    pub fn run<W: Write>(self, writer: &mut W) -> Result<()> {
-        let conf = Conf::load()?;
-        let Cli {
-            json,
-            with_raw,
-            original_order,
-            keylog_file,
-            with_packet_numbers,
-            pcap,
-        } = self;
-
-        let Some(pcap_path) = pcap.to_str() else {
-            return Err(Error::NonUtf8Path(pcap));
-        };
-        check_tshark_version()?;
-        let mut builder = RTSharkBuilder::builder().input_path(pcap_path);
-
-        if let Some(keylog) = &keylog_file {
-            let Some(keylog_path) = keylog.to_str() else {
-                // SAFETY: we've just established that `keylog_file` is Some
-                return Err(Error::NonUtf8Path(keylog_file.unwrap()));
-            };
-            builder = builder.keylog_file(keylog_path);
-        }
-        let mut tshark = builder.spawn()?;
-
-        let mut streams = Streams::default();
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+    /// Manage the configuration file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
 
-        let mut packet_num = 0;
-        while let Some(packet) = tshark.read().unwrap_or_else(|err| {
-            tracing::error!(%err, "failed to parse tshark output");
-            None
-        }) {
-            packet_num += 1;
-            let pkt = Packet::new(&packet, packet_num);
+#[derive(Debug, Clone, clap::Subcommand)]
+enum ConfigCommand {
+    /// Print the default configuration: every setting at its default value, with
+    /// a comment.
+    Init {
+        /// Write the configuration to `$XDG_CONFIG_HOME/ja4/config.toml`
+        /// (`~/.config/ja4/config.toml`) instead of the standard output.
+        #[arg(long)]
+        write: bool,
+        /// Overwrite the configuration file if it exists. Not needed for the
+        /// example configuration that ja4 creates when the file is missing.
+        #[arg(long, requires = "write")]
+        force: bool,
+    },
+}
 
-            if let Err(error) = streams.update(&pkt, &conf, with_packet_numbers) {
-                tracing::debug!(packet_num, %error, "failed to handle packet");
+impl Cli {
+    /// Write JSON with JA4 fingerprints to the standard output.
+    pub fn run<W: Write>(mut self, writer: &mut W) -> Result<()> {
+        match self.command.take() {
+            Some(Command::Config(ConfigCommand::Init { write, force })) => {
+                conf::init(write, force, writer)
             }
+            None if self.version => self.print_version(writer),
+            None => Processor::from_cli(self).run(writer),
         }
+    }
 
-        let flags = FormatFlags {
-            with_raw,
-            original_order,
-        };
-        // HACK: The purpose of the `io::stdout` mumbo-jumbo is to handle
-        // BrokenPipe error. Rust throws it when the stdout is piped to `head`.
-        if json {
-            for rec in streams.into_out(flags) {
-                serde_json::to_writer(&mut *writer, &rec)?;
-                writeln!(writer)?;
-            }
-        } else {
-            let s = serde_yaml::to_string(&streams.into_out(flags).collect::<Vec<_>>())?;
-            writer.write_all(s.as_bytes())?;
+    /// Prints the version of ja4, or with `--json` that of tshark as well.
+    fn print_version<W: Write>(self, writer: &mut W) -> Result<()> {
+        if self.json != Some(true) {
+            writeln!(writer, "ja4 {}", env!("CARGO_PKG_VERSION"))?;
+            return Ok(());
+        }
+        // tshark is looked for as when processing a capture file, but a broken
+        // configuration doesn't prevent printing the version.
+        let mut conf = Conf::load_from(self.config.as_deref(), self.profile.as_deref())
+            .inspect_err(|error| tracing::warn!(%error, "failed to load configuration"))
+            .or_else(|_| Conf::builder().build())?;
+        if let Some(version) = self.tshark_min_version {
+            conf.tshark_min_version = version.to_string();
         }
+        let required = conf.tshark_requirement();
+        let tshark = tshark::Program::new(self.tshark_path.or(conf.tshark_path), Vec::new())
+            .and_then(|program| tshark_version(&program, &required));
+        let info = VersionInfo::new(tshark, &required);
+        serde_json::to_writer(&mut *writer, &info)?;
+        writeln!(writer)?;
         Ok(())
     }
 }
 
+/// Returns an iterator over the records of the given capture file.
+///
+/// Records are produced lazily: a TCP stream is yielded as soon as the connection is
+/// closed (FIN from both sides or RST), the remaining streams --- after tshark has
+/// processed the entire file. Dropping the iterator kills tshark.
+///
+/// The configuration is read the same way as by the command line tool without
+/// `--config`: from the configuration file and `JA4_*` environment variables.
+pub fn stream_fingerprints(pcap: impl AsRef<Path>, flags: FormatFlags) -> Records {
+    let options = ReaderOptions {
+        flags,
+        ..Default::default()
+    };
+    Reader::open_lazily(pcap.as_ref(), options)
+}
+
+/// Same as [`stream_fingerprints`], but with the given configuration (see
+/// [`Conf::builder`]) instead of the one from the file and the environment.
+pub fn stream_fingerprints_with_conf(
+    pcap: impl AsRef<Path>,
+    conf: Conf,
+    flags: FormatFlags,
+) -> Records {
+    let options = ReaderOptions {
+        conf: Some(conf),
+        flags,
+        ..Default::default()
+    };
+    Reader::open_lazily(pcap.as_ref(), options)
+}
+
+/// Same as [`stream_fingerprints`], but calls `enrich` on every record before it
+/// is yielded.
+///
+/// `enrich` can add fields to the record with [`OutputRecord::extra_mut`], e.g.
+/// GeoIP, ASN, or threat intelligence lookups of the addresses; they are
+/// serialized along with the fingerprints. It runs as the records are produced,
+/// so the records of a large file need not be held in memory:
+///
+/// ```no_run
+/// let recs = ja4::fingerprint_pcap_with("capture.pcap", Default::default(), |rec| {
+///     let server = rec.to_record().dst;
+///     rec.extra_mut().insert("server_is_local".into(), server.starts_with("10.").into());
+/// });
+/// for rec in recs {
+///     serde_json::to_writer(std::io::stdout(), &rec?)?;
+/// }
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+///
+/// `enrich` is called on the thread that iterates over the records, one record at
+/// a time and in the order they are yielded. It must be [`Send`] because the
+/// iterator is; if it shares state with other threads (e.g. a lookup cache), the
+/// state must be synchronized as usual. Use [`Reader::enrich`] for the options
+/// of [`Reader`].
+pub fn fingerprint_pcap_with(
+    pcap: impl AsRef<Path>,
+    flags: FormatFlags,
+    enrich: impl FnMut(&mut OutputRecord) + Send + 'static,
+) -> Records {
+    stream_fingerprints(pcap, flags).enrich(enrich)
+}
+
 #[derive(Debug, Clone, Copy, Default)]
-pub(crate) struct FormatFlags {
+pub struct FormatFlags {
     /// Whether to add raw (unhashed) fingerprints to the output.
     ///
-    /// Corresponds to [`Cli::with_raw`].
-    pub(crate) with_raw: bool,
+    /// Corresponds to `--with-raw` command line option.
+    pub with_raw: bool,
     /// Whether to preserve the original order of values.
     ///
-    /// Corresponds to [`Cli::original_order`].
-    pub(crate) original_order: bool,
+    /// Corresponds to `--original-order` command line option.
+    pub original_order: bool,
+    /// Whether to add exact counts of the values that fingerprints summarize,
+    /// e.g. `cipher_count`.
+    ///
+    /// Corresponds to `--with-details` command line option.
+    pub with_details: bool,
 }
 
 /// Which side of the connection sent the packet?
@@ -144,10 +850,11 @@ pub(crate) enum Sender {
     Server,
 }
 
-/// Returns first 12 characters of the SHA-256 hash of the given string.
+/// Returns first 12 characters of the SHA-256 hash of the given string, the hash
+/// that the fingerprints are made of.
 ///
 /// Returns `"000000000000"` (12 zeros) if the input string is empty.
-fn hash12(s: impl AsRef<str>) -> String {
+pub fn hash12(s: impl AsRef<str>) -> String {
     use sha2::{Digest as _, Sha256};
 
     let s = s.as_ref();
@@ -165,28 +872,190 @@ fn test_hash12() {
     assert_eq!(hash12(""), "000000000000");
 }
 
-fn check_tshark_version() -> Result<()> {
+/// Warns that `--keylog-file` has not decrypted any TLS session.
+fn warn_decryption_failed() {
     use owo_colors::OwoColorize as _;
 
-    let out = duct::cmd!("tshark", "--version")
+    let warning = "⚠️  The key log file has not decrypted any TLS session.\n\
+        The keys may not match the capture file. Fingerprints that require \
+        decryption (e.g. JA4H of HTTPS traffic) are missing; the affected \
+        records have `decryption_failed: true`.";
+    eprintln!("{}", warning.bold().red());
+}
+
+/// Returns the version of tshark and whether it meets `required`.
+fn tshark_version(
+    program: &tshark::Program,
+    required: &semver::VersionReq,
+) -> Result<(semver::Version, bool)> {
+    let out = program
+        .cmd(["--version"])
         .read()
         .map_err(|e| Error::TsharkNotFound { source: e })?;
     tracing::debug!(%out, "tshark --version");
 
     let ver = parse_tshark_version(&out).ok_or(Error::ParseTsharkVersion)?;
     let available = semver::Version::parse(ver)?;
+    let ok = required.matches(&available);
+    Ok((available, ok))
+}
+
+/// Checks that tshark is one of the supported versions (`tshark_min_version`), as
+/// `tshark_version_check` setting tells. Returns the version of tshark, if known.
+fn check_tshark_version(program: &tshark::Program, conf: &Conf) -> Result<Option<semver::Version>> {
+    use owo_colors::OwoColorize as _;
 
-    let required = semver::VersionReq::parse(">=4.0.6").expect("BUG");
-    if !required.matches(&available) {
+    let required = conf.tshark_requirement();
+    if conf.tshark_version_check == VersionCheck::Off {
+        return Ok(tshark_version(program, &required)
+            .ok()
+            .map(|(available, _)| available));
+    }
+    let (available, ok) = tshark_version(program, &required)?;
+    if !ok {
+        if conf.tshark_version_check == VersionCheck::Error {
+            return Err(Error::TsharkUnsupported {
+                available,
+                required,
+            });
+        }
         tracing::warn!(%available, %required, "tshark version is outdated");
         let warning = format!(
             "⚠️  You are running an older version of tshark ({available}).\n\
-            JA4 is designed to work with tshark version 4.0.6 and above.\n\
-            Some functionality may not work properly with older versions."
+            JA4 is designed to work with tshark version {} and above.\n\
+            Some functionality may not work properly with older versions.",
+            conf.tshark_min_version
         );
         eprintln!("{}", warning.bold().red());
     }
-    Ok(())
+    Ok(Some(available))
+}
+
+#[cfg(unix)]
+#[test]
+fn test_check_tshark_version() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("ja4-test-tshark-{}", std::process::id()));
+    fs_err::create_dir_all(&dir).unwrap();
+    let path = dir.join("tshark");
+    fs_err::write(
+        &path,
+        "#!/bin/sh\necho 'TShark (Wireshark) 3.6.2 (Git v3.6.2 packaged as 3.6.2-2)'\n",
+    )
+    .unwrap();
+    fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let program = tshark::Program::new(Some(path), Vec::new()).unwrap();
+    let conf = |min_version, check| {
+        Conf::builder()
+            .tshark_min_version(min_version)
+            .tshark_version_check(check)
+            .build()
+            .unwrap()
+    };
+    let old = semver::Version::new(3, 6, 2);
+
+    let version = check_tshark_version(&program, &conf("4.0.6", VersionCheck::Warn)).unwrap();
+    assert_eq!(version, Some(old.clone()));
+    let version = check_tshark_version(&program, &conf("4.0.6", VersionCheck::Off)).unwrap();
+    assert_eq!(version, Some(old.clone()));
+    let version = check_tshark_version(&program, &conf("3.6.0", VersionCheck::Error)).unwrap();
+    assert_eq!(version, Some(old));
+
+    let error = check_tshark_version(&program, &conf("4.0.6", VersionCheck::Error)).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "tshark 3.6.2 is not supported, >=4.0.6 is required (see --tshark-version-check)"
+    );
+    assert_eq!(error.kind(), "tshark_unsupported");
+    assert_eq!(error.exit_code(), 3);
+    fs_err::remove_dir_all(&dir).unwrap();
+
+    let error = Conf::builder()
+        .tshark_min_version("4.2")
+        .build()
+        .unwrap_err();
+    assert_eq!(error.kind(), "config");
+    assert_eq!(error.exit_code(), 1);
+}
+
+/// Versions of ja4 and tshark, printed by `--version --json`.
+#[derive(Debug, serde::Serialize)]
+struct VersionInfo {
+    ja4_version: &'static str,
+    /// `None` if tshark cannot be run or its version cannot be parsed.
+    tshark_version: Option<String>,
+    /// Whether the version of tshark is `required_tshark`.
+    tshark_ok: bool,
+    /// Supported tshark versions, e.g. `>=4.0.6` (`tshark_min_version`).
+    required_tshark: String,
+}
+
+impl VersionInfo {
+    fn new(tshark: Result<(semver::Version, bool)>, required: &semver::VersionReq) -> Self {
+        let tshark = tshark
+            .inspect_err(|error| tracing::warn!(%error, "failed to get tshark version"))
+            .ok();
+        Self {
+            ja4_version: env!("CARGO_PKG_VERSION"),
+            tshark_version: tshark.as_ref().map(|(version, _)| version.to_string()),
+            tshark_ok: tshark.is_some_and(|(_, ok)| ok),
+            required_tshark: required.to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_version_info() {
+    let cli = Cli::try_parse_from(["ja4", "--version"]).unwrap();
+    let mut output = Vec::new();
+    cli.run(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!("ja4 {}\n", env!("CARGO_PKG_VERSION"))
+    );
+
+    let required = semver::VersionReq::parse(">=4.0.6").unwrap();
+    let info = VersionInfo::new(Ok((semver::Version::new(4, 2, 5), true)), &required);
+    let json = serde_json::to_string(&info)
+        .unwrap()
+        .replace(env!("CARGO_PKG_VERSION"), "X.Y.Z");
+    expect_test::expect![[r#"{"ja4_version":"X.Y.Z","tshark_version":"4.2.5","tshark_ok":true,"required_tshark":">=4.0.6"}"#]]
+        .assert_eq(&json);
+    let info = VersionInfo::new(Err(Error::ParseTsharkVersion), &required);
+    let json = serde_json::to_string(&info)
+        .unwrap()
+        .replace(env!("CARGO_PKG_VERSION"), "X.Y.Z");
+    expect_test::expect![[r#"{"ja4_version":"X.Y.Z","tshark_version":null,"tshark_ok":false,"required_tshark":">=4.0.6"}"#]]
+        .assert_eq(&json);
+}
+
+/// Checks the shape of a `--decode-as` value: `<layer>==<selector>,<protocol>`.
+///
+/// Anything more specific is left to tshark to report.
+fn parse_decode_as(spec: &str) -> std::result::Result<String, String> {
+    let valid = spec.split_once("==").is_some_and(|(layer, rest)| {
+        !layer.is_empty()
+            && rest
+                .rsplit_once(',')
+                .is_some_and(|(selector, proto)| !selector.is_empty() && !proto.is_empty())
+    });
+    if valid {
+        Ok(spec.to_owned())
+    } else {
+        Err("expected <layer>==<selector>,<protocol>, e.g. tcp.port==8888,http".to_owned())
+    }
+}
+
+#[test]
+fn test_parse_decode_as() {
+    assert!(parse_decode_as("tcp.port==8888,http").is_ok());
+    assert!(parse_decode_as("tcp.port==8443-8445,tls").is_ok());
+    assert!(parse_decode_as("tcp.port==2222,ssh").is_ok());
+    assert!(parse_decode_as("tcp.port==8888").is_err());
+    assert!(parse_decode_as("tcp.port,http").is_err());
+    assert!(parse_decode_as("==8888,http").is_err());
+    assert!(parse_decode_as("tcp.port==8888,").is_err());
 }
 
 /// Parses the version number from the output of `tshark --version`.
@@ -220,20 +1089,329 @@ fn test_insta() {
         concat!(env!("CARGO_MANIFEST_DIR"), "/../.."),
         "pcap/*.pcap*",
         |path| {
-            let cli = Cli {
-                json: false,
-                with_raw: false,
-                original_order: false,
-                keylog_file: None,
-                with_packet_numbers: false,
-                pcap: path.to_path_buf(),
-            };
-
             let mut output = Vec::<u8>::new();
-            cli.run(&mut output).unwrap();
+            Processor::builder()
+                .build()
+                .run_pcap(path, &mut output)
+                .unwrap();
             let output = String::from_utf8(output).unwrap();
 
             insta::assert_snapshot!(output);
+
+            let mut output = Vec::<u8>::new();
+            Processor::builder()
+                .json(true)
+                .with_packet_numbers(true)
+                .build()
+                .run_pcap(path, &mut output)
+                .unwrap();
+            assert_lists_in_packet_order(std::str::from_utf8(&output).unwrap());
+
+            // Every fingerprint matches its raw form.
+            let mut output = Vec::<u8>::new();
+            Processor::builder()
+                .json(true)
+                .validate(true)
+                .build()
+                .run_pcap(path, &mut output)
+                .unwrap();
+            assert_eq!(std::str::from_utf8(&output).unwrap(), "");
         }
     );
 }
+
+/// Checks that the lists of the records --- HTTP requests, certificates --- are in
+/// the order of their packets, see [`test_insta`].
+#[cfg(test)]
+fn assert_lists_in_packet_order(json_lines: &str) {
+    fn check(rec: &serde_json::Value) {
+        for (list, pkt) in [("http", "pkt_ja4h"), ("tls_certs", "pkt_x509")] {
+            let nums = rec[list]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|item| item[pkt].as_u64().unwrap())
+                .collect::<Vec<_>>();
+            assert!(nums.windows(2).all(|w| w[0] <= w[1]), "{list}: {nums:?}");
+        }
+        if let Some(inner) = rec.get("tls_inner") {
+            check(inner);
+        }
+    }
+    for line in json_lines.lines() {
+        check(&serde_json::from_str(line).unwrap());
+    }
+}
+
+#[cfg(not(windows))]
+/// `--tshark-output ek` produces the same output as the default, see [`test_insta`].
+#[test]
+fn test_insta_tshark_output_ek() {
+    let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+    for entry in std::fs::read_dir(pcap_dir).unwrap() {
+        let pcap = entry.unwrap().path();
+        let name = pcap.file_name().unwrap().to_str().unwrap();
+        if !pcap
+            .extension()
+            .is_some_and(|ext| ext.to_str().unwrap().starts_with("pcap"))
+        {
+            continue;
+        }
+        let cli = Cli::parse_from(["ja4", "--tshark-output", "ek", pcap.to_str().unwrap()]);
+        let mut output = Vec::<u8>::new();
+        cli.run(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let snapshot = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/snapshots")
+            .join(format!("ja4__insta@{name}.snap"));
+        let snapshot = std::fs::read_to_string(snapshot).unwrap();
+        let (_header, expected) = snapshot.split_once("\n---\n").unwrap();
+        assert_eq!(output.trim_end(), expected.trim_end(), "{name}");
+    }
+}
+
+#[cfg(not(windows))]
+/// SSH on a port other than 22 gets the same fingerprints once the port is listed in
+/// `[ports]` table of the configuration. The capture is `ssh2-moloch-crash.pcap`
+/// moved to port 2222; it is not named `*.pcap` as [`test_insta`] would run it
+/// without the configuration.
+#[test]
+fn test_insta_ssh_port_2222() {
+    let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+    let conf = Conf::builder().ports([], [2222], []).build().unwrap();
+    let mut output = Vec::<u8>::new();
+    Processor::builder()
+        .conf(conf)
+        .build()
+        .run_pcap(pcap_dir.join("ssh2-port-2222.cap"), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let snapshot = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/snapshots/ja4__insta@ssh2-moloch-crash.pcap.snap");
+    let snapshot = std::fs::read_to_string(snapshot).unwrap();
+    let (_header, expected) = snapshot.split_once("\n---\n").unwrap();
+    let expected = expected.replace("dst_port: 22\n", "dst_port: 2222\n");
+    assert!(output.contains("ja4ssh:"), "{output}");
+    assert_eq!(output.trim_end(), expected.trim_end());
+}
+
+/// `--merge` outputs the same records as a tshark process per file, except for the
+/// stream numbers.
+#[cfg(not(windows))]
+#[test]
+fn test_insta_merge() {
+    let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+    let pcaps = ["tls12.pcap", "socks4-https.pcap", "http1.pcapng"]
+        .map(|name| pcap_dir.join(name).to_str().unwrap().to_owned());
+    let run = |merge| {
+        let mut args = vec!["ja4", "--json", "--jobs=1"];
+        if merge {
+            args.push("--merge");
+        }
+        args.extend(pcaps.iter().map(String::as_str));
+        let mut output = Vec::<u8>::new();
+        Cli::parse_from(args).run(&mut output).unwrap();
+        std::str::from_utf8(&output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut rec = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                rec.as_object_mut().unwrap().remove("stream");
+                rec
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let separate = run(false);
+    for pcap in &pcaps {
+        assert!(
+            separate.iter().any(|rec| rec["pcap"] == pcap.as_str()),
+            "{pcap}"
+        );
+    }
+    assert_eq!(run(true), separate);
+}
+
+#[test]
+fn test_insta_skip_empty() {
+    let pcap = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../pcap/quic-gquic-mixed.pcap"
+    );
+    let cli = Cli::parse_from(["ja4", "--skip-empty", pcap]);
+
+    let mut output = Vec::<u8>::new();
+    cli.run(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    // The QUIC streams with unsupported versions have no fingerprints.
+    insta::assert_snapshot!(output);
+}
+
+/// `--experimental`: TLS tunneled in a WebSocket connection. The capture has a
+/// `.cap` extension, so that [`test_insta`] doesn't pick it up.
+#[test]
+fn test_insta_experimental_websocket() {
+    let pcap = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../pcap/tls-over-websocket.cap"
+    );
+    let run = |experimental| {
+        let mut output = Vec::<u8>::new();
+        Processor::builder()
+            .json(true)
+            .experimental(experimental)
+            .build()
+            .run_pcap(pcap, &mut output)
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()
+    };
+
+    let rec = run(false);
+    assert_eq!(rec["http"][0]["websocket"], true);
+    assert!(rec.get("tunnel").is_none());
+    assert!(rec.get("ja4").is_none());
+
+    let rec = run(true);
+    assert_eq!(rec["tunnel"], "websocket");
+    assert_eq!(rec["tunnel_dst"], "example.com:8080");
+    assert_eq!(rec["ja4"], "t13d1516h2_8daaf6152771_e5627efa2ab1");
+    assert_eq!(rec["tls_server_name"], "example.com");
+}
+
+#[test]
+fn test_capture_filter_requires_interface() {
+    use clap::error::ErrorKind;
+
+    let err = Cli::try_parse_from(["ja4", "-f", "tcp port 443", "in.pcap"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    let err = Cli::try_parse_from(["ja4", "-f", "tcp port 443"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    let err = Cli::try_parse_from(["ja4", "-i", "eth0", "in.pcap"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+
+    let cli = Cli::try_parse_from(["ja4", "-i", "eth0", "-f", "tcp port 443"]).unwrap();
+    assert_eq!(cli.capture_filter.as_deref(), Some("tcp port 443"));
+    assert!(cli.pcap.is_empty());
+}
+
+#[test]
+fn test_config_init() {
+    use clap::error::ErrorKind;
+
+    let cli = Cli::try_parse_from(["ja4", "config", "init"]).unwrap();
+    let mut output = Vec::new();
+    cli.run(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), conf::default_config());
+
+    let err = Cli::try_parse_from(["ja4", "config", "init", "--force"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+}
+
+#[test]
+fn test_output_options() {
+    // Absent options leave the choice to `[output]` table of the configuration.
+    let cli = Cli::try_parse_from(["ja4", "in.pcap"]).unwrap();
+    assert_eq!(cli.json, None);
+    assert_eq!(cli.with_raw, None);
+
+    let cli = Cli::try_parse_from(["ja4", "--json", "-r", "-O=false", "in.pcap"]).unwrap();
+    assert_eq!(cli.json, Some(true));
+    assert_eq!(cli.with_raw, Some(true));
+    assert_eq!(cli.original_order, Some(false));
+    assert_eq!(cli.with_packet_numbers, None);
+    assert_eq!(cli.pcap, [Path::new("in.pcap")]);
+
+    let cli = Cli::try_parse_from([
+        "ja4",
+        "--json=false",
+        "--with-packet-numbers=true",
+        "in.pcap",
+    ])
+    .unwrap();
+    assert_eq!(cli.json, Some(false));
+    assert_eq!(cli.with_packet_numbers, Some(true));
+    assert!(Cli::try_parse_from(["ja4", "--json=yes", "in.pcap"]).is_err());
+}
+
+#[test]
+fn test_engine_options() {
+    // `--engine tshark` is the default; giving it doesn't rule out any option.
+    let cli = Cli::try_parse_from([
+        "ja4",
+        "--engine",
+        "tshark",
+        "--since",
+        "+10s",
+        "--max-streams",
+        "5",
+        "--resolve-dns",
+        "in.pcap",
+    ])
+    .unwrap();
+    assert_eq!(cli.engine, Engine::Tshark);
+    assert!(cli.since.is_some() && cli.resolve_dns);
+
+    // With `--engine native`, these options are an error before anything is read.
+    let cli =
+        Cli::try_parse_from(["ja4", "--engine", "native", "--until", "+1m", "in.pcap"]).unwrap();
+    let error = cli.run(&mut Vec::new()).unwrap_err();
+    assert!(matches!(error, Error::NativeEngine("--until")), "{error}");
+    assert_eq!(error.kind(), "native_engine");
+}
+
+#[test]
+fn test_multiple_pcaps() {
+    let cli = Cli::try_parse_from(["ja4", "--jobs", "2", "a.pcap", "b.pcapng"]).unwrap();
+    assert_eq!(cli.jobs, Some(2));
+    assert_eq!(cli.pcap, [Path::new("a.pcap"), Path::new("b.pcapng")]);
+    assert!(Cli::try_parse_from(["ja4", "--jobs", "0", "a.pcap"]).is_err());
+}
+
+#[test]
+fn test_rotate_requires_json_output() {
+    use clap::error::ErrorKind;
+
+    let err = Cli::try_parse_from(["ja4", "-i", "eth0", "--rotate-size", "1000"]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    // JSON output may come from the configuration file; see `Processor::run`.
+    Cli::try_parse_from([
+        "ja4",
+        "-i",
+        "eth0",
+        "-o",
+        "ja4.ndjson",
+        "--rotate-size",
+        "1000",
+    ])
+    .unwrap();
+    let err = Cli::try_parse_from([
+        "ja4",
+        "-i",
+        "eth0",
+        "-j",
+        "--pretty",
+        "-o",
+        "ja4.json",
+        "--rotate-interval",
+        "60",
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+
+    let cli = Cli::try_parse_from([
+        "ja4",
+        "-i",
+        "eth0",
+        "-j",
+        "-o",
+        "ja4.ndjson",
+        "--rotate-interval",
+        "3600",
+    ])
+    .unwrap();
+    assert_eq!(cli.output.as_deref(), Some(Path::new("ja4.ndjson")));
+    assert_eq!(cli.rotate_interval, Some(3600));
+}
@@ -21,6 +21,10 @@ fn main() -> eyre::Result<()> {
 
     match ja4::Cli::parse().run(&mut io::stdout()) {
         Err(ja4::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::BrokenPipe) => Ok(()),
+        Err(e) if e.exit_code() != 1 => {
+            eprintln!("Error: {e}");
+            std::process::exit(e.exit_code());
+        }
         Err(e) => Err(e.into()),
         Ok(()) => Ok(()),
     }
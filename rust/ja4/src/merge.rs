@@ -0,0 +1,244 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Processing several capture files with a single tshark process (`--merge`)
+//!
+//! Starting tshark takes longer than dissecting a small capture file. With
+//! `--merge` the files are concatenated by `mergecap -a` into a temporary pcapng
+//! file, which one tshark process reads. The packets of every input file are
+//! counted beforehand, so the frame numbers of the merged file tell which file a
+//! packet comes from; the streams of each file are fingerprinted separately, as
+//! without `--merge`.
+//!
+//! The separation is not complete: tshark keeps the state of a TCP connection
+//! across the files, so a message split between the end of one file and the
+//! beginning of the next is reassembled and attributed to the latter. This only
+//! matters for files cut from one capture, e.g. by `dumpcap -b`. Compressed files
+//! are rejected, since their packets cannot be counted without decompressing them.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    native::{self, read_u32},
+    tshark::Program,
+    Error, Result,
+};
+
+/// Input file of a merged capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Part<'a> {
+    pub(crate) pcap: &'a Path,
+    /// Frame number of the last packet of this file in the merged capture.
+    pub(crate) last_frame: usize,
+}
+
+/// Temporary capture file holding the merged inputs. Deleted when dropped.
+pub(crate) struct Merged<'a> {
+    path: PathBuf,
+    pub(crate) parts: Vec<Part<'a>>,
+}
+
+impl<'a> Merged<'a> {
+    /// Counts the packets of the capture files and concatenates them.
+    pub(crate) fn new(program: &Program, pcaps: &'a [PathBuf]) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let mut parts = Vec::with_capacity(pcaps.len());
+        let mut last_frame = 0;
+        for pcap in pcaps {
+            let data = native::map(pcap)?;
+            last_frame += count_packets(&data).map_err(|reason| Error::Merge {
+                path: pcap.clone(),
+                reason: reason.to_owned(),
+            })?;
+            parts.push(Part { pcap, last_frame });
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "ja4-merge-{}-{}.pcapng",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let merged = Self { path, parts };
+        let mut args = ["-a", "-F", "pcapng", "-w"].map(OsString::from).to_vec();
+        args.push(merged.path.clone().into());
+        args.extend(pcaps.iter().map(OsString::from));
+        let out = duct::cmd(mergecap_path(program), args)
+            .stdout_null()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|e| Error::MergecapNotFound { source: e })?;
+        if !out.status.success() {
+            let message = String::from_utf8_lossy(&out.stderr);
+            return Err(Error::Mergecap(
+                message.trim().trim_start_matches("mergecap: ").to_owned(),
+            ));
+        }
+        Ok(merged)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Merged<'_> {
+    fn drop(&mut self) {
+        // mergecap may have failed before creating the file.
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Returns the `mergecap` next to the tshark of `--tshark-path`, or the one in
+/// `PATH`.
+fn mergecap_path(program: &Program) -> PathBuf {
+    let tshark = program.path();
+    if tshark.parent().is_none_or(|dir| dir.as_os_str().is_empty()) {
+        return PathBuf::from("mergecap");
+    }
+    let mut path = tshark.with_file_name("mergecap");
+    if let Some(ext) = tshark.extension() {
+        path.set_extension(ext);
+    }
+    path
+}
+
+/// Returns the frame number that tshark gives to the packet.
+pub(crate) fn frame_number(packet: &rtshark::Packet) -> Option<usize> {
+    packet
+        .layer_name("frame")?
+        .metadata("frame.number")?
+        .value()
+        .parse()
+        .ok()
+}
+
+// Block types of pcapng.
+// Reference: <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html>
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const OBSOLETE_PACKET: u32 = 0x0000_0002;
+const SIMPLE_PACKET: u32 = 0x0000_0003;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+
+/// Returns the number of packets in a pcap or pcapng file. A truncated record ends
+/// the file, as it does for tshark.
+fn count_packets(data: &[u8]) -> std::result::Result<usize, &'static str> {
+    match data.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1]) => Ok(count_pcap(data, false)),
+        Some([0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d]) => Ok(count_pcap(data, true)),
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => count_pcapng(data),
+        _ => Err("file format other than pcap or pcapng"),
+    }
+}
+
+fn count_pcap(data: &[u8], big_endian: bool) -> usize {
+    const HEADER_LEN: usize = 24;
+    const RECORD_HEADER_LEN: usize = 16;
+
+    let mut records = data.get(HEADER_LEN..).unwrap_or_default();
+    let mut count = 0;
+    while let Some(header) = records.get(..RECORD_HEADER_LEN) {
+        let captured_len = read_u32(&header[8..], big_endian) as usize;
+        let Some(rest) = records.get(RECORD_HEADER_LEN + captured_len..) else {
+            break;
+        };
+        records = rest;
+        count += 1;
+    }
+    count
+}
+
+fn count_pcapng(data: &[u8]) -> std::result::Result<usize, &'static str> {
+    let mut blocks = data;
+    let mut big_endian = false;
+    let mut count = 0;
+    while let Some(header) = blocks.get(..12) {
+        // The byte order of a section is that of its header's byte-order magic.
+        if read_u32(header, false) == SECTION_HEADER {
+            big_endian = match header[8..12] {
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                _ => return Err("invalid pcapng section header"),
+            };
+        }
+        let block_type = read_u32(header, big_endian);
+        let len = read_u32(&header[4..], big_endian) as usize;
+        if len < 12 {
+            return Err("invalid pcapng block length");
+        }
+        let Some(rest) = blocks.get(len..) else {
+            break;
+        };
+        if matches!(
+            block_type,
+            OBSOLETE_PACKET | SIMPLE_PACKET | ENHANCED_PACKET
+        ) {
+            count += 1;
+        }
+        blocks = rest;
+    }
+    Ok(count)
+}
+
+#[test]
+fn test_count_packets() {
+    let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+    let counts = [
+        "tls12.pcap",
+        "socks4-https.pcap",
+        "http1.pcapng",
+        "tls-handshake.pcapng",
+    ]
+    .map(|name| {
+        let data = native::map(&pcap_dir.join(name)).unwrap();
+        (name, count_packets(&data))
+    });
+    expect_test::expect![[r#"
+        [
+            (
+                "tls12.pcap",
+                Ok(
+                    1,
+                ),
+            ),
+            (
+                "socks4-https.pcap",
+                Ok(
+                    16,
+                ),
+            ),
+            (
+                "http1.pcapng",
+                Ok(
+                    184,
+                ),
+            ),
+            (
+                "tls-handshake.pcapng",
+                Ok(
+                    193,
+                ),
+            ),
+        ]
+    "#]]
+    .assert_debug_eq(&counts);
+
+    // A truncated record ends the capture.
+    let data = native::map(&pcap_dir.join("tls12.pcap")).unwrap();
+    let count = count_packets(&data).unwrap();
+    assert_eq!(count_packets(&data[..data.len() - 1]), Ok(count - 1));
+
+    assert_eq!(
+        count_packets(b"\x1f\x8b\x08\x00"),
+        Err("file format other than pcap or pcapng")
+    );
+}
@@ -0,0 +1,1267 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! JA4, JA4S, and JA4X fingerprinting without tshark (`--engine native`)
+//!
+//! Most of the time of a JA4 run is spent by tshark dissecting every protocol of
+//! every packet. For the common case --- unencrypted TLS handshakes over TCP in a
+//! pcap file --- we can do without it: the capture file is memory-mapped, and the
+//! link layer, IP, TCP, and the TLS handshake messages up to ChangeCipherSpec are
+//! parsed here.
+//!
+//! As soon as the capture contains something that tshark would handle differently
+//! (pcapng format, tunnels, IP fragments, QUIC, TLS started after other data on the
+//! stream, out-of-order TCP segments, ...), we give up on the whole file and the
+//! caller runs tshark. This way the output is always the same as with `--only`
+//! listing the TLS fingerprints.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use crate::{
+    conf::Conf,
+    filter::StreamFilter,
+    select::Selection,
+    stream::{SocketPair, StreamId, Streams},
+    tls::{self, Quality, RawClientHello, RawHandshake, RawServerHello},
+    PacketNum, Sender,
+};
+
+/// Returns the TLS streams of the capture file and the number of packets in it, or
+/// `None` if the file has to be processed by tshark.
+pub(crate) fn tls_streams(
+    path: &Path,
+    conf: &Conf,
+    store_pkt_num: bool,
+    filter: StreamFilter,
+) -> Option<(Streams, usize)> {
+    let data = match map(path) {
+        Ok(data) => data,
+        Err(error) => {
+            tracing::info!(%error, "cannot map the capture file, falling back to tshark");
+            return None;
+        }
+    };
+    let tracker = match track(&data, conf.tls.selection) {
+        Ok(tracker) => tracker,
+        Err(Unsupported(reason)) => {
+            tracing::info!(%reason, "cannot process the capture without tshark");
+            return None;
+        }
+    };
+
+    let mut streams = Streams::new(filter);
+    let nr_packets = tracker.nr_packets;
+    for (id, stream) in tracker.streams.into_iter().enumerate() {
+        if !matches!(stream.state, State::Tls { .. }) {
+            continue;
+        }
+        let tls = tls::Stream::from_raw(stream.handshake, store_pkt_num, &conf.tls);
+        let id = u32::try_from(id).expect("BUG: too many streams");
+        let (src, dst) = stream.sockets;
+        streams.insert_tls(
+            StreamId(id),
+            SocketPair::new(src, dst),
+            stream.first_packet_micros,
+            stream.nr_packets,
+            tls,
+        );
+    }
+    Some((streams, nr_packets))
+}
+
+pub(crate) fn map(path: &Path) -> std::io::Result<memmap2::Mmap> {
+    let file = fs_err::File::open(path)?;
+    // SAFETY: The mapping is read-only. If another process truncates the file while
+    // we are reading it, we get a SIGBUS; this is the price of not copying the data.
+    unsafe { memmap2::Mmap::map(file.file()) }
+}
+
+/// Something that only tshark can process properly.
+#[derive(Debug)]
+pub(crate) struct Unsupported(String);
+
+type Parsed<T> = std::result::Result<T, Unsupported>;
+
+fn unsupported<T>(what: impl Into<String>) -> Parsed<T> {
+    Err(Unsupported(what.into()))
+}
+
+fn track(data: &[u8], selection: Selection) -> Parsed<Tracker> {
+    let capture = Capture::new(data)?;
+    let mut tracker = Tracker::new(selection);
+    for (index, record) in capture.enumerate() {
+        // tshark numbers packets starting from 1.
+        let num = PacketNum(index + 1);
+        tracker.nr_packets += 1;
+        let Some(ip) = capture.link_type.ip_packet(record.frame)? else {
+            continue;
+        };
+        if let Some(segment) = Segment::parse(ip)? {
+            tracker.update(&segment, num, record.micros, record.truncated)?;
+        }
+    }
+    Ok(tracker)
+}
+
+// -----------------------------------------------------------------------------
+// Capture file
+
+/// Reference: <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcap-03.html>
+#[derive(Debug, Clone, Copy)]
+struct Capture<'a> {
+    big_endian: bool,
+    /// Whether timestamps have nanosecond (rather than microsecond) resolution.
+    nanos: bool,
+    link_type: LinkType,
+    /// Packet records following the file header.
+    records: &'a [u8],
+}
+
+impl<'a> Capture<'a> {
+    const HEADER_LEN: usize = 24;
+    const RECORD_HEADER_LEN: usize = 16;
+
+    fn new(data: &'a [u8]) -> Parsed<Self> {
+        let (big_endian, nanos) = match data.get(..4) {
+            Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, false),
+            Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, true),
+            Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, false),
+            Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, true),
+            _ => return unsupported("file format other than pcap"),
+        };
+        let Some(header) = data.get(..Self::HEADER_LEN) else {
+            return unsupported("truncated pcap file header");
+        };
+        // The upper bits of the field carry FCS length, which we don't need.
+        let link_type = read_u32(&header[20..], big_endian) & 0xffff;
+        Ok(Self {
+            big_endian,
+            nanos,
+            link_type: LinkType::new(link_type)?,
+            records: &data[Self::HEADER_LEN..],
+        })
+    }
+}
+
+impl<'a> Iterator for Capture<'a> {
+    type Item = Record<'a>;
+
+    /// Returns the next packet. A truncated record ends the capture, as it does for
+    /// tshark.
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.records.get(..Self::RECORD_HEADER_LEN)?;
+        let secs = i64::from(read_u32(header, self.big_endian));
+        let frac = i64::from(read_u32(&header[4..], self.big_endian));
+        let captured_len = read_u32(&header[8..], self.big_endian) as usize;
+        let original_len = read_u32(&header[12..], self.big_endian) as usize;
+        let end = Self::RECORD_HEADER_LEN.checked_add(captured_len)?;
+        let frame = self.records.get(Self::RECORD_HEADER_LEN..end)?;
+        self.records = &self.records[end..];
+        Some(Record {
+            micros: secs * 1_000_000 + if self.nanos { frac / 1000 } else { frac },
+            frame,
+            truncated: captured_len < original_len,
+        })
+    }
+}
+
+/// Packet record of a pcap file.
+struct Record<'a> {
+    /// Timestamp, in microseconds since the Unix epoch.
+    micros: i64,
+    /// Captured bytes of the packet.
+    frame: &'a [u8],
+    /// Some bytes of the packet were not captured (snapshot length).
+    truncated: bool,
+}
+
+/// Reference: <https://www.tcpdump.org/linktypes.html>
+#[derive(Debug, Clone, Copy)]
+enum LinkType {
+    Ethernet,
+    /// BSD loopback encapsulation (`LINKTYPE_NULL`, `LINKTYPE_LOOP`).
+    Loopback,
+    /// Raw IPv4 or IPv6 packets.
+    Raw,
+    /// Linux cooked capture, version 1 (`tcpdump -i any`).
+    LinuxSll,
+    /// Linux cooked capture, version 2.
+    LinuxSll2,
+}
+
+impl LinkType {
+    fn new(link_type: u32) -> Parsed<Self> {
+        Ok(match link_type {
+            1 => Self::Ethernet,
+            0 | 108 => Self::Loopback,
+            101 | 228 | 229 => Self::Raw,
+            113 => Self::LinuxSll,
+            276 => Self::LinuxSll2,
+            _ => return unsupported(format!("link type {link_type}")),
+        })
+    }
+
+    /// Returns the IP packet carried by the frame, or `None` if it's not IP.
+    fn ip_packet(self, frame: &[u8]) -> Parsed<Option<&[u8]>> {
+        let (ether_type, offset) = match self {
+            Self::Ethernet => {
+                let mut offset = 12;
+                loop {
+                    let Some(ether_type) = frame.get(offset..).and_then(read_u16_be) else {
+                        return Ok(None);
+                    };
+                    // IEEE 802.1Q and 802.1ad VLAN tags.
+                    if matches!(ether_type, 0x8100 | 0x88a8 | 0x9100) {
+                        offset += 4;
+                    } else {
+                        break (ether_type, offset + 2);
+                    }
+                }
+            }
+            Self::Loopback => return Ok(frame.get(4..)),
+            Self::Raw => return Ok(Some(frame)),
+            Self::LinuxSll => match frame.get(14..).and_then(read_u16_be) {
+                Some(ether_type) => (ether_type, 16),
+                None => return Ok(None),
+            },
+            Self::LinuxSll2 => match read_u16_be(frame) {
+                Some(ether_type) => (ether_type, 20),
+                None => return Ok(None),
+            },
+        };
+        match ether_type {
+            0x0800 | 0x86dd => Ok(frame.get(offset..)),
+            // MPLS, PPPoE, transparent Ethernet bridging.
+            0x8847 | 0x8848 | 0x8864 | 0x6558 => {
+                unsupported(format!("EtherType {ether_type:#06x}"))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// IP, TCP, UDP
+
+/// TCP segment.
+#[derive(Debug)]
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    /// SYN without ACK.
+    is_syn: bool,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// Returns `None` for packets other than TCP.
+    fn parse(ip: &'a [u8]) -> Parsed<Option<Self>> {
+        let Some(IpPacket {
+            src,
+            dst,
+            proto,
+            payload,
+        }) = parse_ip(ip)?
+        else {
+            return Ok(None);
+        };
+        match proto {
+            IPPROTO_TCP => {}
+            IPPROTO_UDP => {
+                check_udp(payload)?;
+                return Ok(None);
+            }
+            // IP-in-IP, IPv6-in-IP, GRE, EtherIP, L2TPv3, MPLS-in-IP.
+            4 | 41 | 47 | 97 | 115 | 137 => {
+                return unsupported(format!("tunneled traffic (IP protocol {proto})"))
+            }
+            _ => return Ok(None),
+        }
+
+        let (Some(ports), Some(seq), Some(&offset), Some(&flags)) = (
+            payload.get(..4),
+            payload.get(4..).and_then(read_u32_be),
+            payload.get(12),
+            payload.get(13),
+        ) else {
+            return Ok(None);
+        };
+        const SYN: u8 = 0x02;
+        const ACK: u8 = 0x10;
+        let header_len = usize::from(offset >> 4) * 4;
+        if header_len < 20 {
+            // Corrupt data offset; the payload would start inside the header.
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            src: SocketAddr::new(src, u16::from_be_bytes([ports[0], ports[1]])),
+            dst: SocketAddr::new(dst, u16::from_be_bytes([ports[2], ports[3]])),
+            seq,
+            is_syn: flags & (SYN | ACK) == SYN,
+            payload: payload.get(header_len..).unwrap_or_default(),
+        }))
+    }
+}
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+struct IpPacket<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    /// Protocol of the payload (IPv4) or the upper-layer header (IPv6).
+    proto: u8,
+    payload: &'a [u8],
+}
+
+fn parse_ip(ip: &[u8]) -> Parsed<Option<IpPacket<'_>>> {
+    match ip.first().map(|b| b >> 4) {
+        Some(4) if ip.len() >= 20 => {
+            let header_len = usize::from(ip[0] & 0x0f) * 4;
+            // A corrupt IHL, or options cut off by the snapshot length.
+            if header_len < 20 || header_len > ip.len() {
+                return Ok(None);
+            }
+            let total_len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+            let fragment = u16::from_be_bytes([ip[6], ip[7]]);
+            // More Fragments flag or a non-zero fragment offset.
+            if fragment & 0x3fff != 0 {
+                return unsupported("IP fragments");
+            }
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&ip[12..16]).expect("BUG"));
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&ip[16..20]).expect("BUG"));
+            // Ethernet frames may be padded beyond the end of the IP packet.
+            let end = total_len.clamp(header_len, ip.len());
+            let payload = ip.get(header_len..end).unwrap_or_default();
+            Ok(Some(IpPacket {
+                src: src.into(),
+                dst: dst.into(),
+                proto: ip[9],
+                payload,
+            }))
+        }
+        Some(6) if ip.len() >= 40 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[8..24]).expect("BUG"));
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[24..40]).expect("BUG"));
+            let payload_len = usize::from(u16::from_be_bytes([ip[4], ip[5]]));
+            let mut next_header = ip[6];
+            let mut payload = &ip[40..(40 + payload_len).min(ip.len())];
+            // Hop-by-Hop Options, Routing, Destination Options, Mobility, HIP, Shim6
+            // extension headers precede the upper-layer header.
+            while matches!(next_header, 0 | 43 | 60 | 135 | 139 | 140) {
+                let Some(&[nh, len, ..]) = payload.get(..2) else {
+                    return Ok(None);
+                };
+                let Some(rest) = payload.get((usize::from(len) + 1) * 8..) else {
+                    return Ok(None);
+                };
+                (next_header, payload) = (nh, rest);
+            }
+            if next_header == 44 {
+                return unsupported("IP fragments");
+            }
+            Ok(Some(IpPacket {
+                src: src.into(),
+                dst: dst.into(),
+                proto: next_header,
+                payload,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Checks that the UDP datagram carries nothing tshark would fingerprint.
+fn check_udp(udp: &[u8]) -> Parsed<()> {
+    /// VXLAN, Geneve, GTP-U, L2TP, CAPWAP, Teredo, TZSP.
+    const TUNNEL_PORTS: [u16; 8] = [4789, 6081, 2152, 1701, 5246, 5247, 3544, 37008];
+
+    let (Some(src_port), Some(dst_port)) = (read_u16_be(udp), udp.get(2..).and_then(read_u16_be))
+    else {
+        return Ok(());
+    };
+    if let Some(port) = [src_port, dst_port]
+        .into_iter()
+        .find(|port| TUNNEL_PORTS.contains(port))
+    {
+        return unsupported(format!("tunneled traffic (UDP port {port})"));
+    }
+    // QUIC and gQUIC long header packets; see `tls::QuicVersion`.
+    let payload = udp.get(8..).unwrap_or_default();
+    if let Some(&[first, ref version @ ..]) = payload.get(..5) {
+        let version = u32::from_be_bytes(version.try_into().expect("BUG"));
+        let is_known_version = matches!(version, 1 | 0x6b33_43cf | 0xff00_0000..=0xff00_00ff)
+            || version.to_be_bytes()[0] == b'Q';
+        if first & 0x80 != 0 && is_known_version {
+            return unsupported("QUIC");
+        }
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// TCP streams
+
+/// TCP streams, numbered the same way as tshark's `tcp.stream`.
+#[derive(Debug, Default)]
+struct Tracker {
+    selection: Selection,
+    /// Stream IDs by the endpoints of the stream, the smaller one first.
+    ids: HashMap<(SocketAddr, SocketAddr), usize>,
+    /// Streams in the order of their IDs.
+    streams: Vec<TcpStream>,
+    /// Number of packets in the capture, TCP or not.
+    nr_packets: usize,
+}
+
+#[derive(Debug)]
+struct TcpStream {
+    /// Source and destination of the earliest packet.
+    sockets: (SocketAddr, SocketAddr),
+    /// Timestamp of the earliest packet, in microseconds since the Unix epoch.
+    first_packet_micros: i64,
+    /// Sequence number of the SYN packet.
+    syn_seq: Option<u32>,
+    nr_packets: usize,
+    state: State,
+    handshake: RawHandshake,
+}
+
+#[derive(Debug)]
+enum State {
+    /// No payload yet.
+    Pending,
+    /// The stream starts with a Client Hello sent by `client`.
+    Tls {
+        client: SocketAddr,
+        from_client: Flight,
+        from_server: Flight,
+    },
+    /// The stream doesn't start with a Client Hello.
+    Other,
+}
+
+impl Tracker {
+    fn new(selection: Selection) -> Self {
+        Self {
+            selection,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a TCP segment. `truncated` tells that the packet was cut short by the
+    /// snapshot length, so the segment has less payload than was sent.
+    fn update(
+        &mut self,
+        segment: &Segment,
+        num: PacketNum,
+        micros: i64,
+        truncated: bool,
+    ) -> Parsed<()> {
+        let Segment {
+            src,
+            dst,
+            seq,
+            is_syn,
+            payload,
+        } = *segment;
+        let key = if src < dst { (src, dst) } else { (dst, src) };
+        let id = *self.ids.entry(key).or_insert_with(|| {
+            self.streams.push(TcpStream {
+                sockets: (src, dst),
+                first_packet_micros: micros,
+                syn_seq: is_syn.then_some(seq),
+                nr_packets: 0,
+                state: State::Pending,
+                handshake: RawHandshake::default(),
+            });
+            self.streams.len() - 1
+        });
+        let stream = &mut self.streams[id];
+        // tshark starts a new stream when a connection reuses the ports of an
+        // earlier one; we don't follow its heuristics.
+        if is_syn && stream.syn_seq != Some(seq) {
+            return unsupported("reused TCP ports");
+        }
+        stream.nr_packets += 1;
+        if payload.is_empty() {
+            return Ok(());
+        }
+
+        // JA4S and JA4X need the rest of the handshake, JA4 only the Client Hello.
+        let whole_handshake = self.selection.ja4s || self.selection.ja4x;
+        match &mut stream.state {
+            State::Pending => {
+                if is_sslv2_client_hello(payload) {
+                    return unsupported("SSL 2.0 Client Hello");
+                }
+                if !starts_with_client_hello(payload) {
+                    // tshark would fingerprint the server's messages even though the
+                    // Client Hello has not been captured.
+                    if whole_handshake && starts_with_handshake(payload) {
+                        return unsupported("TLS handshake without Client Hello");
+                    }
+                    stream.state = State::Other;
+                    return Ok(());
+                }
+                stream.handshake.is_dot_port = [src, dst].iter().any(|socket| socket.port() == 853);
+                stream.state = State::Tls {
+                    client: src,
+                    from_client: Flight::default(),
+                    from_server: Flight::default(),
+                };
+            }
+            State::Tls { client, .. } => {
+                if !whole_handshake && (src != *client || stream.handshake.client_hello.is_some()) {
+                    return Ok(());
+                }
+            }
+            State::Other => {
+                if starts_with_client_hello(payload) {
+                    return unsupported("TLS started after other data (STARTTLS, proxy)");
+                }
+                return Ok(());
+            }
+        }
+
+        let State::Tls {
+            client,
+            from_client,
+            from_server,
+        } = &mut stream.state
+        else {
+            unreachable!();
+        };
+        let is_client = src == *client;
+        let flight = if is_client { from_client } else { from_server };
+        for message in flight.push(seq, payload, truncated)? {
+            let handshake = &mut stream.handshake;
+            match message.msg_type {
+                CLIENT_HELLO if is_client && handshake.client_hello.is_none() => {
+                    if message.fragmented {
+                        return unsupported("Client Hello spanning several TLS records");
+                    }
+                    let mut hello = parse_client_hello(&message.body)?;
+                    hello.quality = message.quality();
+                    if !hello.sni.iter().chain(&hello.alpn).all(|s| is_printable(s)) {
+                        return unsupported("control characters in TLS handshake");
+                    }
+                    handshake.client_hello = Some((hello, num));
+                }
+                SERVER_HELLO if self.selection.ja4s && handshake.server_hello.is_none() => {
+                    let mut hello = parse_server_hello(&message.body)?;
+                    hello.quality = message.quality();
+                    handshake.server_hello = Some((hello, num));
+                }
+                CERTIFICATE if self.selection.ja4x => {
+                    // Same guess as `Streams::update` makes.
+                    let sender = if src == stream.sockets.0 {
+                        Sender::Client
+                    } else {
+                        Sender::Server
+                    };
+                    let certificates = parse_certificate(&message.body)?;
+                    handshake
+                        .certificates
+                        .push((certificates, num, sender, message.quality()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// TLS records sent by one side of a TCP stream, up to the point where they get
+/// encrypted.
+#[derive(Debug, Default)]
+struct Flight {
+    /// Sequence number of the next byte; `None` before the first payload.
+    next_seq: Option<u32>,
+    /// Payload not yet split into TLS records.
+    records: Vec<u8>,
+    /// Handshake data not yet split into messages.
+    handshake: Vec<u8>,
+    /// A record other than handshake (ChangeCipherSpec, alert, application data)
+    /// has been seen. Whatever follows is encrypted or of no interest.
+    done: bool,
+}
+
+/// TLS handshake message.
+#[derive(Debug)]
+struct Message {
+    msg_type: u8,
+    body: Vec<u8>,
+    /// The message spans several TLS records.
+    fragmented: bool,
+    /// The TLS record carrying the message spans several TCP segments.
+    reassembled: bool,
+    /// The message completes in a segment truncated by the snapshot length.
+    truncated: bool,
+}
+
+impl Message {
+    /// Returns the quality of the message as tshark determines it.
+    fn quality(&self) -> Quality {
+        Quality::new(self.truncated, false, self.reassembled)
+    }
+}
+
+impl Flight {
+    /// Adds the payload of a TCP segment, returning the handshake messages it
+    /// completes.
+    ///
+    /// The payload of a `truncated` segment ends the flight: whatever was not
+    /// captured is lost to tshark as well. A handshake message that it cuts short
+    /// is left to tshark, which fingerprints the fields up to the cut.
+    fn push(&mut self, seq: u32, payload: &[u8], truncated: bool) -> Parsed<Vec<Message>> {
+        if self.done {
+            return Ok(Vec::new());
+        }
+        if let Some(next_seq) = self.next_seq {
+            if seq != next_seq {
+                let end = seq.wrapping_add(payload.len() as u32);
+                // A retransmission of the data we already have.
+                if end.wrapping_sub(next_seq) as i32 <= 0 {
+                    return Ok(Vec::new());
+                }
+                return unsupported("out-of-order TCP segments");
+            }
+        }
+        // Bytes of the records that came in earlier segments.
+        let buffered = self.records.len();
+        self.records.extend_from_slice(payload);
+        self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+
+        let mut messages = Vec::new();
+        let mut pos = 0;
+        while let Some(header) = self.records.get(pos..pos + TLS_RECORD_HEADER_LEN) {
+            let &[content_type, major, _, len_hi, len_lo] = header else {
+                unreachable!();
+            };
+            if major != 3 {
+                return unsupported("malformed TLS record");
+            }
+            let end =
+                pos + TLS_RECORD_HEADER_LEN + usize::from(u16::from_be_bytes([len_hi, len_lo]));
+            let Some(fragment) = self.records.get(pos + TLS_RECORD_HEADER_LEN..end) else {
+                break;
+            };
+            let reassembled = pos < buffered;
+            pos = end;
+            if content_type != TLS_HANDSHAKE {
+                self.done = true;
+                self.records.clear();
+                self.handshake.clear();
+                return Ok(messages);
+            }
+            // Bytes of a message that started in an earlier record.
+            let carried = !self.handshake.is_empty();
+            self.handshake.extend_from_slice(fragment);
+            let mut start = 0;
+            while let Some(header) = self.handshake.get(start..start + 4) {
+                let len = usize::from(header[1]) << 16
+                    | usize::from(header[2]) << 8
+                    | usize::from(header[3]);
+                let Some(body) = self.handshake.get(start + 4..start + 4 + len) else {
+                    break;
+                };
+                messages.push(Message {
+                    msg_type: header[0],
+                    body: body.to_vec(),
+                    fragmented: carried && start == 0,
+                    reassembled,
+                    truncated,
+                });
+                start += 4 + len;
+            }
+            self.handshake.drain(..start);
+        }
+        self.records.drain(..pos);
+        if truncated {
+            if !self.records.is_empty() || !self.handshake.is_empty() {
+                return unsupported("TLS handshake message truncated by the snapshot length");
+            }
+            self.done = true;
+        }
+        Ok(messages)
+    }
+}
+
+// -----------------------------------------------------------------------------
+// TLS
+
+pub(crate) const TLS_RECORD_HEADER_LEN: usize = 5;
+/// Content type of handshake records.
+pub(crate) const TLS_HANDSHAKE: u8 = 22;
+
+// Handshake message types.
+pub(crate) const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+
+const EXT_ALPN: u16 = 16;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+
+/// Returns `true` if the data starts with a TLS record carrying a Client Hello.
+fn starts_with_client_hello(data: &[u8]) -> bool {
+    matches!(data, [TLS_HANDSHAKE, 3, _, _, _, CLIENT_HELLO, ..])
+}
+
+/// Returns `true` if the data starts with a TLS handshake record.
+fn starts_with_handshake(data: &[u8]) -> bool {
+    matches!(data, [TLS_HANDSHAKE, 3, ..])
+}
+
+/// Reference: <https://datatracker.ietf.org/doc/html/rfc6101#appendix-E.2>
+fn is_sslv2_client_hello(data: &[u8]) -> bool {
+    matches!(data, [len_hi, _, 1, 0 | 2 | 3, ..] if len_hi & 0x80 != 0)
+}
+
+/// Parses the body of a Client Hello message. Fails only if the message is
+/// truncated.
+///
+/// The server name and ALPN protocol IDs are converted to strings lossily; see
+/// [`is_printable`] for the values that tshark would show differently.
+///
+/// Reference: <https://datatracker.ietf.org/doc/html/rfc8446#section-4.1.2>
+pub(crate) fn parse_client_hello(body: &[u8]) -> Parsed<RawClientHello> {
+    const EXT_SERVER_NAME: u16 = 0;
+    const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+    const EXT_SESSION_TICKET: u16 = 35;
+
+    let mut r = Reader(body);
+
+    let mut hello = RawClientHello {
+        version: r.u16()?,
+        ..Default::default()
+    };
+    r.bytes(32)?; // random
+    r.vec8()?; // legacy_session_id
+    let mut ciphers = r.vec16()?;
+    while !ciphers.is_empty() {
+        hello.ciphers.push(ciphers.u16()?);
+    }
+    r.vec8()?; // legacy_compression_methods
+    if r.is_empty() {
+        return Ok(hello);
+    }
+
+    let mut exts = r.vec16()?;
+    let mut has_sig_algs = false;
+    while !exts.is_empty() {
+        let ext_type = exts.u16()?;
+        let mut data = exts.vec16()?;
+        hello.exts.push(ext_type);
+        match ext_type {
+            EXT_SERVER_NAME if hello.sni.is_none() => {
+                let mut names = data.vec16()?;
+                while !names.is_empty() {
+                    let name_type = names.u8()?;
+                    let name = names.vec16()?;
+                    if name_type == 0 && hello.sni.is_none() {
+                        hello.sni = Some(String::from_utf8_lossy(name.0).into_owned());
+                    }
+                }
+            }
+            EXT_ALPN if hello.alpn.is_empty() => {
+                let mut protocols = data.vec16()?;
+                while !protocols.is_empty() {
+                    let protocol = protocols.vec8()?.0;
+                    hello
+                        .alpn
+                        .push(String::from_utf8_lossy(protocol).into_owned());
+                }
+            }
+            EXT_SUPPORTED_VERSIONS if hello.supported_versions.is_empty() => {
+                let mut versions = data.vec8()?;
+                while !versions.is_empty() {
+                    hello.supported_versions.push(versions.u16()?);
+                }
+            }
+            EXT_SESSION_TICKET => hello.session_ticket_len = data.0.len(),
+            EXT_SIGNATURE_ALGORITHMS if !has_sig_algs => {
+                has_sig_algs = true;
+                let mut algs = data.vec16()?;
+                while !algs.is_empty() {
+                    hello.sig_algs.push(algs.u16()?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(hello)
+}
+
+/// Parses the body of a Server Hello message.
+///
+/// Reference: <https://datatracker.ietf.org/doc/html/rfc8446#section-4.1.3>
+fn parse_server_hello(body: &[u8]) -> Parsed<RawServerHello> {
+    let mut r = Reader(body);
+    let mut hello = RawServerHello {
+        version: r.u16()?,
+        ..Default::default()
+    };
+    r.bytes(32)?; // random
+    r.vec8()?; // legacy_session_id_echo
+    hello.cipher = r.u16()?;
+    r.u8()?; // legacy_compression_method
+    if r.is_empty() {
+        return Ok(hello);
+    }
+
+    let mut exts = r.vec16()?;
+    while !exts.is_empty() {
+        let ext_type = exts.u16()?;
+        let mut data = exts.vec16()?;
+        hello.exts.push(ext_type);
+        match ext_type {
+            EXT_ALPN if hello.alpn.is_none() => {
+                let mut protocols = data.vec16()?;
+                if !protocols.is_empty() {
+                    hello.alpn = Some(printable(protocols.vec8()?.0)?);
+                }
+            }
+            EXT_SUPPORTED_VERSIONS if hello.supported_version.is_none() => {
+                hello.supported_version = Some(data.u16()?);
+            }
+            _ => {}
+        }
+    }
+    Ok(hello)
+}
+
+/// Returns DER encodings of the certificates of a Certificate message. TLS 1.3
+/// encrypts these messages, so we only see the TLS 1.2 format.
+///
+/// Reference: <https://datatracker.ietf.org/doc/html/rfc5246#section-7.4.2>
+fn parse_certificate(body: &[u8]) -> Parsed<Vec<Vec<u8>>> {
+    let mut list = Reader(body).vec24()?;
+    let mut certificates = Vec::new();
+    while !list.is_empty() {
+        certificates.push(list.vec24()?.0.to_vec());
+    }
+    Ok(certificates)
+}
+
+/// Converts a server name or an ALPN protocol ID to a string.
+fn printable(bytes: &[u8]) -> Parsed<String> {
+    let s = String::from_utf8_lossy(bytes);
+    if !is_printable(&s) {
+        return unsupported("control characters in TLS handshake");
+    }
+    Ok(s.into_owned())
+}
+
+/// tshark escapes control characters; we leave such values to it.
+fn is_printable(s: &str) -> bool {
+    !s.chars().any(char::is_control)
+}
+
+/// Reads big-endian values and length-prefixed vectors.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Parsed<&'a [u8]> {
+        if n > self.0.len() {
+            return unsupported("malformed TLS handshake message");
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Parsed<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Parsed<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Parsed<usize> {
+        let b = self.bytes(3)?;
+        Ok(usize::from(b[0]) << 16 | usize::from(b[1]) << 8 | usize::from(b[2]))
+    }
+
+    fn vec8(&mut self) -> Parsed<Self> {
+        let len = self.u8()?;
+        Ok(Self(self.bytes(len.into())?))
+    }
+
+    fn vec16(&mut self) -> Parsed<Self> {
+        let len = self.u16()?;
+        Ok(Self(self.bytes(len.into())?))
+    }
+
+    fn vec24(&mut self) -> Parsed<Self> {
+        let len = self.u24()?;
+        Ok(Self(self.bytes(len)?))
+    }
+}
+
+fn read_u16_be(data: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(..2)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+pub(crate) fn read_u32(data: &[u8], big_endian: bool) -> u32 {
+    let bytes = data[..4].try_into().expect("BUG");
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Returns the DER encodings of the certificates in the capture file, in the order
+/// of the streams.
+#[cfg(test)]
+pub(crate) fn certificates(data: &[u8]) -> Vec<Vec<u8>> {
+    use crate::select::FingerprintKind;
+
+    let tracker = track(data, Selection::new(&[FingerprintKind::Ja4x])).unwrap();
+    tracker
+        .streams
+        .into_iter()
+        .flat_map(|stream| stream.handshake.certificates)
+        .flat_map(|(ders, _, _, _)| ders)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_yaml::Mapping;
+
+    use super::*;
+    use crate::{select::FingerprintKind, FormatFlags};
+
+    /// Keys of the output records that the native reader produces. The other keys
+    /// (`ja4l_c`, `ja4plus`, ...) are not computed with `--only ja4,ja4s,ja4x`.
+    const KEYS: [&str; 11] = [
+        "stream",
+        "src",
+        "dst",
+        "src_port",
+        "dst_port",
+        "tls_server_name",
+        "ja4",
+        "resumed",
+        "ja4s",
+        "dns_encrypted",
+        "tls_certs",
+    ];
+
+    /// Returns the TLS fingerprints of the TCP streams, as in the output records.
+    fn only_tls(recs: impl IntoIterator<Item = Mapping>) -> Vec<Mapping> {
+        recs.into_iter()
+            .filter(|rec| rec["transport"] == "tcp")
+            .filter(|rec| {
+                ["ja4", "ja4s", "tls_certs"]
+                    .iter()
+                    .any(|&k| rec.contains_key(k))
+            })
+            .map(|rec| {
+                rec.into_iter()
+                    .filter(|(k, _)| KEYS.contains(&k.as_str().unwrap()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns TLS fingerprints computed without tshark.
+    fn native_fingerprints(pcap: &Path) -> Option<Vec<Mapping>> {
+        let mut conf = Conf::load().unwrap();
+        conf.restrict(Selection::new(&[
+            FingerprintKind::Ja4,
+            FingerprintKind::Ja4s,
+            FingerprintKind::Ja4x,
+        ]));
+        let (streams, _) = tls_streams(pcap, &conf, false, StreamFilter::default())?;
+        let recs = streams.into_out(FormatFlags::default()).collect::<Vec<_>>();
+        let recs: Vec<Mapping> =
+            serde_yaml::from_value(serde_yaml::to_value(recs).unwrap()).unwrap();
+        Some(only_tls(recs))
+    }
+
+    /// Returns TLS fingerprints from the tshark-based snapshot.
+    fn snapshot_fingerprints(pcap: &Path) -> Vec<Mapping> {
+        let name = pcap.file_name().unwrap().to_str().unwrap();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/snapshots")
+            .join(format!("ja4__insta@{name}.snap"));
+        let snapshot = fs::read_to_string(path).unwrap();
+        let (_header, body) = snapshot.split_once("\n---\n").unwrap();
+        let recs: Vec<Mapping> = serde_yaml::from_str(body).unwrap();
+        only_tls(recs)
+    }
+
+    #[test]
+    fn test_flight() {
+        // A Certificate message split across two records, the second of which
+        // arrives in two TCP segments, followed by ChangeCipherSpec.
+        let message = [&[CERTIFICATE, 0, 0, 9][..], &[0, 0, 6, 0, 0, 3, 1, 2, 3]].concat();
+        let record = |fragment: &[u8]| {
+            let len = u16::try_from(fragment.len()).unwrap().to_be_bytes();
+            [&[TLS_HANDSHAKE, 3, 3, len[0], len[1]], fragment].concat()
+        };
+        let first = record(&message[..4]);
+        let second = record(&message[4..]);
+        let ccs = [20, 3, 3, 0, 1, 1];
+
+        let mut flight = Flight::default();
+        assert!(flight.push(100, &first, false).unwrap().is_empty());
+        let seq = 100 + first.len() as u32;
+        assert!(flight.push(seq, &second[..3], false).unwrap().is_empty());
+        // Retransmission.
+        assert!(flight.push(100, &first, false).unwrap().is_empty());
+        let seq = seq + 3;
+        let messages = flight
+            .push(seq, &[&second[3..], &ccs[..]].concat(), false)
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].msg_type, CERTIFICATE);
+        assert!(messages[0].fragmented);
+        assert!(messages[0].reassembled);
+        assert_eq!(
+            parse_certificate(&messages[0].body).unwrap(),
+            [vec![1, 2, 3]]
+        );
+        assert!(flight.done);
+        assert!(flight.push(1, &first, false).unwrap().is_empty());
+    }
+
+    /// Messages completed by a segment that the snapshot length cut short are
+    /// `partial`; a message cut in the middle is left to tshark.
+    #[test]
+    fn test_flight_truncated() {
+        let record = |msg_type, body: &[u8]| {
+            let len = u8::try_from(body.len()).unwrap();
+            [
+                &[TLS_HANDSHAKE, 3, 3, 0, len + 4, msg_type, 0, 0, len],
+                body,
+            ]
+            .concat()
+        };
+        let server_hello = record(SERVER_HELLO, &[1, 2, 3]);
+        let certificate = record(CERTIFICATE, &[0, 0, 6, 0, 0, 3, 1, 2, 3]);
+
+        let mut flight = Flight::default();
+        let messages = flight.push(1, &server_hello, true).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].quality(), Quality::Partial);
+        // The rest of the flight was not captured.
+        assert!(flight.done);
+        assert!(flight.push(1 + 8, &certificate, false).unwrap().is_empty());
+
+        let data = [&server_hello[..], &certificate[..10]].concat();
+        assert!(Flight::default().push(1, &data, true).is_err());
+
+        let messages = Flight::default().push(1, &server_hello, false).unwrap();
+        assert_eq!(messages[0].quality(), Quality::Full);
+    }
+
+    /// `tls::ja4_from_client_hello` agrees with tshark on the first Client Hello of
+    /// the capture files.
+    #[test]
+    fn test_ja4_from_client_hello() {
+        let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+        for name in [
+            "tls-alpn-h2.pcap",
+            "tls12.pcap",
+            "badcurveball.pcap",
+            "macos_tcp_flags.pcap",
+            "tls-port-9999.pcap",
+        ] {
+            let pcap = pcap_dir.join(name);
+            let data = fs::read(&pcap).unwrap();
+            let capture = Capture::new(&data).unwrap();
+            let payload = capture
+                .filter_map(|record| {
+                    let ip = capture.link_type.ip_packet(record.frame).unwrap()?;
+                    Segment::parse(ip).unwrap()
+                })
+                .map(|segment| segment.payload)
+                .find(|payload| starts_with_client_hello(payload))
+                .expect(name);
+            let fp = tls::ja4_from_client_hello(payload, tls::Transport::Tcp).unwrap();
+            let expected = snapshot_fingerprints(&pcap)
+                .into_iter()
+                .find_map(|rec| rec.get("ja4").cloned())
+                .unwrap();
+            assert_eq!(fp.ja4, expected.as_str().unwrap(), "{name}");
+        }
+    }
+
+    /// TCP segments are found behind IPv6 extension headers; IPv6 fragments are
+    /// left to tshark, which reassembles them.
+    #[test]
+    fn test_ipv6_extension_headers() {
+        let pcap = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap/ipv6-ext-headers.pcap");
+        let data = fs::read(pcap).unwrap();
+        let capture = Capture::new(&data).unwrap();
+        let segments = capture
+            .map(|record| {
+                let ip = capture.link_type.ip_packet(record.frame).unwrap().unwrap();
+                Segment::parse(ip).map(|segment| {
+                    let segment = segment.unwrap();
+                    (segment.src, segment.dst, segment.payload.len())
+                })
+            })
+            .collect::<Vec<_>>();
+        expect_test::expect![[r#"
+            [
+                Ok(
+                    (
+                        [2001:db8::1]:36372,
+                        [2001:db8::2]:443,
+                        517,
+                    ),
+                ),
+                Err(
+                    Unsupported(
+                        "IP fragments",
+                    ),
+                ),
+                Err(
+                    Unsupported(
+                        "IP fragments",
+                    ),
+                ),
+            ]
+        "#]]
+        .assert_debug_eq(&segments);
+    }
+
+    /// Malformed IPv4 and TCP headers are skipped, not parsed or panicked on.
+    #[test]
+    fn test_malformed_headers() {
+        // IPv4 with 20 bytes of options (IHL 10) and a TCP header with the given
+        // data offset, truncated to `len` bytes.
+        let packet = |ihl: u8, tcp_offset: u8, len: usize| {
+            let mut ip = vec![0x40 | ihl, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_TCP, 0, 0];
+            ip.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+            ip.resize(usize::from(ihl) * 4, 1); // NOP options
+            let tcp_start = ip.len();
+            ip.extend([0xc3, 0x50, 0x01, 0xbb, 0, 0, 0, 1, 0, 0, 0, 0]);
+            ip.extend([tcp_offset << 4, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+            ip.extend(b"\x16\x03\x01");
+            let total_len = u16::try_from(ip.len()).unwrap();
+            ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+            assert_eq!(tcp_start, usize::from(ihl) * 4);
+            ip.truncate(len);
+            ip
+        };
+        let payload_len = |ip: &[u8]| {
+            Segment::parse(ip)
+                .unwrap()
+                .map(|segment| segment.payload.len())
+        };
+
+        assert_eq!(payload_len(&packet(10, 5, usize::MAX)), Some(3));
+        // Snaplen-truncated inside the IP options.
+        assert_eq!(payload_len(&packet(10, 5, 32)), None);
+        // IHL below the minimum header length.
+        assert_eq!(payload_len(&packet(3, 5, usize::MAX)), None);
+        // TCP data offset below the minimum header length.
+        assert_eq!(payload_len(&packet(10, 2, usize::MAX)), None);
+    }
+
+    /// `x509::ja4x` agrees with tshark on the certificates of the capture files.
+    #[test]
+    fn test_ja4x() {
+        let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+        for name in [
+            "badcurveball.pcap",
+            "ipv6.pcapng",
+            "tls-alpn-h2.pcap",
+            "tls-alpn-h2-raw.pcap",
+            "tls-alpn-h2-sll.pcap",
+        ] {
+            let pcap = pcap_dir.join(name);
+            let mut fps = certificates(&fs::read(&pcap).unwrap())
+                .iter()
+                .map(|der| crate::x509::ja4x(der).unwrap().ja4x)
+                .collect::<Vec<_>>();
+            let mut expected = snapshot_fingerprints(&pcap)
+                .iter()
+                .filter_map(|rec| rec.get("tls_certs")?.as_sequence())
+                .flatten()
+                .filter_map(|certs| certs.get("x509")?.as_sequence())
+                .flatten()
+                .map(|x509| x509["ja4x"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            // A certificate is output once per stream.
+            fps.sort();
+            fps.dedup();
+            expected.sort();
+            expected.dedup();
+            assert!(!fps.is_empty(), "{name}");
+            assert_eq!(fps, expected, "{name}");
+        }
+    }
+
+    /// The native reader and tshark produce the same JA4, JA4S, and JA4X
+    /// fingerprints for every capture file that the native reader supports.
+    #[test]
+    fn test_native_matches_tshark() {
+        let pcap_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap");
+        let mut supported = Vec::new();
+        for entry in fs::read_dir(pcap_dir).unwrap() {
+            let pcap = entry.unwrap().path();
+            if !pcap
+                .extension()
+                .is_some_and(|ext| ext.to_str().unwrap().starts_with("pcap"))
+            {
+                continue;
+            }
+            if let Some(native) = native_fingerprints(&pcap) {
+                assert_eq!(native, snapshot_fingerprints(&pcap), "{}", pcap.display());
+                supported.push(pcap.file_name().unwrap().to_str().unwrap().to_owned());
+            }
+        }
+        supported.sort();
+        expect_test::expect![[r#"
+            [
+                "CVE-2018-6794.pcap",
+                "badcurveball.pcap",
+                "http1.pcapng",
+                "ipv6.pcapng",
+                "macos_tcp_flags.pcap",
+                "single-packets.pcap",
+                "socks4-https.pcap",
+                "ssh-r.pcap",
+                "ssh-scp-1050.pcap",
+                "ssh2-malformed.pcap",
+                "ssh2-moloch-crash.pcap",
+                "sshv1.pcap",
+                "tls-alpn-h2-raw.pcap",
+                "tls-alpn-h2-sll.pcap",
+                "tls-alpn-h2.pcap",
+                "tls-port-9999.pcap",
+                "tls12.pcap",
+                "v6.pcap",
+            ]
+        "#]]
+        .assert_debug_eq(&supported);
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Writing the records to a file (`--output`), rotating it
+//! (`--rotate-size`, `--rotate-interval`)
+//!
+//! On rotation the output file is renamed after the time of the rotation,
+//! `ja4.ndjson` becoming `ja4.20231114T221320Z.ndjson`, and a new one is started.
+//! The renamed files are complete, so log shippers can pick them up.
+
+use std::{
+    io::{self, LineWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use fs_err as fs;
+
+use crate::time::Timestamp;
+
+/// When to start a new output file.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Rotation {
+    /// Once the file has this many bytes.
+    pub(crate) max_size: Option<u64>,
+    /// Once the file has been open this long.
+    pub(crate) interval: Option<Duration>,
+}
+
+/// Output file.
+///
+/// The file is only rotated at the start of a line: JSON records are written one
+/// per line and are never split between files. The rotation is due when a record
+/// is written, so with `--rotate-interval` and no records the file stays open.
+pub(crate) struct OutputFile {
+    path: PathBuf,
+    file: LineWriter<fs::File>,
+    rotation: Rotation,
+    /// Bytes written to the current file.
+    written: u64,
+    opened: Instant,
+    at_line_start: bool,
+}
+
+impl OutputFile {
+    pub(crate) fn create(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let file = LineWriter::new(fs::File::create(&path)?);
+        Ok(Self {
+            path,
+            file,
+            rotation,
+            written: 0,
+            opened: Instant::now(),
+            at_line_start: true,
+        })
+    }
+
+    fn is_rotation_due(&self) -> bool {
+        let Rotation { max_size, interval } = self.rotation;
+        max_size.is_some_and(|max| self.written >= max)
+            || interval.is_some_and(|interval| self.opened.elapsed() >= interval)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, rotated_path(&self.path, Timestamp::now()))?;
+        *self = Self::create(self.path.clone(), self.rotation)?;
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start && !buf.is_empty() && self.is_rotation_due() {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        if n > 0 {
+            self.written += n as u64;
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Returns the name for the output file rotated at the given time: the timestamp
+/// goes before the extension. A counter is appended to the timestamp if a file of
+/// that name exists already.
+fn rotated_path(path: &Path, time: Timestamp) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let time = time.to_basic_utc();
+    let mut rotated = path.with_file_name(format!("{stem}.{time}{ext}"));
+    let mut counter = 1;
+    while rotated.exists() {
+        rotated = path.with_file_name(format!("{stem}.{time}-{counter}{ext}"));
+        counter += 1;
+    }
+    rotated
+}
+
+#[test]
+fn test_output_file() {
+    let dir = std::env::temp_dir().join(format!("ja4-test-output-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ja4.ndjson");
+    let rotation = Rotation {
+        max_size: Some(10),
+        interval: None,
+    };
+    let mut out = OutputFile::create(path.clone(), rotation).unwrap();
+    // Records are written in pieces, as by `serde_json::to_writer`.
+    for rec in ["{\"a\":1}", "{\"b\":22222}", "{\"c\":3}"] {
+        for piece in rec.as_bytes().chunks(3) {
+            out.write_all(piece).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out.flush().unwrap();
+
+    let mut rotated = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|file| file != &path)
+        .collect::<Vec<_>>();
+    assert_eq!(rotated.len(), 1, "{rotated:?}");
+    let rotated = rotated.pop().unwrap();
+    assert_eq!(
+        fs::read_to_string(&rotated).unwrap(),
+        "{\"a\":1}\n{\"b\":22222}\n"
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{\"c\":3}\n");
+
+    // The name of a rotated file is unique.
+    let time = "2023-11-14T22:13:20Z".parse().unwrap();
+    fs::write(dir.join("ja4.20231114T221320Z.ndjson"), "").unwrap();
+    let names = [
+        rotated_path(&path, time),
+        rotated_path(&dir.join("ja4"), time),
+    ];
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(names[0], dir.join("ja4.20231114T221320Z-1.ndjson"));
+    assert_eq!(names[1], dir.join("ja4.20231114T221320Z"));
+}
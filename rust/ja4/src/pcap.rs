@@ -13,7 +13,7 @@ use serde::Serialize;
 
 use crate::{Error, Result};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub(crate) struct PacketNum(pub(crate) usize);
 
 impl fmt::Display for PacketNum {
@@ -22,11 +22,23 @@ impl fmt::Display for PacketNum {
     }
 }
 
+/// Order of the fields of a protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum FieldOrder {
+    /// The order of the dissection tree, as in PDML.
+    #[default]
+    Tree,
+    /// Grouped by name, each group in the order of the tree (`tshark -T ek`).
+    /// Fields of different names don't show which subtree they belong to.
+    Grouped,
+}
+
 #[derive(Clone)]
 pub(crate) struct Packet<'a> {
     inner: &'a rtshark::Packet,
     /// Sequential number of this packet in the capture file.
     pub(crate) num: PacketNum,
+    field_order: FieldOrder,
 }
 
 impl<'a> Packet<'a> {
@@ -34,17 +46,26 @@ impl<'a> Packet<'a> {
         Self {
             inner,
             num: PacketNum(num),
+            field_order: FieldOrder::Tree,
+        }
+    }
+
+    pub(crate) fn with_field_order(self, field_order: FieldOrder) -> Self {
+        Self {
+            field_order,
+            ..self
         }
     }
 
     /// Returns an iterator over the [protocols][Proto] with the given name.
-    pub(crate) fn protos<'b>(&'b self, name: &'b str) -> impl Iterator<Item = Proto> + 'b {
+    pub(crate) fn protos<'b>(&'b self, name: &'b str) -> impl Iterator<Item = Proto<'b>> + 'b {
         self.inner
             .iter()
             .filter(move |layer| layer.name() == name)
             .map(|layer| Proto {
                 inner: layer,
                 packet_num: self.num,
+                field_order: self.field_order,
             })
     }
 
@@ -53,31 +74,79 @@ impl<'a> Packet<'a> {
         self.inner.layer_name(name).map(|inner| Proto {
             inner,
             packet_num: self.num,
+            field_order: self.field_order,
         })
     }
 
     // XXX-TODO(vvv): Propose to change the type of `rtshark::Packet::timestamp_micros`
     // to `Option<u64>` (*unsigned*).
+    //
+    // Packets read from `tshark -T ek` output don't have the timestamp set by rtshark;
+    // it is taken from `frame.time_epoch` then.
     pub(crate) fn timestamp_micros(&self) -> Result<i64> {
-        self.inner.timestamp_micros().ok_or(Error::MissingTimestamp)
+        self.inner
+            .timestamp_micros()
+            .or_else(|| {
+                let frame = self.find_proto("frame")?;
+                parse_epoch_micros(frame.first("frame.time_epoch").ok()?)
+            })
+            .ok_or(Error::MissingTimestamp)
+    }
+
+    /// Returns the protocol stack of the packet (`frame.protocols`), e.g.
+    /// `eth:ethertype:ip:tcp:tls`.
+    pub(crate) fn frame_protocols(&self) -> Option<&'a str> {
+        let frame = self.inner.layer_name("frame")?;
+        frame.metadata("frame.protocols").map(|md| md.value())
     }
 
     /// Returns an iterator over the [`Proto`]cols of this packet.
-    pub(crate) fn iter(&self) -> impl Iterator<Item = Proto> {
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Proto<'_>> {
         self.inner.iter().map(|layer| Proto {
             inner: layer,
             packet_num: self.num,
+            field_order: self.field_order,
         })
     }
 }
 
+/// Checks that tshark describes the field as expected. Fields read from
+/// `tshark -T ek` output have no description.
+pub(crate) fn debug_assert_display(md: &rtshark::Metadata, expected: &str) {
+    debug_assert!(
+        md.display().is_empty() || md.display() == expected,
+        "{:?} != {expected:?}",
+        md.display()
+    );
+}
+
+/// Parses seconds since the Unix epoch with a fractional part, e.g.
+/// `1690000000.123456000`, into microseconds.
+fn parse_epoch_micros(s: &str) -> Option<i64> {
+    let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+    let frac = frac.get(..6).unwrap_or(frac);
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let micros = format!("{frac:0<6}").parse::<i64>().ok()?;
+    secs.parse::<i64>()
+        .ok()?
+        .checked_mul(1_000_000)?
+        .checked_add(micros)
+}
+
 #[derive(Clone)]
 pub(crate) struct Proto<'a> {
     inner: &'a rtshark::Layer,
     pub(crate) packet_num: PacketNum,
+    field_order: FieldOrder,
 }
 
 impl Proto<'_> {
+    pub(crate) fn field_order(&self) -> FieldOrder {
+        self.field_order
+    }
+
     /// Returns the name of the underlying [`rtshark::Layer`], i.e., the name of the protocol
     /// returned by `tshark`.
     pub(crate) fn name(&self) -> &str {
@@ -92,7 +161,10 @@ impl Proto<'_> {
     /// Returns an iterator over the sequence of [`rtshark::Metadata`] with the given [name].
     ///
     /// [name]: rtshark::Metadata::name
-    pub(crate) fn fields<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &rtshark::Metadata> {
+    pub(crate) fn fields<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a rtshark::Metadata> {
         self.inner.iter().filter(move |md| md.name() == name)
     }
 
@@ -100,7 +172,7 @@ impl Proto<'_> {
     ///
     /// [name]: rtshark::Metadata::name
     /// [values]: rtshark::Metadata::value
-    pub(crate) fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &str> {
+    pub(crate) fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
         self.fields(name).map(|md| md.value())
     }
 
@@ -121,3 +193,20 @@ impl Proto<'_> {
         self.find(name).map(|md| md.value())
     }
 }
+
+#[test]
+fn test_parse_epoch_micros() {
+    assert_eq!(
+        parse_epoch_micros("1690000000.123456789"),
+        Some(1_690_000_000_123_456)
+    );
+    assert_eq!(
+        parse_epoch_micros("1690000000.5"),
+        Some(1_690_000_000_500_000)
+    );
+    assert_eq!(
+        parse_epoch_micros("1690000000"),
+        Some(1_690_000_000_000_000)
+    );
+    assert_eq!(parse_epoch_micros("Jul 22, 2023 04:26:40"), None);
+}
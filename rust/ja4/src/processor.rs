@@ -0,0 +1,869 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! What `ja4` does, for programs that embed it, see [`Processor`]
+
+use std::{
+    io::Write,
+    net::IpAddr,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use itertools::Either;
+
+use crate::{
+    anonymize::Anonymizer,
+    capture::{Capture, Engine},
+    conf::OutputFormat,
+    dedupe::Dedupe,
+    error::ErrorReport,
+    filter::StreamFilter,
+    foxio::FoxioNames,
+    output::{OutputFile, Rotation},
+    progress::Progress,
+    select::{FingerprintKind, Selection},
+    stream::{self, MinPackets},
+    summary::Summary,
+    syslog::{Syslog, SyslogTransport},
+    timing::{Phase, Timing},
+    tshark::{self, TsharkOutput},
+    unique::Unique,
+    validate,
+    watchlist::Watchlist,
+    Cli, Conf, Error, FormatFlags, Result, VersionCheck,
+};
+
+/// The `ja4` command line tool as a library: reads capture files and writes the
+/// records, the same way as `ja4` does with the equivalent options.
+///
+/// ```no_run
+/// use ja4::{FingerprintKind, Processor};
+///
+/// let processor = Processor::builder()
+///     .json(true)
+///     .original_order(true)
+///     .only([FingerprintKind::Ja4, FingerprintKind::Ja4s])
+///     .build();
+/// processor.run_pcap("capture.pcap", &mut std::io::stdout())?;
+/// # Ok::<_, ja4::Error>(())
+/// ```
+///
+/// To process the records in the program rather than write them, see
+/// [`crate::Reader`].
+#[derive(Debug, Clone)]
+pub struct Processor {
+    /// Options, as if given on the command line.
+    cli: Cli,
+    /// `None` to read the configuration file (`--config`) and `JA4_*` environment
+    /// variables.
+    conf: Option<Conf>,
+}
+
+impl Processor {
+    /// Returns a builder with the options at their defaults, as `ja4` has them
+    /// without arguments.
+    pub fn builder() -> ProcessorBuilder {
+        ProcessorBuilder(Self {
+            cli: default_options(),
+            conf: None,
+        })
+    }
+
+    pub(crate) fn from_cli(cli: Cli) -> Self {
+        Self { cli, conf: None }
+    }
+
+    /// Processes the capture file, writing the records to `sink`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the configuration cannot be read, tshark is missing or fails, or
+    /// the records cannot be written; see also [`ProcessorBuilder::fail_on_empty`].
+    pub fn run_pcap(&self, pcap: impl AsRef<Path>, sink: &mut impl Write) -> Result<()> {
+        let mut processor = self.clone();
+        processor.cli.pcap = vec![pcap.as_ref().to_owned()];
+        processor.run(sink)
+    }
+
+    /// Processes the capture files or the live capture of the options.
+    pub(crate) fn run<W: Write>(self, writer: &mut W) -> Result<()> {
+        let mut conf = match self.conf {
+            Some(conf) => conf,
+            None => Conf::load_from(self.cli.config.as_deref(), self.cli.profile.as_deref())?,
+        };
+        let Cli {
+            config: _,
+            profile: _,
+            json,
+            pretty,
+            with_raw,
+            original_order,
+            with_details,
+            foxio_names,
+            keylog_file,
+            with_packet_numbers,
+            display_filter,
+            decode_as,
+            host,
+            net,
+            port,
+            port_range,
+            stream,
+            after,
+            before,
+            since,
+            until,
+            sni,
+            only,
+            engine,
+            max_streams,
+            fail_on_empty,
+            skip_empty,
+            udp_timeout,
+            tcp_idle_timeout,
+            min_packets,
+            strict_min,
+            tshark_timeout,
+            all_fields,
+            experimental,
+            resolve_dns,
+            tshark_output,
+            tshark_path,
+            tshark_min_version,
+            tshark_version_check,
+            tshark_args,
+            errors_json,
+            summary,
+            unique,
+            validate,
+            anonymize,
+            anonymize_key,
+            match_file,
+            match_only,
+            dedupe_streams,
+            list_streams,
+            syslog,
+            syslog_transport,
+            output,
+            rotate_size,
+            rotate_interval,
+            interface,
+            capture_filter,
+            jobs,
+            unordered,
+            merge,
+            progress,
+            timing,
+            version: _,
+            pcap,
+            command: _,
+        } = self.cli;
+        if engine == Engine::Native {
+            let needs_tshark = [
+                (since.is_some(), "--since"),
+                (until.is_some(), "--until"),
+                (max_streams.is_some(), "--max-streams"),
+                (experimental, "--experimental"),
+                (resolve_dns, "--resolve-dns"),
+                (display_filter.is_some(), "--display-filter"),
+                (!decode_as.is_empty(), "--decode-as"),
+                (interface.is_some(), "--interface"),
+            ];
+            if let Some(&(_, option)) = needs_tshark.iter().find(|(given, _)| *given) {
+                return Err(Error::NativeEngine(option));
+            }
+        }
+        // Explicit options win over `[output]` table.
+        let json = json.unwrap_or(conf.output.format == OutputFormat::Json);
+        if !json {
+            let needs_json = [
+                (pretty, "--pretty"),
+                (rotate_size.is_some(), "--rotate-size"),
+                (rotate_interval.is_some(), "--rotate-interval"),
+            ];
+            if let Some(&(_, option)) = needs_json.iter().find(|(given, _)| *given) {
+                return Err(Error::RequiresJson(option));
+            }
+        }
+        let with_raw = with_raw.unwrap_or(conf.output.with_raw);
+        let original_order = original_order.unwrap_or(conf.output.original_order);
+        let with_packet_numbers = with_packet_numbers.unwrap_or(conf.output.with_packet_numbers);
+        let mut selection = conf.selection(&only);
+        if engine == Engine::Native && only.is_empty() {
+            // The TLS fingerprints only, as with `--only ja4,ja4s,ja4x`.
+            selection = Selection {
+                ja4h: false,
+                ja4l: false,
+                ja4ssh: false,
+                ..selection
+            };
+        }
+        conf.restrict(selection);
+        conf.tls.experimental = experimental;
+        conf.tls.resolve_dns = resolve_dns;
+        if let Some(version) = tshark_min_version {
+            conf.tshark_min_version = version.to_string();
+        }
+        if let Some(check) = tshark_version_check {
+            conf.tshark_version_check = check;
+        }
+        if list_streams {
+            conf.disable_all();
+        }
+        if tshark_output == TsharkOutput::Pdml && !tshark_args.is_empty() {
+            return Err(Error::TsharkArgs);
+        }
+        let tshark_program = tshark::Program::new(
+            tshark_path.or_else(|| conf.tshark_path.clone()),
+            tshark_args,
+        )?;
+        let mut output = match output {
+            Some(path) => {
+                let rotation = Rotation {
+                    max_size: rotate_size,
+                    interval: rotate_interval.map(Duration::from_secs),
+                };
+                Some(OutputFile::create(path, rotation)?)
+            }
+            None => None,
+        };
+        let writer = &mut match output.as_mut() {
+            Some(file) => Either::Left(file),
+            None => Either::Right(writer),
+        };
+        // Connect before processing the capture, so that a wrong address fails fast.
+        let mut syslog = match &syslog {
+            Some(addr) => Some(Syslog::connect(addr, syslog_transport)?),
+            None => None,
+        };
+
+        let filter = StreamFilter::new(host, net, port, port_range)
+            .with_streams(stream)
+            .with_time_window(after, before)
+            .with_packet_window(since, until)
+            .with_sni(sni);
+        // Records are output as soon as the streams are finalized if the output
+        // format allows and no option needs to see all records first.
+        let incremental = (syslog.is_some() || json && !pretty)
+            && !summary
+            && unique.is_empty()
+            && !dedupe_streams;
+        // `--decode-as` rules come last, so that they override `[ports]` table.
+        let decode_as = [conf.ports.decode_as(), decode_as].concat();
+        let capture = Capture {
+            conf,
+            filter,
+            flags: FormatFlags {
+                with_raw: with_raw || validate,
+                original_order,
+                with_details,
+            },
+            incremental,
+            list_streams,
+            with_packet_numbers,
+            keylog_file,
+            display_filter,
+            decode_as,
+            engine,
+            tshark_program,
+            tshark_output,
+            max_streams,
+            nr_stopped: AtomicUsize::new(0),
+            udp_timeout: Duration::from_secs(udp_timeout),
+            tcp_idle_timeout: tcp_idle_timeout.map(Duration::from_secs),
+            min_packets: MinPackets {
+                count: min_packets.unwrap_or_default(),
+                strict: strict_min,
+            },
+            progress: Progress::new(progress, &pcap),
+            timing: Timing::new(timing),
+            tshark_timeout: tshark_timeout.map(Duration::from_secs),
+            errors_json,
+            all_fields,
+            tshark_checked: Mutex::new(false),
+            fields: OnceLock::new(),
+        };
+        if engine == Engine::Native && !capture.is_native() {
+            tracing::warn!("tshark is needed for the selected fingerprints or for decryption");
+        }
+        if let Some(interface) = &interface {
+            capture.check_tshark()?;
+            // Ctrl-C stops tshark, which then flushes its output; we only need to
+            // survive the signal ourselves.
+            ctrlc::set_handler(|| {})?;
+            if let Some(filter) = &capture_filter {
+                tshark::check_capture_filter(interface, filter)?;
+            }
+        }
+        if let Some(filter) = &capture.display_filter {
+            capture.check_tshark()?;
+            tshark::check_display_filter(&capture.tshark_program, filter)?;
+        }
+        let jobs = match jobs {
+            Some(jobs) => jobs as usize,
+            None => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        };
+
+        let multiple_files = pcap.len() > 1;
+        // Records of a merged capture have their `pcap` set already.
+        let merge = merge && multiple_files && !capture.is_native();
+        thread::scope(|scope| {
+            let mut recs = match &interface {
+                Some(interface) => capture.live(scope, interface, capture_filter.as_deref()),
+                None if merge => capture.read_merged(scope, &pcap),
+                None => capture.read_files(scope, &pcap, jobs, !unordered),
+            }
+            .peekable();
+            // Fail at once if the first input does, before anything is output.
+            if !errors_json {
+                if let Some((_, Err(_))) = recs.peek() {
+                    if let Some((_, Err(e))) = recs.next() {
+                        return Err(e);
+                    }
+                }
+            }
+
+            let mut anonymizer = anonymize.then(|| Anonymizer::new(anonymize_key));
+            let watchlist = match &match_file {
+                Some(path) => {
+                    let (watchlist, malformed) = Watchlist::load(path)?;
+                    for (line_num, line) in malformed {
+                        eprintln!(
+                            "warning: {}:{line_num}: not a fingerprint, ignoring: {line:?}",
+                            path.display()
+                        );
+                    }
+                    Some(watchlist)
+                }
+                None => None,
+            };
+            let mut error = None;
+            let mut nr_failed = 0;
+            let recs = recs
+                .map_while(|(i, rec)| match rec {
+                    Ok(mut rec) => {
+                        if multiple_files && !merge {
+                            rec.set_pcap(Some(&pcap[i]));
+                        }
+                        Some(Some(rec))
+                    }
+                    Err(e) if errors_json => {
+                        let path = pcap.get(i).filter(|_| !merge);
+                        ErrorReport::new(&e, path.map(PathBuf::as_path), None).print();
+                        nr_failed += 1;
+                        Some(None)
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        None
+                    }
+                })
+                .flatten()
+                .filter(|rec| !(skip_empty && rec.is_empty()))
+                .map(|mut rec| {
+                    if let Some(anonymizer) = &mut anonymizer {
+                        rec.anonymize(anonymizer);
+                    }
+                    rec.set_capture_filter(capture_filter.as_deref());
+                    rec
+                })
+                .filter_map(|mut rec| {
+                    if let Some(watchlist) = &watchlist {
+                        let matched = watchlist.matches(&rec);
+                        if match_only && !matched {
+                            return None;
+                        }
+                        rec.set_matched(matched);
+                    }
+                    Some(rec)
+                });
+            let recs = if dedupe_streams {
+                let mut dedupe = Dedupe::default();
+                recs.for_each(|rec| dedupe.add(rec));
+                Either::Left(dedupe.into_recs())
+            } else {
+                Either::Right(recs)
+            };
+            // HACK: The purpose of the `io::stdout` mumbo-jumbo is to handle
+            // BrokenPipe error. Rust throws it when the stdout is piped to `head`.
+            let mut nr_recs = 0;
+            let mut nr_mismatches = 0;
+            if summary {
+                let mut summary = Summary::default();
+                for rec in recs {
+                    summary.add(&rec);
+                    nr_recs += 1;
+                }
+                let summary = summary.into_out();
+                capture.timing.measure(Phase::Serialize, || -> Result<()> {
+                    if json {
+                        if pretty {
+                            serde_json::to_writer_pretty(&mut *writer, &summary)?;
+                        } else {
+                            serde_json::to_writer(&mut *writer, &summary)?;
+                        }
+                        writeln!(writer)?;
+                    } else {
+                        let s = serde_yaml::to_string(&summary)?;
+                        writer.write_all(s.as_bytes())?;
+                    }
+                    Ok(())
+                })?;
+                capture.timing.add_records(1);
+            } else if validate {
+                let mismatches = recs.flat_map(|rec| {
+                    nr_recs += 1;
+                    validate::check(&rec)
+                });
+                nr_mismatches = write_records(writer, mismatches, json, pretty, &capture.timing)?;
+            } else if let Some(syslog) = &mut syslog {
+                for rec in recs {
+                    capture
+                        .timing
+                        .measure(Phase::Serialize, || syslog.send(&rec))?;
+                    nr_recs += 1;
+                }
+                capture.timing.add_records(nr_recs);
+            } else if !unique.is_empty() {
+                let mut groups = Unique::new(unique);
+                for rec in recs {
+                    groups.add(&rec);
+                }
+                let groups = groups.into_out();
+                nr_recs = write_records(writer, groups, json, pretty, &capture.timing)?;
+            } else if foxio_names {
+                let recs = recs.map(FoxioNames);
+                nr_recs = write_records(writer, recs, json, pretty, &capture.timing)?;
+            } else {
+                nr_recs = write_records(writer, recs, json, pretty, &capture.timing)?;
+            }
+            writer.flush()?;
+            capture.progress.finish();
+            capture.timing.report()?;
+            if let Some(max) = capture.max_streams {
+                if capture.nr_stopped.load(Ordering::Relaxed) > 0 {
+                    eprintln!(
+                        "note: stopped after {max} streams with fingerprints; \
+                        the rest of the capture was not processed"
+                    );
+                }
+            }
+            if let Some(e) = error {
+                return Err(e);
+            }
+            if nr_failed > 0 {
+                return Err(Error::FailedInputs(nr_failed));
+            }
+            if fail_on_empty && nr_recs == 0 {
+                return Err(Error::NoFingerprints);
+            }
+            if nr_mismatches > 0 {
+                return Err(Error::Validation(nr_mismatches));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Builder of [`Processor`]. The methods are named after the command line options
+/// of `ja4`; see `ja4 --help` for details. The options not set are at their
+/// defaults.
+#[derive(Debug, Clone)]
+pub struct ProcessorBuilder(Processor);
+
+impl ProcessorBuilder {
+    /// Configuration of the fingerprinters (see [`Conf::builder`]) instead of the one
+    /// from the configuration file and the environment.
+    pub fn conf(mut self, conf: Conf) -> Self {
+        self.0.conf = Some(conf);
+        self
+    }
+
+    /// `--config`; ignored if [`ProcessorBuilder::conf`] is set.
+    pub fn config(mut self, path: impl Into<PathBuf>) -> Self {
+        self.0.cli.config = Some(path.into());
+        self
+    }
+
+    /// `--profile`; ignored if [`ProcessorBuilder::conf`] is set.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.0.cli.profile = Some(name.into());
+        self
+    }
+
+    /// `--json`
+    pub fn json(mut self, json: bool) -> Self {
+        self.0.cli.json = Some(json);
+        self
+    }
+
+    /// `--pretty`; requires JSON output, see [`Self::json`].
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.0.cli.pretty = pretty;
+        self
+    }
+
+    /// `--with-raw`, `--original-order`, and `--with-details`.
+    pub fn format(mut self, flags: FormatFlags) -> Self {
+        let FormatFlags {
+            with_raw,
+            original_order,
+            with_details,
+        } = flags;
+        self.0.cli.with_raw = Some(with_raw);
+        self.0.cli.original_order = Some(original_order);
+        self.0.cli.with_details = with_details;
+        self
+    }
+
+    /// `--with-raw`
+    pub fn with_raw(mut self, with_raw: bool) -> Self {
+        self.0.cli.with_raw = Some(with_raw);
+        self
+    }
+
+    /// `--original-order`
+    pub fn original_order(mut self, original_order: bool) -> Self {
+        self.0.cli.original_order = Some(original_order);
+        self
+    }
+
+    /// `--with-details`
+    pub fn with_details(mut self, with_details: bool) -> Self {
+        self.0.cli.with_details = with_details;
+        self
+    }
+
+    /// `--with-packet-numbers`
+    pub fn with_packet_numbers(mut self, with_packet_numbers: bool) -> Self {
+        self.0.cli.with_packet_numbers = Some(with_packet_numbers);
+        self
+    }
+
+    /// `--foxio-names`
+    pub fn foxio_names(mut self, foxio_names: bool) -> Self {
+        self.0.cli.foxio_names = foxio_names;
+        self
+    }
+
+    /// `--keylog-file`
+    pub fn keylog_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.0.cli.keylog_file = Some(path.into());
+        self
+    }
+
+    /// `--display-filter`
+    pub fn display_filter(mut self, filter: impl Into<String>) -> Self {
+        self.0.cli.display_filter = Some(filter.into());
+        self
+    }
+
+    /// `--decode-as`; may be called repeatedly.
+    pub fn decode_as(mut self, spec: impl Into<String>) -> Self {
+        self.0.cli.decode_as.push(spec.into());
+        self
+    }
+
+    /// `--host`; may be called repeatedly.
+    pub fn host(mut self, ip: IpAddr) -> Self {
+        self.0.cli.host.push(ip);
+        self
+    }
+
+    /// `--port`; may be called repeatedly.
+    pub fn port(mut self, port: u16) -> Self {
+        self.0.cli.port.push(port);
+        self
+    }
+
+    /// `--only`: the fingerprint types to compute. Empty selects all of them.
+    pub fn only(mut self, kinds: impl IntoIterator<Item = FingerprintKind>) -> Self {
+        self.0.cli.only = kinds.into_iter().collect();
+        self
+    }
+
+    /// `--skip-empty`
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.0.cli.skip_empty = skip_empty;
+        self
+    }
+
+    /// `--fail-on-empty`: [`Processor::run_pcap`] fails with
+    /// [`Error::NoFingerprints`] if no records are written.
+    pub fn fail_on_empty(mut self, fail_on_empty: bool) -> Self {
+        self.0.cli.fail_on_empty = fail_on_empty;
+        self
+    }
+
+    /// `--min-packets`
+    pub fn min_packets(mut self, min_packets: usize) -> Self {
+        self.0.cli.min_packets = Some(min_packets);
+        self
+    }
+
+    /// `--experimental`
+    pub fn experimental(mut self, experimental: bool) -> Self {
+        self.0.cli.experimental = experimental;
+        self
+    }
+
+    /// `--resolve-dns`
+    pub fn resolve_dns(mut self, resolve_dns: bool) -> Self {
+        self.0.cli.resolve_dns = resolve_dns;
+        self
+    }
+
+    /// `--validate`: [`Processor::run_pcap`] fails with [`Error::Validation`] if
+    /// a fingerprint doesn't match its raw form.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.0.cli.validate = validate;
+        self
+    }
+
+    /// `--tshark-version-check`
+    pub fn tshark_version_check(mut self, check: VersionCheck) -> Self {
+        self.0.cli.tshark_version_check = Some(check);
+        self
+    }
+
+    /// `--tshark-path`
+    pub fn tshark_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.0.cli.tshark_path = Some(path.into());
+        self
+    }
+
+    /// `--tshark-timeout`
+    pub fn tshark_timeout(mut self, timeout: Duration) -> Self {
+        self.0.cli.tshark_timeout = Some(timeout.as_secs().max(1));
+        self
+    }
+
+    /// Returns the processor. The options are checked when it runs.
+    pub fn build(self) -> Processor {
+        self.0
+    }
+}
+
+/// Returns the options of `ja4` without arguments, as [`Cli::parse`] would.
+fn default_options() -> Cli {
+    Cli {
+        config: None,
+        profile: None,
+        json: None,
+        pretty: false,
+        with_raw: None,
+        original_order: None,
+        with_details: false,
+        foxio_names: false,
+        keylog_file: None,
+        with_packet_numbers: None,
+        display_filter: None,
+        decode_as: Vec::new(),
+        host: Vec::new(),
+        net: Vec::new(),
+        port: Vec::new(),
+        port_range: Vec::new(),
+        stream: Vec::new(),
+        after: None,
+        before: None,
+        since: None,
+        until: None,
+        sni: Vec::new(),
+        only: Vec::new(),
+        engine: Engine::Tshark,
+        max_streams: None,
+        fail_on_empty: false,
+        skip_empty: false,
+        udp_timeout: stream::DEFAULT_UDP_TIMEOUT_SECS,
+        tcp_idle_timeout: None,
+        min_packets: None,
+        strict_min: false,
+        tshark_timeout: None,
+        all_fields: false,
+        experimental: false,
+        resolve_dns: false,
+        tshark_output: TsharkOutput::Pdml,
+        tshark_path: None,
+        tshark_min_version: None,
+        tshark_version_check: None,
+        tshark_args: Vec::new(),
+        errors_json: false,
+        summary: false,
+        unique: Vec::new(),
+        validate: false,
+        anonymize: false,
+        anonymize_key: None,
+        match_file: None,
+        match_only: false,
+        dedupe_streams: false,
+        list_streams: false,
+        syslog: None,
+        syslog_transport: SyslogTransport::Udp,
+        output: None,
+        rotate_size: None,
+        rotate_interval: None,
+        interface: None,
+        capture_filter: None,
+        jobs: None,
+        unordered: false,
+        merge: false,
+        progress: false,
+        timing: false,
+        version: false,
+        pcap: Vec::new(),
+        command: None,
+    }
+}
+
+/// Writes the records as JSON lines, a pretty-printed JSON array, or a YAML list.
+/// Returns the number of records.
+fn write_records<W: Write, T: serde::Serialize>(
+    writer: &mut W,
+    recs: impl IntoIterator<Item = T>,
+    json: bool,
+    pretty: bool,
+    timing: &Timing,
+) -> Result<usize> {
+    if json && !pretty {
+        let mut nr_recs = 0;
+        for rec in recs {
+            timing.measure(Phase::Serialize, || -> Result<()> {
+                serde_json::to_writer(&mut *writer, &rec)?;
+                writeln!(writer)?;
+                Ok(())
+            })?;
+            nr_recs += 1;
+        }
+        timing.add_records(nr_recs);
+        return Ok(nr_recs);
+    }
+    let recs = recs.into_iter().collect::<Vec<_>>();
+    timing.measure(Phase::Serialize, || -> Result<()> {
+        if json {
+            serde_json::to_writer_pretty(&mut *writer, &recs)?;
+            writeln!(writer)?;
+        } else {
+            let s = serde_yaml::to_string(&recs)?;
+            writer.write_all(s.as_bytes())?;
+        }
+        Ok(())
+    })?;
+    timing.add_records(recs.len());
+    Ok(recs.len())
+}
+
+#[test]
+fn test_default_options() {
+    use clap::Parser as _;
+
+    // The defaults match those of the command line.
+    let mut cli = Cli::parse_from(["ja4", "in.pcap"]);
+    cli.pcap.clear();
+    assert_eq!(format!("{cli:?}"), format!("{:?}", default_options()));
+
+    let processor = Processor::builder()
+        .format(FormatFlags {
+            with_raw: true,
+            ..FormatFlags::default()
+        })
+        .original_order(true)
+        .only([FingerprintKind::Ja4, FingerprintKind::Ja4s])
+        .decode_as("tcp.port==8888,http")
+        .build();
+    let mut cli = Cli::parse_from([
+        "ja4",
+        "--with-raw",
+        "--original-order",
+        "--only",
+        "ja4,ja4s",
+        "-d",
+        "tcp.port==8888,http",
+        "in.pcap",
+    ]);
+    cli.pcap.clear();
+    assert_eq!(format!("{:?}", processor.cli), format!("{cli:?}"));
+}
+
+#[test]
+fn test_requires_json() {
+    use clap::Parser as _;
+
+    let pcap = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap/tls12.pcap");
+    let cli = |args: &[&str]| {
+        let mut cli = Cli::try_parse_from(
+            ["ja4", "--engine", "native"]
+                .iter()
+                .chain(args)
+                .chain(&["in.pcap"]),
+        )
+        .expect("clap leaves JSON output to Processor");
+        cli.pcap = vec![pcap.clone()];
+        cli
+    };
+    let run = |cli: Cli, format: OutputFormat| {
+        let mut conf = Conf::builder().build().unwrap();
+        conf.output.format = format;
+        let processor = Processor {
+            cli,
+            conf: Some(conf),
+        };
+        let mut out = Vec::new();
+        processor.run(&mut out).map(|()| out)
+    };
+
+    // `format = "json"` in the configuration satisfies `--pretty` ...
+    let out = run(cli(&["--pretty"]), OutputFormat::Json).unwrap();
+    assert!(out.starts_with(b"[\n"), "{}", String::from_utf8_lossy(&out));
+    // ... as does `--json`.
+    run(cli(&["--json", "--pretty"]), OutputFormat::Yaml).unwrap();
+
+    for (args, option) in [
+        (&["--pretty"][..], "--pretty"),
+        (&["--json=false", "--pretty"], "--pretty"),
+        (
+            &["-o", "out.ndjson", "--rotate-size", "100"],
+            "--rotate-size",
+        ),
+        (
+            &["-o", "out.ndjson", "--rotate-interval", "60"],
+            "--rotate-interval",
+        ),
+    ] {
+        match run(cli(args), OutputFormat::Yaml) {
+            Err(Error::RequiresJson(o)) => assert_eq!(o, option),
+            other => panic!("{args:?}: {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_fail_on_empty() {
+    use clap::Parser as _;
+
+    let pcap = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap/tls12.pcap");
+    let run = |args: &[&str]| {
+        let args = ["ja4", "--engine", "native", "--json"].iter().chain(args);
+        let processor = Processor::from_cli(Cli::parse_from(args.chain(&["in.pcap"])));
+        let mut out = Vec::new();
+        processor.run_pcap(&pcap, &mut out).map(|()| out)
+    };
+
+    assert!(!run(&["--fail-on-empty"]).unwrap().is_empty());
+    // No stream on port 1.
+    assert!(run(&["--port", "1"]).unwrap().is_empty());
+    assert!(matches!(
+        run(&["--port", "1", "--fail-on-empty"]),
+        Err(Error::NoFingerprints)
+    ));
+}
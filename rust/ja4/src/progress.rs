@@ -0,0 +1,182 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Progress of reading the captures (`--progress`)
+//!
+//! The bar is drawn on stderr, and only if stderr is a terminal. Its position is
+//! the number of bytes of the capture files consumed so far; tshark doesn't tell
+//! us its offset in the file, so we add up the lengths of the packets and the
+//! headers of their records.
+
+use std::{
+    fmt,
+    io::IsTerminal as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+
+/// Progress of the whole run, shared by the jobs. Does nothing unless enabled.
+#[derive(Debug, Default)]
+pub(crate) struct Progress(Option<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    bar: ProgressBar,
+    packets: Arc<AtomicU64>,
+    streams: Arc<AtomicUsize>,
+    start: Instant,
+}
+
+/// Length of a packet record header in pcap files.
+const RECORD_HEADER_LEN: u64 = 16;
+
+impl Progress {
+    /// Starts tracking the progress of reading `pcaps`, or of a live capture if
+    /// there are none.
+    ///
+    /// The ETA is only shown if the sizes of all the files are known.
+    pub(crate) fn new(enabled: bool, pcaps: &[PathBuf]) -> Self {
+        if !enabled {
+            return Self(None);
+        }
+        let total_bytes = (!pcaps.is_empty())
+            .then(|| {
+                pcaps.iter().try_fold(0, |total, pcap| {
+                    let meta = std::fs::metadata(pcap).ok()?;
+                    meta.is_file().then(|| total + meta.len())
+                })
+            })
+            .flatten();
+
+        let packets = Arc::<AtomicU64>::default();
+        let streams = Arc::<AtomicUsize>::default();
+        let template = if total_bytes.is_some() {
+            "{spinner} [{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} \
+             {packets} packets, {streams} streams, ETA {eta}"
+        } else {
+            "{spinner} [{elapsed_precise}] {packets} packets, {streams} streams"
+        };
+        let style = ProgressStyle::with_template(template)
+            .expect("BUG: invalid template")
+            .with_key("packets", {
+                let packets = Arc::clone(&packets);
+                move |_: &ProgressState, w: &mut dyn fmt::Write| {
+                    let _ = write!(w, "{}", packets.load(Ordering::Relaxed));
+                }
+            })
+            .with_key("streams", {
+                let streams = Arc::clone(&streams);
+                move |_: &ProgressState, w: &mut dyn fmt::Write| {
+                    let _ = write!(w, "{}", streams.load(Ordering::Relaxed));
+                }
+            });
+        let target = if std::io::stderr().is_terminal() {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        let bar = ProgressBar::with_draw_target(total_bytes, target).with_style(style);
+        bar.enable_steady_tick(Duration::from_millis(200));
+        Self(Some(Inner {
+            bar,
+            packets,
+            streams,
+            start: Instant::now(),
+        }))
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Accounts for a packet of `len` bytes (as captured). Returns the estimated
+    /// number of bytes of its record in the capture file.
+    pub(crate) fn add_packet(&self, len: u64) -> u64 {
+        let Some(inner) = &self.0 else {
+            return 0;
+        };
+        inner.packets.fetch_add(1, Ordering::Relaxed);
+        inner.bar.inc(RECORD_HEADER_LEN + len);
+        RECORD_HEADER_LEN + len
+    }
+
+    /// Accounts for the packets of a file that have been read without tshark.
+    pub(crate) fn add_packets(&self, nr_packets: usize) {
+        if let Some(inner) = &self.0 {
+            inner
+                .packets
+                .fetch_add(nr_packets as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts for streams that have got their first fingerprint.
+    pub(crate) fn add_streams(&self, nr_streams: usize) {
+        if let Some(inner) = &self.0 {
+            if nr_streams > 0 {
+                inner.streams.fetch_add(nr_streams, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Marks a file as read. `consumed` is the number of bytes returned by the
+    /// [`Self::add_packet`] calls for the file; the rest of the file (its header, pcapng blocks,
+    /// or the packets not read because of `--max-streams`) is added now.
+    pub(crate) fn finish_file(&self, pcap: &Path, consumed: u64) {
+        if let Some(inner) = &self.0 {
+            if let Ok(meta) = std::fs::metadata(pcap) {
+                inner.bar.inc(meta.len().saturating_sub(consumed));
+            }
+        }
+    }
+
+    /// Removes the bar and prints the summary line to stderr.
+    pub(crate) fn finish(&self) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+        inner.bar.finish_and_clear();
+        let elapsed = inner.start.elapsed();
+        eprintln!(
+            "{}",
+            summary(
+                inner.packets.load(Ordering::Relaxed),
+                inner.streams.load(Ordering::Relaxed),
+                elapsed,
+            )
+        );
+    }
+}
+
+fn summary(packets: u64, streams: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 {
+        packets as f64 / secs
+    } else {
+        0.0
+    };
+    format!(
+        "{packets} packets, {streams} streams fingerprinted in {secs:.1}s ({rate:.0} packets/s)"
+    )
+}
+
+#[test]
+fn test_summary() {
+    assert_eq!(
+        summary(12_345, 67, Duration::from_millis(2_500)),
+        "12345 packets, 67 streams fingerprinted in 2.5s (4938 packets/s)"
+    );
+    assert_eq!(
+        summary(0, 0, Duration::ZERO),
+        "0 packets, 0 streams fingerprinted in 0.0s (0 packets/s)"
+    );
+}
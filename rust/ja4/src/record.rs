@@ -0,0 +1,196 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Output records of `ja4` as public types
+//!
+//! [`Record`] is what `ja4` writes for every stream, in JSON or YAML. It
+//! deserializes the output of the command line tool and serializes back to the
+//! same fields, so tools written in Rust don't need their own copy of the schema.
+//! Library users get it from [`crate::OutputRecord::to_record`].
+//!
+//! # Stability
+//!
+//! - The named fields of [`Record`] and [`HttpRecord`] are stable: they are not
+//!   removed, renamed, or given another type without incrementing
+//!   [`SCHEMA_VERSION`].
+//! - New fields may be added at any time; the types are `#[non_exhaustive]`.
+//! - Fields without a named counterpart, e.g. `tls_certs`, `rtt_stats`, or
+//!   `cipher_count`, are kept in `other` as they are and may change between
+//!   releases.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Version of the schema of [`Record`]. Incremented when a stable field is
+/// removed, renamed, or changes its type.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Record of a TCP or UDP stream.
+///
+/// Optional fields are missing from the output if unset, as are `false` flags and
+/// empty lists. Fingerprints of the `_o` variants are given with
+/// `--original-order`, the `_r` and `_ro` ones with `--with-raw`; packet numbers
+/// (`pkt_*`) with `--with-packet-numbers`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Record {
+    /// Index of the stream, as assigned by tshark (`tcp.stream`, `udp.stream`).
+    pub stream: u32,
+    pub transport: Transport,
+    /// Address of the sender of the stream's earliest packet, usually the client.
+    pub src: String,
+    pub dst: String,
+    pub src_port: u16,
+    pub dst_port: u16,
+
+    /// Capture file of the stream, if several were read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<PathBuf>,
+    /// BPF filter of the live capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_filter: Option<String>,
+    /// Any fingerprint of the stream is on the `--match-file` list.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub matched: bool,
+    /// Number of later streams dropped as repetitions of this one
+    /// (`--dedupe-streams`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<usize>,
+
+    /// Server Name Indication of the TLS Client Hello.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_server_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkt_ja4: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4_o: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4_r: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4_ro: Option<String>,
+    /// The client attempts to resume an earlier TLS session.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resumed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkt_ja4s: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4s: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4s_r: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4l_c: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4l_s: Option<String>,
+
+    /// HTTP requests, in the order of appearance.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http: Vec<HttpRecord>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ja4ssh: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ja4ssh_c: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ja4ssh_s: Vec<String>,
+
+    /// Hash of all fingerprints of the stream. Missing with `--list-streams`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4plus: Option<String>,
+
+    /// Protocols above the transport layer (`--list-streams`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protocols: Vec<String>,
+    /// Number of packets (`--list-streams`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packets: Option<usize>,
+    /// Number of bytes on the wire (`--list-streams`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+
+    /// The remaining fields, see the [module documentation](self).
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Transport protocol of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// JA4H fingerprints of an HTTP request, an element of [`Record::http`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct HttpRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkt_ja4h: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4h: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4h_o: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4h_r: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ja4h_ro: Option<String>,
+    /// The server switched the connection to the WebSocket protocol.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub websocket: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket_protocol: Option<String>,
+    /// TCP segments with payload that followed the WebSocket upgrade.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket_packets: Option<u64>,
+    /// TCP payload bytes that followed the WebSocket upgrade.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket_bytes: Option<u64>,
+    /// The remaining fields, e.g. `ja4h_b` (`--with-details`).
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[test]
+fn test_record() {
+    let json = r#"{
+        "stream": 3,
+        "transport": "tcp",
+        "src": "192.168.1.168",
+        "dst": "142.251.16.94",
+        "src_port": 50112,
+        "dst_port": 443,
+        "tls_server_name": "example.com",
+        "ja4": "t13d1516h2_8daaf6152771_e5627efa2ab1",
+        "ja4_r": "t13d1516h2_002f,0035_0005,000a_0403,0804",
+        "cipher_count": 16,
+        "ja4s": "t130200_1301_234ea6891581",
+        "http": [
+            {"pkt_ja4h": 12, "ja4h": "ge11nn05enus_9ed1ff1f7b03_000000000000_000000000000", "ja4h_b": "9ed1ff1f7b03"}
+        ],
+        "ja4l_c": "57_128",
+        "ja4l_s": "2133_60",
+        "ja4plus": "0123456789ab"
+    }"#;
+    let rec: Record = serde_json::from_str(json).unwrap();
+    assert_eq!(rec.transport, Transport::Tcp);
+    assert_eq!(rec.src_port, 50112);
+    assert_eq!(rec.ja4s.as_deref(), Some("t130200_1301_234ea6891581"));
+    assert_eq!(rec.http[0].pkt_ja4h, Some(12));
+    assert_eq!(rec.http[0].other["ja4h_b"], "9ed1ff1f7b03");
+    assert_eq!(rec.other["cipher_count"], 16);
+    assert!(rec.ja4ssh.is_empty() && rec.packets.is_none());
+
+    // Serializing gives back the same fields.
+    let value: Value = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_value(&rec).unwrap(), value);
+}
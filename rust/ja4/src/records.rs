@@ -0,0 +1,335 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Lazy iteration over the records of a capture file
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+
+use rtshark::RTSharkBuilder;
+
+use crate::{
+    conf::Conf,
+    ek::EkCommand,
+    fields,
+    handler::{Event, EventContext},
+    select::FingerprintKind,
+    stream::Streams,
+    tshark::{Input, Program, Tshark, TsharkOutput},
+    Error, FormatFlags, OutputRecord, Packet, Result,
+};
+
+/// Options of [`Reader`], named after the command line options of `ja4`.
+#[derive(Debug, Clone, Default)]
+pub struct ReaderOptions {
+    /// Configuration of the fingerprinters. If `None`, it is read the same way as
+    /// by the command line tool: from the configuration file and `JA4_*` environment
+    /// variables.
+    pub conf: Option<Conf>,
+    /// TLS key log file to decrypt the sessions with (`--keylog-file`).
+    pub keylog_file: Option<PathBuf>,
+    /// Output format: `--with-raw`, `--original-order`, `--with-details`.
+    pub flags: FormatFlags,
+    /// Fingerprint types to compute (`--only`). An empty list selects those enabled
+    /// in the configuration, all of them by default.
+    pub only: Vec<FingerprintKind>,
+    /// Format of tshark output (`--tshark-output`).
+    pub tshark_output: TsharkOutput,
+}
+
+/// Reader of the records of a capture file.
+///
+/// The records are produced lazily, as tshark dissects the file: a TCP stream is
+/// yielded as soon as the connection is closed (FIN from both sides or RST), the
+/// remaining streams --- after tshark has processed the entire file.
+///
+/// Dropping the reader kills tshark and waits for it to exit. The reader is
+/// [`Send`], so async programs can consume it on a dedicated thread, e.g. with
+/// `tokio::task::spawn_blocking`, passing the records over a channel.
+pub struct Reader(Inner);
+
+/// Iterator over the records of a capture file.
+///
+/// Created by [`crate::stream_fingerprints`], [`crate::stream_fingerprints_with_conf`],
+/// and [`crate::fingerprint_pcap_with`]. If tshark cannot be started, the error is
+/// the only item.
+pub type Records = Reader;
+
+enum Inner {
+    /// tshark could not be started; the error is yielded once.
+    Failed(Option<Error>),
+    Running(Box<Running>),
+}
+
+impl Reader {
+    /// Starts tshark on the capture file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the configuration cannot be read, the paths are not valid UTF-8,
+    /// or tshark is missing, too old, or cannot be started.
+    pub fn open(pcap: impl AsRef<Path>, options: ReaderOptions) -> Result<Self> {
+        Running::new(pcap.as_ref(), options).map(|running| Self(Inner::Running(Box::new(running))))
+    }
+
+    /// Same as [`Reader::open`], but the error is yielded by the iterator.
+    pub(crate) fn open_lazily(pcap: &Path, options: ReaderOptions) -> Self {
+        Self::open(pcap, options).unwrap_or_else(|error| Self(Inner::Failed(Some(error))))
+    }
+
+    /// Calls `enrich` on every record before it is yielded, e.g. to add fields with
+    /// [`OutputRecord::extra_mut`]. See [`crate::fingerprint_pcap_with`].
+    pub fn enrich(mut self, enrich: impl FnMut(&mut OutputRecord) + Send + 'static) -> Self {
+        if let Inner::Running(running) = &mut self.0 {
+            running.enrich = Some(Box::new(enrich));
+        }
+        self
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<OutputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            Inner::Failed(error) => error.take().map(Err),
+            Inner::Running(running) => running.next(),
+        }
+    }
+}
+
+/// User function applied to the records, see [`crate::fingerprint_pcap_with`].
+type Enrich = Box<dyn FnMut(&mut OutputRecord) + Send>;
+
+pub(crate) struct Running {
+    conf: Conf,
+    flags: FormatFlags,
+    /// `None` once tshark has processed the entire file or failed.
+    tshark: Option<Tshark>,
+    streams: Streams,
+    packet_num: usize,
+    /// Records of finalized streams that haven't been yielded yet.
+    ready: VecDeque<OutputRecord>,
+    enrich: Option<Enrich>,
+}
+
+impl Running {
+    pub(crate) fn new(pcap: &Path, options: ReaderOptions) -> Result<Self> {
+        let ReaderOptions {
+            conf,
+            keylog_file,
+            flags,
+            only,
+            tshark_output,
+        } = options;
+        let mut conf = match conf {
+            Some(conf) => conf,
+            None => Conf::load()?,
+        };
+        conf.restrict(conf.selection(&only));
+        let Some(pcap_path) = pcap.to_str() else {
+            return Err(Error::NonUtf8Path(pcap.to_owned()));
+        };
+        let program = Program::new(conf.tshark_path.clone(), Vec::new())?;
+        crate::check_tshark_version(&program, &conf)?;
+        let keylog_path = match &keylog_file {
+            Some(keylog) => match keylog.to_str() {
+                Some(path) => Some(path),
+                None => return Err(Error::NonUtf8Path(keylog.clone())),
+            },
+            None => None,
+        };
+        let decode_as = conf.ports.decode_as();
+        let tshark = match tshark_output {
+            TsharkOutput::Pdml => {
+                let mut builder = RTSharkBuilder::builder().input_path(pcap_path);
+                if let Some(dir) = program.env_path()? {
+                    builder = builder.env_path(dir);
+                }
+                if let Some(path) = keylog_path {
+                    builder = builder.keylog_file(path);
+                }
+                for spec in &decode_as {
+                    builder = builder.decode_as(spec);
+                }
+                Tshark::spawn(&builder, None)?
+            }
+            TsharkOutput::Ek => {
+                let mut command =
+                    EkCommand::new(&program, Input::File(pcap_path), &fields::needed(&conf));
+                if let Some(path) = keylog_path {
+                    command = command.keylog_file(path);
+                }
+                for spec in &decode_as {
+                    command = command.decode_as(spec);
+                }
+                Tshark::spawn_ek(&command, None)?
+            }
+        };
+        Ok(Self {
+            conf,
+            flags,
+            tshark: Some(tshark),
+            streams: Streams::default(),
+            packet_num: 0,
+            ready: VecDeque::new(),
+            enrich: None,
+        })
+    }
+
+    /// Collects the fingerprints as they are computed, see [`crate::handler`].
+    pub(crate) fn with_events(mut self, original_order: bool) -> Self {
+        self.streams = std::mem::take(&mut self.streams).with_events(original_order);
+        self
+    }
+
+    pub(crate) fn take_events(&mut self) -> Vec<(EventContext, Event)> {
+        self.streams.take_events()
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Result<OutputRecord>> {
+        loop {
+            if let Some(mut rec) = self.ready.pop_front() {
+                if let Some(enrich) = &mut self.enrich {
+                    enrich(&mut rec);
+                }
+                return Some(Ok(rec));
+            }
+            let tshark = self.tshark.as_mut()?;
+            match tshark.read() {
+                Ok(Some(packet)) => {
+                    self.packet_num += 1;
+                    let pkt = Packet::new(&packet, self.packet_num)
+                        .with_field_order(tshark.field_order());
+                    if let Err(error) = self.streams.update(&pkt, &self.conf, false) {
+                        tracing::debug!(self.packet_num, %error, "failed to handle packet");
+                    }
+                    self.ready.extend(self.streams.drain_closed(self.flags));
+                }
+                Ok(None) => {
+                    self.tshark = None;
+                    let streams = std::mem::take(&mut self.streams);
+                    self.ready.extend(streams.into_out(self.flags));
+                }
+                Err(error) => {
+                    self.tshark = None;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_reader_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Reader>();
+}
+
+/// Writes a stand-in tshark script that prints the ek output of a closed HTTP
+/// connection. With `hang`, it then keeps running, as tshark does on a large
+/// capture.
+#[cfg(unix)]
+#[cfg(test)]
+fn stand_in_tshark(name: &str, hang: bool) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("ja4-test-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("tshark");
+    std::fs::write(
+        &script,
+        r#"#!/bin/sh
+if [ "$1" = --version ]; then
+    echo 'TShark (Wireshark) 4.2.0 (v4.2.0-0-g1ff2b2d8ad52).'
+    exit
+fi
+dir=$(dirname "$0")
+echo "$@" > "$dir/args"
+echo $$ > "$dir/pid"
+addr='"ip":{"ip_ip_src":"10.0.0.1","ip_ip_dst":"10.0.0.2"}'
+back='"ip":{"ip_ip_src":"10.0.0.2","ip_ip_dst":"10.0.0.1"}'
+echo '{"index":{}}'
+echo '{"layers":{"frame":{"frame_frame_time_epoch":"1690000000.1"},'"$addr"',"tcp":{"tcp_tcp_stream":"0","tcp_tcp_srcport":"50000","tcp_tcp_dstport":"80"},"http":{"http_http_request_method":"GET","http_http_request_version":"HTTP/1.1"}}}'
+echo '{"layers":{"frame":{"frame_frame_time_epoch":"1690000000.2"},'"$addr"',"tcp":{"tcp_tcp_stream":"0","tcp_tcp_srcport":"50000","tcp_tcp_dstport":"80","tcp_tcp_flags_fin":true}}}'
+echo '{"layers":{"frame":{"frame_frame_time_epoch":"1690000000.3"},'"$back"',"tcp":{"tcp_tcp_stream":"0","tcp_tcp_srcport":"80","tcp_tcp_dstport":"50000","tcp_tcp_flags_fin":true}}}'
+"#
+        .to_owned()
+            + if hang { "exec sleep 60\n" } else { "" },
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+#[cfg(unix)]
+#[cfg(test)]
+fn ek_options(script: &Path) -> ReaderOptions {
+    ReaderOptions {
+        conf: Some(Conf::builder().tshark_path(script).build().unwrap()),
+        tshark_output: TsharkOutput::Ek,
+        ..Default::default()
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_reader_drop() {
+    let script = stand_in_tshark("reader-drop", true);
+    let dir = script.parent().unwrap();
+    let mut reader = Reader::open("in.pcap", ek_options(&script)).unwrap();
+    // The closed connection is yielded while tshark is still running.
+    let rec = reader.next().unwrap().unwrap();
+    assert!(serde_json::to_value(&rec).unwrap().get("http").is_some());
+    let pid = std::fs::read_to_string(dir.join("pid")).unwrap();
+    let alive = || {
+        std::process::Command::new("kill")
+            .args(["-0", pid.trim()])
+            .status()
+            .unwrap()
+            .success()
+    };
+    assert!(alive());
+    // Dropping the reader mid-capture kills tshark and reaps it.
+    drop(reader);
+    assert!(!alive());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_reader_options() {
+    let script = stand_in_tshark("reader-options", false);
+    let dir = script.parent().unwrap();
+    let has_http = |only: Vec<FingerprintKind>| {
+        let options = ReaderOptions {
+            only,
+            ..ek_options(&script)
+        };
+        let rec = Reader::open("in.pcap", options).unwrap().next();
+        rec.is_some_and(|rec| {
+            serde_json::to_value(rec.unwrap())
+                .unwrap()
+                .get("http")
+                .is_some()
+        })
+    };
+    assert!(has_http(vec![FingerprintKind::Ja4h]));
+    assert!(!has_http(vec![FingerprintKind::Ja4]));
+
+    let options = ReaderOptions {
+        keylog_file: Some(dir.join("keys.txt")),
+        ..ek_options(&script)
+    };
+    Reader::open("in.pcap", options).unwrap().next();
+    let args = std::fs::read_to_string(dir.join("args")).unwrap();
+    let keylog = format!("-o tls.keylog_file:{}", dir.join("keys.txt").display());
+    assert!(args.contains(&keylog), "{args}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
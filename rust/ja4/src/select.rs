@@ -0,0 +1,99 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Selection of fingerprint types to compute (`--only`, `[fingerprints]` table of
+//! the configuration file)
+
+use serde::Deserialize;
+
+/// Fingerprint type, as named on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FingerprintKind {
+    Ja4,
+    Ja4s,
+    Ja4h,
+    Ja4l,
+    Ja4x,
+    Ja4ssh,
+}
+
+impl FingerprintKind {
+    /// Returns the name of the fingerprint type, as on the command line.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Ja4 => "ja4",
+            Self::Ja4s => "ja4s",
+            Self::Ja4h => "ja4h",
+            Self::Ja4l => "ja4l",
+            Self::Ja4x => "ja4x",
+            Self::Ja4ssh => "ja4ssh",
+        }
+    }
+}
+
+/// Fingerprint types to compute. By default, all of them are selected.
+///
+/// Packets are not inspected for the fingerprints that are not selected, so these
+/// fingerprints are absent from the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub(crate) struct Selection {
+    pub(crate) ja4: bool,
+    pub(crate) ja4s: bool,
+    pub(crate) ja4h: bool,
+    pub(crate) ja4l: bool,
+    pub(crate) ja4x: bool,
+    pub(crate) ja4ssh: bool,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self {
+            ja4: true,
+            ja4s: true,
+            ja4h: true,
+            ja4l: true,
+            ja4x: true,
+            ja4ssh: true,
+        }
+    }
+}
+
+impl Selection {
+    /// Selects the given fingerprint types only. An empty list selects all of them.
+    pub(crate) fn new(kinds: &[FingerprintKind]) -> Self {
+        if kinds.is_empty() {
+            return Self::default();
+        }
+        let has = |kind| kinds.contains(&kind);
+        Self {
+            ja4: has(FingerprintKind::Ja4),
+            ja4s: has(FingerprintKind::Ja4s),
+            ja4h: has(FingerprintKind::Ja4h),
+            ja4l: has(FingerprintKind::Ja4l),
+            ja4x: has(FingerprintKind::Ja4x),
+            ja4ssh: has(FingerprintKind::Ja4ssh),
+        }
+    }
+
+    /// Returns `true` if any of the TLS fingerprints (JA4, JA4S, JA4X) is selected.
+    pub(crate) fn tls(&self) -> bool {
+        self.ja4 || self.ja4s || self.ja4x
+    }
+}
+
+#[test]
+fn test_selection() {
+    assert_eq!(Selection::new(&[]), Selection::default());
+
+    let sel = Selection::new(&[FingerprintKind::Ja4, FingerprintKind::Ja4s]);
+    assert!(sel.ja4 && sel.ja4s && sel.tls());
+    assert!(!sel.ja4h && !sel.ja4l && !sel.ja4x && !sel.ja4ssh);
+
+    assert!(!Selection::new(&[FingerprintKind::Ja4l]).tls());
+    assert!(Selection::new(&[FingerprintKind::Ja4x]).tls());
+}
@@ -6,6 +6,15 @@
 // For full license text, see the repo root.
 
 //! JA4SSH -- SSH traffic fingerprinting
+//!
+//! Unlike JA4 and JA4S, JA4SSH has no section describing the protocol (transport,
+//! version, ALPN): all three sections are computed from the observed TCP segments
+//! of the stream. Neither the port numbers nor the version banners of the peers
+//! affect the fingerprint. tshark only dissects SSH on port 22 by itself, so SSH on
+//! another port needs `--decode-as`, e.g. `-d tcp.port==2222,ssh`.
+//!
+//! [`Ja4SshCalculator`] computes JA4SSH of live connections, as `ja4` does for the
+//! SSH streams of capture files.
 
 use std::collections::HashMap;
 
@@ -15,12 +24,13 @@ use crate::{Packet, Result, Sender};
 
 #[derive(Debug, Default)]
 pub(crate) struct Stream {
-    /// Statistics obtained from up to [`crate::conf::ConfSsh::sample_size`] packets.
-    stats: Stats,
+    /// Statistics of the current sample. Created with the first packet, when the
+    /// sample size is known.
+    calculator: Option<Ja4SshCalculator>,
     /// SSH fingerprints.
     ///
-    /// New entry is added every [`crate::conf::ConfSsh::sample_size`] packets.
-    ja4ssh: Vec<Fingerprint>,
+    /// New entries are added every [`crate::conf::ConfSsh::sample_size`] packets.
+    fingerprints: Fingerprints,
     extras: StreamExtras,
 }
 
@@ -31,27 +41,175 @@ impl Stream {
         sender: Sender,
         sample_size: usize,
     ) -> Result<()> {
-        self.stats.update(pkt, sender)?;
-        if self.stats.nr_ssh_client_packets + self.stats.nr_ssh_server_packets == sample_size {
-            let stats = std::mem::take(&mut self.stats);
-            if let Some(fp) = stats.into() {
-                self.ja4ssh.push(fp);
-            }
-        }
+        self.add(Segment::new(pkt)?, sender, sample_size);
         self.extras.update(pkt, sender);
         Ok(())
     }
 
-    pub(crate) fn finish(self) -> (Vec<Fingerprint>, Option<Extras>) {
+    fn add(&mut self, segment: Segment, sender: Sender, sample_size: usize) {
+        let calculator = self
+            .calculator
+            .get_or_insert_with(|| Ja4SshCalculator::new(sample_size));
+        if let Some(fp) = calculator.add(segment, sender) {
+            self.fingerprints.push(fp);
+        }
+    }
+
+    /// Returns the number of complete samples so far.
+    pub(crate) fn nr_fingerprints(&self) -> usize {
+        self.fingerprints.ja4ssh.len()
+    }
+
+    /// Returns the fingerprint of the sample with the given index.
+    pub(crate) fn fingerprint(&self, index: usize) -> Option<Ja4SshFingerprint> {
+        let fps = &self.fingerprints;
+        Some(Ja4SshFingerprint {
+            ja4ssh: fps.ja4ssh.get(index)?.0.clone(),
+            ja4ssh_c: fps.ja4ssh_c.get(index)?.0.clone(),
+            ja4ssh_s: fps.ja4ssh_s.get(index)?.0.clone(),
+        })
+    }
+
+    /// Returns `true` if [`Stream::finish`] would produce a JA4SSH fingerprint.
+    pub(crate) fn has_fingerprints(&self) -> bool {
+        !self.fingerprints.is_empty()
+            || self
+                .calculator
+                .as_ref()
+                .is_some_and(|calc| calc.stats.has_ssh_packets())
+    }
+
+    pub(crate) fn finish(self) -> (Fingerprints, Option<Extras>) {
         let Stream {
-            stats: counts,
-            mut ja4ssh,
+            calculator,
+            mut fingerprints,
             extras,
         } = self;
-        if let Some(fp) = counts.into() {
-            ja4ssh.push(fp);
+        if let Some(fp) = calculator.and_then(Ja4SshCalculator::finish) {
+            fingerprints.push(fp);
         }
-        (ja4ssh, extras.try_into().ok())
+        (fingerprints, extras.try_into().ok())
+    }
+}
+
+/// Computes JA4SSH fingerprints of an SSH session from the sizes of its TCP
+/// segments, without a capture file.
+///
+/// The calculator is fed with the segments of one TCP connection in the order
+/// they are sent; a fingerprint is returned once every `sample_size` SSH packets,
+/// and [`Ja4SshCalculator::finish`] returns the fingerprint of the last, partial
+/// sample. `ja4` uses this calculator for the SSH streams of capture files.
+#[derive(Debug, Clone)]
+pub struct Ja4SshCalculator {
+    /// Statistics of the current sample of up to `sample_size` packets.
+    stats: Stats,
+    sample_size: usize,
+}
+
+impl Default for Ja4SshCalculator {
+    /// Returns a calculator with the default sample size of 200 packets.
+    fn default() -> Self {
+        Self::new(crate::conf::ConfSsh::DEFAULT_SAMPLE_SIZE)
+    }
+}
+
+impl Ja4SshCalculator {
+    /// Returns a calculator producing a fingerprint every `sample_size` SSH packets
+    /// (the `ssh.sample_size` setting of `ja4`).
+    ///
+    /// # Panics
+    ///
+    /// If `sample_size` is 0.
+    pub fn new(sample_size: usize) -> Self {
+        assert!(sample_size > 0, "JA4SSH sample size must not be 0");
+        Self {
+            stats: Stats::default(),
+            sample_size,
+        }
+    }
+
+    /// Counts an SSH packet sent by the client, with `tcp_len` bytes of TCP
+    /// payload. Returns the fingerprint of the sample that this packet completes.
+    pub fn client_packet(&mut self, tcp_len: usize) -> Option<Ja4SshFingerprint> {
+        self.add(Segment::Ssh { tcp_len }, Sender::Client)
+    }
+
+    /// Counts an SSH packet sent by the server, with `tcp_len` bytes of TCP
+    /// payload. Returns the fingerprint of the sample that this packet completes.
+    pub fn server_packet(&mut self, tcp_len: usize) -> Option<Ja4SshFingerprint> {
+        self.add(Segment::Ssh { tcp_len }, Sender::Server)
+    }
+
+    /// Counts a TCP segment sent by the client with the ACK flag only and no data.
+    pub fn client_bare_ack(&mut self) {
+        self.add(Segment::BareAck, Sender::Client);
+    }
+
+    /// Counts a TCP segment sent by the server with the ACK flag only and no data.
+    pub fn server_bare_ack(&mut self) {
+        self.add(Segment::BareAck, Sender::Server);
+    }
+
+    /// Returns the fingerprint of the last sample, unless it has no SSH packets.
+    pub fn finish(mut self) -> Option<Ja4SshFingerprint> {
+        self.stats.take_counts().map(Counts::fingerprints)
+    }
+
+    /// Counts the segment. A fingerprint is produced right after the
+    /// `sample_size`-th SSH segment of the sample; bare ACKs that follow it belong to
+    /// the next sample.
+    fn add(&mut self, segment: Segment, sender: Sender) -> Option<Ja4SshFingerprint> {
+        self.stats.add(segment, sender);
+        if self.stats.nr_ssh_client_packets + self.stats.nr_ssh_server_packets == self.sample_size {
+            self.stats.take_counts().map(Counts::fingerprints)
+        } else {
+            None
+        }
+    }
+}
+
+/// JA4SSH fingerprint of a sample of packets, see [`Ja4SshCalculator`].
+///
+/// The fields are named as in the output records of `ja4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4SshFingerprint {
+    /// The fingerprint: `c{mode}s{mode}_c{packets}s{packets}_c{acks}s{acks}`.
+    pub ja4ssh: String,
+    /// The client-to-server part: `c{mode}_c{packets}_c{acks}`.
+    pub ja4ssh_c: String,
+    /// The server-to-client part: `s{mode}_s{packets}_s{acks}`.
+    pub ja4ssh_s: String,
+}
+
+/// SSH fingerprints of a TCP stream, one entry per sample of
+/// [`crate::conf::ConfSsh::sample_size`] packets.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Fingerprints {
+    /// JA4SSH fingerprints.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ja4ssh: Vec<Fingerprint>,
+    /// The client-to-server part of JA4SSH fingerprints: `c{mode}_c{packets}_c{acks}`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ja4ssh_c: Vec<Fingerprint>,
+    /// The server-to-client part of JA4SSH fingerprints: `s{mode}_s{packets}_s{acks}`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ja4ssh_s: Vec<Fingerprint>,
+}
+
+impl Fingerprints {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ja4ssh.is_empty()
+    }
+
+    /// Returns JA4SSH fingerprints, in the order of appearance.
+    pub(crate) fn ja4ssh(&self) -> impl Iterator<Item = &str> {
+        self.ja4ssh.iter().map(|fp| fp.0.as_str())
+    }
+
+    fn push(&mut self, fp: Ja4SshFingerprint) {
+        self.ja4ssh.push(Fingerprint(fp.ja4ssh));
+        self.ja4ssh_c.push(Fingerprint(fp.ja4ssh_c));
+        self.ja4ssh_s.push(Fingerprint(fp.ja4ssh_s));
     }
 }
 
@@ -131,8 +289,12 @@ impl StreamExtras {
         #[cfg(debug_assertions)]
         if let Ok(dir) = ssh.find("ssh.direction") {
             match sender {
-                Sender::Client => assert_eq!(dir.display(), "Direction: client-to-server"),
-                Sender::Server => assert_eq!(dir.display(), "Direction: server-to-client"),
+                Sender::Client => {
+                    crate::pcap::debug_assert_display(dir, "Direction: client-to-server")
+                }
+                Sender::Server => {
+                    crate::pcap::debug_assert_display(dir, "Direction: server-to-client")
+                }
             }
         }
 
@@ -150,9 +312,11 @@ impl StreamExtras {
                         s.split(',').map(|s| s.to_owned()).collect(),
                     ));
                 }
-                if let Ok(s) = ssh.first("ssh.protocol") {
-                    debug_assert!(self.ssh_protocol_client.is_none());
-                    self.ssh_protocol_client = Some(s.to_owned());
+                if self.ssh_protocol_client.is_none() {
+                    self.ssh_protocol_client = ssh
+                        .values("ssh.protocol")
+                        .find_map(version_banner)
+                        .map(str::to_owned);
                 }
             }
             Sender::Server => {
@@ -160,9 +324,11 @@ impl StreamExtras {
                     debug_assert!(self.hassh_server.is_none());
                     self.hassh_server = Some(s.to_owned());
                 }
-                if let Ok(s) = ssh.first("ssh.protocol") {
-                    debug_assert!(self.ssh_protocol_server.is_none());
-                    self.ssh_protocol_server = Some(s.to_owned());
+                if self.ssh_protocol_server.is_none() {
+                    self.ssh_protocol_server = ssh
+                        .values("ssh.protocol")
+                        .find_map(version_banner)
+                        .map(str::to_owned);
                 }
                 let Ok(server_algs) = ssh.first("ssh.encryption_algorithms_server_to_client")
                 else {
@@ -194,7 +360,55 @@ impl StreamExtras {
     }
 }
 
-#[derive(Debug, Default)]
+/// Returns the protocol version exchange string (e.g. `SSH-2.0-OpenSSH_8.9`) if
+/// the line is one.
+///
+/// A server may send other lines of text before the version string (RFC 4253,
+/// section 4.2), and a retransmitted segment repeats it; only the first version
+/// string of each peer is reported.
+fn version_banner(line: &str) -> Option<&str> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    line.starts_with("SSH-").then_some(line)
+}
+
+/// TCP segment of an SSH stream, as JA4SSH counts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    /// Segment that tshark has dissected as SSH.
+    Ssh {
+        /// TCP payload length (`tcp.len`).
+        tcp_len: usize,
+    },
+    /// Segment with the ACK flag only and no SSH data.
+    BareAck,
+    Other,
+}
+
+impl Segment {
+    fn new(pkt: &Packet) -> Result<Self> {
+        const BARE_ACK_FLAG: &str = "0x0010";
+
+        // SAFETY: We would not reach this point if the packet didn't have a "tcp" layer;
+        // see `Streams::update` and `StreamId2::new`. It's safe to unwrap.
+        let tcp = pkt.protos("tcp").last().unwrap();
+
+        Ok(if pkt.find_proto("ssh").is_some() {
+            Self::Ssh {
+                tcp_len: tcp.first("tcp.len")?.parse()?,
+            }
+        } else if tcp.first("tcp.flags")? == BARE_ACK_FLAG {
+            Self::BareAck
+        } else {
+            Self::Other
+        })
+    }
+}
+
+/// Running aggregates of a sample. Per-packet data is not kept: the maps have at
+/// most one entry per SSH packet of the sample, and are cleared (keeping their
+/// memory) as soon as the sample is fingerprinted. So the state of a stream doesn't
+/// grow with the length of the session.
+#[derive(Debug, Default, Clone)]
 struct Stats {
     /// Key -- client TCP payload length, bytes; value -- number of packets with this length.
     /// Notes:
@@ -217,32 +431,39 @@ struct Stats {
 }
 
 impl Stats {
-    fn update(&mut self, pkt: &Packet, sender: Sender) -> Result<()> {
-        const BARE_ACK_FLAG: &str = "0x0010";
-
-        // SAFETY: We would not reach this point if the packet didn't have a "tcp" layer;
-        // see `Streams::update` and `StreamId2::new`. It's safe to unwrap.
-        let tcp = pkt.find_proto("tcp").unwrap();
-
-        if pkt.find_proto("ssh").is_some() {
-            let tcp_len = tcp.first("tcp.len")?.parse()?;
-            match sender {
-                Sender::Client => {
-                    *self.client_tcp_len_counts.entry(tcp_len).or_default() += 1;
-                    self.nr_ssh_client_packets += 1;
-                }
-                Sender::Server => {
-                    *self.server_tcp_len_counts.entry(tcp_len).or_default() += 1;
-                    self.nr_ssh_server_packets += 1;
-                }
+    fn add(&mut self, segment: Segment, sender: Sender) {
+        match (segment, sender) {
+            (Segment::Ssh { tcp_len }, Sender::Client) => {
+                *self.client_tcp_len_counts.entry(tcp_len).or_default() += 1;
+                self.nr_ssh_client_packets += 1;
             }
-        } else if tcp.first("tcp.flags")? == BARE_ACK_FLAG {
-            match sender {
-                Sender::Client => self.nr_tcp_client_acks += 1,
-                Sender::Server => self.nr_tcp_server_acks += 1,
+            (Segment::Ssh { tcp_len }, Sender::Server) => {
+                *self.server_tcp_len_counts.entry(tcp_len).or_default() += 1;
+                self.nr_ssh_server_packets += 1;
             }
+            (Segment::BareAck, Sender::Client) => self.nr_tcp_client_acks += 1,
+            (Segment::BareAck, Sender::Server) => self.nr_tcp_server_acks += 1,
+            (Segment::Other, _) => {}
         }
-        Ok(())
+    }
+
+    fn has_ssh_packets(&self) -> bool {
+        !self.client_tcp_len_counts.is_empty() || !self.server_tcp_len_counts.is_empty()
+    }
+
+    /// Returns the counts of the sample and resets the statistics for the next one.
+    fn take_counts(&mut self) -> Option<Counts> {
+        let counts = Counts::new(self);
+        let mut client_tcp_len_counts = std::mem::take(&mut self.client_tcp_len_counts);
+        let mut server_tcp_len_counts = std::mem::take(&mut self.server_tcp_len_counts);
+        client_tcp_len_counts.clear();
+        server_tcp_len_counts.clear();
+        *self = Self {
+            client_tcp_len_counts,
+            server_tcp_len_counts,
+            ..Default::default()
+        };
+        counts
     }
 }
 
@@ -250,16 +471,44 @@ impl Stats {
 #[derive(Debug, Serialize)]
 pub(crate) struct Fingerprint(String);
 
-impl From<Stats> for Option<Fingerprint> {
-    fn from(counters: Stats) -> Self {
+/// Per-direction counts that JA4SSH fingerprint is made of.
+#[derive(Debug)]
+struct DirectionCounts {
+    /// The mode of TCP payload lengths of SSH packets.
+    mode: usize,
+    /// The number of SSH packets.
+    nr_ssh_packets: usize,
+    /// The number of bare ACK packets.
+    nr_tcp_acks: usize,
+}
+
+impl DirectionCounts {
+    fn fingerprint(&self, marker: char) -> String {
+        let Self {
+            mode,
+            nr_ssh_packets,
+            nr_tcp_acks,
+        } = self;
+        format!("{marker}{mode}_{marker}{nr_ssh_packets}_{marker}{nr_tcp_acks}")
+    }
+}
+
+#[derive(Debug)]
+struct Counts {
+    client: DirectionCounts,
+    server: DirectionCounts,
+}
+
+impl Counts {
+    fn new(stats: &Stats) -> Option<Self> {
         let Stats {
-            client_tcp_len_counts,
-            server_tcp_len_counts,
+            ref client_tcp_len_counts,
+            ref server_tcp_len_counts,
             nr_ssh_client_packets,
             nr_ssh_server_packets,
             nr_tcp_client_acks,
             nr_tcp_server_acks,
-        } = counters;
+        } = *stats;
 
         if client_tcp_len_counts.is_empty() && server_tcp_len_counts.is_empty() {
             // This doesn't seem to be an *SSH* TCP stream after all.
@@ -274,15 +523,179 @@ impl From<Stats> for Option<Fingerprint> {
         // byte value.
         //
         // Reference: https://github.com/FoxIO-LLC/ja4/blob/16850cc2c8bcb8328c1a43a851a3a9a6eaa56103/technical_details/JA4SSH.md#how-to-measure-the-mode-for-tcp-payload-lengths-across-200-packets-in-the-session
-        let mode_client = min_key_with_max_value(client_tcp_len_counts).unwrap_or(0);
-        let mode_server = min_key_with_max_value(server_tcp_len_counts).unwrap_or(0);
-
-        let fp = format!(
-            "c{mode_client}s{mode_server}_c{}s{}_c{}s{}",
-            nr_ssh_client_packets, nr_ssh_server_packets, nr_tcp_client_acks, nr_tcp_server_acks,
-        );
-        Some(Fingerprint(fp))
+        let mode_client =
+            min_key_with_max_value(client_tcp_len_counts.iter().map(|(&k, &v)| (k, v)))
+                .unwrap_or(0);
+        let mode_server =
+            min_key_with_max_value(server_tcp_len_counts.iter().map(|(&k, &v)| (k, v)))
+                .unwrap_or(0);
+
+        Some(Self {
+            client: DirectionCounts {
+                mode: mode_client,
+                nr_ssh_packets: nr_ssh_client_packets,
+                nr_tcp_acks: nr_tcp_client_acks,
+            },
+            server: DirectionCounts {
+                mode: mode_server,
+                nr_ssh_packets: nr_ssh_server_packets,
+                nr_tcp_acks: nr_tcp_server_acks,
+            },
+        })
+    }
+
+    fn fingerprints(self) -> Ja4SshFingerprint {
+        Ja4SshFingerprint {
+            ja4ssh: self.fingerprint(),
+            ja4ssh_c: self.client.fingerprint('c'),
+            ja4ssh_s: self.server.fingerprint('s'),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        let Self { client, server } = self;
+        format!(
+            "c{}s{}_c{}s{}_c{}s{}",
+            client.mode,
+            server.mode,
+            client.nr_ssh_packets,
+            server.nr_ssh_packets,
+            client.nr_tcp_acks,
+            server.nr_tcp_acks,
+        )
+    }
+}
+
+#[test]
+fn test_counts_fingerprints() {
+    let stats = Stats {
+        client_tcp_len_counts: [(36, 20), (128, 10), (200, 15)].into_iter().collect(),
+        server_tcp_len_counts: [(52, 3), (36, 3)].into_iter().collect(),
+        nr_ssh_client_packets: 45,
+        nr_ssh_server_packets: 6,
+        nr_tcp_client_acks: 2,
+        nr_tcp_server_acks: 40,
+    };
+    let fp = Counts::new(&stats).unwrap().fingerprints();
+    assert_eq!(fp.ja4ssh, "c36s36_c45s6_c2s40");
+    assert_eq!(fp.ja4ssh_c, "c36_c45_c2");
+    assert_eq!(fp.ja4ssh_s, "s36_s6_s40");
+
+    // Not an SSH stream.
+    assert!(Counts::new(&Stats {
+        nr_tcp_client_acks: 1,
+        ..Default::default()
+    })
+    .is_none());
+}
+
+#[test]
+fn test_sample_boundary() {
+    let mut stream = Stream::default();
+    // A sample of 4 SSH segments: exactly one fingerprint, no empty trailing one.
+    for (segment, sender) in [
+        (Segment::Ssh { tcp_len: 36 }, Sender::Client),
+        (Segment::BareAck, Sender::Server),
+        (Segment::Ssh { tcp_len: 36 }, Sender::Server),
+        (Segment::Other, Sender::Client),
+        (Segment::Ssh { tcp_len: 52 }, Sender::Client),
+        (Segment::Ssh { tcp_len: 36 }, Sender::Server),
+    ] {
+        stream.add(segment, sender, 4);
+    }
+    assert_eq!(
+        stream.fingerprints.ja4ssh().collect::<Vec<_>>(),
+        ["c36s36_c2s2_c0s1"]
+    );
+
+    // The ACK after the 4th SSH segment starts the next sample.
+    stream.add(Segment::BareAck, Sender::Client, 4);
+    stream.add(Segment::Ssh { tcp_len: 36 }, Sender::Client, 4);
+    let (fps, _) = stream.finish();
+    assert_eq!(
+        fps.ja4ssh().collect::<Vec<_>>(),
+        ["c36s36_c2s2_c0s1", "c36s0_c1s0_c1s0"]
+    );
+}
+
+#[test]
+fn test_calculator() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let mut calc = Ja4SshCalculator::new(4);
+    assert_send(&calc);
+    assert!(calc.client_packet(36).is_none());
+    calc.server_bare_ack();
+    assert!(calc.server_packet(36).is_none());
+    assert!(calc.client_packet(52).is_none());
+    expect_test::expect![[r#"
+        Some(
+            Ja4SshFingerprint {
+                ja4ssh: "c36s36_c2s2_c0s1",
+                ja4ssh_c: "c36_c2_c0",
+                ja4ssh_s: "s36_s2_s1",
+            },
+        )
+    "#]]
+    .assert_debug_eq(&calc.server_packet(36));
+
+    // The last sample is fingerprinted if it has SSH packets.
+    calc.client_bare_ack();
+    let mut last = calc.clone();
+    assert_eq!(calc.finish(), None);
+    last.client_packet(36);
+    assert_eq!(last.finish().unwrap().ja4ssh, "c36s0_c1s0_c1s0");
+
+    assert_eq!(Ja4SshCalculator::default().sample_size, 200);
+}
+
+#[test]
+fn test_long_session() {
+    const SAMPLE_SIZE: usize = 200;
+    const NR_SAMPLES: usize = 2_000;
+
+    let mut stream = Stream::default();
+    let mut capacity = None;
+    for sample in 0..NR_SAMPLES {
+        for i in 0..SAMPLE_SIZE {
+            let sender = if i % 2 == 0 {
+                Sender::Client
+            } else {
+                Sender::Server
+            };
+            let tcp_len = 36 + (sample + i) % 150;
+            stream.add(Segment::Ssh { tcp_len }, sender, SAMPLE_SIZE);
+            stream.add(Segment::BareAck, sender, SAMPLE_SIZE);
+        }
+        // The statistics of the sample are gone once it is fingerprinted...
+        let stats = &stream.calculator.as_ref().unwrap().stats;
+        assert!(stats.client_tcp_len_counts.is_empty());
+        assert!(stats.server_tcp_len_counts.is_empty());
+        assert_eq!(stats.nr_ssh_client_packets + stats.nr_ssh_server_packets, 0);
+        // ... and the next sample reuses their memory rather than adding to it.
+        let cap = stats.client_tcp_len_counts.capacity() + stats.server_tcp_len_counts.capacity();
+        assert_eq!(*capacity.get_or_insert(cap), cap, "sample {sample}");
     }
+    assert_eq!(stream.fingerprints.ja4ssh().count(), NR_SAMPLES);
+}
+
+#[test]
+fn test_version_banner() {
+    // OpenSSH and Dropbear announce themselves differently; both are reported as is.
+    assert_eq!(
+        version_banner("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1\r\n"),
+        Some("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1")
+    );
+    assert_eq!(
+        version_banner("SSH-2.0-dropbear_2022.83"),
+        Some("SSH-2.0-dropbear_2022.83")
+    );
+    assert_eq!(
+        version_banner("SSH-1.99-OpenSSH_3.9p1"),
+        Some("SSH-1.99-OpenSSH_3.9p1")
+    );
+    // Text a server may send before its version string.
+    assert_eq!(version_banner("Welcome to example.com\r\n"), None);
 }
 
 fn min_key_with_max_value(kvs: impl IntoIterator<Item = (usize, usize)>) -> Option<usize> {
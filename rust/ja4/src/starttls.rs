@@ -0,0 +1,91 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Cleartext connections upgraded to TLS (STARTTLS)
+//!
+//! SMTP, IMAP, XMPP and some other protocols begin in cleartext; the client then
+//! issues STARTTLS (or equivalent) command and the TLS handshake follows on the same
+//! TCP stream. tshark dissects that handshake as usual, so [`crate::tls`] fingerprints
+//! it. We only remember which protocol preceded the upgrade.
+
+use serde::Serialize;
+
+use crate::Packet;
+
+#[derive(Debug, Default)]
+pub(crate) enum Stream {
+    /// Neither an upgradable protocol nor TLS has been seen yet.
+    #[default]
+    None,
+    /// Cleartext protocol that supports STARTTLS.
+    Cleartext(StarttlsProto),
+    /// TLS handshake has followed the cleartext protocol.
+    Upgraded(StarttlsProto),
+    /// TLS handshake has been seen first; this is not a STARTTLS stream.
+    Tls,
+}
+
+impl Stream {
+    pub(crate) fn update(&mut self, pkt: &Packet) {
+        if let Self::Upgraded(_) | Self::Tls = self {
+            return;
+        }
+        if pkt
+            .protos("tls")
+            .any(|tls| tls.find("tls.handshake.type").is_ok())
+        {
+            *self = match self {
+                Self::Cleartext(proto) => Self::Upgraded(*proto),
+                _ => Self::Tls,
+            };
+        } else if let Some(proto) = StarttlsProto::detect(pkt) {
+            *self = Self::Cleartext(proto);
+        }
+    }
+
+    pub(crate) fn into_out(self) -> Option<StarttlsProto> {
+        match self {
+            Self::Upgraded(proto) => Some(proto),
+            Self::None | Self::Cleartext(_) | Self::Tls => None,
+        }
+    }
+}
+
+/// Application protocol that preceded the upgrade to TLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StarttlsProto {
+    Smtp,
+    Imap,
+    Pop3,
+    Xmpp,
+    Ftp,
+    Ldap,
+    Nntp,
+}
+
+impl StarttlsProto {
+    /// Names of the corresponding tshark protocols.
+    const TSHARK_NAMES: [(&'static str, Self); 7] = [
+        ("smtp", Self::Smtp),
+        ("imap", Self::Imap),
+        ("pop", Self::Pop3),
+        ("xmpp", Self::Xmpp),
+        ("ftp", Self::Ftp),
+        ("ldap", Self::Ldap),
+        ("nntp", Self::Nntp),
+    ];
+
+    fn detect(pkt: &Packet) -> Option<Self> {
+        pkt.iter().find_map(|proto| {
+            Self::TSHARK_NAMES
+                .iter()
+                .find(|(name, _)| *name == proto.name())
+                .map(|&(_, starttls_proto)| starttls_proto)
+        })
+    }
+}
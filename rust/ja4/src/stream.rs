@@ -5,63 +5,434 @@
 // JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
 // For full license text, see the repo root.
 
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use indexmap::{map::Entry, IndexMap};
+use itertools::{Either, Itertools as _};
 use serde::Serialize;
 
 use crate::{
+    anonymize::Anonymizer,
     conf::Conf,
-    http, ssh,
+    dedupe, dns,
+    filter::StreamFilter,
+    handler::{Event, EventContext, Events},
+    hash12, http, record,
+    select::FingerprintKind,
+    ssh, starttls,
     time::{self, TcpTimestamps, Timestamps, UdpTimestamps},
-    tls, FormatFlags, Packet, Result, Sender,
+    tls, tunnel, FormatFlags, Packet, Result, Sender,
 };
 
 /// User-facing record containing data obtained from a TCP or UDP stream.
 #[derive(Debug, Serialize)]
-pub(crate) struct OutRec {
+pub struct OutRec {
     stream: StreamId,
     transport: Transport,
     #[serde(flatten)]
     sockets: SocketPair,
     #[serde(flatten)]
     payload: OutStream,
+    /// Name that a DNS response of the capture has resolved to the server's address
+    /// (`--resolve-dns`). Only for TLS streams; a best-effort guess, see
+    /// [`crate::dns`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_host: Option<String>,
+    /// Whether any fingerprint of the stream is on the `--match-file` list.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    matched: bool,
+    /// BPF filter of the live capture (`--capture-filter`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture_filter: Option<String>,
+    /// Capture file of the stream, if there are several.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pcap: Option<PathBuf>,
+    /// Number of later streams dropped as repetitions of this one
+    /// (`--dedupe-streams`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicates: Option<usize>,
+    /// Contents of the stream (`--list-streams`).
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    listing: Option<Listing>,
+    /// Fields added by the library user, see [`OutRec::extra_mut`].
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
+impl OutRec {
+    /// Returns the address of the server, i.e. the destination of the stream's
+    /// earliest packet.
+    pub(crate) fn server_ip(&self) -> &str {
+        &self.sockets.dst
+    }
+
+    /// Replaces IP addresses with pseudonyms.
+    pub(crate) fn anonymize(&mut self, anonymizer: &mut Anonymizer) {
+        self.sockets.src = anonymizer.anonymize(&self.sockets.src);
+        self.sockets.dst = anonymizer.anonymize(&self.sockets.dst);
+        if let Some(tunnel) = &mut self.payload.tunnel {
+            tunnel.anonymize(anonymizer);
+        }
+    }
+
+    /// Sets `resolved_host` of a TLS stream to the name resolved to the server's
+    /// address, if any.
+    fn resolve_host(&mut self, dns: &dns::Resolutions) {
+        if self.payload.tls.is_some() {
+            self.resolved_host = dns.lookup(self.server_ip()).map(str::to_owned);
+        }
+    }
+
+    pub(crate) fn set_matched(&mut self, matched: bool) {
+        self.matched = matched;
+    }
+
+    /// Returns all fingerprints of the record, including JA4+.
+    pub(crate) fn fingerprints(&self) -> impl Iterator<Item = &str> {
+        let payload = &self.payload;
+        let tls = payload.tls.as_ref();
+        let ja4l = payload.ja4l.as_ref();
+        tls.and_then(|tls| tls.ja4())
+            .into_iter()
+            .chain(tls.and_then(|tls| tls.ja4s()))
+            .chain(tls.into_iter().flat_map(|tls| tls.ja4x()))
+            .chain(payload.http.iter().flat_map(|http| http.ja4h()))
+            .chain(ja4l.map(|fps| fps.ja4l_c()))
+            .chain(ja4l.map(|fps| fps.ja4l_s()))
+            .chain(payload.ja4ssh.ja4ssh())
+            .chain(payload.ja4plus.as_deref())
+    }
+
+    /// Records the capture filter the packets of the stream were captured with.
+    pub(crate) fn set_capture_filter(&mut self, filter: Option<&str>) {
+        self.capture_filter = filter.map(str::to_owned);
+    }
+
+    /// Returns the fingerprint of the given type. Multiple fingerprints of the type
+    /// (e.g. JA4H of every HTTP request) are joined with `,`; JA4L is given as
+    /// `<JA4L-C>,<JA4L-S>`.
+    pub(crate) fn fingerprint(&self, kind: FingerprintKind) -> Option<String> {
+        fn join<'a>(fps: impl Iterator<Item = &'a str>) -> Option<String> {
+            let s = fps.collect::<Vec<_>>().join(",");
+            (!s.is_empty()).then_some(s)
+        }
+
+        let payload = &self.payload;
+        let tls = payload.tls.as_ref();
+        match kind {
+            FingerprintKind::Ja4 => tls?.ja4().map(str::to_owned),
+            FingerprintKind::Ja4s => tls?.ja4s().map(str::to_owned),
+            FingerprintKind::Ja4h => join(payload.http.as_ref()?.ja4h()),
+            FingerprintKind::Ja4l => {
+                let ja4l = payload.ja4l.as_ref()?;
+                Some(format!("{},{}", ja4l.ja4l_c(), ja4l.ja4l_s()))
+            }
+            FingerprintKind::Ja4x => join(tls?.ja4x()),
+            FingerprintKind::Ja4ssh => join(payload.ja4ssh.ja4ssh()),
+        }
+    }
+
+    /// Returns the fields that identify repeated connections for `--dedupe-streams`.
+    pub(crate) fn dedupe_key(&self) -> dedupe::Key {
+        dedupe::Key {
+            src: self.sockets.src.clone(),
+            dst: self.sockets.dst.clone(),
+            dst_port: self.sockets.dst_port,
+            ja4: self.fingerprint(FingerprintKind::Ja4),
+            ja4h: self.fingerprint(FingerprintKind::Ja4h),
+        }
+    }
+
+    /// Records the capture file the stream was read from.
+    pub(crate) fn set_pcap(&mut self, pcap: Option<&Path>) {
+        self.pcap = pcap.map(Path::to_owned);
+    }
+
+    pub(crate) fn set_duplicates(&mut self, duplicates: usize) {
+        self.duplicates = Some(duplicates);
+    }
+
+    pub(crate) fn add_duplicate(&mut self) {
+        *self.duplicates.get_or_insert(0) += 1;
+    }
+
+    /// Returns the identification of the stream: its index and endpoints.
+    pub(crate) fn tuple(&self) -> StreamTuple {
+        StreamTuple {
+            stream: self.stream,
+            transport: self.transport,
+            sockets: self.sockets.clone(),
+        }
+    }
+
+    /// Returns `true` unless the record is a TLS stream ruled out by `--sni`.
+    fn selected_by(&self, filter: &StreamFilter) -> bool {
+        let tls = self.payload.tls.as_ref();
+        filter.selects_sni(tls.is_some(), tls.and_then(tls::OutStream::sni))
+    }
+
+    /// Returns the JA4S fingerprint of the stream, if any.
+    pub(crate) fn ja4s(&self) -> Option<&str> {
+        self.payload.tls.as_ref()?.ja4s()
+    }
+
+    /// Returns `true` if the record has no fingerprints, only the addresses of the
+    /// stream and annotations, e.g. `quic_version`.
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// Returns the fields added with [`OutRec::extra_mut`].
+    pub fn extra(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.extra
+    }
+
+    /// Returns the fields to add to the record, e.g. GeoIP or threat intelligence
+    /// lookups of the addresses. They are serialized after the fields of `ja4`,
+    /// at the top level of the record, and come in [`Record::other`].
+    ///
+    /// Names must not clash with those of the record's own fields, or the output
+    /// will have duplicate keys; prefixing them, e.g. `geoip_country`, avoids
+    /// this.
+    ///
+    /// [`Record::other`]: crate::record::Record::other
+    pub fn extra_mut(&mut self) -> &mut serde_json::Map<String, serde_json::Value> {
+        &mut self.extra
+    }
+
+    /// Returns the record as a [`Record`](crate::record::Record), with the same
+    /// fields as in the output of `ja4`.
+    pub fn to_record(&self) -> crate::record::Record {
+        let value = serde_json::to_value(self).expect("output record is serializable");
+        serde_json::from_value(value).expect("output record matches the public schema")
+    }
+}
+
+/// Stream index and endpoints, i.e. the leading fields of [`OutRec`].
 #[derive(Debug, Serialize)]
+pub(crate) struct StreamTuple {
+    stream: StreamId,
+    transport: Transport,
+    #[serde(flatten)]
+    sockets: SocketPair,
+}
+
+#[derive(Debug, Default, Serialize)]
 struct OutStream {
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     tls: Option<tls::OutStream>,
+    /// Proxy tunnel (HTTP CONNECT, SOCKS5) or WebSocket connection (`--experimental`)
+    /// established on this stream.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    tunnel: Option<tunnel::Tunnel>,
+    /// Cleartext protocol (SMTP, IMAP, ...) upgraded to TLS on this stream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starttls_proto: Option<starttls::StarttlsProto>,
     /// Light distance (latency) fingerprints.
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     ja4l: Option<time::Fingerprints>,
+    /// Round-trip times over the whole TCP stream (`--with-details`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_stats: Option<time::RttStats>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     http: Option<http::OutStream>,
     /// SSH fingerprints.
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    ja4ssh: Vec<ssh::Fingerprint>,
+    #[serde(flatten)]
+    ja4ssh: ssh::Fingerprints,
     /// Additional information from SSH packets.
     #[serde(skip_serializing_if = "Option::is_none")]
     ssh_extras: Option<ssh::Extras>,
+    /// The deepest protocol stack that tshark has dissected in the packets of the
+    /// stream, e.g. `eth:ethertype:ip:tcp:tls` (`--with-details`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_protocols: Option<String>,
+    /// Packet and byte counts of the stream (`--with-details`).
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    traffic: Option<Traffic>,
+    /// Hash of all JA4+ fingerprints above, see [`OutStream::ja4plus`]. Absent
+    /// with `--list-streams`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ja4plus: Option<String>,
+}
+
+/// What `--list-streams` tells about a stream instead of its fingerprints.
+#[derive(Debug, Default, Serialize)]
+struct Listing {
+    /// Protocols above the transport layer, in the order tshark has first
+    /// dissected them, e.g. `[tls]` or `[http, data]`.
+    protocols: Vec<String>,
+    /// Number of packets.
+    packets: usize,
+    /// Number of bytes of the packets on the wire (`frame.len`).
+    bytes: u64,
+}
+
+/// Returns the length of the packet on the wire (`frame.len`), 0 if unknown.
+fn frame_len(pkt: &Packet) -> u64 {
+    pkt.find_proto("frame")
+        .and_then(|frame| frame.first("frame.len").ok()?.parse().ok())
+        .unwrap_or(0)
+}
+
+impl Listing {
+    fn update(&mut self, pkt: &Packet) {
+        self.packets += 1;
+        self.bytes += frame_len(pkt);
+        // The layers above the innermost transport layer, as in `StreamAttrs::new`.
+        let protos = pkt.iter().collect::<Vec<_>>();
+        let transport = protos
+            .iter()
+            .rposition(|proto| matches!(proto.name(), "tcp" | "udp"));
+        for proto in transport.map_or(&[][..], |i| &protos[i + 1..]) {
+            let name = proto.name();
+            // Skip tshark's pseudo-protocols: `_ws.malformed`, `tcp.segments`, ...
+            if !name.contains('.') && !self.protocols.iter().any(|p| p == name) {
+                self.protocols.push(name.to_owned());
+            }
+        }
+    }
+}
+
+/// Packets and bytes of a stream, by direction.
+///
+/// Every packet is counted as observed: retransmitted and duplicate packets are
+/// counted again, so the numbers may exceed what the endpoints have exchanged.
+/// The direction is that of [`Sender`], which guesses the client from the
+/// stream's earliest packet.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct Traffic {
+    /// Number of packets in both directions.
+    packets: usize,
+    /// Number of bytes on the wire (`frame.len`) sent by the client.
+    bytes_client: u64,
+    /// Number of bytes on the wire (`frame.len`) sent by the server.
+    bytes_server: u64,
+}
+
+impl Traffic {
+    fn update(&mut self, pkt: &Packet, sender: Sender) {
+        self.packets += 1;
+        let bytes = frame_len(pkt);
+        match sender {
+            Sender::Client => self.bytes_client += bytes,
+            Sender::Server => self.bytes_server += bytes,
+        }
+    }
+}
+
+impl OutStream {
+    fn is_empty(&self) -> bool {
+        let has_fingerprints = self
+            .tls
+            .as_ref()
+            .is_some_and(tls::OutStream::has_fingerprints)
+            || self.ja4l.is_some()
+            || self
+                .http
+                .as_ref()
+                .is_some_and(|http| http.ja4h().next().is_some())
+            || !self.ja4ssh.is_empty();
+        !has_fingerprints
+    }
+
+    /// Returns a single hash of all JA4+ fingerprints of the stream, so that streams
+    /// with identical fingerprints can be compared by one value.
+    ///
+    /// The hash is computed with [`hash12`] over the fingerprints in this order:
+    /// JA4, JA4S, JA4H, JA4L-C, JA4L-S, JA4X, JA4SSH. The components are joined
+    /// with `|`; multiple values of a component (e.g. JA4H of every HTTP request)
+    /// are joined with `,` in the order of appearance. An absent component is
+    /// represented as `000000000000`. Only the outer TLS session is taken into
+    /// account.
+    ///
+    /// Returns `None` if the stream has none of JA4, JA4S, JA4H, JA4X and JA4SSH:
+    /// the hash would then be the same for all such streams, JA4L aside.
+    fn ja4plus(&self) -> Option<String> {
+        fn join<'a>(fps: impl Iterator<Item = &'a str>) -> Option<String> {
+            let s = fps.collect::<Vec<_>>().join(",");
+            (!s.is_empty()).then_some(s)
+        }
+
+        let tls = self.tls.as_ref();
+        let components = [
+            tls.and_then(|tls| tls.ja4()).map(str::to_owned),
+            tls.and_then(|tls| tls.ja4s()).map(str::to_owned),
+            self.http.as_ref().and_then(|http| join(http.ja4h())),
+            self.ja4l.as_ref().map(|fps| fps.ja4l_c().to_owned()),
+            self.ja4l.as_ref().map(|fps| fps.ja4l_s().to_owned()),
+            tls.and_then(|tls| join(tls.ja4x())),
+            join(self.ja4ssh.ja4ssh()),
+        ];
+        let [ja4, ja4s, ja4h, _, _, ja4x, ja4ssh] = &components;
+        if [ja4, ja4s, ja4h, ja4x, ja4ssh]
+            .iter()
+            .all(|fp| fp.is_none())
+        {
+            return None;
+        }
+        let s = components
+            .into_iter()
+            .map(|fp| fp.unwrap_or_else(|| "000000000000".to_owned()))
+            .join("|");
+        Some(hash12(s))
+    }
 }
 
 #[derive(Debug, Default)]
 struct Stream<T> {
     tls: Option<tls::Stream>,
+    tunnel: tunnel::Stream,
+    /// TLS tunneled in a WebSocket connection (`--experimental`).
+    websocket_tls: tunnel::WebSocketTls,
+    starttls: starttls::Stream,
     timestamps: Option<T>,
+    rtt: time::Rtt,
     http: http::Stream,
     ssh: ssh::Stream,
+    /// The longest `frame.protocols` of the packets.
+    frame_protocols: Option<String>,
+    /// Counted by [`Streams::update`]; `None` for streams added by
+    /// [`Streams::insert_tls`], whose packets are not seen.
+    traffic: Option<Traffic>,
 }
 
 impl<T: Timestamps> Stream<T> {
+    /// Returns `true` if any fingerprint has been computed, i.e. the stream will be
+    /// output.
+    fn has_fingerprints(&self) -> bool {
+        self.tls.as_ref().is_some_and(tls::Stream::has_fingerprints)
+            || self.timestamps.as_ref().is_some_and(T::is_done)
+            || self.http.has_fingerprints()
+            || self.ssh.has_fingerprints()
+    }
+
     fn into_out(self, flags: FormatFlags) -> Option<OutStream> {
         let Self {
             tls,
+            tunnel,
+            websocket_tls: _,
+            starttls,
             timestamps,
+            rtt,
             http,
             ssh,
+            frame_protocols,
+            traffic,
         } = self;
 
         let tls = tls.and_then(|stats| stats.into_out(flags));
         let ja4l = timestamps.and_then(|ts| ts.finish());
+        let rtt_stats = if flags.with_details {
+            rtt.finish()
+        } else {
+            None
+        };
         let http = http.into_out(flags);
         let (ja4ssh, ssh_extras) = ssh.finish();
 
@@ -69,13 +440,21 @@ impl<T: Timestamps> Stream<T> {
             return None;
         }
 
-        Some(OutStream {
+        let mut out = OutStream {
             tls,
+            tunnel: tunnel.into_out(),
+            starttls_proto: starttls.into_out(),
             ja4l,
+            rtt_stats,
             http,
             ja4ssh,
             ssh_extras,
-        })
+            frame_protocols: frame_protocols.filter(|_| flags.with_details),
+            traffic: traffic.filter(|_| flags.with_details),
+            ja4plus: None,
+        };
+        out.ja4plus = out.ja4plus();
+        Some(out)
     }
 }
 
@@ -83,30 +462,164 @@ impl<T: Timestamps> Stream<T> {
 struct AddressedStream<T> {
     sockets: SocketPair,
     stream: Stream<T>,
+    close: TcpClose,
+    /// Timestamp of the latest packet, in microseconds.
+    last_seen: i64,
+    /// Number of packets of the stream, for `--min-packets`.
+    nr_packets: usize,
+    /// Whether the stream has been counted in [`Streams::nr_fingerprinted`].
+    fingerprinted: bool,
+    /// Counts that replace the fingerprints with `--list-streams`.
+    listing: Option<Listing>,
 }
 
 impl<T: Timestamps> AddressedStream<T> {
-    fn new(sockets: SocketPair) -> Self {
+    /// With `list_streams`, the packets of the stream are counted rather than
+    /// fingerprinted.
+    fn new(sockets: SocketPair, list_streams: bool) -> Self {
         Self {
             sockets,
             stream: Stream::default(),
+            close: TcpClose::default(),
+            last_seen: 0,
+            nr_packets: 0,
+            fingerprinted: false,
+            listing: list_streams.then(Listing::default),
+        }
+    }
+
+    fn into_out(self, sid: StreamId, transport: Transport, flags: FormatFlags) -> Option<OutRec> {
+        let Self {
+            sockets,
+            stream,
+            listing,
+            ..
+        } = self;
+        let payload = match listing {
+            Some(_) => OutStream::default(),
+            None => stream.into_out(flags)?,
+        };
+        Some(OutRec {
+            stream: sid,
+            transport,
+            sockets,
+            payload,
+            resolved_host: None,
+            matched: false,
+            capture_filter: None,
+            pcap: None,
+            duplicates: None,
+            listing,
+            extra: serde_json::Map::new(),
+        })
+    }
+
+    /// Returns the numbers of fingerprints computed so far, see
+    /// [`AddressedStream::add_events`].
+    fn computed(&self) -> Computed {
+        let tls = self.stream.tls.as_ref();
+        Computed {
+            tls_client: tls.is_some_and(|tls| tls.client.is_some()),
+            tls_server: tls.is_some_and(|tls| tls.server.is_some()),
+            http_requests: self.stream.http.nr_requests(),
+            ssh_samples: self.stream.ssh.nr_fingerprints(),
+        }
+    }
+
+    /// Adds an event for every fingerprint computed since `before`.
+    fn add_events(&self, before: Computed, ctx: EventContext, events: &mut Events) {
+        let tls = self.stream.tls.as_ref();
+        let mut new = Vec::new();
+        if !before.tls_client {
+            if let Some(client) = tls.and_then(|tls| tls.client.as_ref()) {
+                new.push(Event::TlsClient(client.ja4(events.original_order)));
+            }
+        }
+        if !before.tls_server {
+            if let Some(server) = tls.and_then(|tls| tls.server.as_ref()) {
+                new.push(Event::TlsServer(server.ja4s()));
+            }
+        }
+        let http = &self.stream.http;
+        for index in before.http_requests..http.nr_requests() {
+            new.extend(
+                http.ja4h(index, events.original_order)
+                    .map(|ja4h| Event::HttpRequest { index, ja4h }),
+            );
+        }
+        let ssh = &self.stream.ssh;
+        for index in before.ssh_samples..ssh.nr_fingerprints() {
+            new.extend(ssh.fingerprint(index).map(Event::SshWindow));
         }
+        events
+            .queue
+            .extend(new.into_iter().map(|event| (ctx, event)));
     }
 
+    /// Adds the packet to the stream's [`Traffic`], unless streams are listed
+    /// (`--list-streams`), which counts them its own way.
+    fn count(&mut self, pkt: &Packet, sender: Sender) {
+        if self.listing.is_none() {
+            self.stream
+                .traffic
+                .get_or_insert_with(Traffic::default)
+                .update(pkt, sender);
+        }
+    }
+
+    /// Returns `true` if the stream will be output: it has a fingerprint, or streams
+    /// are listed (`--list-streams`).
+    fn has_output(&self) -> bool {
+        self.listing.is_some() || self.stream.has_fingerprints()
+    }
+
+    /// Feeds the packet to every enabled fingerprinter.
+    ///
+    /// We don't route packets by port numbers: each fingerprinter looks for the
+    /// protocol layers (`tls`, `http`, `ssh`, ...) detected by tshark, so services on
+    /// non-standard ports are fingerprinted as long as tshark dissects them.
     fn update(&mut self, pkt: &Packet, conf: &Conf, store_pkt_num: bool, guessed_sender: Sender) {
-        if conf.tls.enabled {
-            if let Err(error) = self
+        if let Some(listing) = &mut self.listing {
+            listing.update(pkt);
+            return;
+        }
+        if let Some(protocols) = pkt.frame_protocols() {
+            let depth = |protocols: &str| protocols.split(':').count();
+            if self
                 .stream
-                .tls
-                .get_or_insert_with(Default::default)
-                .update(pkt, store_pkt_num)
+                .frame_protocols
+                .as_deref()
+                .is_none_or(|deepest| depth(protocols) > depth(deepest))
             {
+                self.stream.frame_protocols = Some(protocols.to_owned());
+            }
+        }
+        // Proxy tunnels annotate the records of any fingerprint type, e.g. JA4H of
+        // the CONNECT request. WebSocket connections only matter for the TLS inside.
+        self.stream.tunnel.update(
+            pkt,
+            guessed_sender,
+            conf.tls.enabled && conf.tls.experimental,
+        );
+        if conf.tls.enabled {
+            let tls = self.stream.tls.get_or_insert_with(Default::default);
+            if let Err(error) = tls.update(pkt, &conf.tls, store_pkt_num, guessed_sender) {
                 tracing::debug!(%pkt.num, %error, "failed to fingerprint TLS");
             }
+            if self.stream.tunnel.is_websocket() {
+                if let Some(hello) = self.stream.websocket_tls.update(pkt, guessed_sender) {
+                    tls.add_tunneled_client_hello(
+                        hello,
+                        store_pkt_num.then_some(pkt.num),
+                        &conf.tls,
+                    );
+                }
+            }
+            self.stream.starttls.update(pkt);
         }
 
         if conf.http.enabled {
-            if let Err(error) = self.stream.http.update(pkt, store_pkt_num) {
+            if let Err(error) = self.stream.http.update(pkt, &conf.http, store_pkt_num) {
                 tracing::debug!(%pkt.num, %error, "failed to fingerprint HTTP");
             }
         }
@@ -122,6 +635,9 @@ impl<T: Timestamps> AddressedStream<T> {
                 Ok(ts) => self.stream.timestamps = Some(ts),
                 Err(error) => tracing::debug!(%pkt.num, %error, "failed to store timestamp"),
             }
+            if let Err(error) = self.stream.rtt.update(pkt, guessed_sender) {
+                tracing::debug!(%pkt.num, %error, "failed to measure RTT");
+            }
         }
 
         if conf.ssh.enabled && pkt.find_proto("tcp").is_some() {
@@ -136,16 +652,166 @@ impl<T: Timestamps> AddressedStream<T> {
     }
 }
 
+/// Numbers of fingerprints of a stream, to tell the new ones apart.
+#[derive(Debug, Clone, Copy)]
+struct Computed {
+    tls_client: bool,
+    tls_server: bool,
+    http_requests: usize,
+    ssh_samples: usize,
+}
+
+/// Teardown of a TCP connection, as far as we have observed it.
+#[derive(Debug, Default)]
+struct TcpClose {
+    client_fin: bool,
+    server_fin: bool,
+    reset: bool,
+    /// No packets for longer than `--tcp-idle-timeout`.
+    idle: bool,
+}
+
+impl TcpClose {
+    fn update(&mut self, pkt: &Packet, sender: Sender) {
+        let Some(tcp) = pkt.protos("tcp").last() else {
+            return;
+        };
+        let is_set = |field| matches!(tcp.first(field), Ok("1" | "True"));
+        if is_set("tcp.flags.reset") {
+            self.reset = true;
+        }
+        if is_set("tcp.flags.fin") {
+            match sender {
+                Sender::Client => self.client_fin = true,
+                Sender::Server => self.server_fin = true,
+            }
+        }
+    }
+
+    /// Returns `true` if both sides have sent FIN, either side has sent RST, or the
+    /// stream has been idle for too long.
+    fn is_closed(&self) -> bool {
+        self.reset || (self.client_fin && self.server_fin) || self.idle
+    }
+}
+
 /// Information collected from the capture file.
 #[derive(Debug, Default)]
 pub(crate) struct Streams {
     tcp: IndexMap<StreamId, AddressedStream<TcpTimestamps>>,
     udp: IndexMap<StreamId, AddressedStream<UdpTimestamps>>,
+    /// TCP streams that have been closed since the last [`Streams::drain_closed`] call.
+    closed: Vec<StreamId>,
+    /// TCP streams that have been removed by [`Streams::drain_closed`].
+    /// Packets that arrive after the teardown (e.g. the final ACK) are ignored.
+    drained: Ignored<StreamId>,
+    /// UDP flows that have been idle for longer than `udp_timeout`, not yet drained.
+    ///
+    /// UDP has no teardown, so this is how UDP flows are finalized. A later packet
+    /// with the same stream ID starts a new flow.
+    expired_udp: Vec<(StreamId, AddressedStream<UdpTimestamps>)>,
+    /// Records of the streams finalized by [`Streams::finalize_closed`].
+    finalized: Vec<OutRec>,
+    udp_timeout: IdleTimeout,
+    /// Idle time after which a TCP stream is closed, if any (`--tcp-idle-timeout`).
+    /// Later packets of the stream are ignored.
+    tcp_idle_timeout: Option<IdleTimeout>,
+    /// Streams with fewer packets are not output.
+    min_packets: MinPackets,
+    /// When idle streams were last looked for, in microseconds.
+    last_idle_sweep: i64,
+    /// When [`Streams::finalize_closed`] last looked for closed streams.
+    last_finalized: i64,
+    /// Number of streams that have got at least one fingerprint so far.
+    nr_fingerprinted: usize,
+    /// Whether to count the packets of the streams instead of fingerprinting them
+    /// (`--list-streams`).
+    list_streams: bool,
+    filter: StreamFilter,
+    /// Timestamp of the capture's first packet, for the relative bounds of the
+    /// packet time window. Only set if there is a window.
+    first_packet_micros: Option<i64>,
+    /// Streams not selected by `filter`. We only remember their IDs, so that the
+    /// filter is not evaluated for every packet.
+    excluded: Ignored<(Transport, StreamId)>,
+    /// Fingerprints computed since the last [`Streams::take_events`] call, if
+    /// events are collected (see [`crate::handler`]).
+    events: Option<Events>,
+    /// Names resolved by the DNS responses seen so far (`--resolve-dns`).
+    dns: dns::Resolutions,
 }
 
 impl Streams {
+    pub(crate) fn new(filter: StreamFilter) -> Self {
+        Self {
+            filter,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the idle time after which a UDP flow is finalized.
+    pub(crate) fn with_udp_timeout(self, timeout: Duration) -> Self {
+        Self {
+            udp_timeout: IdleTimeout(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the idle time after which a TCP stream is closed.
+    pub(crate) fn with_tcp_idle_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            tcp_idle_timeout: timeout.map(IdleTimeout),
+            ..self
+        }
+    }
+
+    /// Sets the number of packets a stream needs to be output.
+    pub(crate) fn with_min_packets(self, min_packets: MinPackets) -> Self {
+        Self {
+            min_packets,
+            ..self
+        }
+    }
+
+    /// Makes the records list every stream with its packet counts, without
+    /// fingerprints.
+    pub(crate) fn with_list_streams(self, list_streams: bool) -> Self {
+        Self {
+            list_streams,
+            ..self
+        }
+    }
+
+    /// Collects an event for every fingerprint as it is computed, see
+    /// [`Streams::take_events`]. `original_order` is that of [`FormatFlags`].
+    pub(crate) fn with_events(self, original_order: bool) -> Self {
+        Self {
+            events: Some(Events {
+                original_order,
+                queue: Vec::new(),
+            }),
+            ..self
+        }
+    }
+
     pub(crate) fn update(&mut self, pkt: &Packet, conf: &Conf, store_pkt_num: bool) -> Result<()> {
         tracing::debug!(%pkt.num, "processing packet");
+        if self.filter.has_packet_window() {
+            let micros = pkt.timestamp_micros()?;
+            let first = *self.first_packet_micros.get_or_insert(micros);
+            if !self.filter.selects_packet_time(micros, first) {
+                return Ok(());
+            }
+        }
+        let now = pkt.timestamp_micros().ok();
+        if let Some(now) = now {
+            self.expire_idle(now);
+        }
+        if conf.tls.enabled && conf.tls.resolve_dns {
+            // Regardless of the stream filters: the DNS traffic is not what they
+            // select.
+            self.dns.update(pkt);
+        }
         let Some(attrs) = StreamAttrs::new(pkt)? else {
             return Ok(());
         };
@@ -155,6 +821,26 @@ impl Streams {
             stream_id,
             sockets,
         } = attrs;
+        if !self.filter.selects_stream(transport, stream_id) {
+            return Ok(());
+        }
+
+        let is_known = match transport {
+            Transport::Tcp => self.tcp.contains_key(&stream_id),
+            Transport::Udp => self.udp.contains_key(&stream_id),
+        };
+        if !is_known
+            && (self.excluded.check(&(transport, stream_id), now)
+                || !self.filter.matches(
+                    (&sockets.src, sockets.src_port),
+                    (&sockets.dst, sockets.dst_port),
+                )
+                || !self.filter.selects_time(pkt.timestamp_micros()?))
+        {
+            let last_seen = now.unwrap_or(self.last_idle_sweep);
+            self.excluded.insert((transport, stream_id), last_seen);
+            return Ok(());
+        }
 
         let sender_ip = sockets.src.clone();
 
@@ -171,66 +857,391 @@ impl Streams {
 
         match transport {
             Transport::Tcp => {
+                if self.drained.check(&stream_id, now) {
+                    return Ok(());
+                }
                 let stream = match self.tcp.entry(stream_id) {
-                    Entry::Vacant(x) => x.insert(AddressedStream::new(sockets)),
+                    Entry::Vacant(x) => x.insert(AddressedStream::new(sockets, self.list_streams)),
+                    Entry::Occupied(x) if x.get().close.idle => return Ok(()),
                     Entry::Occupied(x) => {
                         x.get().sockets.check(&sockets);
                         x.into_mut()
                     }
                 };
-                stream.update(
-                    pkt,
-                    conf,
-                    store_pkt_num,
-                    guess_sender(&sender_ip, &stream.sockets),
-                );
+                if let Some(now) = now {
+                    stream.last_seen = now;
+                }
+                stream.nr_packets += 1;
+                let sender = guess_sender(&sender_ip, &stream.sockets);
+                stream.count(pkt, sender);
+                let before = self.events.as_ref().map(|_| stream.computed());
+                stream.update(pkt, conf, store_pkt_num, sender);
+                if let (Some(events), Some(before)) = (&mut self.events, before) {
+                    let ctx = EventContext::new(stream_id, transport, pkt);
+                    stream.add_events(before, ctx, events);
+                }
+                if !stream.fingerprinted && stream.has_output() {
+                    stream.fingerprinted = true;
+                    self.nr_fingerprinted += 1;
+                }
+
+                let was_closed = stream.close.is_closed();
+                stream.close.update(pkt, sender);
+                if !was_closed && stream.close.is_closed() {
+                    self.closed.push(stream_id);
+                }
             }
             Transport::Udp => {
+                if let Some(now) = now {
+                    if self
+                        .udp
+                        .get(&stream_id)
+                        .is_some_and(|stream| self.udp_timeout.is_expired(stream.last_seen, now))
+                    {
+                        self.expire_udp_flow(stream_id);
+                    }
+                }
                 let stream = match self.udp.entry(stream_id) {
-                    Entry::Vacant(x) => x.insert(AddressedStream::new(sockets)),
+                    Entry::Vacant(x) => x.insert(AddressedStream::new(sockets, self.list_streams)),
                     Entry::Occupied(x) => {
                         x.get().sockets.check(&sockets);
                         x.into_mut()
                     }
                 };
-                stream.update(
-                    pkt,
-                    conf,
-                    store_pkt_num,
-                    guess_sender(&sender_ip, &stream.sockets),
-                );
+                if let Some(now) = now {
+                    stream.last_seen = now;
+                }
+                stream.nr_packets += 1;
+                let sender = guess_sender(&sender_ip, &stream.sockets);
+                stream.count(pkt, sender);
+                let before = self.events.as_ref().map(|_| stream.computed());
+                stream.update(pkt, conf, store_pkt_num, sender);
+                if let (Some(events), Some(before)) = (&mut self.events, before) {
+                    let ctx = EventContext::new(stream_id, transport, pkt);
+                    stream.add_events(before, ctx, events);
+                }
+                if !stream.fingerprinted && stream.has_output() {
+                    stream.fingerprinted = true;
+                    self.nr_fingerprinted += 1;
+                }
             }
         }
         Ok(())
     }
 
+    /// Returns the fingerprints computed since the previous call, if events are
+    /// collected (see [`Streams::with_events`]).
+    pub(crate) fn take_events(&mut self) -> Vec<(EventContext, Event)> {
+        self.events
+            .as_mut()
+            .map(|events| std::mem::take(&mut events.queue))
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of streams that have got at least one fingerprint so far,
+    /// including the streams that have been drained. With `--list-streams`, the
+    /// number of streams seen so far.
+    pub(crate) fn nr_fingerprinted(&self) -> usize {
+        self.nr_fingerprinted
+    }
+
+    /// Finalizes the UDP flows that have been idle for longer than `udp_timeout` and
+    /// closes the TCP streams idle for longer than `tcp_idle_timeout`.
+    ///
+    /// Looking through all streams for every packet would be wasteful, so this is
+    /// done at most once a second of capture time. A UDP flow that receives a packet
+    /// is checked right away in [`Self::update`].
+    fn expire_idle(&mut self, now: i64) {
+        if now - self.last_idle_sweep < 1_000_000 {
+            return;
+        }
+        self.last_idle_sweep = now;
+        self.drained.forget_idle(now);
+        self.excluded.forget_idle(now);
+        let expired = self
+            .udp
+            .iter()
+            .filter(|(_, stream)| self.udp_timeout.is_expired(stream.last_seen, now))
+            .map(|(&sid, _)| sid)
+            .collect::<Vec<_>>();
+        for sid in expired {
+            self.expire_udp_flow(sid);
+        }
+        if let Some(timeout) = self.tcp_idle_timeout {
+            for (&sid, stream) in &mut self.tcp {
+                if !stream.close.is_closed() && timeout.is_expired(stream.last_seen, now) {
+                    tracing::debug!(?sid, "TCP stream timed out");
+                    stream.close.idle = true;
+                    self.closed.push(sid);
+                }
+            }
+        }
+    }
+
+    fn expire_udp_flow(&mut self, sid: StreamId) {
+        // The order of the remaining flows is restored in `Self::into_out`.
+        if let Some(stream) = self.udp.swap_remove(&sid) {
+            tracing::debug!(?sid, "UDP flow timed out");
+            self.expired_udp.push((sid, stream));
+        }
+    }
+
+    /// Removes the TCP streams that have been closed and the UDP flows that have
+    /// timed out since the previous call and returns their records.
+    pub(crate) fn drain_closed(&mut self, flags: FormatFlags) -> Vec<OutRec> {
+        let closed = std::mem::take(&mut self.closed);
+        self.drain(closed, flags)
+    }
+
+    /// Removes the given TCP streams and the timed out UDP flows and returns their
+    /// records.
+    fn drain(&mut self, closed: Vec<StreamId>, flags: FormatFlags) -> Vec<OutRec> {
+        let mut recs = Vec::new();
+        for sid in closed {
+            // Dropping the stream from `IndexMap` with `swap_remove` alters the order
+            // of the remaining streams; callers of this method don't rely on it.
+            let Some(addressed) = self.tcp.swap_remove(&sid) else {
+                continue;
+            };
+            self.drained.insert(sid, addressed.last_seen);
+            if self.min_packets.admits(&addressed) {
+                recs.extend(addressed.into_out(sid, Transport::Tcp, flags));
+            }
+        }
+        for (sid, addressed) in self.expired_udp.drain(..) {
+            if self.min_packets.admits(&addressed) {
+                recs.extend(addressed.into_out(sid, Transport::Udp, flags));
+            }
+        }
+        recs.retain(|rec| rec.selected_by(&self.filter));
+        for rec in &mut recs {
+            rec.resolve_host(&self.dns);
+        }
+        recs
+    }
+
+    /// Same as [`Self::drain_closed`], but the records are kept and returned by
+    /// [`Self::into_out`] in the order of the streams.
+    ///
+    /// Streams without fingerprints (port scans, plain text traffic) then take no
+    /// memory once closed, and the others only take that of their records. A
+    /// closed TCP stream is kept for [`CLOSE_GRACE`] after its latest packet, so
+    /// that the output is the same as if it were kept till the end of the capture.
+    pub(crate) fn finalize_closed(&mut self, flags: FormatFlags) {
+        // Once a second of capture time, as `Self::expire_idle`.
+        let now = self.last_idle_sweep;
+        if now == self.last_finalized {
+            return;
+        }
+        self.last_finalized = now;
+        let (closed, waiting) = std::mem::take(&mut self.closed)
+            .into_iter()
+            .partition(|sid| {
+                self.tcp
+                    .get(sid)
+                    .is_none_or(|stream| CLOSE_GRACE.is_expired(stream.last_seen, now))
+            });
+        self.closed = waiting;
+        let recs = self.drain(closed, flags);
+        self.finalized.extend(recs);
+    }
+
+    /// Checks whether the key log file has decrypted any TLS session. If there are
+    /// sessions with application data but none of them has been decrypted, flags
+    /// them with `decryption_failed` and returns `true`.
+    ///
+    /// Only meaningful with `--keylog-file`.
+    pub(crate) fn check_decryption(&mut self) -> bool {
+        let sessions = || {
+            self.tcp
+                .values()
+                .filter_map(|addressed| addressed.stream.tls.as_ref())
+        };
+        let with_app_data = |app_data| sessions().filter(|tls| tls.app_data() == app_data).count();
+        let nr_encrypted = with_app_data(tls::AppData::Encrypted);
+        if nr_encrypted == 0 || with_app_data(tls::AppData::Decrypted) > 0 {
+            return false;
+        }
+        tracing::warn!(nr_encrypted, "no TLS session has been decrypted");
+        for addressed in self.tcp.values_mut() {
+            if let Some(tls) = &mut addressed.stream.tls {
+                tls.mark_decryption_failed();
+            }
+        }
+        true
+    }
+
+    /// Adds a TCP stream with TLS data obtained without tshark, see [`crate::native`].
+    ///
+    /// Streams must be added in the order of their IDs. `first_packet_micros` is the
+    /// timestamp of the stream's earliest packet.
+    pub(crate) fn insert_tls(
+        &mut self,
+        stream_id: StreamId,
+        sockets: SocketPair,
+        first_packet_micros: i64,
+        nr_packets: usize,
+        tls: tls::Stream,
+    ) {
+        if !self.filter.selects_stream(Transport::Tcp, stream_id)
+            || !self.filter.matches(
+                (&sockets.src, sockets.src_port),
+                (&sockets.dst, sockets.dst_port),
+            )
+            || !self.filter.selects_time(first_packet_micros)
+        {
+            return;
+        }
+        let mut addressed = AddressedStream::new(sockets, false);
+        addressed.stream.tls = Some(tls);
+        addressed.last_seen = first_packet_micros;
+        addressed.nr_packets = nr_packets;
+        self.tcp.insert(stream_id, addressed);
+    }
+
     pub(crate) fn into_out(self, flags: FormatFlags) -> impl Iterator<Item = OutRec> {
-        let Self { tcp, udp } = self;
-        let tcp = tcp.into_iter().filter_map(move |(sid, addressed)| {
-            let AddressedStream { sockets, stream } = addressed;
-            Some(OutRec {
-                stream: sid,
-                transport: Transport::Tcp,
-                sockets,
-                payload: stream.into_out(flags)?,
-            })
-        });
-        let udp = udp.into_iter().filter_map(move |(sid, addressed)| {
-            let AddressedStream { sockets, stream } = addressed;
-            Some(OutRec {
-                stream: sid,
-                transport: Transport::Udp,
-                sockets,
-                payload: stream.into_out(flags)?,
-            })
-        });
-        tcp.chain(udp)
+        let Self {
+            tcp,
+            udp,
+            expired_udp,
+            finalized,
+            min_packets,
+            filter,
+            dns,
+            ..
+        } = self;
+        let tcp = tcp
+            .into_iter()
+            .filter(move |(_, addressed)| min_packets.admits(addressed))
+            .filter_map(move |(sid, addressed)| addressed.into_out(sid, Transport::Tcp, flags));
+        // tshark numbers streams in the order of their first packets. The sort is
+        // stable, so a timed out flow precedes the flow that reused its stream ID.
+        let udp = expired_udp
+            .into_iter()
+            .chain(udp)
+            .sorted_by_key(|(sid, _)| sid.0)
+            .filter(move |(_, addressed)| min_packets.admits(addressed))
+            .filter_map(move |(sid, addressed)| addressed.into_out(sid, Transport::Udp, flags));
+        let recs = tcp
+            .chain(udp)
+            .filter(move |rec| rec.selected_by(&filter))
+            .map(move |mut rec| {
+                rec.resolve_host(&dns);
+                rec
+            });
+        if finalized.is_empty() {
+            return Either::Left(recs);
+        }
+        // TCP streams first, as above. The sort is stable, so the finalized flows
+        // precede the flows that reused their stream IDs.
+        Either::Right(
+            finalized
+                .into_iter()
+                .chain(recs)
+                .sorted_by_key(|rec| (rec.transport == Transport::Udp, rec.stream.0)),
+        )
     }
 }
 
 // -----------------------------------------------------------------------------
 // Auxiliary definitions
 
+/// Default value of `--udp-timeout`, in seconds.
+pub(crate) const DEFAULT_UDP_TIMEOUT_SECS: u64 = 60;
+
+/// Time that [`Streams::finalize_closed`] waits after the latest packet of a closed
+/// TCP stream: the final ACK and retransmissions may follow the FINs.
+const CLOSE_GRACE: IdleTimeout = IdleTimeout(Duration::from_secs(10));
+
+/// Time after the latest packet of an ignored stream (see [`Ignored`]) after which
+/// its ID is forgotten.
+const IGNORED_RETENTION: IdleTimeout = IdleTimeout(Duration::from_secs(60));
+
+/// IDs of the streams whose packets are ignored, with the timestamp of the latest
+/// packet of each, in microseconds.
+///
+/// An ID is forgotten once the stream has been quiet for [`IGNORED_RETENTION`],
+/// so that the set doesn't grow with the capture. A packet that comes later
+/// starts a new stream with the same ID.
+#[derive(Debug)]
+struct Ignored<K>(HashMap<K, i64>);
+
+impl<K> Default for Ignored<K> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<K: Hash + Eq> Ignored<K> {
+    fn insert(&mut self, id: K, last_seen: i64) {
+        self.0.insert(id, last_seen);
+    }
+
+    /// Returns `true` if the stream is ignored; if so, `now` is its latest packet.
+    fn check(&mut self, id: &K, now: Option<i64>) -> bool {
+        match self.0.get_mut(id) {
+            Some(last_seen) => {
+                if let Some(now) = now {
+                    *last_seen = now;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn forget_idle(&mut self, now: i64) {
+        self.0
+            .retain(|_, &mut last_seen| !IGNORED_RETENTION.is_expired(last_seen, now));
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Idle time after which a stream is finalized (`--udp-timeout`,
+/// `--tcp-idle-timeout`).
+#[derive(Debug, Clone, Copy)]
+struct IdleTimeout(Duration);
+
+/// The default is that of UDP flows.
+impl Default for IdleTimeout {
+    fn default() -> Self {
+        Self(Duration::from_secs(DEFAULT_UDP_TIMEOUT_SECS))
+    }
+}
+
+impl IdleTimeout {
+    /// Returns `true` if a flow whose latest packet was seen at `last_seen` has
+    /// timed out by `now` (both in microseconds).
+    fn is_expired(self, last_seen: i64, now: i64) -> bool {
+        now - last_seen > i64::try_from(self.0.as_micros()).unwrap_or(i64::MAX)
+    }
+}
+
+/// Minimum number of packets of a stream to output it (`--min-packets`).
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct MinPackets {
+    pub(crate) count: usize,
+    /// If `false`, streams with TLS fingerprints are output regardless of `count`:
+    /// a Client Hello is all JA4 needs (`--strict-min`).
+    pub(crate) strict: bool,
+}
+
+impl MinPackets {
+    fn admits<T>(self, addressed: &AddressedStream<T>) -> bool {
+        addressed.nr_packets >= self.count
+            || !self.strict
+                && addressed
+                    .stream
+                    .tls
+                    .as_ref()
+                    .is_some_and(tls::Stream::has_fingerprints)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum IpVersion {
     Ipv4,
@@ -239,16 +1250,29 @@ enum IpVersion {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 #[serde(rename_all = "lowercase")]
-enum Transport {
+pub(crate) enum Transport {
     Tcp,
     Udp,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
-struct StreamId(u32);
+pub(crate) struct StreamId(pub(crate) u32);
+
+impl EventContext {
+    fn new(sid: StreamId, transport: Transport, pkt: &Packet) -> Self {
+        Self {
+            stream: sid.0,
+            transport: match transport {
+                Transport::Tcp => record::Transport::Tcp,
+                Transport::Udp => record::Transport::Udp,
+            },
+            packet: pkt.num.0,
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
-struct SocketPair {
+pub(crate) struct SocketPair {
     #[serde(skip)]
     ip_ver: IpVersion,
     src: String,
@@ -258,6 +1282,21 @@ struct SocketPair {
 }
 
 impl SocketPair {
+    /// `src` is the sender of the stream's earliest packet.
+    pub(crate) fn new(src: SocketAddr, dst: SocketAddr) -> Self {
+        Self {
+            ip_ver: if src.is_ipv4() {
+                IpVersion::Ipv4
+            } else {
+                IpVersion::Ipv6
+            },
+            src: src.ip().to_string(),
+            dst: dst.ip().to_string(),
+            src_port: src.port().into(),
+            dst_port: dst.port().into(),
+        }
+    }
+
     #[cfg(debug_assertions)]
     fn opposite(self) -> Self {
         Self {
@@ -328,6 +1367,15 @@ impl StreamAttrs {
         //
         // Therefore we cannot use `Packet::find_proto` here --- it would return the first
         // protocol with given name. We need *the last* protocol.
+        //
+        // Streams are keyed on the conversation index of tshark (`tcp.stream`,
+        // `udp.stream`), never on the addresses and ports. tshark finds the transport
+        // header behind IPv6 extension headers and reassembles IP fragments, so the
+        // packets of a stream are attributed to it however they were carried.
+        //
+        // Note that we never look at the link layer. Ethernet (`eth`), Linux cooked
+        // capture (`sll`, `tcpdump -i any`), BSD loopback (`null`), and raw IP captures
+        // all yield the same stream attributes.
 
         #[cfg_attr(debug_assertions, derive(Debug))]
         struct IpAttrs {
@@ -351,6 +1399,8 @@ impl StreamAttrs {
             match proto.name() {
                 "icmp" | "icmpv6" => return Ok(None), // ignore ICMP packets
                 "ip" => {
+                    // Transport layers seen so far belong to an outer packet.
+                    last_transport = None;
                     last_ip = Some(IpAttrs {
                         ip_ver: IpVersion::Ipv4,
                         src: proto.first("ip.src")?.to_owned(),
@@ -358,6 +1408,7 @@ impl StreamAttrs {
                     })
                 }
                 "ipv6" => {
+                    last_transport = None;
                     last_ip = Some(IpAttrs {
                         ip_ver: IpVersion::Ipv6,
                         src: proto.first("ipv6.src")?.to_owned(),
@@ -409,3 +1460,630 @@ impl StreamAttrs {
         }))
     }
 }
+
+#[test]
+fn test_ja4plus_absent_components() {
+    let out = OutStream {
+        tls: None,
+        tunnel: None,
+        starttls_proto: None,
+        ja4l: None,
+        rtt_stats: None,
+        http: None,
+        ja4ssh: ssh::Fingerprints::default(),
+        ssh_extras: None,
+        frame_protocols: Some("eth:ethertype:ip:tcp".to_owned()),
+        traffic: None,
+        ja4plus: None,
+    };
+    // Not the hash of seven `000000000000` components, which every stream without
+    // fingerprints would share.
+    assert_eq!(out.ja4plus(), None);
+    assert!(out.is_empty());
+    let json = serde_json::to_value(&out).unwrap();
+    assert!(json.get("ja4plus").is_none(), "{json}");
+}
+
+#[test]
+fn test_udp_timeout() {
+    let timeout = IdleTimeout::default();
+    assert!(!timeout.is_expired(1_000_000, 61_000_000));
+    assert!(timeout.is_expired(1_000_000, 61_000_001));
+    // Out-of-order timestamps.
+    assert!(!timeout.is_expired(61_000_001, 1_000_000));
+
+    let timeout = IdleTimeout(Duration::from_secs(5));
+    assert!(timeout.is_expired(0, 5_000_001));
+}
+
+/// Many short TCP streams that are never torn down: with a TCP idle timeout, only
+/// the streams of the latest seconds are kept in memory.
+#[test]
+fn test_evict_idle_tcp() {
+    let mut streams = Streams::default().with_tcp_idle_timeout(Some(Duration::from_secs(10)));
+    let mut max_in_memory = 0;
+    for i in 0..100_000_u32 {
+        // 1000 new streams per second.
+        let now = i64::from(i) * 1_000;
+        let port = u16::try_from(1024 + i % 60_000).unwrap();
+        let sockets = SocketPair::new(
+            SocketAddr::from(([192, 0, 2, 1], port)),
+            SocketAddr::from(([198, 51, 100, 1], 443)),
+        );
+        streams.insert_tls(StreamId(i), sockets, now, 1, tls::Stream::default());
+        streams.expire_idle(now);
+        streams.drain_closed(FormatFlags::default());
+        max_in_memory = max_in_memory.max(streams.tcp.len());
+    }
+    assert!(max_in_memory <= 11_001, "{max_in_memory} streams in memory");
+    // The IDs of the streams of the last minute, less the idle timeout.
+    let nr_drained = streams.drained.len();
+    assert!(
+        nr_drained <= 51_000,
+        "{nr_drained} drained streams remembered"
+    );
+}
+
+/// Lots of short TCP connections: once closed, they are finalized whether or not
+/// the records are output incrementally.
+#[test]
+fn test_finalize_closed_tcp() {
+    use rtshark::Metadata;
+
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default();
+    let mut max_in_memory = 0;
+    let mut max_drained = 0;
+    let mut packet_num = 0;
+    for i in 0..20_000_u32 {
+        // 100 connections per second, each a SYN, a FIN from both sides, and an ACK.
+        let start = 1_700_000_000_000_000 + i64::from(i) * 10_000;
+        for (j, (from_client, flags)) in [(true, "syn"), (true, "fin"), (false, "fin"), (true, "")]
+            .into_iter()
+            .enumerate()
+        {
+            let port = (1024 + i % 60_000).to_string();
+            let client = ("192.0.2.1", port.as_str());
+            let server = ("198.51.100.1", "443");
+            let (src, dst) = if from_client {
+                (client, server)
+            } else {
+                (server, client)
+            };
+            let micros = start + i64::try_from(j).unwrap() * 1_000;
+            let layers = [
+                (
+                    "frame",
+                    vec![(
+                        "frame.time_epoch",
+                        format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000),
+                    )],
+                ),
+                (
+                    "ip",
+                    vec![("ip.src", src.0.to_owned()), ("ip.dst", dst.0.to_owned())],
+                ),
+                (
+                    "tcp",
+                    vec![
+                        ("tcp.stream", i.to_string()),
+                        ("tcp.srcport", src.1.to_owned()),
+                        ("tcp.dstport", dst.1.to_owned()),
+                        ("tcp.flags.syn", u8::from(flags == "syn").to_string()),
+                        ("tcp.flags.fin", u8::from(flags == "fin").to_string()),
+                    ],
+                ),
+            ];
+            let mut packet = rtshark::Packet::new();
+            for (name, fields) in layers {
+                packet.push(name.to_owned());
+                let layer = packet.last_layer_mut().unwrap();
+                for (name, value) in fields {
+                    layer.add(Metadata::new(name.to_owned(), value, String::new(), 0, 0));
+                }
+            }
+            packet_num += 1;
+            let pkt = Packet::new(&packet, packet_num);
+            streams.update(&pkt, &conf, false).unwrap();
+            streams.finalize_closed(FormatFlags::default());
+            max_in_memory = max_in_memory.max(streams.tcp.len());
+            max_drained = max_drained.max(streams.drained.len());
+        }
+    }
+    // The connections of the grace period and of the latest second.
+    assert!(max_in_memory <= 1_100, "{max_in_memory} streams in memory");
+    // The IDs of the drained streams are kept for a minute after their latest
+    // packet, the grace period included.
+    assert!(
+        max_drained <= 5_100,
+        "{max_drained} drained streams remembered"
+    );
+    // None of the streams has fingerprints.
+    assert_eq!(streams.into_out(FormatFlags::default()).count(), 0);
+}
+
+#[test]
+fn test_frame_protocols() {
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default();
+    for (num, protocols) in [
+        (1, "eth:ethertype:ip:tcp"),
+        (2, "eth:ethertype:ip:tcp:http"),
+        (3, "eth:ethertype:ip:tcp"),
+    ] {
+        let mut packet = rtshark::Packet::new();
+        for (name, fields) in [
+            (
+                "frame",
+                &[
+                    ("frame.time_epoch", "1700000000.0"),
+                    ("frame.protocols", protocols),
+                ][..],
+            ),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            (
+                "tcp",
+                &[
+                    ("tcp.stream", "0"),
+                    ("tcp.srcport", "50000"),
+                    ("tcp.dstport", "80"),
+                ],
+            ),
+            (
+                "http",
+                &[
+                    ("http.request.method", "GET"),
+                    ("http.request.version", "HTTP/1.1"),
+                ],
+            ),
+        ] {
+            packet.push(name.to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            for &(name, value) in fields {
+                layer.add(rtshark::Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+        }
+        streams
+            .update(&Packet::new(&packet, num), &conf, false)
+            .unwrap();
+    }
+    let flags = FormatFlags {
+        with_details: true,
+        ..Default::default()
+    };
+    let rec = streams.into_out(flags).next().unwrap();
+    assert_eq!(
+        serde_json::to_value(&rec).unwrap()["frame_protocols"],
+        "eth:ethertype:ip:tcp:http"
+    );
+}
+
+#[test]
+fn test_traffic() {
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default();
+    let client = ("192.0.2.1", "198.51.100.1", "50000", "80");
+    let server = ("198.51.100.1", "192.0.2.1", "80", "50000");
+    // The request is retransmitted and counted twice.
+    for (num, (src, dst, srcport, dstport), len, request) in [
+        (1, client, "74", false),
+        (2, server, "74", false),
+        (3, client, "200", true),
+        (4, client, "200", true),
+        (5, server, "1500", false),
+    ] {
+        let mut packet = rtshark::Packet::new();
+        let mut layers = vec![
+            (
+                "frame",
+                vec![("frame.time_epoch", "1700000000.0"), ("frame.len", len)],
+            ),
+            ("ip", vec![("ip.src", src), ("ip.dst", dst)]),
+            (
+                "tcp",
+                vec![
+                    ("tcp.stream", "0"),
+                    ("tcp.srcport", srcport),
+                    ("tcp.dstport", dstport),
+                ],
+            ),
+        ];
+        if request {
+            layers.push((
+                "http",
+                vec![
+                    ("http.request.method", "GET"),
+                    ("http.request.version", "HTTP/1.1"),
+                ],
+            ));
+        }
+        for (name, fields) in layers {
+            packet.push(name.to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            for (name, value) in fields {
+                layer.add(rtshark::Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+        }
+        streams
+            .update(&Packet::new(&packet, num), &conf, false)
+            .unwrap();
+    }
+
+    let flags = FormatFlags {
+        with_details: true,
+        ..Default::default()
+    };
+    let rec = serde_json::to_value(streams.into_out(flags).next().unwrap()).unwrap();
+    assert_eq!(rec["packets"], 5);
+    assert_eq!(rec["bytes_client"], 474);
+    assert_eq!(rec["bytes_server"], 1574);
+}
+
+#[test]
+fn test_min_packets() {
+    let conf = crate::conf::ConfTls {
+        enabled: true,
+        doh_servers: Vec::new(),
+        selection: Default::default(),
+        experimental: false,
+        resolve_dns: false,
+    };
+    let hello = tls::RawClientHello {
+        version: 0x0303,
+        ciphers: vec![0x1301],
+        ..Default::default()
+    };
+    let stream = |nr_packets, tls| {
+        let mut addressed = AddressedStream::<TcpTimestamps>::new(
+            SocketPair::new(
+                SocketAddr::from(([192, 0, 2, 1], 50000)),
+                SocketAddr::from(([198, 51, 100, 1], 443)),
+            ),
+            false,
+        );
+        addressed.nr_packets = nr_packets;
+        addressed.stream.tls = tls;
+        addressed
+    };
+    let short = stream(1, None);
+    let long = stream(3, None);
+    let short_ja4 = stream(
+        1,
+        Some(tls::Stream::from_raw(
+            tls::RawHandshake {
+                client_hello: Some((hello, crate::PacketNum(1))),
+                ..Default::default()
+            },
+            false,
+            &conf,
+        )),
+    );
+
+    let lenient = MinPackets {
+        count: 3,
+        strict: false,
+    };
+    assert!(!lenient.admits(&short));
+    assert!(lenient.admits(&long));
+    assert!(lenient.admits(&short_ja4));
+
+    let strict = MinPackets {
+        strict: true,
+        ..lenient
+    };
+    assert!(!strict.admits(&short_ja4));
+    assert!(MinPackets::default().admits(&short));
+}
+
+#[test]
+fn test_list_streams() {
+    use rtshark::Metadata;
+
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default().with_list_streams(true);
+    type Layers<'a> = &'a [(&'a str, &'a [(&'a str, &'a str)])];
+
+    let packets: [Layers; 3] = [
+        &[
+            (
+                "frame",
+                &[("frame.time_epoch", "1700000000.0"), ("frame.len", "74")],
+            ),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            (
+                "tcp",
+                &[
+                    ("tcp.stream", "0"),
+                    ("tcp.srcport", "50000"),
+                    ("tcp.dstport", "443"),
+                ],
+            ),
+        ],
+        // Encapsulated in VXLAN: the layers above the inner TCP count.
+        &[
+            (
+                "frame",
+                &[("frame.time_epoch", "1700000000.0"), ("frame.len", "600")],
+            ),
+            (
+                "ip",
+                &[("ip.src", "203.0.113.1"), ("ip.dst", "203.0.113.2")],
+            ),
+            (
+                "udp",
+                &[
+                    ("udp.stream", "0"),
+                    ("udp.srcport", "4789"),
+                    ("udp.dstport", "4789"),
+                ],
+            ),
+            ("vxlan", &[]),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            (
+                "tcp",
+                &[
+                    ("tcp.stream", "0"),
+                    ("tcp.srcport", "50000"),
+                    ("tcp.dstport", "443"),
+                ],
+            ),
+            ("tls", &[]),
+            ("_ws.malformed", &[]),
+        ],
+        &[
+            (
+                "frame",
+                &[("frame.time_epoch", "1700000000.0"), ("frame.len", "80")],
+            ),
+            (
+                "ip",
+                &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.53")],
+            ),
+            (
+                "udp",
+                &[
+                    ("udp.stream", "1"),
+                    ("udp.srcport", "5353"),
+                    ("udp.dstport", "53"),
+                ],
+            ),
+            ("dns", &[]),
+        ],
+    ];
+    for (num, layers) in packets.into_iter().enumerate() {
+        let mut packet = rtshark::Packet::new();
+        for &(name, fields) in layers {
+            packet.push(name.to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            for &(name, value) in fields {
+                let md = Metadata::new(name.to_owned(), value.to_owned(), String::new(), 0, 0);
+                layer.add(md);
+            }
+        }
+        let pkt = Packet::new(&packet, num + 1);
+        streams.update(&pkt, &conf, false).unwrap();
+    }
+    let recs = streams.into_out(FormatFlags::default()).collect::<Vec<_>>();
+    expect_test::expect![[r#"
+        - stream: 0
+          transport: tcp
+          src: 192.0.2.1
+          dst: 198.51.100.1
+          src_port: 50000
+          dst_port: 443
+          protocols:
+          - tls
+          packets: 2
+          bytes: 674
+        - stream: 1
+          transport: udp
+          src: 192.0.2.1
+          dst: 198.51.100.53
+          src_port: 5353
+          dst_port: 53
+          protocols:
+          - dns
+          packets: 1
+          bytes: 80
+    "#]]
+    .assert_eq(&serde_yaml::to_string(&recs).unwrap());
+
+    let rec = recs[1].to_record();
+    assert_eq!(rec.transport, crate::record::Transport::Udp);
+    assert_eq!(rec.protocols, ["dns"]);
+    assert_eq!((rec.packets, rec.bytes), (Some(1), Some(80)));
+    assert!(rec.ja4plus.is_none() && rec.other.is_empty());
+
+    let mut rec = recs.into_iter().next().unwrap();
+    rec.extra_mut().insert("asn".to_owned(), 64496.into());
+    let json = serde_json::to_string(&rec).unwrap();
+    assert!(json.ends_with(r#""bytes":674,"asn":64496}"#), "{json}");
+    assert_eq!(rec.to_record().other["asn"], 64496);
+}
+
+/// Builds a packet with the given protocol layers, as tshark dissects it.
+#[cfg(test)]
+fn test_packet(layers: &[(&str, &[(&str, &str)])]) -> rtshark::Packet {
+    let mut packet = rtshark::Packet::new();
+    for &(name, fields) in layers {
+        packet.push(name.to_owned());
+        let layer = packet.last_layer_mut().unwrap();
+        for &(name, value) in fields {
+            layer.add(rtshark::Metadata::new(
+                name.to_owned(),
+                value.to_owned(),
+                String::new(),
+                0,
+                0,
+            ));
+        }
+    }
+    packet
+}
+
+/// A proxy tunnel is reported whichever fingerprints the stream has, not only TLS.
+#[test]
+fn test_tunnel_without_tls() {
+    let conf = Conf::builder().tls(false).build().unwrap();
+    let mut streams = Streams::default();
+    let client = [
+        ("tcp.stream", "0"),
+        ("tcp.srcport", "50000"),
+        ("tcp.dstport", "3128"),
+    ];
+    let server = [
+        ("tcp.stream", "0"),
+        ("tcp.srcport", "3128"),
+        ("tcp.dstport", "50000"),
+    ];
+    let packets = [
+        test_packet(&[
+            ("frame", &[("frame.time_epoch", "1700000000.0")]),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            ("tcp", &client),
+            (
+                "http",
+                &[
+                    ("http.request.method", "CONNECT"),
+                    ("http.request.uri", "example.com:443"),
+                    ("http.request.version", "HTTP/1.1"),
+                ],
+            ),
+        ]),
+        test_packet(&[
+            ("frame", &[("frame.time_epoch", "1700000000.1")]),
+            ("ip", &[("ip.src", "198.51.100.1"), ("ip.dst", "192.0.2.1")]),
+            ("tcp", &server),
+            ("http", &[("http.response.code", "200")]),
+        ]),
+    ];
+    for (num, packet) in packets.iter().enumerate() {
+        streams
+            .update(&Packet::new(packet, num + 1), &conf, false)
+            .unwrap();
+    }
+    let rec =
+        serde_json::to_value(streams.into_out(FormatFlags::default()).next().unwrap()).unwrap();
+    assert_eq!(rec["tunnel"], "connect");
+    assert_eq!(rec["tunnel_dst"], "example.com:443");
+    assert!(
+        rec["http"][0]["ja4h"].as_str().unwrap().starts_with("co11"),
+        "{rec}"
+    );
+}
+
+/// Streams excluded by the filters are only remembered while they have packets.
+#[test]
+fn test_forget_excluded() {
+    let conf = Conf::builder().build().unwrap();
+    let filter = StreamFilter::new(Vec::new(), Vec::new(), vec![443], Vec::new());
+    let mut streams = Streams::new(filter);
+    let mut max_excluded = 0;
+    for i in 0..10_000_u32 {
+        // 50 new plain HTTP connections per second.
+        let micros = 1_700_000_000_000_000 + i64::from(i) * 20_000;
+        let stream = i.to_string();
+        let time = format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000);
+        let packet = test_packet(&[
+            ("frame", &[("frame.time_epoch", &time)]),
+            ("ip", &[("ip.src", "192.0.2.1"), ("ip.dst", "198.51.100.1")]),
+            (
+                "tcp",
+                &[
+                    ("tcp.stream", &stream),
+                    ("tcp.srcport", "50000"),
+                    ("tcp.dstport", "80"),
+                ],
+            ),
+        ]);
+        let num = usize::try_from(i).unwrap() + 1;
+        streams
+            .update(&Packet::new(&packet, num), &conf, false)
+            .unwrap();
+        max_excluded = max_excluded.max(streams.excluded.len());
+    }
+    assert!(streams.tcp.is_empty());
+    assert!(
+        max_excluded <= 3_100,
+        "{max_excluded} excluded streams remembered"
+    );
+}
+
+/// Closed TCP streams are drained as soon as the connection is torn down, each
+/// exactly once; the others are left till the end of the capture.
+#[test]
+fn test_drain_closed() {
+    const NONE: [u32; 0] = [];
+
+    let conf = Conf::builder().build().unwrap();
+    let mut streams = Streams::default();
+    let mut num = 0;
+    let mut send = |streams: &mut Streams, stream: &str, from_client: bool, flags: &str| {
+        let (src, dst, srcport, dstport) = if from_client {
+            ("192.0.2.1", "198.51.100.1", "50000", "80")
+        } else {
+            ("198.51.100.1", "192.0.2.1", "80", "50000")
+        };
+        let ip = [("ip.src", src), ("ip.dst", dst)];
+        let tcp = [
+            ("tcp.stream", stream),
+            ("tcp.srcport", srcport),
+            ("tcp.dstport", dstport),
+            ("tcp.flags.fin", if flags == "fin" { "1" } else { "0" }),
+            ("tcp.flags.reset", if flags == "rst" { "1" } else { "0" }),
+        ];
+        let mut layers = vec![
+            ("frame", &[("frame.time_epoch", "1700000000.0")][..]),
+            ("ip", &ip),
+            ("tcp", &tcp),
+        ];
+        let request = [
+            ("http.request.method", "GET"),
+            ("http.request.version", "HTTP/1.1"),
+        ];
+        if flags == "request" {
+            layers.push(("http", &request));
+        }
+        num += 1;
+        let packet = test_packet(&layers);
+        streams
+            .update(&Packet::new(&packet, num), &conf, false)
+            .unwrap();
+        streams
+            .drain_closed(FormatFlags::default())
+            .into_iter()
+            .map(|rec| rec.stream.0)
+            .collect::<Vec<_>>()
+    };
+
+    for stream in ["0", "1", "2"] {
+        assert_eq!(send(&mut streams, stream, true, "request"), NONE);
+    }
+    // FIN from both sides, then the final ACK.
+    assert_eq!(send(&mut streams, "0", true, "fin"), NONE);
+    assert_eq!(send(&mut streams, "0", false, "fin"), [0]);
+    assert_eq!(send(&mut streams, "0", true, ""), NONE);
+    // RST from the server, then a retransmission of it.
+    assert_eq!(send(&mut streams, "1", false, "rst"), [1]);
+    assert_eq!(send(&mut streams, "1", false, "rst"), NONE);
+    // FIN from one side only: the connection is still half-open.
+    assert_eq!(send(&mut streams, "2", true, "fin"), NONE);
+
+    let rest = streams
+        .into_out(FormatFlags::default())
+        .map(|rec| rec.stream.0)
+        .collect::<Vec<_>>();
+    assert_eq!(rest, [2]);
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Aggregate statistics of a capture file (`--summary`)
+
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::OutputRecord;
+
+#[derive(Debug, Default)]
+pub(crate) struct Summary {
+    /// Number of streams with each distinct JA4S fingerprint, keyed by server IP address.
+    ja4s_by_server: IndexMap<String, IndexMap<String, usize>>,
+}
+
+impl Summary {
+    pub(crate) fn add(&mut self, rec: &OutputRecord) {
+        if let Some(ja4s) = rec.ja4s() {
+            self.add_ja4s(rec.server_ip(), ja4s);
+        }
+    }
+
+    fn add_ja4s(&mut self, server: &str, ja4s: &str) {
+        *self
+            .ja4s_by_server
+            .entry(server.to_owned())
+            .or_default()
+            .entry(ja4s.to_owned())
+            .or_default() += 1;
+    }
+
+    pub(crate) fn into_out(self) -> OutSummary {
+        // A server presenting different JA4S fingerprints across connections is
+        // typically a load balancer with heterogeneous TLS stacks, but may also be
+        // a misconfigured or spoofed server.
+        let ja4s_variance = self
+            .ja4s_by_server
+            .into_iter()
+            .filter(|(_, ja4s)| ja4s.len() > 1)
+            .map(|(server, ja4s)| OutJa4sVariance { server, ja4s })
+            .collect();
+        OutSummary { ja4s_variance }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct OutSummary {
+    /// Servers that presented more than one distinct JA4S fingerprint.
+    ja4s_variance: Vec<OutJa4sVariance>,
+}
+
+#[derive(Debug, Serialize)]
+struct OutJa4sVariance {
+    server: String,
+    /// Number of streams with each JA4S fingerprint.
+    ja4s: IndexMap<String, usize>,
+}
+
+#[test]
+fn test_ja4s_variance() {
+    let mut summary = Summary::default();
+    summary.add_ja4s("192.0.2.1", "t130200_1301_a56c5b993250");
+    summary.add_ja4s("192.0.2.2", "t130200_1301_a56c5b993250");
+    summary.add_ja4s("192.0.2.1", "t120400_c030_4e8089b08790");
+    summary.add_ja4s("192.0.2.2", "t130200_1301_a56c5b993250");
+    summary.add_ja4s("192.0.2.1", "t130200_1301_a56c5b993250");
+
+    expect_test::expect![[r#"
+        {
+          "ja4s_variance": [
+            {
+              "server": "192.0.2.1",
+              "ja4s": {
+                "t130200_1301_a56c5b993250": 2,
+                "t120400_c030_4e8089b08790": 1
+              }
+            }
+          ]
+        }"#]]
+    .assert_eq(&serde_json::to_string_pretty(&summary.into_out()).unwrap());
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Forwarding of records to a SIEM as CEF syslog messages (`--syslog`)
+//!
+//! Each record is sent as an [RFC 5424] syslog message whose text is an ArcSight
+//! Common Event Format (CEF) event. The fingerprints are mapped to custom string
+//! extensions, labeled with the fingerprint names:
+//!
+//! | CEF key        | Field             |
+//! |----------------|-------------------|
+//! | `cs1`          | JA4               |
+//! | `cs2`          | JA4S              |
+//! | `cs3`          | JA4H              |
+//! | `cs4`          | JA4X              |
+//! | `cs5`          | JA4SSH            |
+//! | `cs6`          | JA4+ (`ja4plus`)  |
+//! | `flexString1`  | JA4L-C            |
+//! | `flexString2`  | JA4L-S            |
+//! | `dhost`        | TLS server name   |
+//!
+//! Multiple values of a fingerprint (e.g. JA4H of every HTTP request) are joined
+//! with `,`. Records matched by `--match-file` have severity 8, others --- 3.
+//!
+//! [RFC 5424]: https://datatracker.ietf.org/doc/html/rfc5424
+
+use std::{
+    io::Write as _,
+    net::{TcpStream, UdpSocket},
+};
+
+use serde_json::Value;
+
+use crate::{select::FingerprintKind, OutputRecord, Result};
+
+/// Transport protocol for syslog messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SyslogTransport {
+    /// One message per datagram.
+    Udp,
+    /// Messages terminated with a newline (RFC 6587, non-transparent framing).
+    Tcp,
+}
+
+/// Connection to a syslog server.
+pub(crate) enum Syslog {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Syslog {
+    /// Connects to the server at `addr` (`HOST:PORT`).
+    pub(crate) fn connect(addr: &str, transport: SyslogTransport) -> Result<Self> {
+        Ok(match transport {
+            SyslogTransport::Udp => {
+                let socket =
+                    UdpSocket::bind(("::", 0)).or_else(|_| UdpSocket::bind(("0.0.0.0", 0)))?;
+                socket.connect(addr)?;
+                Self::Udp(socket)
+            }
+            SyslogTransport::Tcp => Self::Tcp(TcpStream::connect(addr)?),
+        })
+    }
+
+    pub(crate) fn send(&mut self, rec: &OutputRecord) -> Result<()> {
+        let msg = syslog_message(&cef_event(rec)?);
+        match self {
+            Self::Udp(socket) => {
+                socket.send(msg.as_bytes())?;
+            }
+            Self::Tcp(stream) => {
+                stream.write_all(msg.as_bytes())?;
+                stream.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the CEF event in an RFC 5424 message: facility local0, severity
+/// informational, no timestamp or hostname (the receiver fills them in).
+fn syslog_message(event: &str) -> String {
+    format!("<134>1 - - ja4 - - - {event}")
+}
+
+/// Formats the record as a CEF event.
+fn cef_event(rec: &OutputRecord) -> Result<String> {
+    let json = serde_json::to_value(rec)?;
+    let field = |name| match json.get(name) {
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    };
+    let severity = if json.get("matched") == Some(&Value::Bool(true)) {
+        8
+    } else {
+        3
+    };
+
+    let mut ext = Vec::new();
+    let mut push = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            ext.push(format!("{key}={}", escape_ext(&value)));
+        }
+    };
+    push("src", field("src"));
+    push("spt", field("src_port"));
+    push("dst", field("dst"));
+    push("dpt", field("dst_port"));
+    push("proto", field("transport").map(|s| s.to_ascii_uppercase()));
+    push("dhost", field("tls_server_name"));
+    let custom = [
+        ("cs1", "JA4", rec.fingerprint(FingerprintKind::Ja4)),
+        ("cs2", "JA4S", rec.fingerprint(FingerprintKind::Ja4s)),
+        ("cs3", "JA4H", rec.fingerprint(FingerprintKind::Ja4h)),
+        ("cs4", "JA4X", rec.fingerprint(FingerprintKind::Ja4x)),
+        ("cs5", "JA4SSH", rec.fingerprint(FingerprintKind::Ja4ssh)),
+        ("cs6", "JA4+", field("ja4plus")),
+        ("flexString1", "JA4L-C", field("ja4l_c")),
+        ("flexString2", "JA4L-S", field("ja4l_s")),
+    ];
+    for (key, label, value) in custom {
+        if value.is_some() {
+            push(key, value);
+            push(&format!("{key}Label"), Some(label.to_owned()));
+        }
+    }
+
+    Ok(format!(
+        "CEF:0|FoxIO|JA4|{}|stream|JA4+ fingerprints|{severity}|{}",
+        escape_header(env!("CARGO_PKG_VERSION")),
+        ext.join(" ")
+    ))
+}
+
+/// Escapes a CEF header field.
+fn escape_header(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escapes a CEF extension value.
+fn escape_ext(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape_header(r"a|b\c"), r"a\|b\\c");
+    assert_eq!(escape_ext("a=b\\c\nd"), r"a\=b\\c\nd");
+    assert_eq!(
+        syslog_message("CEF:0|FoxIO|JA4|1.0|stream|JA4+ fingerprints|3|src=192.0.2.1"),
+        "<134>1 - - ja4 - - - CEF:0|FoxIO|JA4|1.0|stream|JA4+ fingerprints|3|src=192.0.2.1"
+    );
+}
@@ -7,13 +7,18 @@
 
 //! Light distance (latency) fingerprints -- JA4L-C (client), JA4L-S (server)
 
+mod rtt;
 mod tcp;
 mod udp;
 
 use serde::Serialize;
 
 use crate::{Packet, PacketNum, Result};
-pub(crate) use {tcp::Timestamps as TcpTimestamps, udp::Timestamps as UdpTimestamps};
+pub(crate) use {
+    rtt::{Rtt, RttStats},
+    tcp::Timestamps as TcpTimestamps,
+    udp::Timestamps as UdpTimestamps,
+};
 
 #[derive(Debug, Serialize)]
 pub(crate) struct Fingerprints {
@@ -21,6 +26,23 @@ pub(crate) struct Fingerprints {
     ja4l_s: String,
 }
 
+impl From<crate::latency::Ja4lFingerprint> for Fingerprints {
+    fn from(fp: crate::latency::Ja4lFingerprint) -> Self {
+        let crate::latency::Ja4lFingerprint { ja4l_c, ja4l_s } = fp;
+        Self { ja4l_c, ja4l_s }
+    }
+}
+
+impl Fingerprints {
+    pub(crate) fn ja4l_c(&self) -> &str {
+        &self.ja4l_c
+    }
+
+    pub(crate) fn ja4l_s(&self) -> &str {
+        &self.ja4l_s
+    }
+}
+
 pub(crate) trait Timestamps: Default {
     fn update(self, pkt: &Packet) -> Result<Self>
     where
@@ -28,6 +50,9 @@ pub(crate) trait Timestamps: Default {
 
     /// Returns the JA4L-C and JA4L-S fingerprints.
     fn finish(self) -> Option<Fingerprints>;
+
+    /// Returns `true` if [`Timestamps::finish`] would return the fingerprints.
+    fn is_done(&self) -> bool;
 }
 
 #[derive(Debug)]
@@ -56,12 +81,17 @@ impl PacketTimestamp {
 pub(crate) struct Ttl(u8);
 
 impl Ttl {
-    // XXX-FIXME(vvv): Some packets (e.g. GRE) may have several "ip" layers.
-    // We should take the last one, not the first one.
+    /// Reads the TTL of the innermost IP layer. Encapsulated packets (GRE, ERSPAN,
+    /// TZSP, VXLAN) have several "ip"/"ipv6" layers, the outer ones belonging to the
+    /// tunnel rather than to the fingerprinted hosts.
     pub(crate) fn new(pkt: &Packet) -> Result<Self> {
-        let ttl = if let Some(ip) = pkt.find_proto("ip") {
+        let ip = pkt
+            .iter()
+            .filter(|proto| matches!(proto.name(), "ip" | "ipv6"))
+            .last();
+        let ttl = if let Some(ip) = ip.as_ref().filter(|ip| ip.name() == "ip") {
             ip.first("ip.ttl")?.parse::<u8>()?
-        } else if let Some(ipv6) = pkt.find_proto("ipv6") {
+        } else if let Some(ipv6) = ip {
             // "hlim" stands for "Hop Limit"
             ipv6.first("ipv6.hlim")?.parse::<u8>()?
         } else {
@@ -72,3 +102,283 @@ impl Ttl {
         Ok(Self(ttl))
     }
 }
+
+/// Point in time given on the command line (`--after`, `--before`, `--since`,
+/// `--until`).
+///
+/// Accepted formats:
+///
+/// - seconds since the Unix epoch, optionally with a fraction: `1700000000.25`;
+/// - RFC 3339 date and time: `2023-11-14T22:13:20Z`, `2023-11-15T00:13:20+02:00`.
+///   The `T` may be replaced with a space. Without a UTC offset, UTC is assumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Timestamp {
+    /// Microseconds since the Unix epoch, same as packet timestamps.
+    micros: i64,
+}
+
+impl Timestamp {
+    pub(crate) fn micros(self) -> i64 {
+        self.micros
+    }
+
+    pub(crate) fn now() -> Self {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            micros: i64::try_from(since_epoch.as_micros()).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Formats the time in UTC as ISO 8601 basic format, with whole seconds:
+    /// `20231114T221320Z`. Fit for file names.
+    pub(crate) fn to_basic_utc(self) -> String {
+        let secs = self.micros.div_euclid(1_000_000);
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let secs_of_day = secs.rem_euclid(86400);
+        format!(
+            "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        )
+    }
+
+    fn parse_epoch(s: &str) -> Option<Self> {
+        let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+        if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let secs = secs.parse::<i64>().ok()?;
+        Some(Self {
+            micros: secs
+                .checked_mul(1_000_000)?
+                .checked_add(parse_fraction(frac)?)?,
+        })
+    }
+
+    fn parse_rfc3339(s: &str) -> Option<Self> {
+        let number = |s: &str| -> Option<i64> {
+            s.bytes()
+                .all(|b| b.is_ascii_digit())
+                .then(|| s.parse().ok())?
+        };
+        let (date, time) = s.split_once(['T', 't', ' '])?;
+
+        let [year, month, day] = split3(date, '-', [4, 2, 2])?;
+        let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+
+        // UTC offset, if any.
+        let (time, offset_secs) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+            (time, 0)
+        } else if let Some(i) = time.rfind(['+', '-']) {
+            let (time, offset) = time.split_at(i);
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let (hours, minutes) = (number(hours)?, number(minutes)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            (time, sign * (hours * 3600 + minutes * 60))
+        } else {
+            (time, 0)
+        };
+
+        let (time, frac) = time.split_once('.').unwrap_or((time, ""));
+        let [hours, minutes, secs] = split3(time, ':', [2, 2, 2])?;
+        let (hours, minutes, secs) = (number(hours)?, number(minutes)?, number(secs)?);
+        if hours > 23 || minutes > 59 || secs > 60 {
+            return None;
+        }
+
+        let secs = days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + secs
+            - offset_secs;
+        Some(Self {
+            micros: secs * 1_000_000 + parse_fraction(frac)?,
+        })
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::parse_epoch(s)
+            .or_else(|| Self::parse_rfc3339(s))
+            .ok_or_else(|| {
+                "expected seconds since the Unix epoch or RFC 3339 date and time, \
+                e.g. 1700000000 or 2023-11-14T22:13:20Z"
+                    .to_owned()
+            })
+    }
+}
+
+/// Bound of the packet time window (`--since`, `--until`): a point in time, see
+/// [`Timestamp`], or `+` and a duration since the first packet of the capture,
+/// e.g. `+90s`, `+30m`, `+1h30m`, `+2d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimeBound {
+    At(Timestamp),
+    /// Microseconds after the first packet.
+    Offset(i64),
+}
+
+impl TimeBound {
+    /// Returns the time in microseconds since the Unix epoch, given the timestamp
+    /// of the capture's first packet.
+    pub(crate) fn resolve(self, first_packet_micros: i64) -> i64 {
+        match self {
+            Self::At(timestamp) => timestamp.micros,
+            Self::Offset(micros) => first_packet_micros.saturating_add(micros),
+        }
+    }
+
+    /// Parses a duration: numbers with units `d`, `h`, `m`, `s`, in this order.
+    fn parse_offset(s: &str) -> Option<i64> {
+        const UNITS: [(char, i64); 4] = [('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+        let mut rest = s;
+        let mut units = UNITS.iter();
+        let mut secs: i64 = 0;
+        while !rest.is_empty() {
+            let len = rest.find(|c: char| !c.is_ascii_digit())?;
+            let (number, tail) = rest.split_at(len);
+            let unit = tail.chars().next()?;
+            let &(_, unit_secs) = units.find(|&&(name, _)| name == unit)?;
+            secs = secs.checked_add(number.parse::<i64>().ok()?.checked_mul(unit_secs)?)?;
+            rest = &tail[1..];
+        }
+        (!s.is_empty()).then_some(secs.checked_mul(1_000_000)?)
+    }
+}
+
+impl std::str::FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.strip_prefix('+') {
+            Some(offset) => Self::parse_offset(offset).map(Self::Offset).ok_or_else(|| {
+                "expected a duration after '+', e.g. +90s, +30m, or +1h30m".to_owned()
+            }),
+            None => s.parse().map(Self::At),
+        }
+    }
+}
+
+/// Splits the string into three parts of the given lengths.
+fn split3(s: &str, sep: char, lens: [usize; 3]) -> Option<[&str; 3]> {
+    let mut iter = s.split(sep);
+    let parts = [iter.next()?, iter.next()?, iter.next()?];
+    let lens_match = parts.iter().zip(lens).all(|(part, len)| part.len() == len);
+    (lens_match && iter.next().is_none()).then_some(parts)
+}
+
+/// Converts the digits after the decimal point to microseconds, ignoring the digits
+/// beyond microsecond precision.
+fn parse_fraction(frac: &str) -> Option<i64> {
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits = frac.bytes().chain(std::iter::repeat(b'0')).take(6);
+    Some(digits.fold(0, |acc, b| acc * 10 + i64::from(b - b'0')))
+}
+
+/// Returns the number of days since 1970-01-01 in the proleptic Gregorian calendar.
+///
+/// Reference: <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12; // March is 0
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]: returns the year, month, and day.
+///
+/// Reference: <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // March is 0
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[test]
+fn test_to_basic_utc() {
+    let basic = |s: &str| s.parse::<Timestamp>().unwrap().to_basic_utc();
+
+    assert_eq!(basic("0"), "19700101T000000Z");
+    assert_eq!(basic("1700000000.75"), "20231114T221320Z");
+    assert_eq!(basic("2000-02-29T23:59:59Z"), "20000229T235959Z");
+    assert_eq!(basic("2024-12-31T12:00:00-13:00"), "20250101T010000Z");
+}
+
+#[test]
+fn test_parse_timestamp() {
+    let parse = |s: &str| s.parse::<Timestamp>().map(Timestamp::micros);
+
+    assert_eq!(parse("0"), Ok(0));
+    assert_eq!(parse("1700000000"), Ok(1_700_000_000_000_000));
+    assert_eq!(parse("1700000000.25"), Ok(1_700_000_000_250_000));
+    assert_eq!(parse("1970-01-01T00:00:00Z"), Ok(0));
+    assert_eq!(parse("2023-11-14T22:13:20Z"), Ok(1_700_000_000_000_000));
+    // UTC is the default.
+    assert_eq!(parse("2023-11-14 22:13:20"), Ok(1_700_000_000_000_000));
+    assert_eq!(
+        parse("2023-11-15T00:13:20+02:00"),
+        Ok(1_700_000_000_000_000)
+    );
+    assert_eq!(
+        parse("2023-11-14T17:13:20.5-05:00"),
+        Ok(1_700_000_000_500_000)
+    );
+    assert_eq!(
+        parse("2000-02-29T00:00:00.1234567Z"),
+        Ok(951_782_400_123_456)
+    );
+
+    assert!(parse("").is_err());
+    assert!(parse("-1").is_err());
+    assert!(parse("2023-11-14").is_err());
+    assert!(parse("2023-13-14T22:13:20Z").is_err());
+    assert!(parse("2023-11-14T22:13:20+2").is_err());
+    assert!(parse("yesterday").is_err());
+}
+
+#[test]
+fn test_parse_time_bound() {
+    let parse = |s: &str| s.parse::<TimeBound>();
+
+    assert_eq!(
+        parse("1700000000"),
+        Ok(TimeBound::At(Timestamp {
+            micros: 1_700_000_000_000_000
+        }))
+    );
+    assert_eq!(parse("+90s"), Ok(TimeBound::Offset(90_000_000)));
+    assert_eq!(parse("+1h30m"), Ok(TimeBound::Offset(5_400_000_000)));
+    assert_eq!(parse("+2d1s"), Ok(TimeBound::Offset(172_801_000_000)));
+    assert_eq!(parse("+0s").unwrap().resolve(1_000), 1_000);
+    assert_eq!(parse("+1s").unwrap().resolve(1_000), 1_001_000);
+    assert_eq!(parse("5").unwrap().resolve(1_000), 5_000_000);
+    for bad in ["+", "+30", "+m", "+30m1h", "+1h1h", "+-5s", "+1.5s", "-1h"] {
+        assert!(parse(bad).is_err(), "{bad}");
+    }
+}
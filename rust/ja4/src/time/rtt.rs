@@ -0,0 +1,172 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Round-trip times measured over the whole TCP stream
+
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use crate::{Packet, Result, Sender};
+
+/// Collects RTT samples of a TCP stream.
+///
+/// A sample is the time between a segment and the first ACK from the other side
+/// that covers it. Segments that have been retransmitted give no samples (Karn's
+/// algorithm), as it's unknown which transmission the ACK is for.
+#[derive(Debug, Default)]
+pub(crate) struct Rtt {
+    /// Unacknowledged segments sent by the client.
+    client: Direction,
+    /// Unacknowledged segments sent by the server.
+    server: Direction,
+    /// RTT samples, in microseconds.
+    samples: Vec<i64>,
+}
+
+#[derive(Debug, Default)]
+struct Direction {
+    /// Segments in the order of their sequence numbers.
+    in_flight: VecDeque<Segment>,
+    /// Highest sequence number acknowledged by the other side.
+    acked: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// Sequence number following the segment.
+    end: u64,
+    /// Timestamp of the segment, in microseconds.
+    sent: i64,
+    retransmitted: bool,
+}
+
+impl Rtt {
+    pub(crate) fn update(&mut self, pkt: &Packet, sender: Sender) -> Result<()> {
+        let Some(tcp) = pkt.protos("tcp").last() else {
+            return Ok(());
+        };
+        let is_set = |field| matches!(tcp.first(field), Ok("1" | "True"));
+        let timestamp = pkt.timestamp_micros()?;
+        let (ours, theirs) = match sender {
+            Sender::Client => (&mut self.client, &mut self.server),
+            Sender::Server => (&mut self.server, &mut self.client),
+        };
+
+        // SYN and FIN occupy a sequence number each, same as a byte of payload.
+        let len = tcp.first("tcp.len")?.parse::<u64>()?
+            + u64::from(is_set("tcp.flags.syn"))
+            + u64::from(is_set("tcp.flags.fin"));
+        if len > 0 {
+            let end = tcp.first("tcp.seq")?.parse::<u64>()? + len;
+            ours.send(end, timestamp);
+        }
+
+        if is_set("tcp.flags.ack") {
+            let ack = tcp.first("tcp.ack")?.parse::<u64>()?;
+            if let Some(sent) = theirs.ack(ack) {
+                self.samples.push(timestamp - sent);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the statistics, if there is at least one sample.
+    pub(crate) fn finish(self) -> Option<RttStats> {
+        let mut samples = self.samples;
+        samples.sort_unstable();
+        let (&min, &max) = (samples.first()?, samples.last()?);
+        let mid = samples.len() / 2;
+        let median = if samples.len().is_multiple_of(2) {
+            (samples[mid - 1] + samples[mid]) / 2
+        } else {
+            samples[mid]
+        };
+        Some(RttStats {
+            min,
+            median,
+            max,
+            samples: samples.len(),
+        })
+    }
+}
+
+impl Direction {
+    fn send(&mut self, end: u64, sent: i64) {
+        if end <= self.acked {
+            // Retransmission of a segment that has been acknowledged already.
+            return;
+        }
+        match self.in_flight.iter_mut().find(|seg| seg.end >= end) {
+            Some(seg) => seg.retransmitted = true,
+            None => self.in_flight.push_back(Segment {
+                end,
+                sent,
+                retransmitted: false,
+            }),
+        }
+    }
+
+    /// Removes the segments covered by the acknowledgment number. Returns the
+    /// timestamp of the latest of them, unless it has been retransmitted.
+    fn ack(&mut self, ack: u64) -> Option<i64> {
+        if ack <= self.acked {
+            return None;
+        }
+        self.acked = ack;
+        let mut latest = None;
+        while self.in_flight.front().is_some_and(|seg| seg.end <= ack) {
+            latest = self.in_flight.pop_front();
+        }
+        latest.and_then(|seg| (!seg.retransmitted).then_some(seg.sent))
+    }
+}
+
+/// Round-trip time statistics, in microseconds.
+#[derive(Debug, Serialize)]
+pub(crate) struct RttStats {
+    min: i64,
+    median: i64,
+    max: i64,
+    /// Number of RTT samples.
+    samples: usize,
+}
+
+#[test]
+fn test_rtt() {
+    let mut client = Direction::default();
+    client.send(1, 0); // SYN
+    assert_eq!(client.ack(1), Some(0));
+
+    client.send(101, 1_000);
+    client.send(201, 1_500);
+    // Delayed ACK of both segments: the sample is taken for the latest one.
+    assert_eq!(client.ack(201), Some(1_500));
+    // Duplicate ACK.
+    assert_eq!(client.ack(201), None);
+
+    client.send(301, 2_000);
+    client.send(301, 5_000); // retransmission
+    assert_eq!(client.ack(301), None);
+    assert!(client.in_flight.is_empty());
+
+    let rtt = Rtt {
+        samples: vec![40, 10, 30, 20],
+        ..Default::default()
+    };
+    expect_test::expect![[r#"
+        Some(
+            RttStats {
+                min: 10,
+                median: 25,
+                max: 40,
+                samples: 4,
+            },
+        )
+    "#]]
+    .assert_debug_eq(&rtt.finish());
+}
@@ -8,6 +8,7 @@
 //! Timestamps obtained from a TCP handshake
 
 use crate::{
+    latency::Ja4lCalculator,
     time::{Fingerprints, PacketTimestamp, Ttl},
     Packet, Result,
 };
@@ -16,6 +17,8 @@ use crate::{
 pub(crate) enum Timestamps {
     Collecting(Expect),
     Done(Fingerprints),
+    /// The timestamps are out of order; no fingerprints.
+    Failed,
 }
 
 impl Default for Timestamps {
@@ -34,6 +37,18 @@ pub(crate) enum Expect {
     Ack(state::Ack),
 }
 
+impl Timestamps {
+    fn done(calc: &Ja4lCalculator) -> Self {
+        match calc.finish() {
+            Ok(fps) => Self::Done(fps.into()),
+            Err(error) => {
+                tracing::debug!(%error, "failed to compute JA4L");
+                Self::Failed
+            }
+        }
+    }
+}
+
 impl From<Expect> for Timestamps {
     fn from(st: Expect) -> Self {
         Self::Collecting(st)
@@ -43,7 +58,7 @@ impl From<Expect> for Timestamps {
 impl crate::time::Timestamps for Timestamps {
     fn update(self, pkt: &Packet) -> Result<Self> {
         match self {
-            done @ Self::Done(_) => Ok(done),
+            done @ (Self::Done(_) | Self::Failed) => Ok(done),
             Self::Collecting(expect) => {
                 let Some(t) = Timestamp::from_packet(pkt)? else {
                     return Ok(Self::Collecting(expect));
@@ -59,14 +74,19 @@ impl crate::time::Timestamps for Timestamps {
 
     fn finish(self) -> Option<Fingerprints> {
         match self {
-            Self::Collecting(_) => None,
+            Self::Collecting(_) | Self::Failed => None,
             Self::Done(fps) => Some(fps),
         }
     }
+
+    fn is_done(&self) -> bool {
+        matches!(self, Self::Done(_))
+    }
 }
 
 mod state {
-    use super::{Expect, Fingerprints, PacketTimestamp, Timestamp, Timestamps, Ttl};
+    use super::{Expect, Ja4lCalculator, PacketTimestamp, Timestamp, Timestamps, Ttl};
+    use crate::tls::Transport;
 
     /// Initial state. Waiting for a SYN.
     #[derive(Debug)]
@@ -176,16 +196,11 @@ mod state {
                 server_ttl,
             } = st;
 
-            let ja4l_c = (t_c.timestamp - t_b.timestamp) / 2;
-            debug_assert!(ja4l_c >= 0); // 0 if the difference == 1
-
-            let ja4l_s = (t_b.timestamp - t_a.timestamp) / 2;
-            debug_assert!(ja4l_s >= 0); // 0 if the difference == 1
-
-            Self::Done(Fingerprints {
-                ja4l_c: format!("{ja4l_c}_{}", client_ttl.0),
-                ja4l_s: format!("{ja4l_s}_{}", server_ttl.0),
-            })
+            let mut calc = Ja4lCalculator::new(Transport::Tcp);
+            calc.syn(t_a.timestamp, client_ttl.0)
+                .syn_ack(t_b.timestamp, server_ttl.0)
+                .ack(t_c.timestamp);
+            Self::done(&calc)
         }
     }
 }
@@ -0,0 +1,206 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Where the time goes (`--timing`)
+//!
+//! The time of every phase is summed over all jobs, so with several capture files
+//! processed concurrently the phases may add up to more than the elapsed time.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::Packet;
+
+/// Phase of processing whose duration is measured.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    /// Waiting for tshark to dissect the next packet, including the parsing of its
+    /// output.
+    Tshark,
+    /// Updating the streams with a packet and computing their fingerprints.
+    Fingerprint,
+    /// Formatting the records and writing them out.
+    Serialize,
+}
+
+impl Phase {
+    const COUNT: usize = 3;
+}
+
+/// Protocols whose packets are counted.
+const PROTOCOLS: [&str; 9] = [
+    "tcp", "udp", "tls", "quic", "gquic", "dtls", "http", "http2", "ssh",
+];
+
+/// Durations and counters of the run, shared by the jobs. Does nothing unless
+/// enabled.
+#[derive(Debug, Default)]
+pub(crate) struct Timing(Option<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    start: Instant,
+    /// Nanoseconds spent in each [`Phase`].
+    phases: [AtomicU64; Phase::COUNT],
+    packets_read: AtomicU64,
+    /// Number of packets with each of [`PROTOCOLS`].
+    protocols: [AtomicU64; PROTOCOLS.len()],
+    records_serialized: AtomicU64,
+    /// Version of tshark, once checked.
+    tshark_version: OnceLock<String>,
+}
+
+impl Timing {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self(enabled.then(|| Inner {
+            start: Instant::now(),
+            phases: Default::default(),
+            packets_read: AtomicU64::new(0),
+            protocols: Default::default(),
+            records_serialized: AtomicU64::new(0),
+            tshark_version: OnceLock::new(),
+        }))
+    }
+
+    /// Runs `f`, adding its duration to that of `phase`.
+    pub(crate) fn measure<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let Some(inner) = &self.0 else {
+            return f();
+        };
+        let start = Instant::now();
+        let result = f();
+        let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        inner.phases[phase as usize].fetch_add(nanos, Ordering::Relaxed);
+        result
+    }
+
+    /// Counts a packet read from tshark.
+    pub(crate) fn add_packet(&self, pkt: &Packet) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+        inner.packets_read.fetch_add(1, Ordering::Relaxed);
+        let mut seen = [false; PROTOCOLS.len()];
+        for proto in pkt.iter() {
+            if let Some(i) = PROTOCOLS.iter().position(|&name| name == proto.name()) {
+                seen[i] = true;
+            }
+        }
+        for (count, seen) in inner.protocols.iter().zip(seen) {
+            if seen {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Counts packets read without tshark, see [`crate::native`].
+    pub(crate) fn add_packets(&self, nr_packets: usize) {
+        if let Some(inner) = &self.0 {
+            inner
+                .packets_read
+                .fetch_add(nr_packets as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn add_records(&self, nr_records: usize) {
+        if let Some(inner) = &self.0 {
+            inner
+                .records_serialized
+                .fetch_add(nr_records as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the version of tshark that dissects the packets.
+    pub(crate) fn set_tshark_version(&self, version: &semver::Version) {
+        if let Some(inner) = &self.0 {
+            let _ = inner.tshark_version.set(version.to_string());
+        }
+    }
+
+    /// Prints the summary to stderr as a JSON object.
+    pub(crate) fn report(&self) -> crate::Result<()> {
+        let Some(inner) = &self.0 else {
+            return Ok(());
+        };
+        let report = inner.report(inner.start.elapsed());
+        eprintln!("{}", serde_json::to_string(&report)?);
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn report(&self, elapsed: Duration) -> Report {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let secs = |phase: Phase| Duration::from_nanos(load(&self.phases[phase as usize]));
+        let packets_read = load(&self.packets_read);
+        let elapsed_secs = elapsed.as_secs_f64();
+        Report {
+            elapsed_secs,
+            tshark_secs: secs(Phase::Tshark).as_secs_f64(),
+            fingerprint_secs: secs(Phase::Fingerprint).as_secs_f64(),
+            serialize_secs: secs(Phase::Serialize).as_secs_f64(),
+            packets_read,
+            packets_per_sec: if elapsed_secs > 0.0 {
+                packets_read as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            packets_by_protocol: PROTOCOLS
+                .iter()
+                .zip(&self.protocols)
+                .map(|(&name, count)| (name, load(count)))
+                .collect(),
+            records_serialized: load(&self.records_serialized),
+            version: env!("CARGO_PKG_VERSION"),
+            tshark_version: self.tshark_version.get().cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    elapsed_secs: f64,
+    tshark_secs: f64,
+    fingerprint_secs: f64,
+    serialize_secs: f64,
+    packets_read: u64,
+    packets_per_sec: f64,
+    packets_by_protocol: indexmap::IndexMap<&'static str, u64>,
+    records_serialized: u64,
+    /// Version of ja4, to tell apart the reports of different versions.
+    version: &'static str,
+    /// Version of tshark, for the record of the environment of the run. Absent
+    /// if tshark has not been run (`--engine native`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tshark_version: Option<String>,
+}
+
+#[test]
+fn test_report() {
+    let timing = Timing::new(true);
+    timing.measure(Phase::Fingerprint, || {});
+    timing.add_packets(10);
+    timing.add_records(2);
+    timing.set_tshark_version(&semver::Version::new(4, 2, 5));
+    let inner = timing.0.as_ref().unwrap();
+    inner.phases[Phase::Tshark as usize].store(1_500_000_000, Ordering::Relaxed);
+    inner.protocols[2].store(4, Ordering::Relaxed);
+
+    let mut report = serde_json::to_value(inner.report(Duration::from_secs(2))).unwrap();
+    report["fingerprint_secs"] = 0.into();
+    report["version"] = "x".into();
+    expect_test::expect![[r#"
+        {"elapsed_secs":2.0,"fingerprint_secs":0,"packets_by_protocol":{"dtls":0,"gquic":0,"http":0,"http2":0,"quic":0,"ssh":0,"tcp":0,"tls":4,"udp":0},"packets_per_sec":5.0,"packets_read":10,"records_serialized":2,"serialize_secs":0.0,"tshark_secs":1.5,"tshark_version":"4.2.5","version":"x"}"#]]
+    .assert_eq(&report.to_string());
+}
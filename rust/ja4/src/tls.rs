@@ -7,24 +7,91 @@
 
 //! JA4 (TLS client), JA4S (TLS server), and JA4X (X.509 certificate) fingerprinting
 
-use std::fmt;
+use std::{collections::HashSet, fmt, ops::Range};
 
 use itertools::Itertools as _;
 use ja4x::x509_parser::{certificate::X509Certificate, prelude::FromDer as _};
 use serde::Serialize;
 use tracing::{debug, warn};
 
-use crate::{Error, FormatFlags, Packet, PacketNum, Proto, Result};
+use crate::{
+    conf::ConfTls,
+    pcap::{debug_assert_display, FieldOrder},
+    Error, FormatFlags, Packet, PacketNum, Proto, Result, Sender,
+};
 
 #[derive(Debug, Default)]
 pub(crate) struct Stream {
     pub(crate) client: Option<ClientStats>,
     pub(crate) server: Option<ServerStats>,
     pub(crate) x509: Vec<X509Stats>,
+    /// DER encodings of the certificates in `x509`, used to skip retransmitted or
+    /// repeatedly dissected certificates.
+    x509_seen: HashSet<Vec<u8>>,
+    /// The server's certificate chain, from its first Certificate message.
+    cert_chain: Option<CertChain>,
+    /// Whether this stream carries DNS queries.
+    pub(crate) dns_encrypted: Option<DnsEncrypted>,
+    /// TLS session tunneled inside this one (TLS-in-TLS).
+    pub(crate) inner: Option<Box<Stream>>,
+    /// Version of the QUIC protocol carrying this stream.
+    pub(crate) quic_version: Option<QuicVersion>,
+    /// Whether tshark has seen (and decrypted) application data of the session.
+    app_data: AppData,
+    /// Set by [`Stream::mark_decryption_failed`].
+    decryption_failed: bool,
+}
+
+/// Application data of a TLS session, as far as tshark could dissect it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AppData {
+    #[default]
+    None,
+    /// Only encrypted application data has been seen.
+    Encrypted,
+    /// tshark has decrypted at least one application data record.
+    Decrypted,
 }
 
 impl Stream {
-    pub(crate) fn update(&mut self, pkt: &Packet, store_pkt_num: bool) -> Result<()> {
+    /// Returns `true` if a JA4, JA4S, or JA4X fingerprint has been computed for the
+    /// outer or the inner session.
+    pub(crate) fn has_fingerprints(&self) -> bool {
+        self.client.is_some()
+            || self.server.is_some()
+            || !self.x509.is_empty()
+            || self
+                .inner
+                .as_ref()
+                .is_some_and(|inner| inner.has_fingerprints())
+    }
+
+    pub(crate) fn app_data(&self) -> AppData {
+        self.app_data
+    }
+
+    /// Flags the session if it carries application data that wasn't decrypted.
+    ///
+    /// Called when the key log file given with `--keylog-file` has not decrypted any
+    /// of the sessions, which usually means the keys don't match the capture.
+    pub(crate) fn mark_decryption_failed(&mut self) {
+        self.decryption_failed = self.app_data == AppData::Encrypted;
+    }
+
+    pub(crate) fn update(
+        &mut self,
+        pkt: &Packet,
+        conf: &ConfTls,
+        store_pkt_num: bool,
+        sender: Sender,
+    ) -> Result<()> {
+        if self.dns_encrypted.is_none() {
+            self.dns_encrypted = DnsEncrypted::detect(pkt);
+        }
+        if conf.selection.ja4 && self.quic_version.is_none() {
+            self.quic_version = QuicVersion::detect(pkt);
+        }
+
         // Some QUIC frames contain fragmented TLS protocols that do not have `tls.handshake.type` field:
         //
         // ```xml
@@ -40,64 +107,137 @@ impl Stream {
         // ```
         //
         // Because of that, we should not use `Packet::find_proto` --- it returns the first proto,
-        // which may not have `tls.handshake.type` field.
-        let Some(tls) = pkt
-            .protos("tls")
-            .find(|tls| tls.find("tls.handshake.type").is_ok())
-        else {
-            return Ok(());
+        // which may not have `tls.handshake.type` field. A single packet may also carry
+        // several `tls` protocols with handshake messages, e.g. the end of a reassembled
+        // Server Hello followed by a Certificate; we process all of them.
+        //
+        // Decrypted application data may carry another TLS session (TLS-in-TLS). tshark
+        // places the `tls` protocol of the inner session after the outer one, which has
+        // `tls.app_data_proto` field. We fingerprint the outer and the inner sessions
+        // separately and ignore deeper nesting.
+        //
+        // DTLS handshakes (`dtls` protocol) are not fingerprinted yet. Therefore WebRTC
+        // flows, which multiplex STUN, DTLS and SRTP on a single UDP 5-tuple, produce
+        // no TLS records at all.
+        let mut handshakes: [Vec<Proto>; 2] = Default::default();
+        let mut depth = 0;
+        for tls in pkt.protos("tls") {
+            let Some(handshake) = handshakes.get_mut(depth) else {
+                debug!(%pkt.num, "ignoring TLS nested more than two levels deep");
+                break;
+            };
+            let carries_app_data = tls.find("tls.app_data_proto").is_ok();
+            if depth == 0 {
+                if carries_app_data {
+                    self.app_data = AppData::Decrypted;
+                } else if self.app_data == AppData::None && tls.find("tls.app_data").is_ok() {
+                    self.app_data = AppData::Encrypted;
+                }
+            }
+            if tls.find("tls.handshake.type").is_ok() {
+                handshake.push(tls);
+            }
+            if carries_app_data {
+                depth += 1;
+            }
+        }
+
+        let [outer, inner] = handshakes;
+        let is_quic = pkt.find_proto("quic").is_some();
+        for tls in outer {
+            // TLS 1.3 encrypts the handshake messages following the Server Hello;
+            // tshark shows the type of such records as `tls.record.opaque_type`.
+            let decrypted = is_quic || tls.find("tls.record.opaque_type").is_ok();
+            let quality = Quality::of_packet(pkt, decrypted);
+            self.update_handshake(pkt, &tls, quality, conf, store_pkt_num, sender)?;
+        }
+        for tls in inner {
+            let quality = Quality::of_packet(pkt, true);
+            self.inner
+                .get_or_insert_with(Default::default)
+                .update_handshake(pkt, &tls, quality, conf, store_pkt_num, sender)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the Client Hello of a TLS session tunneled in WebSocket messages, see
+    /// [`crate::tunnel::WebSocketTls`]. It belongs to the inner session if this
+    /// stream has a Client Hello of its own, i.e. the WebSocket runs over TLS and
+    /// tshark has decrypted it.
+    pub(crate) fn add_tunneled_client_hello(
+        &mut self,
+        hello: RawClientHello,
+        packet: Option<PacketNum>,
+        conf: &ConfTls,
+    ) {
+        if !conf.selection.ja4 {
+            return;
+        }
+        let stream = if self.client.is_some() {
+            self.inner.get_or_insert_with(Default::default).as_mut()
+        } else {
+            self
         };
+        if stream.client.is_none() {
+            stream.client = ClientStats::from_raw(hello, packet);
+        }
+    }
 
+    /// Collects data from the handshake messages of a `tls` protocol.
+    fn update_handshake(
+        &mut self,
+        pkt: &Packet,
+        tls: &Proto,
+        quality: Quality,
+        conf: &ConfTls,
+        store_pkt_num: bool,
+        sender: Sender,
+    ) -> Result<()> {
         const CLIENT_HELLO: &str = "1";
         const SERVER_HELLO: &str = "2";
         const CERTIFICATE: &str = "11";
 
+        let selection = conf.selection;
         for tls_handshake_type in tls.fields("tls.handshake.type") {
             match tls_handshake_type.value() {
-                CLIENT_HELLO => {
-                    debug_assert_eq!(
-                        tls_handshake_type.display(),
-                        "Handshake Type: Client Hello (1)",
-                        "packet={}",
-                        pkt.num
-                    );
+                CLIENT_HELLO if selection.ja4 => {
+                    debug_assert_display(tls_handshake_type, "Handshake Type: Client Hello (1)");
                     // We only process a single TLS Client Hello packet per stream.
                     if self.client.is_none() {
-                        self.client = Some(ClientStats::new(pkt, &tls, store_pkt_num)?);
+                        let client = ClientStats::new(pkt, tls, quality, store_pkt_num)?;
+                        if client
+                            .sni
+                            .as_deref()
+                            .is_some_and(|sni| conf.is_doh_server(sni))
+                        {
+                            self.dns_encrypted = Some(DnsEncrypted::Doh);
+                        }
+                        self.client = Some(client);
                     }
                 }
-                SERVER_HELLO => {
-                    debug_assert_eq!(
-                        tls_handshake_type.display(),
-                        "Handshake Type: Server Hello (2)"
-                    );
+                SERVER_HELLO if selection.ja4s => {
+                    debug_assert_display(tls_handshake_type, "Handshake Type: Server Hello (2)");
                     // We only need data from a single TLS Server Hello packet per stream.
                     if self.server.is_none() {
-                        self.server = ServerStats::try_new(pkt, &tls, store_pkt_num)?;
+                        self.server = ServerStats::try_new(pkt, tls, quality, store_pkt_num)?;
                     }
                 }
-                CERTIFICATE => {
-                    debug_assert_eq!(
-                        tls_handshake_type.display(),
-                        "Handshake Type: Certificate (11)"
-                    );
-
-                    let mut recs = Vec::new();
-                    for hexdump in tls.values("tls.handshake.certificate") {
-                        let der = hexdump
-                            .split(':')
-                            .map(|s| u8::from_str_radix(s, 16).map_err(|e| e.into()))
-                            .collect::<Result<Vec<_>>>()?;
-                        let (rem, x509) = X509Certificate::from_der(&der)?;
-                        debug_assert!(rem.is_empty());
-                        recs.push(ja4x::X509Rec::from(x509));
-                    }
-                    debug_assert!(!recs.is_empty());
-
-                    self.x509.push(X509Stats {
-                        packet: store_pkt_num.then_some(pkt.num),
-                        recs,
-                    });
+                CERTIFICATE if selection.ja4x => {
+                    debug_assert_display(tls_handshake_type, "Handshake Type: Certificate (11)");
+
+                    // TLS 1.3 encrypts Certificate messages; tshark shows them only
+                    // if it can decrypt the session (see `--keylog-file`).
+                    let ders = tls
+                        .values("tls.handshake.certificate")
+                        .map(|hexdump| {
+                            hexdump
+                                .split(':')
+                                .map(|s| u8::from_str_radix(s, 16).map_err(|e| e.into()))
+                                .collect::<Result<Vec<_>>>()
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let packet = store_pkt_num.then_some(pkt.num);
+                    self.add_certificates(ders, packet, sender, quality)?;
                 }
                 _ => {}
             }
@@ -105,23 +245,80 @@ impl Stream {
         Ok(())
     }
 
+    /// Adds the certificates of a Certificate message, skipping those seen before.
+    fn add_certificates(
+        &mut self,
+        ders: Vec<Vec<u8>>,
+        packet: Option<PacketNum>,
+        sender: Sender,
+        quality: Quality,
+    ) -> Result<()> {
+        if sender == Sender::Server && self.cert_chain.is_none() {
+            self.cert_chain = Some(CertChain {
+                cert_chain_len: ders.len(),
+                cert_chain_bytes: ders.iter().map(Vec::len).sum(),
+            });
+        }
+        let mut recs = Vec::new();
+        for der in ders {
+            if self.x509_seen.contains(&der) {
+                continue;
+            }
+            let (rem, x509) = X509Certificate::from_der(&der)?;
+            debug_assert!(rem.is_empty());
+            recs.push(ja4x::X509Rec::from(x509));
+            self.x509_seen.insert(der);
+        }
+        if !recs.is_empty() {
+            self.x509.push(X509Stats {
+                packet,
+                sender,
+                quality,
+                recs,
+            });
+        }
+        Ok(())
+    }
+
     pub(crate) fn into_out(self, flags: FormatFlags) -> Option<OutStream> {
         let Stream {
             client,
             server,
             x509,
+            x509_seen: _,
+            cert_chain,
+            dns_encrypted,
+            inner,
+            quic_version,
+            app_data: _,
+            decryption_failed,
         } = self;
 
-        if client.is_none() && server.is_none() && x509.is_empty() {
+        // tshark cannot decrypt the Initial packets of gQUIC and of some draft or
+        // unknown versions of QUIC. We report the version of such a stream, so that
+        // the user knows why there is no JA4 fingerprint.
+        let quic_version = quic_version.filter(|_| client.is_none());
+        if let Some(version) = quic_version {
+            debug!(%version, "no TLS Client Hello in QUIC stream");
+        }
+
+        // Certificate messages are added as the packets are processed.
+        debug_assert!(x509.windows(2).all(|w| w[0].packet <= w[1].packet));
+
+        if client.is_none() && server.is_none() && x509.is_empty() && quic_version.is_none() {
             None
         } else {
             Some(OutStream {
                 client: client.map(|x| x.into_out(flags)),
                 server: server.map(|x| x.into_out(flags)),
-                tls_certs: x509
-                    .into_iter()
-                    .map(|x| x.into_out(flags.with_raw))
-                    .collect(),
+                quic_version,
+                dns_encrypted,
+                decryption_failed,
+                cert_chain: cert_chain.filter(|_| flags.with_details),
+                tls_inner: inner
+                    .and_then(|x| x.into_out(flags))
+                    .map(|tls| Box::new(OutInner { inner: true, tls })),
+                tls_certs: x509.into_iter().map(|x| x.into_out(flags)).collect(),
             })
         }
     }
@@ -133,23 +330,218 @@ pub(crate) struct OutStream {
     client: Option<OutClient>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     server: Option<OutServer>,
+    /// Set only if we could not get a TLS Client Hello from the QUIC stream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quic_version: Option<QuicVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dns_encrypted: Option<DnsEncrypted>,
+    /// The session carries application data that the key log file (`--keylog-file`)
+    /// didn't decrypt.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    decryption_failed: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    cert_chain: Option<CertChain>,
+    /// Certificate messages in the order of their packets.
     #[serde(skip_serializing_if = "Vec::is_empty")]
     tls_certs: Vec<OutX509>,
+    /// Fingerprints of the TLS session tunneled inside this one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls_inner: Option<Box<OutInner>>,
+}
+
+impl OutStream {
+    /// Returns the JA4 fingerprint of the (outer) TLS session.
+    pub(crate) fn ja4(&self) -> Option<&str> {
+        self.client.as_ref().map(|client| match &client.ja4 {
+            OutJa4::Sorted(s) | OutJa4::Unsorted(s) => s.as_str(),
+        })
+    }
+
+    /// Returns the server name (SNI) from the Client Hello of the (outer) TLS session.
+    pub(crate) fn sni(&self) -> Option<&str> {
+        self.client.as_ref()?.sni.as_deref()
+    }
+
+    /// Returns the JA4S fingerprint of the (outer) TLS session.
+    pub(crate) fn ja4s(&self) -> Option<&str> {
+        self.server.as_ref().map(|server| server.ja4s.as_str())
+    }
+
+    /// Returns `true` if there is a JA4, JA4S, or JA4X fingerprint in the outer or
+    /// the inner TLS session.
+    pub(crate) fn has_fingerprints(&self) -> bool {
+        self.client.is_some()
+            || self.server.is_some()
+            || !self.tls_certs.is_empty()
+            || self
+                .tls_inner
+                .as_ref()
+                .is_some_and(|inner| inner.tls.has_fingerprints())
+    }
+
+    /// Returns JA4X fingerprints of the certificates, in the order of appearance.
+    pub(crate) fn ja4x(&self) -> impl Iterator<Item = &str> {
+        self.tls_certs
+            .iter()
+            .flat_map(|certs| certs.x509.iter().map(|x509| x509.ja4x()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OutInner {
+    /// Always `true`; makes the inner session's fingerprints easy to tell apart.
+    inner: bool,
+    #[serde(flatten)]
+    tls: OutStream,
+}
+
+/// Encrypted DNS transport detected on a TLS stream.
+///
+/// This is an annotation only; it doesn't affect the fingerprints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DnsEncrypted {
+    /// DNS over HTTPS, see [RFC 8484](https://datatracker.ietf.org/doc/html/rfc8484).
+    Doh,
+    /// DNS over TLS, see [RFC 7858](https://datatracker.ietf.org/doc/html/rfc7858).
+    Dot,
+}
+
+impl DnsEncrypted {
+    /// Port number reserved for DNS over TLS.
+    const DOT_PORT: &'static str = "853";
+    /// ALPN protocol ID of DNS over TLS.
+    const DOT_ALPN: &'static str = "dot";
+    /// Media type of DNS messages carried over HTTPS.
+    const DOH_CONTENT_TYPE: &'static str = "application/dns-message";
+
+    /// Detects encrypted DNS by the packet's TCP port, ALPN, or (decrypted) HTTP
+    /// content type. Server names of DNS-over-HTTPS resolvers are checked separately,
+    /// see [`ConfTls::is_doh_server`].
+    fn detect(pkt: &Packet) -> Option<Self> {
+        if let Some(http) = pkt.find_proto("http") {
+            if http
+                .values("http.content_type")
+                .any(|s| s.starts_with(Self::DOH_CONTENT_TYPE))
+            {
+                return Some(Self::Doh);
+            }
+        }
+        if let Some(http2) = pkt.find_proto("http2") {
+            if http2
+                .values("http2.headers.content_type")
+                .any(|s| s.starts_with(Self::DOH_CONTENT_TYPE))
+            {
+                return Some(Self::Doh);
+            }
+        }
+        if pkt.protos("tls").any(|tls| {
+            tls.values("tls.handshake.extensions_alpn_str")
+                .any(|s| s == Self::DOT_ALPN)
+        }) {
+            return Some(Self::Dot);
+        }
+        let tcp = pkt.protos("tcp").last()?;
+        if pkt.find_proto("tls").is_some()
+            && (matches!(tcp.first("tcp.srcport"), Ok(Self::DOT_PORT))
+                || matches!(tcp.first("tcp.dstport"), Ok(Self::DOT_PORT)))
+        {
+            return Some(Self::Dot);
+        }
+        None
+    }
+}
+
+/// QUIC version field of a long header packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuicVersion(u32);
+
+impl QuicVersion {
+    /// Version Negotiation packets have the version field set to zero. They are sent
+    /// by the server in response to a client's unsupported version and carry no
+    /// handshake.
+    ///
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc9000#section-17.2.1>
+    const NEGOTIATION: u32 = 0;
+
+    /// Gets the version of an IETF QUIC or Google QUIC (gQUIC) long header packet.
+    fn detect(pkt: &Packet) -> Option<Self> {
+        let version = if let Some(gquic) = pkt.find_proto("gquic") {
+            // gQUIC versions are 4-character strings, e.g. "Q043".
+            let version = gquic.first("gquic.version").ok()?;
+            let bytes: [u8; 4] = version.as_bytes().try_into().ok()?;
+            u32::from_be_bytes(bytes)
+        } else {
+            pkt.protos("quic")
+                .filter_map(|quic| {
+                    let version = quic.first("quic.version").ok()?;
+                    u32::from_str_radix(version.strip_prefix("0x")?, 16).ok()
+                })
+                .find(|&version| version != Self::NEGOTIATION)?
+        };
+        (version != Self::NEGOTIATION).then_some(Self(version))
+    }
+}
+
+impl fmt::Display for QuicVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.0.to_be_bytes();
+        // gQUIC versions, e.g. "Q046" or "T051", are printed as is.
+        if matches!(bytes[0], b'Q' | b'T') && bytes[1..].iter().all(u8::is_ascii_digit) {
+            bytes
+                .iter()
+                .try_for_each(|&b| write!(f, "{}", char::from(b)))
+        } else {
+            write!(f, "{:#010x}", self.0)
+        }
+    }
+}
+
+impl Serialize for QuicVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Size of the certificate chain that the server sent (`--with-details`).
+///
+/// TLS 1.3 encrypts the Certificate message, so the chain is only known if the
+/// session is decrypted (`--keylog-file`).
+#[derive(Debug, Clone, Copy, Serialize)]
+struct CertChain {
+    /// Number of certificates.
+    cert_chain_len: usize,
+    /// Total size of their DER encodings, in bytes.
+    cert_chain_bytes: usize,
 }
 
 /// X.509 certificates collected from a single packet.
 #[derive(Debug)]
 pub(crate) struct X509Stats {
     packet: Option<PacketNum>,
+    /// Which side presented the certificates. Clients send them when the server
+    /// requests mutual authentication.
+    sender: Sender,
+    quality: Quality,
     recs: Vec<ja4x::X509Rec>,
 }
 
 impl X509Stats {
-    fn into_out(self, with_raw: bool) -> OutX509 {
-        let X509Stats { packet, recs } = self;
-        let x509 = recs.into_iter().map(|x| x.into_out(with_raw)).collect();
+    fn into_out(self, flags: FormatFlags) -> OutX509 {
+        let X509Stats {
+            packet,
+            sender,
+            quality,
+            recs,
+        } = self;
+        let x509 = recs
+            .into_iter()
+            .map(|x| x.into_out(flags.with_raw))
+            .collect();
         OutX509 {
             pkt_x509: packet,
+            from_client: sender == Sender::Client,
+            ja4x_quality: flags.with_details.then_some(quality),
             x509,
         }
     }
@@ -159,12 +551,17 @@ impl X509Stats {
 pub(crate) struct OutX509 {
     #[serde(skip_serializing_if = "Option::is_none")]
     pkt_x509: Option<PacketNum>,
+    /// Set for client certificates; server certificates are far more common.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    from_client: bool,
+    /// Same for all the certificates of the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ja4x_quality: Option<Quality>,
     x509: Vec<ja4x::OutX509Rec>,
 }
 
 /// Information obtained from a TLS Client Hello packet.
-#[derive(Debug)]
-#[cfg_attr(test, derive(Clone))]
+#[derive(Debug, Clone)]
 pub(crate) struct ClientStats {
     packet: Option<PacketNum>,
     tls_ver: TlsVersion,
@@ -172,12 +569,42 @@ pub(crate) struct ClientStats {
     exts: Vec<u16>,
     /// Server Name Indication (SNI)
     sni: Option<String>,
+    /// First and last characters of the first ALPN value; only these go into JA4.
     alpn: (Option<char>, Option<char>),
+    /// All ALPN values, in the order of the Client Hello.
+    alpn_list: Vec<String>,
     sig_hash_algs: Vec<String>,
+    /// The client attempts to resume a session, see [`offers_resumption`].
+    resumed: bool,
+    quality: Quality,
+    /// Inner Client Hello of Encrypted Client Hello, if tshark has decrypted it.
+    ech_inner: Option<Box<ClientStats>>,
 }
 
 impl ClientStats {
-    fn new(pkt: &Packet, tls: &Proto, store_pkt_num: bool) -> Result<Self> {
+    fn new(pkt: &Packet, tls: &Proto, quality: Quality, store_pkt_num: bool) -> Result<Self> {
+        let Some([outer, inner]) = split_ech(tls) else {
+            return Self::from_proto(pkt, tls, quality, store_pkt_num);
+        };
+        let tls_of = |packet| {
+            Packet::new(packet, pkt.num.0)
+                .find_proto("tls")
+                .expect("the packet consists of a `tls` protocol")
+        };
+        let mut client = Self::from_proto(pkt, &tls_of(&outer), quality, store_pkt_num)?;
+        match Self::from_proto(pkt, &tls_of(&inner), Quality::Decrypted, false) {
+            Ok(inner) => client.ech_inner = Some(Box::new(inner)),
+            Err(error) => debug!(%pkt.num, %error, "failed to fingerprint ECH inner Client Hello"),
+        }
+        Ok(client)
+    }
+
+    fn from_proto(
+        pkt: &Packet,
+        tls: &Proto,
+        quality: Quality,
+        store_pkt_num: bool,
+    ) -> Result<Self> {
         let exts = tls_extensions_client(tls);
         let tls_ver = TlsVersion::new(tls, exts.contains(&TLS_EXT_SUPPORTED_VERSIONS))?;
         let sni = tls
@@ -187,6 +614,10 @@ impl ClientStats {
         let alpn = tls
             .first("tls.handshake.extensions_alpn_str")
             .map_or((None, None), first_last);
+        let alpn_list = tls
+            .values("tls.handshake.extensions_alpn_str")
+            .map(str::to_owned)
+            .collect();
         let ciphers = tls
             .values("tls.handshake.ciphersuite")
             .filter(|v| !TLS_GREASE_VALUES_STR.contains(v))
@@ -198,6 +629,7 @@ impl ClientStats {
                 s.map(str::to_owned)
             })
             .collect();
+        let quality = quality.of_client_hello(&exts);
 
         Ok(Self {
             packet: store_pkt_num.then_some(pkt.num),
@@ -206,43 +638,348 @@ impl ClientStats {
             exts,
             sni,
             alpn,
+            alpn_list,
             sig_hash_algs: sig_hash_algs(pkt, tls),
+            resumed: offers_resumption(tls),
+            quality,
+            ech_inner: None,
         })
     }
 
+    /// Returns JA4 (`ja4_o` with `original_order`).
+    pub(crate) fn ja4(&self, original_order: bool) -> String {
+        let flags = FormatFlags {
+            original_order,
+            ..Default::default()
+        };
+        match self.clone().into_out(flags).ja4 {
+            OutJa4::Sorted(s) | OutJa4::Unsorted(s) => s,
+        }
+    }
+
     fn into_out(mut self, flags: FormatFlags) -> OutClient {
         let FormatFlags {
             with_raw,
             original_order,
+            with_details,
         } = flags;
 
         let sni = self.sni.take();
         let pkt_ja4 = self.packet.take();
-        let parts = PartsOfClientFingerprint::from_client_stats(self, original_order);
+        let resumed = self.resumed;
+        let ech_inner = self.ech_inner.take();
+        let ech = self.exts.contains(&TLS_EXT_ENCRYPTED_CLIENT_HELLO);
+        let alpn_list = std::mem::take(&mut self.alpn_list);
+        let lists = (with_raw || with_details).then(|| ClientLists {
+            ciphers: self.ciphers.clone(),
+            extensions: self.exts.iter().map(|ext| format!("{ext:04x}")).collect(),
+        });
+        let details = with_details.then_some(ClientDetails {
+            cipher_count: self.ciphers.len(),
+            extension_count: self.exts.len(),
+            sig_alg_count: self.sig_hash_algs.len(),
+            alpn_list,
+            ja4_quality: self.quality,
+        });
+        let quic = self.exts.contains(&TLS_EXT_QUIC_TRANSPORT_PARAMETERS);
+        let parts = PartsOfClientFingerprint::from_client_stats(self, quic, original_order);
 
         let ja4 = {
             let s = parts.as_hashed_fingerprint();
             if original_order {
-                Ja4Fingerprint::Unsorted(s)
+                OutJa4::Unsorted(s)
             } else {
-                Ja4Fingerprint::Sorted(s)
+                OutJa4::Sorted(s)
             }
         };
         let ja4_r = with_raw.then(|| {
             let s = parts.as_raw_fingerprint();
             if original_order {
-                Ja4RawFingerprint::Unsorted(s)
+                OutJa4Raw::Unsorted(s)
             } else {
-                Ja4RawFingerprint::Sorted(s)
+                OutJa4Raw::Sorted(s)
             }
         });
+        let ja4_outer = ech.then(|| match &ja4 {
+            OutJa4::Sorted(s) | OutJa4::Unsorted(s) => s.clone(),
+        });
         OutClient {
             sni,
             pkt_ja4,
             ja4,
             ja4_r,
+            ja4_outer,
+            ja4_inner: ech_inner.map(|inner| inner.ja4(original_order)),
+            lists,
+            details,
+            resumed,
+        }
+    }
+}
+
+/// TLS handshake of a TCP stream parsed without tshark, see [`crate::native`].
+///
+/// Each message comes with the number of the packet that completes it.
+#[derive(Debug, Default)]
+pub(crate) struct RawHandshake {
+    pub(crate) client_hello: Option<(RawClientHello, PacketNum)>,
+    pub(crate) server_hello: Option<(RawServerHello, PacketNum)>,
+    /// DER encodings of the certificates of each Certificate message.
+    pub(crate) certificates: Vec<(Vec<Vec<u8>>, PacketNum, Sender, Quality)>,
+    /// Either side of the stream uses the port of DNS over TLS.
+    pub(crate) is_dot_port: bool,
+}
+
+/// TLS Client Hello message parsed without tshark, see [`crate::native`].
+///
+/// Values are stored as they appear in the message, GREASE values included.
+#[derive(Debug, Default)]
+pub(crate) struct RawClientHello {
+    /// `legacy_version` field.
+    pub(crate) version: u16,
+    pub(crate) ciphers: Vec<u16>,
+    pub(crate) exts: Vec<u16>,
+    /// Contents of `supported_versions` extension.
+    pub(crate) supported_versions: Vec<u16>,
+    /// Host name from `server_name` extension.
+    pub(crate) sni: Option<String>,
+    /// Protocol names from `application_layer_protocol_negotiation` extension.
+    pub(crate) alpn: Vec<String>,
+    /// Contents of `signature_algorithms` extension.
+    pub(crate) sig_algs: Vec<u16>,
+    /// Length of the data of `session_ticket` extension.
+    pub(crate) session_ticket_len: usize,
+    pub(crate) quality: Quality,
+}
+
+/// TLS Server Hello message parsed without tshark, see [`crate::native`].
+#[derive(Debug, Default)]
+pub(crate) struct RawServerHello {
+    /// `legacy_version` field.
+    pub(crate) version: u16,
+    pub(crate) cipher: u16,
+    pub(crate) exts: Vec<u16>,
+    /// Contents of `supported_versions` extension.
+    pub(crate) supported_version: Option<u16>,
+    /// Protocol name from `application_layer_protocol_negotiation` extension.
+    pub(crate) alpn: Option<String>,
+    pub(crate) quality: Quality,
+}
+
+impl Stream {
+    /// Creates a stream with the handshake messages parsed without tshark.
+    ///
+    /// The result is the same as if tshark had dissected the messages and
+    /// [`Stream::update`] had processed them.
+    pub(crate) fn from_raw(handshake: RawHandshake, store_pkt_num: bool, conf: &ConfTls) -> Self {
+        let RawHandshake {
+            client_hello,
+            server_hello,
+            certificates,
+            is_dot_port,
+        } = handshake;
+        let selection = conf.selection;
+        let mut stream = Self::default();
+        if let Some((hello, packet)) = client_hello {
+            stream.dns_encrypted = if selection.ja4
+                && hello
+                    .sni
+                    .as_deref()
+                    .is_some_and(|sni| conf.is_doh_server(sni))
+            {
+                Some(DnsEncrypted::Doh)
+            } else if is_dot_port || hello.alpn.iter().any(|s| s == DnsEncrypted::DOT_ALPN) {
+                Some(DnsEncrypted::Dot)
+            } else {
+                None
+            };
+            if selection.ja4 {
+                stream.client = ClientStats::from_raw(hello, store_pkt_num.then_some(packet));
+            }
+        }
+        if let Some((hello, packet)) = server_hello.filter(|_| selection.ja4s) {
+            stream.server = ServerStats::from_raw(hello, store_pkt_num.then_some(packet));
+        }
+        if selection.ja4x {
+            for (ders, packet, sender, quality) in certificates {
+                if let Err(error) =
+                    stream.add_certificates(ders, store_pkt_num.then_some(packet), sender, quality)
+                {
+                    debug!(%packet, %error, "failed to fingerprint TLS");
+                }
+            }
+        }
+        stream
+    }
+}
+
+impl ClientStats {
+    /// Returns `None` if the `supported_versions` extension has no versions; tshark
+    /// would report the missing field in this case.
+    fn from_raw(hello: RawClientHello, packet: Option<PacketNum>) -> Option<Self> {
+        let RawClientHello {
+            version,
+            ciphers,
+            exts,
+            supported_versions,
+            sni,
+            alpn,
+            sig_algs,
+            session_ticket_len,
+            quality,
+        } = hello;
+        let not_grease = |v: &u16| !TLS_GREASE_VALUES_INT.contains(v);
+
+        let exts = exts.into_iter().filter(not_grease).collect::<Vec<_>>();
+        let tls_ver = if exts.contains(&TLS_EXT_SUPPORTED_VERSIONS) {
+            supported_versions.into_iter().filter(not_grease).max()?
+        } else {
+            version
+        };
+        let resumed = exts.contains(&TLS_EXT_PRE_SHARED_KEY) || session_ticket_len > 0;
+        let quality = quality.of_client_hello(&exts);
+        Some(Self {
+            packet,
+            tls_ver: TlsVersion::from(format!("0x{tls_ver:04x}").as_str()),
+            ciphers: ciphers
+                .into_iter()
+                .filter(not_grease)
+                .map(|v| format!("{v:04x}"))
+                .collect(),
+            exts,
+            sni,
+            alpn: alpn.first().map_or((None, None), |s| first_last(s)),
+            alpn_list: alpn,
+            sig_hash_algs: sig_algs.into_iter().map(|v| format!("{v:04x}")).collect(),
+            resumed,
+            quality,
+            ech_inner: None,
+        })
+    }
+}
+
+/// Transport of a TLS handshake, which determines the first character of JA4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// TLS over TCP (`t`)
+    Tcp,
+    /// QUIC (`q`)
+    Quic,
+}
+
+/// JA4 fingerprint of a Client Hello, see [`ja4_from_client_hello`].
+///
+/// The fields are named as in the output records of `ja4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4Fingerprint {
+    /// Fingerprint with sorted cipher suites and extensions.
+    pub ja4: String,
+    /// `ja4` before hashing: the cipher suites and extensions are listed.
+    pub ja4_r: String,
+    /// Fingerprint with the cipher suites and extensions in the original order,
+    /// SNI and ALPN extensions included.
+    pub ja4_o: String,
+    /// `ja4_o` before hashing.
+    pub ja4_ro: String,
+}
+
+/// Computes JA4 of a TLS Client Hello.
+///
+/// `data` is either a Client Hello message, starting with its handshake type, or
+/// TLS handshake records carrying it; the message may span several records. Data
+/// following the message is ignored. GREASE values are skipped, as are the
+/// extensions that JA4 only counts, known or not.
+///
+/// # Errors
+///
+/// - [`Error::NotClientHello`] if `data` starts with something else.
+/// - [`Error::TruncatedClientHello`] if the message or a length field in it goes
+///   past the end of `data`.
+/// - [`Error::MissingField`] if `supported_versions` extension lists no versions
+///   other than GREASE.
+pub fn ja4_from_client_hello(data: &[u8], transport: Transport) -> Result<Ja4Fingerprint> {
+    let stats = ClientStats {
+        sni: None,
+        ..ClientStats::from_raw(raw_client_hello(data)?, None).ok_or_else(|| {
+            Error::MissingField {
+                name: "tls.handshake.extensions.supported_version".to_owned(),
+            }
+        })?
+    };
+
+    let quic = transport == Transport::Quic;
+    let sorted = PartsOfClientFingerprint::from_client_stats(stats.clone(), quic, false);
+    let unsorted = PartsOfClientFingerprint::from_client_stats(stats, quic, true);
+    Ok(Ja4Fingerprint {
+        ja4: sorted.as_hashed_fingerprint(),
+        ja4_r: sorted.as_raw_fingerprint(),
+        ja4_o: unsorted.as_hashed_fingerprint(),
+        ja4_ro: unsorted.as_raw_fingerprint(),
+    })
+}
+
+/// Parses a Client Hello message or the TLS handshake records carrying it, see
+/// [`ja4_from_client_hello`].
+pub(crate) fn raw_client_hello(data: &[u8]) -> Result<RawClientHello> {
+    use crate::native::{parse_client_hello, CLIENT_HELLO, TLS_HANDSHAKE};
+
+    let message = if data.first() == Some(&TLS_HANDSHAKE) {
+        handshake_fragments(data)?
+    } else {
+        data.to_vec()
+    };
+    let Some((&CLIENT_HELLO, message)) = message.split_first() else {
+        return Err(Error::NotClientHello);
+    };
+    let Some(&[len_0, len_1, len_2]) = message.get(..3) else {
+        return Err(Error::TruncatedClientHello);
+    };
+    let len = usize::from(len_0) << 16 | usize::from(len_1) << 8 | usize::from(len_2);
+    let body = message.get(3..3 + len).ok_or(Error::TruncatedClientHello)?;
+    parse_client_hello(body).map_err(|_| Error::TruncatedClientHello)
+}
+
+/// Concatenates the fragments of the handshake records that `data` starts with.
+fn handshake_fragments(data: &[u8]) -> Result<Vec<u8>> {
+    use crate::native::{TLS_HANDSHAKE, TLS_RECORD_HEADER_LEN};
+
+    let mut fragments = Vec::new();
+    let mut rest = data;
+    while let Some(&[content_type, major, _, len_hi, len_lo]) = rest.get(..TLS_RECORD_HEADER_LEN) {
+        if content_type != TLS_HANDSHAKE {
+            break;
         }
+        if major != 3 {
+            return Err(Error::NotClientHello);
+        }
+        let end = TLS_RECORD_HEADER_LEN + usize::from(u16::from_be_bytes([len_hi, len_lo]));
+        let fragment = rest
+            .get(TLS_RECORD_HEADER_LEN..end)
+            .ok_or(Error::TruncatedClientHello)?;
+        fragments.extend_from_slice(fragment);
+        rest = &rest[end..];
+    }
+    if fragments.is_empty() {
+        return Err(Error::TruncatedClientHello);
     }
+    Ok(fragments)
+}
+
+/// Returns `true` if the Client Hello offers to resume a session: it has
+/// `pre_shared_key` extension (TLS 1.3) or a non-empty `session_ticket` extension
+/// (TLS 1.2). An empty `session_ticket` only announces support for tickets.
+///
+/// The server may still decline and perform a full handshake.
+fn offers_resumption(tls: &Proto) -> bool {
+    // Every extension has a type and a length, so the lengths line up with the
+    // types whatever the order of the fields.
+    tls.values("tls.handshake.extension.type")
+        .zip(tls.values("tls.handshake.extension.len"))
+        .any(|(ext, len)| match ext.parse() {
+            Ok(TLS_EXT_PRE_SHARED_KEY) => true,
+            Ok(TLS_EXT_SESSION_TICKET) => len != "0",
+            _ => false,
+        })
 }
 
 /// Returns hex values of the signature algorithms.
@@ -255,11 +992,14 @@ fn sig_hash_algs(pkt: &Packet, tls: &Proto) -> Vec<String> {
     //
     // We are only interested in `signature_algorithms` extension, so we skip forward
     // to it.
+    if tls.field_order() == FieldOrder::Grouped {
+        return grouped_sig_hash_algs(pkt, tls);
+    }
     let mut iter = tls
         .iter()
         .skip_while(|&md| md.name() != "tls.handshake.extension.type" || md.value() != "13");
     match iter.next() {
-        Some(md) => debug_assert_eq!(md.display(), "Type: signature_algorithms (13)"),
+        Some(md) => debug_assert_display(md, "Type: signature_algorithms (13)"),
         None => {
             debug!(%pkt.num, "signature_algorithms TLS extension not found");
             return Vec::new();
@@ -285,7 +1025,42 @@ fn sig_hash_algs(pkt: &Packet, tls: &Proto) -> Vec<String> {
         .collect()
 }
 
-/// Pieces of data that is used to construct [`Ja4Fingerprint`] and [`Ja4RawFingerprint`].
+/// [`sig_hash_algs`] for fields grouped by name.
+///
+/// The lists of all extensions that have them are concatenated then; every list is
+/// preceded by its length in bytes, `tls.handshake.sig_hash_alg_len`, and the lists
+/// are in the order of their extensions.
+fn grouped_sig_hash_algs(pkt: &Packet, tls: &Proto) -> Vec<String> {
+    /// `signature_algorithms`, `delegated_credentials`, `signature_algorithms_cert`
+    const EXTS_WITH_SIG_HASH_ALGS: [&str; 3] = ["13", "34", "50"];
+
+    let exts = tls
+        .values("tls.handshake.extension.type")
+        .filter(|ext| EXTS_WITH_SIG_HASH_ALGS.contains(ext))
+        .collect::<Vec<_>>();
+    let Some(pos) = exts.iter().position(|&ext| ext == "13") else {
+        debug!(%pkt.num, "signature_algorithms TLS extension not found");
+        return Vec::new();
+    };
+    let lens = tls
+        .values("tls.handshake.sig_hash_alg_len")
+        .map(|len| len.parse::<usize>().map(|len| len / 2))
+        .collect::<Result<Vec<_>, _>>();
+    let lens = match lens {
+        Ok(lens) if lens.len() == exts.len() => lens,
+        _ => {
+            warn!(%pkt.num, ?exts, "Unexpected signature algorithm list lengths");
+            return Vec::new();
+        }
+    };
+    tls.values("tls.handshake.sig_hash_alg")
+        .skip(lens[..pos].iter().sum())
+        .take(lens[pos])
+        .filter_map(|alg| alg.strip_prefix("0x").map(str::to_owned))
+        .collect()
+}
+
+/// Pieces of data that is used to construct [`OutJa4`] and [`OutJa4Raw`].
 #[derive(Debug)]
 struct PartsOfClientFingerprint {
     /// Leading part the JA4 fingerprint up to the first underscore, not including it.
@@ -302,7 +1077,7 @@ struct PartsOfClientFingerprint {
 }
 
 impl PartsOfClientFingerprint {
-    fn from_client_stats(stats: ClientStats, original_order: bool) -> Self {
+    fn from_client_stats(stats: ClientStats, quic: bool, original_order: bool) -> Self {
         let ClientStats {
             packet,
             tls_ver,
@@ -310,12 +1085,16 @@ impl PartsOfClientFingerprint {
             mut exts,
             sni,
             alpn,
+            alpn_list: _,
             sig_hash_algs,
+            resumed: _,
+            quality: _,
+            ech_inner: _,
         } = stats;
         // We've taken these out in `ClientStats::into_out`.
         assert!(packet.is_none() && sni.is_none());
 
-        let quic = quic_marker(exts.contains(&TLS_EXT_QUIC_TRANSPORT_PARAMETERS));
+        let quic = quic_marker(quic);
         let sni_marker = if exts.contains(&TLS_EXT_SERVER_NAME) {
             'd'
         } else {
@@ -382,13 +1161,128 @@ struct OutClient {
     #[serde(skip_serializing_if = "Option::is_none")]
     pkt_ja4: Option<PacketNum>,
     #[serde(flatten)]
-    ja4: Ja4Fingerprint,
+    ja4: OutJa4,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    ja4_r: Option<OutJa4Raw>,
+    /// Set if the Client Hello is the outer one of Encrypted Client Hello (ECH); the
+    /// same as `ja4` (`ja4_o` with `--original-order`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ja4_outer: Option<String>,
+    /// Fingerprint of the inner, real Client Hello of ECH. tshark decrypts it if the
+    /// key log file (`--keylog-file`) has the ECH secret of the session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ja4_inner: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    lists: Option<ClientLists>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
-    ja4_r: Option<Ja4RawFingerprint>,
+    details: Option<ClientDetails>,
+    /// The client attempts to resume an earlier session. Its Client Hello may look
+    /// unusual, e.g. offer fewer cipher suites; JA4 is computed as usual.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    resumed: bool,
+}
+
+/// Exact numbers of the values that JA4 counts, see `--with-details`.
+///
+/// The `b` section of JA4 caps the numbers of ciphers and extensions at 99.
+/// GREASE values are not counted.
+/// Values of the Client Hello that JA4 is computed from (`--with-raw`,
+/// `--with-details`).
+#[derive(Debug, Serialize)]
+struct ClientLists {
+    /// Cipher suites as 4-digit hex strings, in the original order, GREASE values
+    /// skipped.
+    ciphers: Vec<String>,
+    /// Extension types as 4-digit hex strings, in the original order, GREASE values
+    /// skipped. Unlike in `ja4_r`, SNI and ALPN are included.
+    extensions: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
-enum Ja4Fingerprint {
+struct ClientDetails {
+    cipher_count: usize,
+    extension_count: usize,
+    sig_alg_count: usize,
+    /// All ALPN values offered by the client. JA4 only uses the first one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    alpn_list: Vec<String>,
+    ja4_quality: Quality,
+}
+
+/// How completely the message that a fingerprint is computed from was seen
+/// (`ja4_quality`, `ja4s_quality`, `ja4x_quality`, see `--with-details`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Quality {
+    /// The message came in a single packet and nothing suggests it is incomplete.
+    #[default]
+    Full,
+    /// tshark put the message together from several TCP segments, TLS records, or
+    /// QUIC CRYPTO frames. The fingerprint is as good as `full` unless a segment was
+    /// lost or reordered in a way that tshark couldn't make up for.
+    Reassembled,
+    /// tshark decrypted the message: a Client or Server Hello of QUIC, a TLS 1.3
+    /// Certificate, the handshake of a session tunneled in TLS, or an HTTP request
+    /// sent over TLS. Except for QUIC, this takes the keys of `--keylog-file`;
+    /// without them, the fingerprint would be missing.
+    Decrypted,
+    /// Part of the message may be missing: the packet was truncated when captured
+    /// (snapshot length), tshark found it malformed, or the message is the outer
+    /// Client Hello of Encrypted Client Hello, which hides the real one.
+    Partial,
+}
+
+impl Quality {
+    /// Fields that tshark adds to the packet that completes a reassembled message.
+    const REASSEMBLY_FIELDS: [&'static str; 3] = [
+        "tcp.segment.count",
+        "tls.handshake.fragment.count",
+        "quic.crypto.fragment.count",
+    ];
+
+    pub(crate) fn new(truncated: bool, decrypted: bool, reassembled: bool) -> Self {
+        if truncated {
+            Self::Partial
+        } else if decrypted {
+            Self::Decrypted
+        } else if reassembled {
+            Self::Reassembled
+        } else {
+            Self::Full
+        }
+    }
+
+    /// Determines the quality of a message carried by the packet. `decrypted` tells
+    /// whether tshark had to decrypt the message.
+    pub(crate) fn of_packet(pkt: &Packet, decrypted: bool) -> Self {
+        let truncated = pkt.find_proto("frame").is_some_and(|frame| {
+            matches!(
+                (frame.first("frame.cap_len"), frame.first("frame.len")),
+                (Ok(cap_len), Ok(len)) if cap_len != len
+            )
+        });
+        let malformed = pkt.find_proto("_ws.malformed").is_some();
+        // tshark places `tcp.segments` outside the `tcp` protocol.
+        let reassembled = pkt.iter().any(|proto| {
+            proto
+                .iter()
+                .any(|md| Self::REASSEMBLY_FIELDS.contains(&md.name()))
+        });
+        Self::new(truncated || malformed, decrypted, reassembled)
+    }
+
+    /// Downgrades the quality of the outer Client Hello of Encrypted Client Hello.
+    fn of_client_hello(self, exts: &[u16]) -> Self {
+        if exts.contains(&TLS_EXT_ENCRYPTED_CLIENT_HELLO) {
+            Self::Partial
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+enum OutJa4 {
     #[serde(rename = "ja4")]
     Sorted(String),
     #[serde(rename = "ja4_o")]
@@ -396,7 +1290,7 @@ enum Ja4Fingerprint {
 }
 
 #[derive(Debug, Serialize)]
-enum Ja4RawFingerprint {
+enum OutJa4Raw {
     #[serde(rename = "ja4_r")]
     Sorted(String),
     #[serde(rename = "ja4_ro")]
@@ -413,10 +1307,16 @@ pub(crate) struct ServerStats {
     cipher: String,
     exts: Vec<u16>,
     alpn: (Option<char>, Option<char>),
+    quality: Quality,
 }
 
 impl ServerStats {
-    fn try_new(pkt: &Packet, tls: &Proto, store_pkt_num: bool) -> Result<Option<Self>> {
+    fn try_new(
+        pkt: &Packet,
+        tls: &Proto,
+        quality: Quality,
+        store_pkt_num: bool,
+    ) -> Result<Option<Self>> {
         let exts = tls_extensions_server(tls);
         let tls_ver = TlsVersion::new(tls, exts.contains(&TLS_EXT_SUPPORTED_VERSIONS))?;
         let alpn = tls
@@ -431,25 +1331,59 @@ impl ServerStats {
 
         Ok(Some(Self {
             packet: store_pkt_num.then_some(pkt.num),
-            is_quic: pkt.find_proto("udp").is_some(),
+            // The innermost transport; TZSP, for one, wraps TCP in UDP.
+            is_quic: pkt
+                .iter()
+                .filter(|proto| matches!(proto.name(), "tcp" | "udp"))
+                .last()
+                .is_some_and(|proto| proto.name() == "udp"),
             tls_ver,
             cipher: cipher.to_owned(),
             exts,
             alpn,
+            quality,
         }))
     }
 
-    fn into_out(self, flags: FormatFlags) -> OutServer {
-        let Self {
+    /// Returns `None` if the `supported_versions` extension has a GREASE value;
+    /// tshark would report the missing field in this case.
+    fn from_raw(hello: RawServerHello, packet: Option<PacketNum>) -> Option<Self> {
+        let RawServerHello {
+            version,
+            cipher,
+            exts,
+            supported_version,
+            alpn,
+            quality,
+        } = hello;
+        let tls_ver = if exts.contains(&TLS_EXT_SUPPORTED_VERSIONS) {
+            supported_version.filter(|v| !TLS_GREASE_VALUES_INT.contains(v))?
+        } else {
+            version
+        };
+        Some(Self {
             packet,
+            is_quic: false,
+            tls_ver: TlsVersion::from(format!("0x{tls_ver:04x}").as_str()),
+            cipher: format!("{cipher:04x}"),
+            exts,
+            alpn: alpn.as_deref().map_or((None, None), first_last),
+            quality,
+        })
+    }
+
+    fn fingerprint(&self) -> Ja4sFingerprint {
+        let Self {
+            packet: _,
             is_quic,
             tls_ver,
             cipher,
             exts,
             alpn,
+            quality: _,
         } = self;
 
-        let quic = quic_marker(is_quic);
+        let quic = quic_marker(*is_quic);
         let nr_exts = 99.min(exts.len());
 
         let two_chunks = format!(
@@ -459,14 +1393,61 @@ impl ServerStats {
         );
 
         // Note that we are preserving the original order of server's TLS extensions.
-        let exts = exts.into_iter().map(|v| format!("{v:04x}")).join(",");
+        let exts = exts.iter().map(|v| format!("{v:04x}")).join(",");
 
-        OutServer {
-            pkt_ja4s: packet,
+        Ja4sFingerprint {
             ja4s: format!("{two_chunks}_{}", crate::hash12(&exts)),
-            ja4s_r: flags.with_raw.then(|| format!("{two_chunks}_{exts}")),
+            ja4s_r: format!("{two_chunks}_{exts}"),
         }
     }
+
+    pub(crate) fn ja4s(&self) -> String {
+        self.fingerprint().ja4s
+    }
+
+    fn into_out(self, flags: FormatFlags) -> OutServer {
+        let Ja4sFingerprint { ja4s, ja4s_r } = self.fingerprint();
+        OutServer {
+            pkt_ja4s: self.packet,
+            ja4s,
+            ja4s_r: flags.with_raw.then_some(ja4s_r),
+            ja4s_quality: flags.with_details.then_some(self.quality),
+        }
+    }
+}
+
+/// JA4S fingerprint of a Server Hello, see [`ja4s`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4sFingerprint {
+    /// Fingerprint with the hash of the extensions, kept in the order sent.
+    pub ja4s: String,
+    /// `ja4s` before hashing: the extensions are listed.
+    pub ja4s_r: String,
+}
+
+/// Computes JA4S from the parameters of a Server Hello.
+///
+/// `version` is the negotiated protocol version: that of `supported_versions`
+/// extension if the server sent one (`0x0304` for TLS 1.3), otherwise the
+/// `legacy_version` field. `extensions` are the extension types in the order the
+/// server sent them, and `alpn` the protocol it selected, if any.
+pub fn ja4s(
+    version: u16,
+    cipher: u16,
+    extensions: &[u16],
+    alpn: Option<&str>,
+    transport: Transport,
+) -> Ja4sFingerprint {
+    let stats = ServerStats {
+        packet: None,
+        is_quic: transport == Transport::Quic,
+        tls_ver: TlsVersion::from(format!("0x{version:04x}").as_str()),
+        cipher: format!("{cipher:04x}"),
+        exts: extensions.to_vec(),
+        alpn: alpn.map_or((None, None), first_last),
+        quality: Quality::Full,
+    };
+    stats.fingerprint()
 }
 
 #[derive(Debug, Serialize)]
@@ -476,6 +1457,8 @@ struct OutServer {
     ja4s: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     ja4s_r: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ja4s_quality: Option<Quality>,
 }
 
 fn quic_marker(is_quic: bool) -> char {
@@ -489,8 +1472,12 @@ fn quic_marker(is_quic: bool) -> char {
 // See https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#tls-extensiontype-values-1
 const TLS_EXT_SERVER_NAME: u16 = 0; // Server Name Indication (SNI)
 const TLS_EXT_ALPN: u16 = 16; // Application-Layer Protocol Negotiation (ALPN)
+const TLS_EXT_SESSION_TICKET: u16 = 35;
+const TLS_EXT_PRE_SHARED_KEY: u16 = 41;
 const TLS_EXT_SUPPORTED_VERSIONS: u16 = 43;
 const TLS_EXT_QUIC_TRANSPORT_PARAMETERS: u16 = 57;
+const TLS_EXT_ENCRYPTED_CLIENT_HELLO: u16 = 0xfe0d;
+const TLS_EXT_ECH_OUTER_EXTENSIONS: u16 = 0xfd00;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum TlsVersion {
@@ -616,6 +1603,148 @@ fn tls_extensions_server(tls: &Proto) -> Vec<u16> {
     .collect()
 }
 
+/// Separates the inner Client Hello of Encrypted Client Hello (ECH) from the outer one.
+///
+/// If tshark has decrypted `encrypted_client_hello` extension, it dissects the inner
+/// Client Hello within the extension, so the fields of both messages are in `tls`.
+/// Returns the fields of the outer and of the inner message as the `tls` protocols
+/// of two packets, or `None` if there is no inner Client Hello.
+///
+/// The inner message ends with its extensions, `tls.handshake.extensions_length`
+/// bytes of them; the extensions of the outer message that follow ECH come after
+/// it. This requires the fields in the order of the dissection tree, so the inner
+/// Client Hello is not recovered from `tshark -T ek` output.
+///
+/// The extensions that the inner message copies from the outer one (listed in its
+/// `ech_outer_extensions`) replace that extension, as the server does when it
+/// reconstructs the inner Client Hello.
+fn split_ech(tls: &Proto) -> Option<[rtshark::Packet; 2]> {
+    let fields = tls.iter().collect::<Vec<_>>();
+    let ech = fields.iter().position(|md| {
+        md.name() == "tls.handshake.extension.type"
+            && md.value().parse() == Ok(TLS_EXT_ENCRYPTED_CLIENT_HELLO)
+    })?;
+    // Without decryption, the extension is followed by the next one, if any.
+    let start = ech
+        + 1
+        + fields[ech + 1..].iter().position(|md| {
+            matches!(
+                md.name(),
+                "tls.handshake.type" | "tls.handshake.extension.type"
+            )
+        })?;
+    if fields[start].name() != "tls.handshake.type" || fields[start].value() != "1" {
+        return None;
+    }
+    if tls.field_order() == FieldOrder::Grouped {
+        debug!(packet = %tls.packet_num, "cannot separate ECH inner Client Hello in grouped fields");
+        return None;
+    }
+
+    let mut remaining = None;
+    let mut end = fields.len();
+    for (i, md) in fields.iter().enumerate().skip(start + 1) {
+        match md.name() {
+            "tls.handshake.type" => {
+                end = i;
+                break;
+            }
+            "tls.handshake.extensions_length" if remaining.is_none() => {
+                remaining = md.value().parse::<usize>().ok();
+            }
+            "tls.handshake.extension.type" if remaining == Some(0) => {
+                end = i;
+                break;
+            }
+            "tls.handshake.extension.len" => {
+                if let (Some(remaining), Ok(len)) = (&mut remaining, md.value().parse::<usize>()) {
+                    // Type and length take 4 bytes.
+                    *remaining = remaining.saturating_sub(4 + len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let packet = |fields: &[&rtshark::Metadata]| {
+        let mut packet = rtshark::Packet::new();
+        packet.push(tls.name().to_owned());
+        let layer = packet
+            .last_layer_mut()
+            .expect("a layer has just been pushed");
+        for md in fields {
+            layer.add(rtshark::Metadata::new(
+                md.name().to_owned(),
+                md.value().to_owned(),
+                md.display().to_owned(),
+                md.size(),
+                md.position(),
+            ));
+        }
+        packet
+    };
+    let outer = [&fields[..start], &fields[end..]].concat();
+    let mut inner = fields[start..end].to_vec();
+    if let Some(span) = extension_span(&inner, TLS_EXT_ECH_OUTER_EXTENSIONS) {
+        match outer_extension_types(&inner[span.clone()]) {
+            Some(types) => {
+                let copied = types
+                    .into_iter()
+                    .filter_map(|ext_type| {
+                        let span = extension_span(&outer, ext_type);
+                        if span.is_none() {
+                            debug!(packet = %tls.packet_num, ext_type, "ech_outer_extensions refers to a missing extension");
+                        }
+                        span
+                    })
+                    .flat_map(|span| outer[span].iter().copied())
+                    .collect::<Vec<_>>();
+                inner.splice(span, copied);
+            }
+            None => debug!(packet = %tls.packet_num, "malformed ech_outer_extensions"),
+        }
+    }
+    Some([packet(&outer), packet(&inner)])
+}
+
+/// Returns the range of the fields of extension `ext_type`: its
+/// `tls.handshake.extension.type` and the fields up to the next extension.
+fn extension_span(fields: &[&rtshark::Metadata], ext_type: u16) -> Option<Range<usize>> {
+    let is_type = |md: &&rtshark::Metadata| md.name() == "tls.handshake.extension.type";
+    let start = fields
+        .iter()
+        .position(|md| is_type(md) && md.value().parse() == Ok(ext_type))?;
+    let end = fields[start + 1..]
+        .iter()
+        .position(|md| is_type(md) || md.name() == "tls.handshake.type")
+        .map_or(fields.len(), |i| start + 1 + i);
+    Some(start..end)
+}
+
+/// Parses the list of extension types of `ech_outer_extensions` (RFC 9849,
+/// section 5.1): a length byte followed by 2-byte types. tshark shows the
+/// contents of the extension as `tls.handshake.extension.data`.
+fn outer_extension_types(fields: &[&rtshark::Metadata]) -> Option<Vec<u16>> {
+    let data = fields
+        .iter()
+        .find(|md| md.name() == "tls.handshake.extension.data")?;
+    let bytes = data
+        .value()
+        .split(':')
+        .map(|s| u8::from_str_radix(s, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (&len, types) = bytes.split_first()?;
+    if usize::from(len) != types.len() || len % 2 != 0 {
+        return None;
+    }
+    Some(
+        types
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect(),
+    )
+}
+
 fn first_last(s: &str) -> (Option<char>, Option<char>) {
     let replace_nonascii_with_9 = |c: char| {
         if c.is_ascii() {
@@ -682,7 +1811,11 @@ mod tests {
             exts,
             sni: Some("example.com".to_owned()),
             alpn: (Some('h'), Some('2')),
+            alpn_list: vec!["h2".to_owned(), "http/1.1".to_owned()],
             sig_hash_algs,
+            resumed: false,
+            quality: Quality::Full,
+            ech_inner: None,
         };
 
         let out = stats.clone().into_out(FormatFlags::default());
@@ -701,7 +1834,42 @@ mod tests {
             {
               "tls_server_name": "example.com",
               "ja4": "t13d1516h2_8daaf6152771_e5627efa2ab1",
-              "ja4_r": "t13d1516h2_002f,0035,009c,009d,1301,1302,1303,c013,c014,c02b,c02c,c02f,c030,cca8,cca9_0005,000a,000b,000d,0012,0015,0017,001b,0023,002b,002d,0033,4469,ff01_0403,0804,0401,0503,0805,0501,0806,0601"
+              "ja4_r": "t13d1516h2_002f,0035,009c,009d,1301,1302,1303,c013,c014,c02b,c02c,c02f,c030,cca8,cca9_0005,000a,000b,000d,0012,0015,0017,001b,0023,002b,002d,0033,4469,ff01_0403,0804,0401,0503,0805,0501,0806,0601",
+              "ciphers": [
+                "1301",
+                "1302",
+                "1303",
+                "c02b",
+                "c02f",
+                "c02c",
+                "c030",
+                "cca9",
+                "cca8",
+                "c013",
+                "c014",
+                "009c",
+                "009d",
+                "002f",
+                "0035"
+              ],
+              "extensions": [
+                "001b",
+                "0000",
+                "0033",
+                "0010",
+                "4469",
+                "0017",
+                "002d",
+                "000d",
+                "0005",
+                "0023",
+                "0012",
+                "002b",
+                "ff01",
+                "000b",
+                "000a",
+                "0015"
+              ]
             }"#]].assert_eq(&serde_json::to_string_pretty(&out).unwrap());
 
         let out = stats.clone().into_out(FormatFlags {
@@ -718,14 +1886,104 @@ mod tests {
         let out = stats.clone().into_out(FormatFlags {
             with_raw: true,
             original_order: true,
+            ..Default::default()
         });
         expect![[r#"
             {
               "tls_server_name": "example.com",
               "ja4_o": "t13d1516h2_acb858a92679_18f69afefd3d",
-              "ja4_ro": "t13d1516h2_1301,1302,1303,c02b,c02f,c02c,c030,cca9,cca8,c013,c014,009c,009d,002f,0035_001b,0000,0033,0010,4469,0017,002d,000d,0005,0023,0012,002b,ff01,000b,000a,0015_0403,0804,0401,0503,0805,0501,0806,0601"
+              "ja4_ro": "t13d1516h2_1301,1302,1303,c02b,c02f,c02c,c030,cca9,cca8,c013,c014,009c,009d,002f,0035_001b,0000,0033,0010,4469,0017,002d,000d,0005,0023,0012,002b,ff01,000b,000a,0015_0403,0804,0401,0503,0805,0501,0806,0601",
+              "ciphers": [
+                "1301",
+                "1302",
+                "1303",
+                "c02b",
+                "c02f",
+                "c02c",
+                "c030",
+                "cca9",
+                "cca8",
+                "c013",
+                "c014",
+                "009c",
+                "009d",
+                "002f",
+                "0035"
+              ],
+              "extensions": [
+                "001b",
+                "0000",
+                "0033",
+                "0010",
+                "4469",
+                "0017",
+                "002d",
+                "000d",
+                "0005",
+                "0023",
+                "0012",
+                "002b",
+                "ff01",
+                "000b",
+                "000a",
+                "0015"
+              ]
             }"#]].assert_eq(&serde_json::to_string_pretty(&out).unwrap());
 
+        let out = stats.clone().into_out(FormatFlags {
+            with_details: true,
+            ..Default::default()
+        });
+        expect![[r#"
+            {
+              "tls_server_name": "example.com",
+              "ja4": "t13d1516h2_8daaf6152771_e5627efa2ab1",
+              "ciphers": [
+                "1301",
+                "1302",
+                "1303",
+                "c02b",
+                "c02f",
+                "c02c",
+                "c030",
+                "cca9",
+                "cca8",
+                "c013",
+                "c014",
+                "009c",
+                "009d",
+                "002f",
+                "0035"
+              ],
+              "extensions": [
+                "001b",
+                "0000",
+                "0033",
+                "0010",
+                "4469",
+                "0017",
+                "002d",
+                "000d",
+                "0005",
+                "0023",
+                "0012",
+                "002b",
+                "ff01",
+                "000b",
+                "000a",
+                "0015"
+              ],
+              "cipher_count": 15,
+              "extension_count": 16,
+              "sig_alg_count": 8,
+              "alpn_list": [
+                "h2",
+                "http/1.1"
+              ],
+              "ja4_quality": "full"
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+
         let stats = ClientStats {
             packet: Some(PacketNum(10)),
             ..stats
@@ -740,6 +1998,372 @@ mod tests {
         .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
     }
 
+    #[test]
+    fn test_resumption() {
+        let hello = |exts: &[u16], session_ticket_len| RawClientHello {
+            version: 0x0303,
+            ciphers: vec![0x1301, 0x1302, 0x1303],
+            exts: exts.to_vec(),
+            supported_versions: vec![0x0304],
+            sni: Some("example.com".to_owned()),
+            alpn: vec!["h2".to_owned()],
+            sig_algs: vec![0x0403, 0x0804],
+            session_ticket_len,
+            quality: Quality::Full,
+        };
+        let out = |hello| {
+            let stats = ClientStats::from_raw(hello, None).unwrap();
+            serde_json::to_string(&stats.into_out(FormatFlags::default())).unwrap()
+        };
+
+        let fresh = out(hello(&[0x0000, 0x0010, 0x000d, 0x002b, 0x0023], 0));
+        expect![[
+            r#"{"tls_server_name":"example.com","ja4":"t13d0305h2_55b375c5d22e_930c6c131472"}"#
+        ]]
+        .assert_eq(&fresh);
+        // Session ticket: same extensions, so the same JA4.
+        let resumed = out(hello(&[0x0000, 0x0010, 0x000d, 0x002b, 0x0023], 192));
+        expect![[r#"{"tls_server_name":"example.com","ja4":"t13d0305h2_55b375c5d22e_930c6c131472","resumed":true}"#]]
+            .assert_eq(&resumed);
+        // PSK: the extension counts towards JA4 like any other.
+        let resumed = out(hello(&[0x0000, 0x0010, 0x000d, 0x002b, 0x0023, 0x0029], 0));
+        expect![[r#"{"tls_server_name":"example.com","ja4":"t13d0306h2_55b375c5d22e_aaf5589889a0","resumed":true}"#]]
+            .assert_eq(&resumed);
+    }
+
+    #[test]
+    fn test_ech() {
+        let hello = |ech: &[(&str, &str)]| {
+            let mut packet = rtshark::Packet::new();
+            packet.push("tls".to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            let outer_head = [
+                ("tls.handshake.type", "1"),
+                ("tls.handshake.version", "0x0303"),
+                ("tls.handshake.ciphersuite", "0x1301"),
+                ("tls.handshake.ciphersuite", "0x1302"),
+                ("tls.handshake.extensions_length", "104"),
+                ("tls.handshake.extension.type", "0"),
+                ("tls.handshake.extension.len", "23"),
+                ("tls.handshake.extensions_server_name", "public.example.com"),
+                ("tls.handshake.extension.type", "43"),
+                ("tls.handshake.extension.len", "3"),
+                ("tls.handshake.extensions.supported_version", "0x0304"),
+                ("tls.handshake.extension.type", "65037"),
+                ("tls.handshake.extension.len", "58"),
+            ];
+            let outer_tail = [
+                ("tls.handshake.extension.type", "13"),
+                ("tls.handshake.extension.len", "4"),
+                ("tls.handshake.sig_hash_alg_len", "2"),
+                ("tls.handshake.sig_hash_alg", "0x0403"),
+            ];
+            for &(name, value) in outer_head.iter().chain(ech).chain(&outer_tail) {
+                layer.add(rtshark::Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+            packet
+        };
+        let out = |packet: &rtshark::Packet| {
+            let pkt = Packet::new(packet, 1);
+            let stats =
+                ClientStats::new(&pkt, &pkt.find_proto("tls").unwrap(), Quality::Full, false)
+                    .unwrap();
+            serde_json::to_string_pretty(&stats.into_out(FormatFlags::default())).unwrap()
+        };
+
+        // The key log file has no ECH secret.
+        let encrypted = hello(&[("tls.ech.payload", "")]);
+        expect![[r#"
+            {
+              "tls_server_name": "public.example.com",
+              "ja4": "t13d020400_62ed6f6ca7ad_aa5de271aa1e",
+              "ja4_outer": "t13d020400_62ed6f6ca7ad_aa5de271aa1e"
+            }"#]]
+        .assert_eq(&out(&encrypted));
+
+        let decrypted = hello(&[
+            ("tls.ech.payload", ""),
+            ("tls.handshake.type", "1"),
+            ("tls.handshake.version", "0x0303"),
+            ("tls.handshake.ciphersuite", "0x1301"),
+            ("tls.handshake.ciphersuite", "0x1302"),
+            ("tls.handshake.ciphersuite", "0x1303"),
+            ("tls.handshake.extensions_length", "45"),
+            ("tls.handshake.extension.type", "0"),
+            ("tls.handshake.extension.len", "19"),
+            ("tls.handshake.extensions_server_name", "secret.example"),
+            ("tls.handshake.extension.type", "43"),
+            ("tls.handshake.extension.len", "3"),
+            ("tls.handshake.extensions.supported_version", "0x0304"),
+            ("tls.handshake.extension.type", "13"),
+            ("tls.handshake.extension.len", "6"),
+            ("tls.handshake.sig_hash_alg_len", "4"),
+            ("tls.handshake.sig_hash_alg", "0x0403"),
+            ("tls.handshake.sig_hash_alg", "0x0804"),
+            ("tls.handshake.extension.type", "65037"),
+            ("tls.handshake.extension.len", "1"),
+        ]);
+        // The outer fingerprint doesn't change.
+        expect![[r#"
+            {
+              "tls_server_name": "public.example.com",
+              "ja4": "t13d020400_62ed6f6ca7ad_aa5de271aa1e",
+              "ja4_outer": "t13d020400_62ed6f6ca7ad_aa5de271aa1e",
+              "ja4_inner": "t13d030400_55b375c5d22e_cb8acc968953"
+            }"#]]
+        .assert_eq(&out(&decrypted));
+
+        // The inner Client Hello copies signature_algorithms from the outer one.
+        let inner = |sig_hash_algs: &[(&'static str, &'static str)]| {
+            let mut fields = vec![
+                ("tls.ech.payload", ""),
+                ("tls.handshake.type", "1"),
+                ("tls.handshake.version", "0x0303"),
+                ("tls.handshake.ciphersuite", "0x1301"),
+                ("tls.handshake.extensions_length", "42"),
+                ("tls.handshake.extension.type", "0"),
+                ("tls.handshake.extension.len", "19"),
+                ("tls.handshake.extensions_server_name", "secret.example"),
+                ("tls.handshake.extension.type", "43"),
+                ("tls.handshake.extension.len", "3"),
+                ("tls.handshake.extensions.supported_version", "0x0304"),
+            ];
+            fields.extend(sig_hash_algs);
+            fields.extend([
+                ("tls.handshake.extension.type", "65037"),
+                ("tls.handshake.extension.len", "1"),
+            ]);
+            hello(&fields)
+        };
+        let compressed = inner(&[
+            ("tls.handshake.extension.type", "64768"),
+            ("tls.handshake.extension.len", "3"),
+            ("tls.handshake.extension.data", "02:00:0d"),
+        ]);
+        let expanded = inner(&[
+            ("tls.handshake.extension.type", "13"),
+            ("tls.handshake.extension.len", "4"),
+            ("tls.handshake.sig_hash_alg_len", "2"),
+            ("tls.handshake.sig_hash_alg", "0x0403"),
+        ]);
+        expect![[r#"
+            {
+              "tls_server_name": "public.example.com",
+              "ja4": "t13d020400_62ed6f6ca7ad_aa5de271aa1e",
+              "ja4_outer": "t13d020400_62ed6f6ca7ad_aa5de271aa1e",
+              "ja4_inner": "t13d010400_0f2cb44170f4_aa5de271aa1e"
+            }"#]]
+        .assert_eq(&out(&compressed));
+        assert_eq!(out(&compressed), out(&expanded));
+    }
+
+    #[test]
+    fn test_inner_stream_into_out() {
+        let server = ServerStats {
+            packet: None,
+            is_quic: false,
+            tls_ver: TlsVersion::Tls1_2,
+            cipher: "c030".to_owned(),
+            exts: vec![0x0005, 0x0017, 0xff01, 0x0000],
+            alpn: (None, None),
+            quality: Quality::Full,
+        };
+        let stream = Stream {
+            server: Some(server.clone()),
+            inner: Some(Box::new(Stream {
+                server: Some(ServerStats {
+                    tls_ver: TlsVersion::Tls1_3,
+                    cipher: "1301".to_owned(),
+                    exts: vec![0x002b, 0x0033],
+                    ..server
+                }),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        let out = stream.into_out(FormatFlags::default());
+        expect![[r#"
+            {
+              "ja4s": "t120400_c030_4e8089b08790",
+              "tls_inner": {
+                "inner": true,
+                "ja4s": "t130200_1301_a56c5b993250"
+              }
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+    }
+
+    #[test]
+    fn test_decryption_failed() {
+        let server = ServerStats {
+            packet: None,
+            is_quic: false,
+            tls_ver: TlsVersion::Tls1_2,
+            cipher: "c030".to_owned(),
+            exts: vec![0x0005, 0x0017, 0xff01, 0x0000],
+            alpn: (None, None),
+            quality: Quality::Full,
+        };
+        let mut decrypted = Stream {
+            server: Some(server.clone()),
+            app_data: AppData::Decrypted,
+            ..Default::default()
+        };
+        decrypted.mark_decryption_failed();
+        assert!(!decrypted.decryption_failed);
+
+        let mut encrypted = Stream {
+            server: Some(server),
+            app_data: AppData::Encrypted,
+            ..Default::default()
+        };
+        encrypted.mark_decryption_failed();
+        let out = encrypted.into_out(FormatFlags::default());
+        expect![[r#"
+            {
+              "ja4s": "t120400_c030_4e8089b08790",
+              "decryption_failed": true
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+    }
+
+    #[test]
+    fn test_cert_chain() {
+        let pcap =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap/tls-alpn-h2.pcap");
+        let ders = crate::native::certificates(&fs_err::read(pcap).unwrap());
+        let bytes = ders.iter().map(Vec::len).sum::<usize>();
+        let mut stream = Stream::default();
+        // Client certificates don't count, nor do later Certificate messages.
+        stream
+            .add_certificates(ders[..1].to_vec(), None, Sender::Client, Quality::Full)
+            .unwrap();
+        stream
+            .add_certificates(ders.clone(), None, Sender::Server, Quality::Full)
+            .unwrap();
+        stream
+            .add_certificates(ders[..1].to_vec(), None, Sender::Server, Quality::Full)
+            .unwrap();
+        let chain = stream.cert_chain.unwrap();
+        assert_eq!(chain.cert_chain_len, ders.len());
+        assert_eq!(chain.cert_chain_bytes, bytes);
+
+        let out = |with_details| {
+            let mut stream = Stream::default();
+            stream
+                .add_certificates(ders.clone(), None, Sender::Server, Quality::Full)
+                .unwrap();
+            let flags = FormatFlags {
+                with_details,
+                ..Default::default()
+            };
+            serde_json::to_value(stream.into_out(flags)).unwrap()
+        };
+        assert!(out(false).get("cert_chain_len").is_none());
+        let out = out(true);
+        assert_eq!(out["cert_chain_len"], ders.len());
+        assert_eq!(out["cert_chain_bytes"], bytes);
+    }
+
+    #[test]
+    fn test_x509_dedup() {
+        let pcap =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../pcap/tls-alpn-h2.pcap");
+        let ders = crate::native::certificates(&fs_err::read(pcap).unwrap());
+        assert!(ders.len() >= 2);
+        let hexdump = |der: &[u8]| der.iter().map(|b| format!("{b:02x}")).join(":");
+        let certificate = |ders: &[Vec<u8>]| {
+            let mut fields = vec![("tls.handshake.type".to_owned(), "11".to_owned())];
+            fields.extend(
+                ders.iter()
+                    .map(|der| ("tls.handshake.certificate".to_owned(), hexdump(der))),
+            );
+            fields
+        };
+        let packet = |tls_protos: &[Vec<(String, String)>]| {
+            let mut packet = rtshark::Packet::new();
+            for fields in tls_protos {
+                packet.push("tls".to_owned());
+                let layer = packet.last_layer_mut().unwrap();
+                for (name, value) in fields {
+                    layer.add(rtshark::Metadata::new(
+                        name.clone(),
+                        value.clone(),
+                        String::new(),
+                        0,
+                        0,
+                    ));
+                }
+            }
+            packet
+        };
+        let conf = ConfTls {
+            enabled: true,
+            doh_servers: Vec::new(),
+            selection: Default::default(),
+            experimental: false,
+            resolve_dns: false,
+        };
+
+        let mut stream = Stream::default();
+        // The chain, then the leaf certificate again in a second `tls` protocol of
+        // the same packet.
+        let first = packet(&[certificate(&ders), certificate(&ders[..1])]);
+        stream
+            .update(&Packet::new(&first, 1), &conf, true, Sender::Server)
+            .unwrap();
+        // A retransmission of the whole packet.
+        stream
+            .update(&Packet::new(&first, 2), &conf, true, Sender::Server)
+            .unwrap();
+        assert_eq!(stream.x509.len(), 1);
+        assert_eq!(stream.x509[0].recs.len(), ders.len());
+        assert_eq!(stream.x509[0].packet, Some(PacketNum(1)));
+
+        let expected = ders
+            .iter()
+            .map(|der| crate::x509::ja4x(der).unwrap().ja4x)
+            .collect::<Vec<_>>();
+        let out = stream.into_out(FormatFlags::default()).unwrap();
+        assert_eq!(out.ja4x().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_quic_version() {
+        assert_eq!(QuicVersion(1).to_string(), "0x00000001");
+        assert_eq!(QuicVersion(0xff00_001d).to_string(), "0xff00001d");
+        assert_eq!(
+            QuicVersion(u32::from_be_bytes(*b"Q046")).to_string(),
+            "Q046"
+        );
+        assert_eq!(
+            QuicVersion(u32::from_be_bytes(*b"T051")).to_string(),
+            "T051"
+        );
+        assert_eq!(
+            QuicVersion(u32::from_be_bytes(*b"Qabc")).to_string(),
+            "0x51616263"
+        );
+
+        let stream = Stream {
+            quic_version: Some(QuicVersion(u32::from_be_bytes(*b"Q043"))),
+            ..Default::default()
+        };
+        let out = stream.into_out(FormatFlags::default());
+        expect![[r#"
+            {
+              "quic_version": "Q043"
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
+    }
+
     #[test]
     fn test_server_stats_into_out() {
         let stats = ServerStats {
@@ -749,6 +2373,7 @@ mod tests {
             cipher: "c030".to_owned(),
             exts: vec![0x0005, 0x0017, 0xff01, 0x0000],
             alpn: (None, None),
+            quality: Quality::Full,
         };
 
         let out = stats.clone().into_out(FormatFlags::default());
@@ -781,4 +2406,406 @@ mod tests {
             }"#]]
         .assert_eq(&serde_json::to_string_pretty(&out).unwrap());
     }
+
+    #[test]
+    fn test_ja4s() {
+        let fp = ja4s(
+            0x0303,
+            0xc030,
+            &[0x0005, 0x0017, 0xff01, 0x0000],
+            None,
+            Transport::Tcp,
+        );
+        expect![[r#"
+            Ja4sFingerprint {
+                ja4s: "t120400_c030_4e8089b08790",
+                ja4s_r: "t120400_c030_0005,0017,ff01,0000",
+            }
+        "#]]
+        .assert_debug_eq(&fp);
+
+        let fp = ja4s(
+            0x0304,
+            0x1301,
+            &[0x002b, 0x0033],
+            Some("h2"),
+            Transport::Tcp,
+        );
+        expect![[r#"
+            Ja4sFingerprint {
+                ja4s: "t1302h2_1301_a56c5b993250",
+                ja4s_r: "t1302h2_1301_002b,0033",
+            }
+        "#]]
+        .assert_debug_eq(&fp);
+
+        let fp = ja4s(
+            0x0304,
+            0x1301,
+            &[0x0033, 0x002b],
+            Some("h3"),
+            Transport::Quic,
+        );
+        expect![[r#"
+            Ja4sFingerprint {
+                ja4s: "q1302h3_1301_234ea6891581",
+                ja4s_r: "q1302h3_1301_0033,002b",
+            }
+        "#]]
+        .assert_debug_eq(&fp);
+
+        let fp = ja4s(0x0301, 0x0005, &[], None, Transport::Tcp);
+        expect![[r#"
+            Ja4sFingerprint {
+                ja4s: "t100000_0005_000000000000",
+                ja4s_r: "t100000_0005_",
+            }
+        "#]]
+        .assert_debug_eq(&fp);
+
+        // The same as from a packet.
+        let stats = ServerStats {
+            packet: None,
+            is_quic: false,
+            tls_ver: TlsVersion::Tls1_2,
+            cipher: "c030".to_owned(),
+            exts: vec![0x0005, 0x0017, 0xff01, 0x0000],
+            alpn: (None, None),
+            quality: Quality::Full,
+        };
+        assert_eq!(
+            stats.fingerprint(),
+            ja4s(0x0303, 0xc030, &stats.exts, None, Transport::Tcp)
+        );
+    }
+
+    /// Client Hello of the example in `technical_details/JA4.md`, with GREASE
+    /// values added.
+    fn spec_client_hello() -> Vec<u8> {
+        fn vec16(data: &[u8]) -> Vec<u8> {
+            [&u16::try_from(data.len()).unwrap().to_be_bytes()[..], data].concat()
+        }
+        fn u16s(values: &[u16]) -> Vec<u8> {
+            values.iter().flat_map(|v| v.to_be_bytes()).collect()
+        }
+
+        let ciphers = [
+            0x0a0a, 0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013,
+            0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+        ];
+        let sni = vec16(&[&[0][..], &vec16(b"example.com")].concat());
+        let alpn = vec16(&[&[2][..], b"h2", &[8], b"http/1.1"].concat());
+        let versions = [&[6][..], &u16s(&[0x3a3a, 0x0304, 0x0303])].concat();
+        let sig_algs = vec16(&u16s(&[
+            0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+        ]));
+        let exts: [(u16, &[u8]); 18] = [
+            (0x1a1a, &[]),
+            (0x001b, &[]),
+            (0x0000, &sni),
+            (0x0033, &[]),
+            (0x0010, &alpn),
+            (0x4469, &[]),
+            (0x0017, &[]),
+            (0x002d, &[]),
+            (0x000d, &sig_algs),
+            (0x0005, &[]),
+            (0x0023, &[]),
+            (0x0012, &[]),
+            (0x002b, &versions),
+            (0xff01, &[]),
+            (0x000b, &[]),
+            (0x000a, &[]),
+            (0x0015, &[]),
+            (0x2a2a, &[]),
+        ];
+        let exts = exts
+            .iter()
+            .flat_map(|&(ext_type, data)| [&ext_type.to_be_bytes()[..], &vec16(data)].concat())
+            .collect::<Vec<_>>();
+
+        let body = [
+            &[3, 3][..],
+            &[0; 32],
+            &[0],
+            &vec16(&u16s(&ciphers)),
+            &[1, 0],
+            &vec16(&exts),
+        ]
+        .concat();
+        let len = u32::try_from(body.len()).unwrap().to_be_bytes();
+        [&[1], &len[1..], &body[..]].concat()
+    }
+
+    fn records(message: &[u8], split_at: usize) -> Vec<u8> {
+        let (first, second) = message.split_at(split_at);
+        [first, second]
+            .iter()
+            .flat_map(|fragment| {
+                let len = u16::try_from(fragment.len()).unwrap().to_be_bytes();
+                [&[22, 3, 1, len[0], len[1]], *fragment].concat()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ja4_from_client_hello() {
+        let message = spec_client_hello();
+        let fp = ja4_from_client_hello(&message, Transport::Tcp).unwrap();
+        assert_eq!(fp.ja4, "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        assert_eq!(
+            fp.ja4_r,
+            "t13d1516h2_002f,0035,009c,009d,1301,1302,1303,c013,c014,c02b,c02c,c02f,c030,\
+            cca8,cca9_0005,000a,000b,000d,0012,0015,0017,001b,0023,002b,002d,0033,4469,\
+            ff01_0403,0804,0401,0503,0805,0501,0806,0601"
+        );
+        assert_eq!(fp.ja4_o, "t13d1516h2_acb858a92679_18f69afefd3d");
+        assert_eq!(
+            fp.ja4_ro,
+            "t13d1516h2_1301,1302,1303,c02b,c02f,c02c,c030,cca9,cca8,c013,c014,009c,009d,\
+            002f,0035_001b,0000,0033,0010,4469,0017,002d,000d,0005,0023,0012,002b,ff01,\
+            000b,000a,0015_0403,0804,0401,0503,0805,0501,0806,0601"
+        );
+
+        // The same message in two TLS records, followed by application data.
+        let data = [&records(&message, 100)[..], &[23, 3, 3, 0, 1, 0]].concat();
+        assert_eq!(ja4_from_client_hello(&data, Transport::Tcp).unwrap(), fp);
+
+        let fp = ja4_from_client_hello(&message, Transport::Quic).unwrap();
+        assert_eq!(fp.ja4, "q13d1516h2_8daaf6152771_e5627efa2ab1");
+    }
+
+    #[test]
+    fn test_ja4_from_client_hello_errors() {
+        let message = spec_client_hello();
+        for len in [0, 1, 3, 50, message.len() - 1] {
+            assert!(
+                matches!(
+                    ja4_from_client_hello(&message[..len], Transport::Tcp),
+                    Err(Error::TruncatedClientHello | Error::NotClientHello)
+                ),
+                "{len}"
+            );
+        }
+        assert!(matches!(
+            ja4_from_client_hello(&message[..message.len() - 1], Transport::Tcp),
+            Err(Error::TruncatedClientHello)
+        ));
+        let data = records(&message, 100);
+        assert!(matches!(
+            ja4_from_client_hello(&data[..data.len() - 1], Transport::Tcp),
+            Err(Error::TruncatedClientHello)
+        ));
+        // Server Hello.
+        assert!(matches!(
+            ja4_from_client_hello(&[2, 0, 0, 0], Transport::Tcp),
+            Err(Error::NotClientHello)
+        ));
+    }
+
+    #[test]
+    fn test_dns_encrypted() {
+        use crate::select::{FingerprintKind, Selection};
+
+        let packet = |layers: &[(&str, &[(&str, &str)])]| {
+            let mut packet = rtshark::Packet::new();
+            for &(name, fields) in layers {
+                packet.push(name.to_owned());
+                let layer = packet.last_layer_mut().unwrap();
+                for &(name, value) in fields {
+                    layer.add(rtshark::Metadata::new(
+                        name.to_owned(),
+                        value.to_owned(),
+                        String::new(),
+                        0,
+                        0,
+                    ));
+                }
+            }
+            packet
+        };
+        let detect = |layers: &[(&str, &[(&str, &str)])]| {
+            DnsEncrypted::detect(&Packet::new(&packet(layers), 1))
+        };
+        let tcp_853: (&str, &[(&str, &str)]) =
+            ("tcp", &[("tcp.srcport", "50000"), ("tcp.dstport", "853")]);
+        let tcp_443: (&str, &[(&str, &str)]) =
+            ("tcp", &[("tcp.srcport", "443"), ("tcp.dstport", "50000")]);
+
+        // Port 853 counts only for TLS.
+        assert_eq!(detect(&[tcp_853, ("tls", &[])]), Some(DnsEncrypted::Dot));
+        assert_eq!(detect(&[tcp_853]), None);
+        assert_eq!(detect(&[tcp_443, ("tls", &[])]), None);
+        let dot_alpn = ("tls", &[("tls.handshake.extensions_alpn_str", "dot")][..]);
+        assert_eq!(detect(&[tcp_443, dot_alpn]), Some(DnsEncrypted::Dot));
+        let http = (
+            "http",
+            &[("http.content_type", "application/dns-message")][..],
+        );
+        assert_eq!(
+            detect(&[tcp_443, ("tls", &[]), http]),
+            Some(DnsEncrypted::Doh)
+        );
+        let http2 = (
+            "http2",
+            &[("http2.headers.content_type", "application/dns-message")][..],
+        );
+        assert_eq!(
+            detect(&[tcp_853, ("tls", &[]), http2]),
+            Some(DnsEncrypted::Doh)
+        );
+
+        // The server name of a DNS-over-HTTPS resolver.
+        let client_hello = |sni| {
+            packet(&[
+                tcp_443,
+                (
+                    "tls",
+                    &[
+                        ("tls.handshake.type", "1"),
+                        ("tls.handshake.version", "0x0303"),
+                        ("tls.handshake.ciphersuite", "0x1301"),
+                        ("tls.handshake.extension.type", "0"),
+                        ("tls.handshake.extensions_server_name", sni),
+                    ],
+                ),
+            ])
+        };
+        let mut conf = ConfTls {
+            enabled: true,
+            doh_servers: vec!["dns.google".to_owned()],
+            selection: Default::default(),
+            experimental: false,
+            resolve_dns: false,
+        };
+        let dns_encrypted = |packet: &rtshark::Packet, conf: &ConfTls| {
+            let mut stream = Stream::default();
+            stream
+                .update(&Packet::new(packet, 1), conf, false, Sender::Client)
+                .unwrap();
+            stream.dns_encrypted
+        };
+        assert_eq!(
+            dns_encrypted(&client_hello("DNS.google"), &conf),
+            Some(DnsEncrypted::Doh)
+        );
+        assert_eq!(dns_encrypted(&client_hello("www.google.com"), &conf), None);
+
+        // The native engine tells the same from the parsed Client Hello.
+        let from_raw = |sni: &str, alpn: &[&str], is_dot_port, conf: &ConfTls| {
+            let hello = RawClientHello {
+                version: 0x0303,
+                ciphers: vec![0x1301],
+                exts: vec![0x0000, 0x0010],
+                sni: Some(sni.to_owned()),
+                alpn: alpn.iter().map(|&s| s.to_owned()).collect(),
+                ..Default::default()
+            };
+            let handshake = RawHandshake {
+                client_hello: Some((hello, PacketNum(1))),
+                server_hello: None,
+                certificates: Vec::new(),
+                is_dot_port,
+            };
+            Stream::from_raw(handshake, false, conf).dns_encrypted
+        };
+        assert_eq!(
+            from_raw("dns.google", &["h2"], false, &conf),
+            Some(DnsEncrypted::Doh)
+        );
+        assert_eq!(
+            from_raw("example.com", &["h2"], true, &conf),
+            Some(DnsEncrypted::Dot)
+        );
+        assert_eq!(
+            from_raw("example.com", &["dot"], false, &conf),
+            Some(DnsEncrypted::Dot)
+        );
+        assert_eq!(from_raw("example.com", &["h2"], false, &conf), None);
+        // Server names are only checked if JA4 is computed, as with tshark.
+        conf.selection = Selection::new(&[FingerprintKind::Ja4s]);
+        assert_eq!(from_raw("dns.google", &["h2"], false, &conf), None);
+    }
+
+    #[test]
+    fn test_quality() {
+        let packet = |layers: &[(&str, &[(&str, &str)])]| {
+            let mut packet = rtshark::Packet::new();
+            for &(name, fields) in layers {
+                packet.push(name.to_owned());
+                let layer = packet.last_layer_mut().unwrap();
+                for &(name, value) in fields {
+                    layer.add(rtshark::Metadata::new(
+                        name.to_owned(),
+                        value.to_owned(),
+                        String::new(),
+                        0,
+                        0,
+                    ));
+                }
+            }
+            packet
+        };
+        let quality = |packet: &rtshark::Packet, exts: &[u16]| {
+            Quality::of_packet(&Packet::new(packet, 1), false).of_client_hello(exts)
+        };
+
+        let frame = packet(&[("frame", &[("frame.len", "517"), ("frame.cap_len", "517")])]);
+        assert_eq!(quality(&frame, &[0, 16]), Quality::Full);
+        assert_eq!(
+            quality(&frame, &[0, TLS_EXT_ENCRYPTED_CLIENT_HELLO]),
+            Quality::Partial
+        );
+
+        let truncated = packet(&[("frame", &[("frame.len", "1514"), ("frame.cap_len", "96")])]);
+        assert_eq!(quality(&truncated, &[0]), Quality::Partial);
+        let malformed = packet(&[("tcp", &[]), ("_ws.malformed", &[])]);
+        assert_eq!(quality(&malformed, &[0]), Quality::Partial);
+
+        let reassembled = packet(&[
+            ("frame", &[("frame.len", "360"), ("frame.cap_len", "360")]),
+            ("tcp", &[]),
+            ("fake-field-wrapper", &[("tcp.segment.count", "2")]),
+            ("tls", &[]),
+        ]);
+        assert_eq!(quality(&reassembled, &[0]), Quality::Reassembled);
+
+        // JA4S quality, as `Stream::update` determines it.
+        let server_hello: &[(&str, &str)] = &[
+            ("tls.handshake.type", "2"),
+            ("tls.handshake.version", "0x0303"),
+            ("tls.handshake.ciphersuite", "0x1301"),
+        ];
+        let conf = ConfTls {
+            enabled: true,
+            doh_servers: Vec::new(),
+            selection: Default::default(),
+            experimental: false,
+            resolve_dns: false,
+        };
+        let ja4s_quality = |packet: &rtshark::Packet| {
+            let mut stream = Stream::default();
+            stream
+                .update(&Packet::new(packet, 1), &conf, false, Sender::Server)
+                .unwrap();
+            let tls = stream.inner.as_deref().unwrap_or(&stream);
+            tls.server.as_ref().unwrap().quality
+        };
+        assert_eq!(
+            ja4s_quality(&packet(&[("tcp", &[]), ("tls", server_hello)])),
+            Quality::Full
+        );
+        let quic = packet(&[
+            ("udp", &[]),
+            ("quic", &[("quic.crypto.fragment.count", "3")]),
+            ("tls", server_hello),
+        ]);
+        assert_eq!(ja4s_quality(&quic), Quality::Decrypted);
+        let tunneled = packet(&[
+            ("tcp", &[]),
+            ("tls", &[("tls.app_data_proto", "Transport Layer Security")]),
+            ("tls", server_hello),
+        ]);
+        assert_eq!(ja4s_quality(&tunneled), Quality::Decrypted);
+    }
 }
@@ -0,0 +1,694 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Running `tshark` process
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rtshark::{RTShark, RTSharkBuilderReady};
+
+use crate::{
+    ek::{EkCommand, EkProcess},
+    pcap::FieldOrder,
+    Error, Result,
+};
+
+/// How many times we try to start tshark before giving up.
+const SPAWN_ATTEMPTS: u32 = 3;
+
+/// Give up after this many consecutive failures to read tshark output.
+const MAX_READ_ERRORS: usize = 10;
+
+/// Format of tshark output (`--tshark-output`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TsharkOutput {
+    /// XML (`-T pdml`), parsed by rtshark.
+    #[default]
+    Pdml,
+    /// Newline-delimited JSON (`-T ek`), which is faster to parse.
+    Ek,
+}
+
+/// Where tshark reads the packets from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Input<'a> {
+    File(&'a str),
+    Interface {
+        name: &'a str,
+        capture_filter: Option<&'a str>,
+    },
+}
+
+/// tshark executable and the extra arguments to run it with (`--tshark-path`,
+/// `--tshark-arg`).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Program {
+    /// `None` to run the `tshark` found in `PATH`.
+    path: Option<PathBuf>,
+    /// Added to the command lines that dissect packets.
+    args: Vec<String>,
+}
+
+impl Program {
+    /// Checks that the executable exists.
+    pub(crate) fn new(path: Option<PathBuf>, args: Vec<String>) -> Result<Self> {
+        let path = match path {
+            Some(path) if !path.is_file() => {
+                return Err(Error::TsharkPath {
+                    path,
+                    reason: "no such file".to_owned(),
+                })
+            }
+            Some(path) if !is_executable(&path) => {
+                return Err(Error::TsharkPath {
+                    path,
+                    reason: "not executable".to_owned(),
+                })
+            }
+            Some(path) => Some(std::path::absolute(&path)?),
+            None => None,
+        };
+        Ok(Self { path, args })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or(Path::new("tshark"))
+    }
+
+    pub(crate) fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns a command that runs tshark with the given arguments.
+    pub(crate) fn cmd<I>(&self, args: I) -> duct::Expression
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        duct::cmd(self.path(), args)
+    }
+
+    /// Returns the directory of the executable, for [`RTSharkBuilder::env_path`].
+    ///
+    /// rtshark runs `tshark` from `PATH`, so the executable has to have that name.
+    ///
+    /// [`RTSharkBuilder::env_path`]: rtshark::RTSharkBuilderReady::env_path
+    pub(crate) fn env_path(&self) -> Result<Option<&str>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        if path.file_stem().is_none_or(|stem| stem != "tshark") {
+            return Err(Error::TsharkPath {
+                path: path.clone(),
+                reason: "the file name must be 'tshark' unless --tshark-output is ek".to_owned(),
+            });
+        }
+        let dir = path.parent().expect("BUG: absolute path of a file");
+        match dir.to_str() {
+            Some(dir) => Ok(Some(dir)),
+            None => Err(Error::NonUtf8Path(dir.to_owned())),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Windows has no executable bit; whether the file runs shows when it is run.
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// Running tshark process.
+///
+/// The process is killed when this value is dropped.
+pub(crate) struct Tshark {
+    process: Process,
+    watchdog: Option<Watchdog>,
+    nr_read_errors: usize,
+    /// Set by [`Stopper::stop`].
+    stopped: Arc<AtomicBool>,
+}
+
+enum Process {
+    Pdml(RTShark),
+    Ek(EkProcess),
+}
+
+impl Process {
+    fn read(&mut self) -> io::Result<Option<rtshark::Packet>> {
+        match self {
+            Self::Pdml(process) => process.read(),
+            Self::Ek(process) => process.read(),
+        }
+    }
+
+    fn killer(&self) -> Killer {
+        match self {
+            Self::Pdml(process) => Killer::Pid(process.pid()),
+            Self::Ek(process) => Killer::Child(process.child()),
+        }
+    }
+
+    fn kill(&mut self) {
+        match self {
+            Self::Pdml(process) => process.kill(),
+            Self::Ek(process) => process.kill(),
+        }
+    }
+}
+
+impl Tshark {
+    /// Starts tshark, retrying a few times if the system fails to spawn the process.
+    ///
+    /// If `timeout` is set, tshark is killed when it doesn't produce a packet for
+    /// that long.
+    pub(crate) fn spawn(builder: &RTSharkBuilderReady, timeout: Option<Duration>) -> Result<Self> {
+        Self::start(|| builder.spawn().map(Process::Pdml), timeout)
+    }
+
+    /// Same as [`Tshark::spawn`], for `tshark -T ek`.
+    pub(crate) fn spawn_ek(command: &EkCommand, timeout: Option<Duration>) -> Result<Self> {
+        Self::start(|| command.spawn().map(Process::Ek), timeout)
+    }
+
+    fn start(spawn: impl Fn() -> io::Result<Process>, timeout: Option<Duration>) -> Result<Self> {
+        let mut attempt = 1;
+        let process = loop {
+            match spawn() {
+                Ok(process) => break process,
+                // There is no point in retrying if tshark is not installed.
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::TsharkNotFound { source: error })
+                }
+                Err(error) if attempt < SPAWN_ATTEMPTS => {
+                    tracing::warn!(%error, attempt, "failed to spawn tshark, retrying");
+                    thread::sleep(Duration::from_millis(100 * u64::from(attempt)));
+                    attempt += 1;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+        let watchdog = timeout.map(|timeout| Watchdog::start(process.killer(), timeout));
+        Ok(Self {
+            process,
+            watchdog,
+            nr_read_errors: 0,
+            stopped: Arc::default(),
+        })
+    }
+
+    /// Returns a handle that stops tshark while it is being read on another thread.
+    pub(crate) fn stopper(&self) -> Stopper {
+        Stopper {
+            killer: self.process.killer(),
+            stopped: Arc::clone(&self.stopped),
+        }
+    }
+
+    /// Returns the order of the fields in the packets that [`Tshark::read`] returns.
+    pub(crate) fn field_order(&self) -> FieldOrder {
+        match self.process {
+            Process::Pdml(_) => FieldOrder::Tree,
+            Process::Ek(_) => FieldOrder::Grouped,
+        }
+    }
+
+    /// Reads the next packet. Returns `None` when tshark has processed the entire
+    /// capture.
+    ///
+    /// Packets that tshark output cannot be parsed for are logged and skipped.
+    pub(crate) fn read(&mut self) -> Result<Option<rtshark::Packet>> {
+        loop {
+            match self.process.read() {
+                Ok(Some(packet)) => {
+                    self.nr_read_errors = 0;
+                    if let Some(watchdog) = &self.watchdog {
+                        watchdog.feed();
+                    }
+                    return Ok(Some(packet));
+                }
+                Ok(None) => break,
+                Err(_) if self.stopped.load(Ordering::SeqCst) => break,
+                Err(_) if self.watchdog.as_ref().is_some_and(Watchdog::has_fired) => break,
+                Err(error) => {
+                    tracing::error!(%error, "failed to parse tshark output");
+                    self.nr_read_errors += 1;
+                    if self.nr_read_errors == MAX_READ_ERRORS {
+                        return Err(Error::TsharkRead(error));
+                    }
+                }
+            }
+        }
+        match self.watchdog.take() {
+            Some(watchdog) => watchdog.finish().map(|()| None),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Drop for Tshark {
+    fn drop(&mut self) {
+        // Before the process is reaped: a PDML watchdog only knows its ID.
+        drop(self.watchdog.take());
+        self.process.kill();
+    }
+}
+
+/// Kills tshark from another thread than the one that reads it.
+#[derive(Clone)]
+enum Killer {
+    /// ek output: the process is ours.
+    Child(Arc<Mutex<Child>>),
+    /// PDML output: rtshark owns the process, so only its ID is known.
+    Pid(Option<u32>),
+}
+
+impl Killer {
+    fn kill(&self) {
+        match self {
+            // The process may have exited already.
+            Self::Child(child) => {
+                let _ = child.lock().expect("BUG: poisoned mutex").kill();
+            }
+            Self::Pid(Some(pid)) => kill(*pid),
+            Self::Pid(None) => {}
+        }
+    }
+}
+
+/// Kills a [`Tshark`] owned by another thread, see [`Tshark::stopper`].
+pub(crate) struct Stopper {
+    killer: Killer,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Stopper {
+    /// Kills tshark. [`Tshark::read`] returns `None` rather than errors for the
+    /// truncated output.
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.killer.kill();
+    }
+}
+
+/// Checks that tshark accepts the display filter.
+///
+/// tshark compiles the filter before it reads the capture, so we run it on an empty
+/// capture file to get its error message, if any.
+pub(crate) fn check_display_filter(program: &Program, filter: &str) -> Result<()> {
+    // Header of a pcap file with no packets: magic number, version 2.4, zero time
+    // zone offset and timestamp accuracy, snapshot length 65535, Ethernet link type.
+    const EMPTY_PCAP: [u8; 24] = [
+        0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xff, 0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    ];
+
+    let args = ["-Y", filter, "-r", "-"]
+        .into_iter()
+        .chain(program.args().iter().map(String::as_str));
+    let out = program
+        .cmd(args)
+        .stdin_bytes(EMPTY_PCAP)
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| Error::TsharkNotFound { source: e })?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(&out.stderr);
+    Err(Error::DisplayFilter {
+        filter: filter.to_owned(),
+        message: message.trim().trim_start_matches("tshark: ").to_owned(),
+    })
+}
+
+/// Returns the names of the fields and protocols that tshark can dissect.
+///
+/// tshark refuses to run if asked (`-e`) for a field it doesn't know, and the set of
+/// fields differs between tshark versions.
+pub(crate) fn known_fields(program: &Program) -> Result<HashSet<String>> {
+    let out = program
+        .cmd(["-G", "fields"])
+        .stderr_null()
+        .read()
+        .map_err(|e| Error::TsharkNotFound { source: e })?;
+    Ok(parse_known_fields(&out))
+}
+
+/// Parses `tshark -G fields` output: one protocol (`P`) or field (`F`) per line,
+/// with tab-separated columns; the third column is the filter name.
+fn parse_known_fields(glossary: &str) -> HashSet<String> {
+    glossary
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            let kind = columns.next()?;
+            let name = columns.nth(1)?;
+            matches!(kind, "P" | "F").then(|| name.to_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_known_fields() {
+    let glossary = "\
+P\tTransport Layer Security\ttls
+F\tCipher Suite\ttls.handshake.ciphersuite\tFT_UINT16\ttls\t\t0x0\t
+F\tServer Name\ttls.handshake.extensions_server_name\tFT_STRING\ttls\t\t0x0\t
+garbage
+";
+    let mut fields = parse_known_fields(glossary).into_iter().collect::<Vec<_>>();
+    fields.sort();
+    assert_eq!(
+        fields,
+        [
+            "tls",
+            "tls.handshake.ciphersuite",
+            "tls.handshake.extensions_server_name"
+        ]
+    );
+}
+
+#[test]
+fn test_program() {
+    let dir = std::env::temp_dir().join(format!("ja4-test-program-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let missing = dir.join("tshark");
+    assert!(matches!(
+        Program::new(Some(missing.clone()), Vec::new()),
+        Err(Error::TsharkPath { .. })
+    ));
+
+    std::fs::write(&missing, "").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        assert!(matches!(
+            Program::new(Some(missing.clone()), Vec::new()),
+            Err(Error::TsharkPath { reason, .. }) if reason == "not executable"
+        ));
+        std::fs::set_permissions(&missing, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let program = Program::new(Some(missing.clone()), Vec::new()).unwrap();
+    assert_eq!(program.env_path().unwrap(), dir.to_str());
+
+    let renamed = dir.join("tshark-4.2");
+    std::fs::copy(&missing, &renamed).unwrap();
+    let program = Program::new(Some(renamed.clone()), Vec::new()).unwrap();
+    assert_eq!(program.path(), renamed);
+    assert!(matches!(program.env_path(), Err(Error::TsharkPath { .. })));
+
+    assert_eq!(Program::default().env_path().unwrap(), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_read_error() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("ja4-test-read-error-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Prints the ek output of three packets, with garbage in the middle.
+    let script = dir.join("tshark");
+    std::fs::write(
+        &script,
+        r#"#!/bin/sh
+echo '{"index":{}}'
+echo '{"layers":{"ip":{"ip_ip_src":"10.0.0.1"}}}'
+echo '{"layers":{"ip":'
+echo '{"layers":{"ip":{"ip_ip_src":"10.0.0.2"}}}'
+echo '{"layers":{"ip":{"ip_ip_src":"10.0.0.3"}}}'
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let program = Program::new(Some(script), Vec::new()).unwrap();
+    let command = EkCommand::new(&program, Input::File("in.pcap"), &["ip.src"]);
+    let mut tshark = Tshark::spawn_ek(&command, None).unwrap();
+    let mut sources = Vec::new();
+    // The unparsable line is skipped; reading stops at the end of the output only.
+    while let Some(packet) = tshark.read().unwrap() {
+        let ip = packet.layer_name("ip").unwrap();
+        sources.push(ip.metadata("ip.src").unwrap().value().to_owned());
+    }
+    assert_eq!(sources, ["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+    assert_eq!(tshark.nr_read_errors, 0);
+
+    // Output that cannot be parsed at all is an error, not the end of the capture.
+    std::fs::write(
+        program.path(),
+        "#!/bin/sh\nwhile :; do echo garbage; done\n",
+    )
+    .unwrap();
+    let mut tshark = Tshark::spawn_ek(&command, None).unwrap();
+    assert!(matches!(tshark.read(), Err(Error::TsharkRead(_))));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// The display filter is compiled by tshark on an empty capture, and its complaint
+/// becomes the error message.
+#[cfg(unix)]
+#[test]
+fn test_check_display_filter() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("ja4-test-display-filter-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("tshark");
+    std::fs::write(
+        &script,
+        format!(
+            r#"#!/bin/sh
+echo "$@" > {dir}/args
+cat > {dir}/stdin
+case "$2" in
+tls.bogus*)
+    echo 'tshark: "tls.bogus" is neither a field nor a protocol name.' >&2
+    exit 4
+    ;;
+esac
+"#,
+            dir = dir.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let program =
+        Program::new(Some(script), vec!["-o".to_owned(), "tcp.len:0".to_owned()]).unwrap();
+
+    check_display_filter(&program, "tls.handshake.type == 1").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("args")).unwrap(),
+        "-Y tls.handshake.type == 1 -r - -o tcp.len:0\n"
+    );
+    let stdin = std::fs::read(dir.join("stdin")).unwrap();
+    assert_eq!(stdin.len(), 24);
+    assert_eq!(stdin[..4], [0xd4, 0xc3, 0xb2, 0xa1]);
+
+    let error = check_display_filter(&program, "tls.bogus == 1").unwrap_err();
+    expect_test::expect![[r#"invalid display filter "tls.bogus == 1": "tls.bogus" is neither a field nor a protocol name."#]]
+        .assert_eq(&error.to_string());
+    assert_eq!(error.kind(), "display_filter");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// tshark that prints a packet and hangs is killed by the watchdog, or by the
+/// stopper on another thread.
+#[cfg(unix)]
+#[test]
+fn test_kill_hanging() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("ja4-test-hanging-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("tshark");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\necho '{\"layers\":{\"ip\":{\"ip_ip_src\":\"10.0.0.1\"}}}'\nexec sleep 60\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let program = Program::new(Some(script), Vec::new()).unwrap();
+    let command = EkCommand::new(&program, Input::File("in.pcap"), &["ip.src"]);
+
+    let start = Instant::now();
+    let mut tshark = Tshark::spawn_ek(&command, Some(Duration::from_millis(200))).unwrap();
+    assert!(tshark.read().unwrap().is_some());
+    assert!(matches!(tshark.read(), Err(Error::TsharkTimeout(0))));
+    assert!(start.elapsed() < Duration::from_secs(30));
+
+    let start = Instant::now();
+    let mut tshark = Tshark::spawn_ek(&command, None).unwrap();
+    let stopper = tshark.stopper();
+    assert!(tshark.read().unwrap().is_some());
+    let stopping = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        stopper.stop();
+    });
+    // The output ends, which is not an error.
+    assert!(tshark.read().unwrap().is_none());
+    assert!(start.elapsed() < Duration::from_secs(30));
+    stopping.join().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Checks that the capture filter compiles for the interface.
+///
+/// dumpcap (which tshark uses for live capture) prints the compiled filter and exits
+/// with `-d`, so syntax errors are reported before the capture starts.
+pub(crate) fn check_capture_filter(interface: &str, filter: &str) -> Result<()> {
+    let out = duct::cmd!("dumpcap", "-i", interface, "-f", filter, "-d")
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| Error::DumpcapNotFound { source: e })?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(&out.stderr);
+    Err(Error::CaptureFilter {
+        filter: filter.to_owned(),
+        message: message.trim().trim_start_matches("dumpcap: ").to_owned(),
+    })
+}
+
+/// Kills tshark if it doesn't produce a packet within the given time.
+struct Watchdog {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    timeout: Duration,
+    /// When tshark has produced a packet most recently.
+    last_fed: Mutex<Instant>,
+    /// Set when the watchdog is no longer needed.
+    done: AtomicBool,
+    /// Set when the watchdog has killed tshark.
+    fired: AtomicBool,
+}
+
+impl Watchdog {
+    fn start(killer: Killer, timeout: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            timeout,
+            last_fed: Mutex::new(Instant::now()),
+            done: AtomicBool::new(false),
+            fired: AtomicBool::new(false),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.watch(&killer))
+        };
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Lets the watchdog know that tshark is making progress.
+    fn feed(&self) {
+        *self.shared.last_fed.lock().expect("BUG: poisoned mutex") = Instant::now();
+    }
+
+    /// Returns `true` if the watchdog has killed tshark.
+    fn has_fired(&self) -> bool {
+        self.shared.fired.load(Ordering::SeqCst)
+    }
+
+    /// Stops the watchdog. Returns an error if tshark has been killed by it.
+    fn finish(mut self) -> Result<()> {
+        self.stop();
+        if self.has_fired() {
+            Err(Error::TsharkTimeout(self.shared.timeout.as_secs()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn stop(&mut self) {
+        self.shared.done.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            // The watchdog thread doesn't panic.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Shared {
+    fn watch(&self, killer: &Killer) {
+        loop {
+            if self.done.load(Ordering::SeqCst) {
+                return;
+            }
+            let deadline = *self.last_fed.lock().expect("BUG: poisoned mutex") + self.timeout;
+            let now = Instant::now();
+            if now < deadline {
+                thread::park_timeout(deadline - now);
+                continue;
+            }
+            tracing::error!(timeout = ?self.timeout, "tshark is stuck, killing it");
+            self.fired.store(true, Ordering::SeqCst);
+            killer.kill();
+            return;
+        }
+    }
+}
+
+/// Kills the process with the given ID, see [`Killer::Pid`].
+#[cfg(unix)]
+fn kill(pid: u32) {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return;
+    };
+    // SAFETY: `kill` only sends a signal; it doesn't touch our memory.
+    if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+        let error = io::Error::last_os_error();
+        tracing::error!(%error, pid, "failed to kill tshark");
+    }
+}
+
+/// Kills the process with the given ID, see [`Killer::Pid`].
+#[cfg(windows)]
+fn kill(pid: u32) {
+    let cmd = duct::cmd!("taskkill", "/F", "/PID", pid.to_string());
+    if let Err(error) = cmd.stdout_null().stderr_null().run() {
+        tracing::error!(%error, pid, "failed to kill tshark");
+    }
+}
@@ -0,0 +1,455 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Proxy tunnels (HTTP CONNECT, SOCKS5) and WebSocket connections carrying TLS traffic
+//!
+//! A client talking to an explicit proxy first asks the proxy to connect it to the
+//! destination host; the TLS handshake with that host follows on the same TCP stream.
+//! We only annotate such streams --- TLS fingerprinting itself is done by [`crate::tls`].
+//!
+//! A WebSocket connection (`--experimental`) starts as an HTTP request that the
+//! server answers with `101 Switching Protocols`. tshark dissects the messages that
+//! follow as WebSocket frames, not as TLS, so we reassemble the Client Hello of a
+//! tunneled TLS session from them ([`WebSocketTls`]).
+
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    anonymize::Anonymizer,
+    tls::{self, Quality, RawClientHello},
+    Error, Packet, Proto, Result, Sender,
+};
+
+#[derive(Debug, Default)]
+pub(crate) enum Stream {
+    /// No tunnel has been requested yet.
+    #[default]
+    None,
+    /// The client has asked the proxy to establish a tunnel.
+    Requested(Tunnel),
+    /// The proxy has confirmed that the tunnel is established.
+    Established(Tunnel),
+}
+
+impl Stream {
+    /// `websocket` enables detection of WebSocket connections (`--experimental`).
+    pub(crate) fn update(&mut self, pkt: &Packet, sender: Sender, websocket: bool) {
+        if let Self::Established(_) = self {
+            return;
+        }
+        if let Some(http) = pkt.find_proto("http") {
+            self.update_connect(&http);
+            if websocket {
+                self.update_websocket(&http);
+            }
+        } else if let Some(socks) = pkt.find_proto("socks") {
+            self.update_socks5(&socks, sender);
+        }
+    }
+
+    /// Returns `true` once the server has accepted a WebSocket upgrade.
+    pub(crate) fn is_websocket(&self) -> bool {
+        matches!(
+            self,
+            Self::Established(Tunnel {
+                kind: TunnelKind::WebSocket,
+                ..
+            })
+        )
+    }
+
+    pub(crate) fn into_out(self) -> Option<Tunnel> {
+        match self {
+            Self::Established(tunnel) => Some(tunnel),
+            Self::None | Self::Requested(_) => None,
+        }
+    }
+
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc9110#section-9.3.6>
+    fn update_connect(&mut self, http: &Proto) {
+        if let Ok("CONNECT") = http.first("http.request.method") {
+            if let Ok(uri) = http.first("http.request.uri") {
+                *self = Self::Requested(Tunnel {
+                    kind: TunnelKind::Connect,
+                    dst: uri.to_owned(),
+                });
+            }
+        } else if http
+            .first("http.response.code")
+            .is_ok_and(|code| code.starts_with('2'))
+        {
+            self.establish(TunnelKind::Connect);
+        }
+    }
+
+    /// The destination of a WebSocket connection is the `Host` header of the
+    /// upgrade request.
+    ///
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc6455#section-4>
+    fn update_websocket(&mut self, http: &Proto) {
+        if let Ok("GET") = http.first("http.request.method") {
+            let upgrade = http.first("http.upgrade");
+            if upgrade.is_ok_and(|s| s.eq_ignore_ascii_case("websocket")) {
+                if let Ok(host) = http.first("http.host") {
+                    *self = Self::Requested(Tunnel {
+                        kind: TunnelKind::WebSocket,
+                        dst: host.to_owned(),
+                    });
+                }
+            }
+        } else if let Ok("101") = http.first("http.response.code") {
+            self.establish(TunnelKind::WebSocket);
+        }
+    }
+
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc1928>
+    fn update_socks5(&mut self, socks: &Proto, sender: Sender) {
+        const CONNECT: &str = "1";
+        const SUCCEEDED: &str = "0";
+
+        if !matches!(socks.first("socks.version"), Ok("5")) {
+            return;
+        }
+        match sender {
+            Sender::Client => {
+                if !matches!(socks.first("socks.command"), Ok(CONNECT)) {
+                    return;
+                }
+                let Some(host) = ["socks.remote_name", "socks.dst", "socks.dstV6"]
+                    .into_iter()
+                    .find_map(|field| socks.first(field).ok())
+                else {
+                    return;
+                };
+                let dst = match socks.first("socks.dstport") {
+                    Ok(port) => format!("{host}:{port}"),
+                    Err(_) => host.to_owned(),
+                };
+                *self = Self::Requested(Tunnel {
+                    kind: TunnelKind::Socks5,
+                    dst,
+                });
+            }
+            Sender::Server => {
+                if let Ok(SUCCEEDED) = socks.first("socks.results_v5") {
+                    self.establish(TunnelKind::Socks5);
+                }
+            }
+        }
+    }
+
+    fn establish(&mut self, kind: TunnelKind) {
+        match std::mem::take(self) {
+            Self::Requested(tunnel) if tunnel.kind == kind => *self = Self::Established(tunnel),
+            st => *self = st,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Tunnel {
+    #[serde(rename = "tunnel")]
+    kind: TunnelKind,
+    /// The destination requested from the proxy, e.g. `"example.com:443"`, or the
+    /// `Host` header of a WebSocket upgrade request.
+    #[serde(rename = "tunnel_dst")]
+    dst: String,
+}
+
+impl Tunnel {
+    /// Replaces the destination IP address, if any, with a pseudonym.
+    pub(crate) fn anonymize(&mut self, anonymizer: &mut Anonymizer) {
+        self.dst = anonymizer.anonymize(&self.dst);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TunnelKind {
+    Connect,
+    Socks5,
+    WebSocket,
+}
+
+/// Client Hello of a TLS session tunneled in a WebSocket connection
+///
+/// The client's binary messages are unmasked and concatenated until they make up a
+/// Client Hello. Anything else --- a text message, data that is not a TLS handshake
+/// --- ends the search.
+#[derive(Debug, Default)]
+pub(crate) struct WebSocketTls {
+    data: Vec<u8>,
+    done: bool,
+}
+
+impl WebSocketTls {
+    /// Gives up on a Client Hello longer than this.
+    const MAX_LEN: usize = 0x10000;
+
+    /// Returns the Client Hello once the packet completes it.
+    ///
+    /// Reference: <https://datatracker.ietf.org/doc/html/rfc6455#section-5>
+    pub(crate) fn update(&mut self, pkt: &Packet, sender: Sender) -> Option<RawClientHello> {
+        const CONTINUATION: &str = "0";
+        const BINARY: &str = "2";
+        const FIRST_CONTROL_OPCODE: u8 = 8;
+
+        if self.done || sender != Sender::Client {
+            return None;
+        }
+        for ws in pkt.protos("websocket") {
+            match ws.first("websocket.opcode") {
+                Ok(BINARY) => {}
+                Ok(CONTINUATION) if !self.data.is_empty() => {}
+                // Control frames (ping, pong, close) may come between the fragments of
+                // a message.
+                Ok(opcode)
+                    if opcode
+                        .parse()
+                        .is_ok_and(|op: u8| op >= FIRST_CONTROL_OPCODE) =>
+                {
+                    continue
+                }
+                _ => {
+                    self.done = true;
+                    return None;
+                }
+            }
+            match unmasked_payload(&ws) {
+                Ok(payload) => self.data.extend(payload),
+                Err(error) => {
+                    debug!(%pkt.num, %error, "failed to read WebSocket payload");
+                    self.done = true;
+                    return None;
+                }
+            }
+            match tls::raw_client_hello(&self.data) {
+                Ok(mut hello) => {
+                    self.done = true;
+                    // WebSocket over TLS can only be seen decrypted.
+                    let decrypted = pkt.find_proto("tls").is_some();
+                    hello.quality = Quality::of_packet(pkt, decrypted);
+                    return Some(hello);
+                }
+                Err(Error::TruncatedClientHello) if self.data.len() < Self::MAX_LEN => {}
+                Err(_) => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Clients mask the payload of every frame they send.
+///
+/// Reference: <https://datatracker.ietf.org/doc/html/rfc6455#section-5.3>
+fn unmasked_payload(ws: &Proto) -> Result<Vec<u8>> {
+    let key = parse_hexdump(ws.first("websocket.masking_key")?)?;
+    let Ok(key) = <[u8; 4]>::try_from(key) else {
+        return Err(Error::MissingField {
+            name: "websocket.masking_key".to_owned(),
+        });
+    };
+    let mut payload = parse_hexdump(ws.first("websocket.masked_payload")?)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+    Ok(payload)
+}
+
+/// Parses bytes as tshark shows them, e.g. `"16:03:01"`.
+fn parse_hexdump(s: &str) -> Result<Vec<u8>> {
+    s.split(':')
+        .map(|s| u8::from_str_radix(s, 16).map_err(|e| e.into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(layers: &[(&str, &[(&str, &str)])]) -> rtshark::Packet {
+        let mut packet = rtshark::Packet::new();
+        for &(name, fields) in layers {
+            packet.push(name.to_owned());
+            let layer = packet.last_layer_mut().unwrap();
+            for &(name, value) in fields {
+                layer.add(rtshark::Metadata::new(
+                    name.to_owned(),
+                    value.to_owned(),
+                    String::new(),
+                    0,
+                    0,
+                ));
+            }
+        }
+        packet
+    }
+
+    #[test]
+    fn test_connect() {
+        let request = packet(&[(
+            "http",
+            &[
+                ("http.request.method", "CONNECT"),
+                ("http.request.uri", "example.com:443"),
+            ],
+        )]);
+        let tunnel = |code| {
+            let mut stream = Stream::default();
+            stream.update(&Packet::new(&request, 1), Sender::Client, false);
+            let response = packet(&[("http", &[("http.response.code", code)])]);
+            stream.update(&Packet::new(&response, 2), Sender::Server, false);
+            stream
+                .into_out()
+                .map(|tunnel| serde_json::to_string(&tunnel).unwrap())
+        };
+        assert_eq!(
+            tunnel("200").unwrap(),
+            r#"{"tunnel":"connect","tunnel_dst":"example.com:443"}"#
+        );
+        // The proxy has refused.
+        assert_eq!(tunnel("407"), None);
+    }
+
+    #[test]
+    fn test_socks5() {
+        let tunnel = |request: &[(&str, &str)], result| {
+            let mut stream = Stream::default();
+            let request = packet(&[("socks", request)]);
+            stream.update(&Packet::new(&request, 1), Sender::Client, false);
+            let response = packet(&[(
+                "socks",
+                &[("socks.version", "5"), ("socks.results_v5", result)],
+            )]);
+            stream.update(&Packet::new(&response, 2), Sender::Server, false);
+            stream
+                .into_out()
+                .map(|tunnel| serde_json::to_string(&tunnel).unwrap())
+        };
+        let connect = [
+            ("socks.version", "5"),
+            ("socks.command", "1"),
+            ("socks.remote_name", "example.com"),
+            ("socks.dstport", "443"),
+        ];
+        assert_eq!(
+            tunnel(&connect, "0").unwrap(),
+            r#"{"tunnel":"socks5","tunnel_dst":"example.com:443"}"#
+        );
+        let by_address = [
+            ("socks.version", "5"),
+            ("socks.command", "1"),
+            ("socks.dst", "192.0.2.1"),
+        ];
+        assert_eq!(
+            tunnel(&by_address, "0").unwrap(),
+            r#"{"tunnel":"socks5","tunnel_dst":"192.0.2.1"}"#
+        );
+        // Connection refused.
+        assert_eq!(tunnel(&connect, "5"), None);
+        // BIND is not a tunnel to the destination.
+        let mut bind = connect;
+        bind[1] = ("socks.command", "2");
+        assert_eq!(tunnel(&bind, "0"), None);
+    }
+
+    /// A binary WebSocket frame as tshark dissects it, the payload masked with `key`.
+    fn binary_frame(payload: &[u8], key: [u8; 4]) -> rtshark::Packet {
+        let hexdump = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+        let masked = payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ key[i % 4])
+            .collect::<Vec<_>>();
+        packet(&[(
+            "websocket",
+            &[
+                ("websocket.opcode", "2"),
+                ("websocket.masking_key", &hexdump(&key)),
+                ("websocket.masked_payload", &hexdump(&masked)),
+            ],
+        )])
+    }
+
+    #[test]
+    fn test_websocket() {
+        let upgrade = packet(&[(
+            "http",
+            &[
+                ("http.request.method", "GET"),
+                ("http.upgrade", "websocket"),
+                ("http.host", "example.com:8080"),
+            ],
+        )]);
+        let switching = packet(&[("http", &[("http.response.code", "101")])]);
+
+        // Not without `--experimental`.
+        let mut stream = Stream::default();
+        stream.update(&Packet::new(&upgrade, 1), Sender::Client, false);
+        stream.update(&Packet::new(&switching, 2), Sender::Server, false);
+        assert!(!stream.is_websocket());
+        assert!(stream.into_out().is_none());
+
+        let mut stream = Stream::default();
+        stream.update(&Packet::new(&upgrade, 1), Sender::Client, true);
+        assert!(!stream.is_websocket());
+        stream.update(&Packet::new(&switching, 2), Sender::Server, true);
+        assert!(stream.is_websocket());
+
+        // A Client Hello with a single cipher suite and no extensions, in a TLS
+        // record split across two messages.
+        let body = [&[3, 3][..], &[0; 32], &[0], &[0, 2, 0x13, 0x01], &[1, 0]].concat();
+        let message = [&[1, 0, 0, body.len() as u8][..], &body].concat();
+        let record = [&[22, 3, 1, 0, message.len() as u8][..], &message].concat();
+        let (first, second) = record.split_at(20);
+
+        let mut websocket_tls = WebSocketTls::default();
+        let server_frame = binary_frame(&[23, 3, 3, 0, 1, 0], [0; 4]);
+        assert!(websocket_tls
+            .update(&Packet::new(&server_frame, 3), Sender::Server)
+            .is_none());
+        let frame = binary_frame(first, [0x12, 0x34, 0x56, 0x78]);
+        assert!(websocket_tls
+            .update(&Packet::new(&frame, 4), Sender::Client)
+            .is_none());
+        let frame = binary_frame(second, [0x9a, 0xbc, 0xde, 0xf0]);
+        let hello = websocket_tls
+            .update(&Packet::new(&frame, 5), Sender::Client)
+            .unwrap();
+        assert_eq!(hello.version, 0x0303);
+        assert_eq!(hello.ciphers, [0x1301]);
+        assert!(websocket_tls
+            .update(&Packet::new(&frame, 6), Sender::Client)
+            .is_none());
+
+        let tunnel = serde_json::to_string(&stream.into_out().unwrap()).unwrap();
+        assert_eq!(
+            tunnel,
+            r#"{"tunnel":"websocket","tunnel_dst":"example.com:8080"}"#
+        );
+
+        // Text messages don't carry TLS.
+        let mut websocket_tls = WebSocketTls::default();
+        let text = packet(&[("websocket", &[("websocket.opcode", "1")])]);
+        assert!(websocket_tls
+            .update(&Packet::new(&text, 4), Sender::Client)
+            .is_none());
+        assert!(websocket_tls
+            .update(&Packet::new(&frame, 5), Sender::Client)
+            .is_none());
+    }
+}
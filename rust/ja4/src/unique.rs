@@ -0,0 +1,83 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Distinct fingerprints of a capture file (`--unique`)
+
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::{select::FingerprintKind, stream::StreamTuple, OutputRecord};
+
+/// Streams grouped by the values of the selected fingerprints.
+///
+/// Only the groups are stored, so the memory use depends on the number of distinct
+/// values rather than the number of streams.
+#[derive(Debug)]
+pub(crate) struct Unique {
+    kinds: Vec<FingerprintKind>,
+    /// Keyed by the fingerprint values, in the order of `kinds`.
+    groups: IndexMap<Vec<String>, Group>,
+}
+
+#[derive(Debug)]
+struct Group {
+    count: usize,
+    /// The earliest stream of the group.
+    example: StreamTuple,
+}
+
+impl Unique {
+    pub(crate) fn new(kinds: Vec<FingerprintKind>) -> Self {
+        Self {
+            kinds,
+            groups: IndexMap::new(),
+        }
+    }
+
+    /// Counts the record in the group of its fingerprints. Records that lack any of
+    /// the selected fingerprints are ignored.
+    pub(crate) fn add(&mut self, rec: &OutputRecord) {
+        let Some(key) = self
+            .kinds
+            .iter()
+            .map(|&kind| rec.fingerprint(kind))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        self.groups
+            .entry(key)
+            .or_insert_with(|| Group {
+                count: 0,
+                example: rec.tuple(),
+            })
+            .count += 1;
+    }
+
+    /// Returns the groups in the order of their first appearance.
+    pub(crate) fn into_out(self) -> Vec<OutUnique> {
+        let Self { kinds, groups } = self;
+        groups
+            .into_iter()
+            .map(|(key, Group { count, example })| OutUnique {
+                fingerprints: kinds.iter().map(|kind| kind.name()).zip(key).collect(),
+                count,
+                example,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct OutUnique {
+    #[serde(flatten)]
+    fingerprints: IndexMap<&'static str, String>,
+    /// Number of streams with these fingerprints.
+    count: usize,
+    /// The earliest stream with these fingerprints.
+    example: StreamTuple,
+}
@@ -0,0 +1,167 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Consistency check of the fingerprints and their raw forms (`--validate`)
+//!
+//! A fingerprint and its raw form are both computed from the parts of the
+//! fingerprint. Hashing the sections of the raw form has to give the fingerprint
+//! back; if it doesn't, the two have diverged, e.g. a list was sorted for one of
+//! them only.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{hash12, stream::StreamTuple, OutputRecord};
+
+/// How a section of a fingerprint is derived from that of its raw form.
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    Plain,
+    Hashed,
+}
+
+use Section::{Hashed, Plain};
+
+/// Fingerprints, their raw forms, and the sections of the fingerprints.
+const RULES: &[(&str, &str, &[Section])] = &[
+    ("ja4", "ja4_r", &[Plain, Hashed, Hashed]),
+    ("ja4_o", "ja4_ro", &[Plain, Hashed, Hashed]),
+    ("ja4s", "ja4s_r", &[Plain, Plain, Hashed]),
+    ("ja4h", "ja4h_r", &[Plain, Hashed, Hashed, Hashed]),
+    ("ja4h_o", "ja4h_ro", &[Plain, Hashed, Hashed, Hashed]),
+    ("ja4x", "ja4x_r", &[Hashed, Hashed, Hashed]),
+];
+
+/// Diagnostic record of a fingerprint that its raw form doesn't hash to.
+#[derive(Debug, Serialize)]
+pub(crate) struct Mismatch {
+    #[serde(flatten)]
+    stream: StreamTuple,
+    /// Location of the fingerprint in the output record, e.g. `http[1].ja4h`.
+    field: String,
+    fingerprint: String,
+    raw: String,
+}
+
+/// Returns the fingerprints of the record that don't match their raw forms.
+///
+/// The record has to be computed with `--with-raw`; fingerprints without a raw
+/// form are not checked.
+pub(crate) fn check(rec: &OutputRecord) -> Vec<Mismatch> {
+    let value = serde_json::to_value(rec).expect("BUG: record is not serializable");
+    let mut found = Vec::new();
+    walk(&value, "", &mut found);
+    found
+        .into_iter()
+        .map(|(field, fingerprint, raw)| Mismatch {
+            stream: rec.tuple(),
+            field,
+            fingerprint,
+            raw,
+        })
+        .collect()
+}
+
+/// Checks the fingerprints of the object and of the objects nested in it
+/// (`http`, `tls_certs`, `tls_inner`, ...).
+fn walk(value: &Value, path: &str, found: &mut Vec<(String, String, String)>) {
+    let field = |name: &str| {
+        if path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{path}.{name}")
+        }
+    };
+    match value {
+        Value::Object(map) => {
+            for &(name, raw_name, sections) in RULES {
+                if let (Some(Value::String(fp)), Some(Value::String(raw))) =
+                    (map.get(name), map.get(raw_name))
+                {
+                    if !hashes_to(raw, fp, sections) {
+                        found.push((field(name), fp.clone(), raw.clone()));
+                    }
+                }
+            }
+            for (key, child) in map {
+                walk(child, &field(key), found);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                walk(item, &format!("{path}[{i}]"), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns `true` if hashing the sections of `raw` gives `fingerprint`.
+fn hashes_to(raw: &str, fingerprint: &str, sections: &[Section]) -> bool {
+    let expected = fingerprint.split('_').collect::<Vec<_>>();
+    expected.len() == sections.len() && sections_match(raw, &expected, sections)
+}
+
+/// Sections of a raw fingerprint may contain underscores themselves (cookie names,
+/// the signature algorithms of JA4), so every split of `raw` is tried.
+fn sections_match(raw: &str, expected: &[&str], sections: &[Section]) -> bool {
+    let section_matches = |s: &str, expected: &str, section| match section {
+        Plain => s == expected,
+        Hashed => hash12(s) == expected,
+    };
+    match (expected, sections) {
+        ([expected], [section]) => section_matches(raw, expected, *section),
+        ([first, expected @ ..], [section, sections @ ..]) => {
+            raw.match_indices('_').any(|(i, _)| {
+                section_matches(&raw[..i], first, *section)
+                    && sections_match(&raw[i + 1..], expected, sections)
+            })
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_hashes_to() {
+    let ja4 = "t13d1516h2_8daaf6152771_e5627efa2ab1";
+    let ja4_r = "t13d1516h2_002f,0035,009c,009d,1301,1302,1303,c013,c014,c02b,c02c,c02f,\
+                 c030,cca8,cca9_0005,000a,000b,000d,0012,0015,0017,001b,0023,002b,002d,0033,\
+                 4469,ff01_0403,0804,0401,0503,0805,0501,0806,0601";
+    assert!(hashes_to(ja4_r, ja4, &[Plain, Hashed, Hashed]));
+    // Unsorted ciphers.
+    let ja4_ro = ja4_r.replacen("002f,0035", "0035,002f", 1);
+    assert!(!hashes_to(&ja4_ro, ja4, &[Plain, Hashed, Hashed]));
+
+    assert!(hashes_to(
+        "t120400_c030_0005,0017,ff01,0000",
+        "t120400_c030_4e8089b08790",
+        &[Plain, Plain, Hashed]
+    ));
+    assert!(!hashes_to(
+        "t120400_c02f_0005,0017,ff01,0000",
+        "t120400_c030_4e8089b08790",
+        &[Plain, Plain, Hashed]
+    ));
+
+    // Underscores in the cookie names and values.
+    let fp = crate::http::ja4h(
+        "GET",
+        "HTTP/1.1",
+        &[
+            ("Host", "example.com"),
+            ("Cookie", "_ga=GA1_2; _dd_s=x; id=1"),
+        ],
+        false,
+    )
+    .unwrap();
+    assert!(hashes_to(
+        &fp.ja4h_r,
+        &fp.ja4h,
+        &[Plain, Hashed, Hashed, Hashed]
+    ));
+    assert!(!hashes_to(&fp.ja4h_r, &fp.ja4h, &[Plain, Hashed, Hashed]));
+}
@@ -0,0 +1,85 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! Fingerprints to look for (`--match-file`)
+
+use std::{collections::HashSet, path::Path};
+
+use crate::{OutputRecord, Result};
+
+/// Set of fingerprints loaded from a file.
+#[derive(Debug, Default)]
+pub(crate) struct Watchlist {
+    fingerprints: HashSet<String>,
+}
+
+impl Watchlist {
+    /// Reads the file with one fingerprint per line. Text after `#` is a comment.
+    ///
+    /// Returns the watchlist and the malformed lines with their (1-based) numbers,
+    /// which are skipped; the caller reports them.
+    pub(crate) fn load(path: &Path) -> Result<(Self, Vec<(usize, String)>)> {
+        let text = fs_err::read_to_string(path)?;
+        let (watchlist, malformed) = Self::parse(&text);
+        let malformed = malformed
+            .into_iter()
+            .map(|(line_num, line)| {
+                tracing::warn!(?path, line_num, line, "malformed fingerprint");
+                (line_num, line.to_owned())
+            })
+            .collect();
+        Ok((watchlist, malformed))
+    }
+
+    /// Returns the watchlist and the malformed lines with their (1-based) numbers.
+    fn parse(text: &str) -> (Self, Vec<(usize, &str)>) {
+        let mut fingerprints = HashSet::new();
+        let mut warnings = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let fp = line
+                .split_once('#')
+                .map_or(line, |(fp, _comment)| fp)
+                .trim();
+            if fp.is_empty() {
+                continue;
+            }
+            if is_fingerprint(fp) {
+                fingerprints.insert(fp.to_owned());
+            } else {
+                warnings.push((i + 1, line));
+            }
+        }
+        (Self { fingerprints }, warnings)
+    }
+
+    /// Returns `true` if any fingerprint of the record is on the list.
+    pub(crate) fn matches(&self, rec: &OutputRecord) -> bool {
+        rec.fingerprints().any(|fp| self.fingerprints.contains(fp))
+    }
+}
+
+/// JA4+ fingerprints consist of ASCII letters, digits, and underscores.
+fn is_fingerprint(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+#[test]
+fn test_parse() {
+    let text = "\
+# Known bad clients
+t13d1516h2_8daaf6152771_e5627efa2ab1
+t130200_1301_a56c5b993250  # JA4S
+
+ge11cn20enus_60ca1bd65281_ac95b44401d9_8df6a44f726c
+not a fingerprint
+c36s36_c51s80_c69s0
+";
+    let (watchlist, warnings) = Watchlist::parse(text);
+    assert_eq!(warnings, [(6, "not a fingerprint")]);
+    assert_eq!(watchlist.fingerprints.len(), 4);
+    assert!(watchlist.fingerprints.contains("t130200_1301_a56c5b993250"));
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2023, FoxIO, LLC.
+// All rights reserved.
+// Patent Pending
+// JA4 is Open-Source, Licensed under BSD 3-Clause
+// JA4+ (JA4S, JA4H, JA4L, JA4X, JA4SSH) are licenced under the FoxIO License 1.1.
+// For full license text, see the repo root.
+
+//! JA4X -- X.509 certificate fingerprinting
+//!
+//! The certificates that `ja4` finds in TLS handshakes are fingerprinted by the
+//! same code as those given to [`ja4x`] and [`ja4x_pem`].
+
+use ja4x::x509_parser::{
+    certificate::X509Certificate, error::X509Error, nom, pem::Pem, prelude::FromDer as _,
+};
+
+use crate::{Error, Result};
+
+/// JA4X fingerprint of an X.509 certificate, see [`ja4x`].
+///
+/// The object identifiers are hex-encoded DER, as in `ja4x_r`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ja4xFingerprint {
+    /// The fingerprint: hashes of the issuer RDN, subject RDN, and extension OIDs.
+    pub ja4x: String,
+    /// `ja4x` before hashing.
+    pub ja4x_r: String,
+    /// OIDs of the attributes of the issuer's relative distinguished names.
+    pub issuer_oids: Vec<String>,
+    /// OIDs of the attributes of the subject's relative distinguished names.
+    pub subject_oids: Vec<String>,
+    /// OIDs of the extensions.
+    pub extension_oids: Vec<String>,
+}
+
+/// Computes JA4X of a DER-encoded certificate.
+///
+/// # Errors
+///
+/// [`Error::Certificate`] if `der` is not a certificate or has data after it.
+pub fn ja4x(der: &[u8]) -> Result<Ja4xFingerprint> {
+    let (rem, x509) = X509Certificate::from_der(der).map_err(certificate_error)?;
+    if !rem.is_empty() {
+        return Err(Error::Certificate(format!(
+            "{} bytes after the certificate",
+            rem.len()
+        )));
+    }
+    let rec = ja4x::X509Rec::from(x509);
+    let oids = |s: &str| {
+        s.split(',')
+            .filter(|oid| !oid.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    Ok(Ja4xFingerprint {
+        ja4x: rec.ja4x(),
+        ja4x_r: rec.ja4x_r(),
+        issuer_oids: oids(&rec.issuer_rdns),
+        subject_oids: oids(&rec.subject_rdns),
+        extension_oids: oids(&rec.extensions),
+    })
+}
+
+/// Computes JA4X of the PEM-encoded certificates, e.g. the contents of a `.pem`
+/// file with a certificate chain. Blocks other than `CERTIFICATE` are skipped.
+///
+/// # Errors
+///
+/// - [`Error::Pem`] if a PEM block is malformed or there are no certificates.
+/// - [`Error::Certificate`] if a `CERTIFICATE` block holds something else.
+pub fn ja4x_pem(pem: &[u8]) -> Result<Vec<Ja4xFingerprint>> {
+    let mut fps = Vec::new();
+    for block in Pem::iter_from_buffer(pem) {
+        let block = block.map_err(|e| Error::Pem(e.to_string()))?;
+        if block.label == "CERTIFICATE" {
+            fps.push(ja4x(&block.contents)?);
+        }
+    }
+    if fps.is_empty() {
+        return Err(Error::Pem("no CERTIFICATE block".to_owned()));
+    }
+    Ok(fps)
+}
+
+fn certificate_error(error: nom::Err<X509Error>) -> Error {
+    let reason = match error {
+        nom::Err::Incomplete(_) => "truncated certificate".to_owned(),
+        nom::Err::Error(e) | nom::Err::Failure(e) => e.to_string(),
+    };
+    Error::Certificate(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    /// DER encodings of the certificates sent by the servers in the capture file.
+    fn certificates(pcap: &str) -> Vec<Vec<u8>> {
+        let pcap = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../pcap")
+            .join(pcap);
+        crate::native::certificates(&fs::read(pcap).unwrap())
+    }
+
+    #[test]
+    fn test_ja4x() {
+        let der = &certificates("tls-alpn-h2.pcap")[0];
+        expect_test::expect![[r#"
+            Ja4xFingerprint {
+                ja4x: "7d5dbb3783b4_ba7ce0880c07_7bf9a7bf7029",
+                ja4x_r: "550406,55040a,55040b,550403_55040f,2b0601040182373c020103,2b0601040182373c020102,550405,550406,550408,550407,55040a,550403_551d23,551d0e,551d11,551d0f,551d25,551d1f,551d20,2b06010505070101,551d13,2b06010401d679020402",
+                issuer_oids: [
+                    "550406",
+                    "55040a",
+                    "55040b",
+                    "550403",
+                ],
+                subject_oids: [
+                    "55040f",
+                    "2b0601040182373c020103",
+                    "2b0601040182373c020102",
+                    "550405",
+                    "550406",
+                    "550408",
+                    "550407",
+                    "55040a",
+                    "550403",
+                ],
+                extension_oids: [
+                    "551d23",
+                    "551d0e",
+                    "551d11",
+                    "551d0f",
+                    "551d25",
+                    "551d1f",
+                    "551d20",
+                    "2b06010505070101",
+                    "551d13",
+                    "2b06010401d679020402",
+                ],
+            }
+        "#]].assert_debug_eq(&ja4x(der).unwrap());
+
+        let mut trailing = der.clone();
+        trailing.push(0);
+        expect_test::expect!["invalid X.509 certificate: 1 bytes after the certificate"]
+            .assert_eq(&ja4x(&trailing).unwrap_err().to_string());
+        expect_test::expect!["invalid X.509 certificate: nom error: Eof"]
+            .assert_eq(&ja4x(&der[..der.len() - 1]).unwrap_err().to_string());
+        expect_test::expect!["invalid X.509 certificate: BER error: Unexpected Tag (expected: Some(Tag(16)), actual: Tag(2))"]
+            .assert_eq(&ja4x(b"\x30\x03\x02\x01\x00").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_ja4x_pem() {
+        use data_encoding::BASE64_MIME;
+
+        let ders = certificates("tls-alpn-h2.pcap");
+        let mut pem = String::new();
+        for der in &ders {
+            pem.push_str("-----BEGIN CERTIFICATE-----\r\n");
+            pem.push_str(&BASE64_MIME.encode(der));
+            pem.push_str("-----END CERTIFICATE-----\r\n");
+        }
+        let fps = ja4x_pem(pem.as_bytes()).unwrap();
+        assert_eq!(fps.len(), ders.len());
+        for (fp, der) in fps.iter().zip(&ders) {
+            assert_eq!(fp, &ja4x(der).unwrap());
+        }
+
+        expect_test::expect!["invalid PEM: no CERTIFICATE block"]
+            .assert_eq(&ja4x_pem(b"").unwrap_err().to_string());
+        expect_test::expect!["invalid PEM: base64 decode error"].assert_eq(
+            &ja4x_pem(b"-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----\n")
+                .unwrap_err()
+                .to_string(),
+        );
+    }
+}
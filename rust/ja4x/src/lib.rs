@@ -35,20 +35,39 @@ pub struct X509Rec {
     pub subject: Vec<Oid>,
 }
 
+impl OutX509Rec {
+    /// Returns the JA4X fingerprint.
+    pub fn ja4x(&self) -> &str {
+        &self.ja4x
+    }
+}
+
 impl X509Rec {
+    /// Returns the JA4X fingerprint.
+    pub fn ja4x(&self) -> String {
+        self.parts().iter().map(hash12).join("_")
+    }
+
+    /// Returns the JA4X fingerprint before hashing.
+    pub fn ja4x_r(&self) -> String {
+        self.parts().join("_")
+    }
+
+    fn parts(&self) -> [&str; 3] {
+        [&self.issuer_rdns, &self.subject_rdns, &self.extensions]
+    }
+
     pub fn into_out(self, with_raw: bool) -> OutX509Rec {
+        let ja4x = self.ja4x();
+        let ja4x_r = with_raw.then(|| self.ja4x_r());
         let X509Rec {
-            issuer_rdns,
-            subject_rdns,
-            extensions,
+            issuer_rdns: _,
+            subject_rdns: _,
+            extensions: _,
             issuer,
             subject,
         } = self;
 
-        let parts = [issuer_rdns, subject_rdns, extensions];
-        let ja4x = parts.iter().map(hash12).join("_");
-        let ja4x_r = with_raw.then(|| parts.join("_"));
-
         let issuer_items = issuer.into_iter().filter_map(|oid| oid.into_kv("issuer"));
         let subject_items = subject.into_iter().filter_map(|oid| oid.into_kv("subject"));
 